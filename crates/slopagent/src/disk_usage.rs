@@ -0,0 +1,93 @@
+//! Worktree disk usage, with a short-lived cache so repeated polls don't re-walk large trees.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How long a computed size is reused before the tree is walked again.
+pub const DISK_USAGE_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Total size in bytes of the files under `root`. Symlinks count as themselves and are not
+/// followed; with `exclude_git`, every `.git` entry (directory or worktree gitfile) is skipped.
+pub async fn dir_size(root: &Path, exclude_git: bool) -> io::Result<u64> {
+    let mut total = 0u64;
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            // The root must exist; anything vanishing mid-walk is just skipped.
+            Err(e) if dir != root && e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            if exclude_git && entry.file_name() == ".git" {
+                continue;
+            }
+            let Ok(meta) = tokio::fs::symlink_metadata(entry.path()).await else {
+                continue;
+            };
+            if meta.is_dir() {
+                pending.push(entry.path());
+            } else {
+                total += meta.len();
+            }
+        }
+    }
+    Ok(total)
+}
+
+/// Recently computed sizes keyed by path and `exclude_git`.
+#[derive(Default)]
+pub struct DiskUsageCache {
+    entries: HashMap<(PathBuf, bool), (Instant, u64)>,
+}
+
+impl DiskUsageCache {
+    pub fn get(&self, path: &Path, exclude_git: bool) -> Option<u64> {
+        self.entries
+            .get(&(path.to_path_buf(), exclude_git))
+            .filter(|(computed_at, _)| computed_at.elapsed() < DISK_USAGE_CACHE_TTL)
+            .map(|(_, bytes)| *bytes)
+    }
+
+    pub fn insert(&mut self, path: PathBuf, exclude_git: bool, bytes: u64) {
+        self.entries
+            .retain(|_, (computed_at, _)| computed_at.elapsed() < DISK_USAGE_CACHE_TTL);
+        self.entries
+            .insert((path, exclude_git), (Instant::now(), bytes));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dir_size, DiskUsageCache};
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn dir_size_sums_nested_files() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("src/nested")).expect("create dirs");
+        std::fs::create_dir_all(root.join(".git/objects")).expect("create git dir");
+        std::fs::write(root.join("README.md"), vec![b'a'; 100]).expect("write");
+        std::fs::write(root.join("src/lib.rs"), vec![b'b'; 250]).expect("write");
+        std::fs::write(root.join("src/nested/data.bin"), vec![0u8; 4096]).expect("write");
+        std::fs::write(root.join(".git/objects/pack"), vec![0u8; 1000]).expect("write");
+
+        assert_eq!(
+            dir_size(root, false).await.unwrap(),
+            100 + 250 + 4096 + 1000
+        );
+        assert_eq!(dir_size(root, true).await.unwrap(), 100 + 250 + 4096);
+        assert!(dir_size(&root.join("missing"), false).await.is_err());
+    }
+
+    #[test]
+    fn cache_keys_on_exclude_git() {
+        let mut cache = DiskUsageCache::default();
+        cache.insert("/worktrees/a".into(), true, 42);
+        assert_eq!(cache.get(Path::new("/worktrees/a"), true), Some(42));
+        assert_eq!(cache.get(Path::new("/worktrees/a"), false), None);
+    }
+}
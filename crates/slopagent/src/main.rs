@@ -1,29 +1,57 @@
+mod disk_usage;
+mod rate_limit;
+mod recording;
 mod state;
+mod task_logs;
 
-use futures::{SinkExt, StreamExt};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use futures::{Sink, SinkExt, StreamExt};
 use http::StatusCode;
 use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+use rate_limit::TokenBucket;
+use recording::CastWriter;
 use slopcoder_core::{
     agent_rpc::{
-        AgentCreateTaskRequest, AgentEnvelope, AgentRequest, AgentResponse, TaskOutputPageRequest,
+        AgentCreateTaskRequest, AgentEnvelope, AgentRequest, AgentResponse, AgentVersion,
+        ArchivedTask, Attachment, BatchError, BundleDiff, CommitInfo, DiffSection, EnvironmentInfo,
+        RequestId, SkippedTask, TaskBundle, TaskDiskUsage, TaskOutputPageRequest,
+        MERGE_CHECK_TIMEOUT_SECS, PROTOCOL_VERSION,
+    },
+    anyagent::{
+        redact_env, resume_anyagent, spawn_anyagent, AgentError, AgentKind, AnyAgentConfig,
+        AttachedFile,
     },
-    anyagent::{resume_anyagent, spawn_anyagent, AgentKind},
     branch_picker::{
         fallback_topic_name, normalize_task_name, pick_task_topic, topic_to_branch_slug,
     },
-    task::{Task, TaskId, TaskWorkspaceKind},
+    environment::EnvironmentError,
+    events::{CompletedItem, UsageStats},
+    export::{render_task, ExportFormat},
+    file_edit,
+    jsonl::RawOutputLog,
+    redact::SecretRedactor,
+    task::{PromptRun, Task, TaskId, TaskOutcome, TaskStatus, TaskWorkspaceKind},
     AgentEvent,
 };
-use state::{AppState, CreateEnvironmentError, StateError};
-use std::collections::HashMap;
-use std::io::{self, Write};
-use std::path::{Path, PathBuf};
+use state::{
+    environment_dir_name, AppState, CreateEnvironmentError, OutboundQueue, StateError,
+    DEFAULT_MAX_DIFF_BYTES, DEFAULT_RUN_HEARTBEAT_SECS, DEFAULT_SPAWN_RETRIES,
+    DEFAULT_SPAWN_RETRY_BACKOFF_MS,
+};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::fs::{copy, create_dir_all, remove_file, rename, File, OpenOptions};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use task_logs::{TaskLogs, TASK_LOG_LINES};
+use tokio::fs::{copy, create_dir_all, remove_dir_all, remove_file, rename, File, OpenOptions};
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader,
+};
 use tokio::process::Command;
-use tokio::sync::{mpsc, Mutex};
-use tokio::time::{sleep, Duration};
+use tokio::sync::{mpsc, Mutex, Semaphore};
+use tokio::time::{sleep, timeout, Duration};
 use tokio_tungstenite::{
     connect_async,
     tungstenite::{self, client::IntoClientRequest, Message},
@@ -46,15 +74,66 @@ impl RpcError {
     }
 }
 
+const DEFAULT_TERMINAL_ROWS: u16 = 24;
+const DEFAULT_TERMINAL_COLS: u16 = 80;
+/// Default seconds without any coordinator message before reconnecting.
+const DEFAULT_HEARTBEAT_TIMEOUT_SECS: u64 = 60;
+/// First wait before reconnecting to the coordinator; doubles per failed attempt.
+const RECONNECT_BASE_DELAY: Duration = Duration::from_secs(2);
+/// Default cap on the reconnect wait (`--reconnect-max-delay-secs`).
+const DEFAULT_RECONNECT_MAX_DELAY_SECS: u64 = 60;
+/// A connection that lasted this long resets the reconnect wait to the base delay.
+const RECONNECT_STABLE_AFTER: Duration = Duration::from_secs(30);
+/// How long runs may go on after the coordinator announces its shutdown before they are
+/// interrupted.
+const SHUTDOWN_RUN_GRACE: Duration = Duration::from_secs(30);
+/// Longest a `merge_check_command` may run before the merge is blocked.
+const MERGE_CHECK_TIMEOUT: Duration = Duration::from_secs(MERGE_CHECK_TIMEOUT_SECS);
+/// Longest a `post_run_command` may run after an agent turn.
+const POST_RUN_COMMAND_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Trailing lines of failed merge-check output kept in the readiness reason.
+const MERGE_CHECK_OUTPUT_LINES: usize = 40;
+/// How often archived conversations are checked against `--archive-retention-days`.
+const ARCHIVE_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Largest diff text carried by one `DiffChunk` envelope.
+const DIFF_CHUNK_BYTES: usize = 64 * 1024;
+/// Longest a diff stream waits for the outbound queue to take another chunk before failing.
+const DIFF_SLOT_WAIT: Duration = Duration::from_secs(60);
+/// Most attachments accepted with one prompt.
+const MAX_ATTACHMENTS: usize = 8;
+/// Largest decoded attachment accepted with a prompt.
+const MAX_ATTACHMENT_BYTES: usize = 10 * 1024 * 1024;
+/// Largest terminal recording returned whole; playback of bigger ones is refused.
+const MAX_RECORDING_BYTES: u64 = 16 * 1024 * 1024;
+/// Attachment types the supported agents can read.
+const ATTACHMENT_MIME_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "application/pdf",
+    "text/plain",
+    "text/markdown",
+];
+/// Directory in the task workspace that prompt attachments are written to.
+const ATTACHMENTS_DIR: &str = ".slopcoder-attachments";
+/// Prepended to the prompt that resumes a session whose last turn was cut off by a restart.
+const CRASHED_TURN_NOTICE: &str =
+    "Note: your previous turn was interrupted when the host restarted and was abandoned; \
+check the workspace for partial changes before continuing.\n\n";
+
 enum PtyCommand {
     Input(Vec<u8>),
     Resize { rows: u16, cols: u16 },
     Shutdown,
 }
 
+/// Open terminal sessions by terminal id, with the task whose workspace each shell runs in.
+type TerminalSessions = HashMap<Uuid, (TaskId, std::sync::mpsc::Sender<PtyCommand>)>;
+
 #[derive(Clone)]
 struct TerminalManager {
-    sessions: Arc<Mutex<HashMap<Uuid, std::sync::mpsc::Sender<PtyCommand>>>>,
+    sessions: Arc<Mutex<TerminalSessions>>,
     out_tx: mpsc::UnboundedSender<AgentEnvelope>,
 }
 
@@ -66,7 +145,7 @@ impl TerminalManager {
         }
     }
 
-    async fn open(&self, state: AppState, terminal_id: Uuid, task_id: TaskId) {
+    async fn open(&self, state: AppState, terminal_id: Uuid, task_id: TaskId, size: PtySize) {
         self.close(terminal_id).await;
         let Some(task) = state.get_task(task_id).await else {
             let _ = self.out_tx.send(AgentEnvelope::TerminalError {
@@ -91,13 +170,15 @@ impl TerminalManager {
             return;
         }
 
+        let idle_timeout = state.terminal_idle_timeout().await;
+        let recorder = if state.record_terminals().await {
+            open_terminal_recording(&state, &task, terminal_id, size.cols, size.rows).await
+        } else {
+            None
+        };
+
         let pty_system = native_pty_system();
-        let pty_pair = match pty_system.openpty(PtySize {
-            rows: 30,
-            cols: 120,
-            pixel_width: 0,
-            pixel_height: 0,
-        }) {
+        let pty_pair = match pty_system.openpty(size) {
             Ok(pair) => pair,
             Err(e) => {
                 let _ = self.out_tx.send(AgentEnvelope::TerminalError {
@@ -111,8 +192,11 @@ impl TerminalManager {
             }
         };
 
-        let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/bash".to_string());
-        let mut cmd = CommandBuilder::new(shell);
+        let settings = match state.find_environment(&task.environment).await {
+            Some(env) => state.environment_settings(&env).await,
+            None => Default::default(),
+        };
+        let mut cmd = CommandBuilder::new(settings.resolve_terminal_shell());
         cmd.cwd(cwd);
 
         let mut child = match pty_pair.slave.spawn_command(cmd) {
@@ -160,22 +244,38 @@ impl TerminalManager {
                 return;
             }
         };
+        // Typed into the shell so its effects (cd, activated venvs) persist for the user.
+        if let Some(init) = settings
+            .terminal_init_command
+            .filter(|command| !command.trim().is_empty())
+        {
+            let line = format!("{}\n", init.trim_end());
+            if let Err(e) = std::io::Write::write_all(&mut pty_writer, line.as_bytes()) {
+                tracing::warn!(%terminal_id, error = %e, "Failed to run terminal init command");
+            }
+        }
         let pty_master = pty_pair.master;
 
         let (pty_command_tx, pty_command_rx) = std::sync::mpsc::channel::<PtyCommand>();
         self.sessions
             .lock()
             .await
-            .insert(terminal_id, pty_command_tx.clone());
+            .insert(terminal_id, (task_id, pty_command_tx.clone()));
 
         let out_tx_for_reader = self.out_tx.clone();
         let sessions_for_reader = self.sessions.clone();
+        let recorder_for_reader = recorder.clone();
         std::thread::spawn(move || {
             let mut buffer = [0_u8; 8192];
             loop {
                 match std::io::Read::read(&mut pty_reader, &mut buffer) {
                     Ok(0) => break,
                     Ok(n) => {
+                        if let Some(recorder) = recorder_for_reader.as_ref() {
+                            if let Ok(mut recorder) = recorder.lock() {
+                                let _ = recorder.write_output(&buffer[..n]);
+                            }
+                        }
                         if out_tx_for_reader
                             .send(AgentEnvelope::TerminalData {
                                 terminal_id,
@@ -195,10 +295,39 @@ impl TerminalManager {
         });
 
         let sessions_for_writer = self.sessions.clone();
+        let out_tx_for_writer = self.out_tx.clone();
         std::thread::spawn(move || {
-            for cmd in pty_command_rx {
+            let mut last_input = std::time::Instant::now();
+            loop {
+                let cmd = match idle_timeout {
+                    Some(limit) => {
+                        match pty_command_rx
+                            .recv_timeout(limit.saturating_sub(last_input.elapsed()))
+                        {
+                            Ok(cmd) => cmd,
+                            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                                tracing::info!(%terminal_id, "Closing idle terminal");
+                                let notice = format!(
+                                    "\r\n[terminal closed after {}s without input]\r\n",
+                                    limit.as_secs()
+                                );
+                                let _ = out_tx_for_writer.send(AgentEnvelope::TerminalData {
+                                    terminal_id,
+                                    data: notice.into_bytes(),
+                                });
+                                break;
+                            }
+                            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+                        }
+                    }
+                    None => match pty_command_rx.recv() {
+                        Ok(cmd) => cmd,
+                        Err(_) => break,
+                    },
+                };
                 match cmd {
                     PtyCommand::Input(data) => {
+                        last_input = std::time::Instant::now();
                         if std::io::Write::write_all(&mut pty_writer, &data).is_err() {
                             break;
                         }
@@ -207,6 +336,11 @@ impl TerminalManager {
                         }
                     }
                     PtyCommand::Resize { rows, cols } => {
+                        if let Some(recorder) = recorder.as_ref() {
+                            if let Ok(mut recorder) = recorder.lock() {
+                                let _ = recorder.write_resize(cols, rows);
+                            }
+                        }
                         let _ = pty_master.resize(PtySize {
                             rows,
                             cols,
@@ -224,7 +358,7 @@ impl TerminalManager {
     }
 
     async fn input(&self, terminal_id: Uuid, data: Vec<u8>) {
-        let tx = { self.sessions.lock().await.get(&terminal_id).cloned() };
+        let tx = { self.session_sender(terminal_id).await };
         if let Some(tx) = tx {
             if tx.send(PtyCommand::Input(data)).is_err() {
                 self.sessions.lock().await.remove(&terminal_id);
@@ -233,7 +367,7 @@ impl TerminalManager {
     }
 
     async fn resize(&self, terminal_id: Uuid, rows: u16, cols: u16) {
-        let tx = { self.sessions.lock().await.get(&terminal_id).cloned() };
+        let tx = { self.session_sender(terminal_id).await };
         if let Some(tx) = tx {
             if tx.send(PtyCommand::Resize { rows, cols }).is_err() {
                 self.sessions.lock().await.remove(&terminal_id);
@@ -241,27 +375,114 @@ impl TerminalManager {
         }
     }
 
+    async fn session_sender(
+        &self,
+        terminal_id: Uuid,
+    ) -> Option<std::sync::mpsc::Sender<PtyCommand>> {
+        self.sessions
+            .lock()
+            .await
+            .get(&terminal_id)
+            .map(|(_, tx)| tx.clone())
+    }
+
     async fn close(&self, terminal_id: Uuid) {
-        if let Some(tx) = self.sessions.lock().await.remove(&terminal_id) {
+        if let Some((_, tx)) = self.sessions.lock().await.remove(&terminal_id) {
             let _ = tx.send(PtyCommand::Shutdown);
         }
     }
+
+    /// Close every terminal open in `task_id`'s workspace.
+    async fn close_task(&self, task_id: TaskId) {
+        let mut sessions = self.sessions.lock().await;
+        let ids: Vec<Uuid> = sessions
+            .iter()
+            .filter(|(_, (task, _))| *task == task_id)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in ids {
+            if let Some((_, tx)) = sessions.remove(&id) {
+                let _ = tx.send(PtyCommand::Shutdown);
+            }
+        }
+    }
+}
+
+/// Start an asciinema recording for a terminal session. Failures only disable recording.
+async fn open_terminal_recording(
+    state: &AppState,
+    task: &Task,
+    terminal_id: Uuid,
+    cols: u16,
+    rows: u16,
+) -> Option<Arc<std::sync::Mutex<CastWriter>>> {
+    let env_state_dir = state.get_environment_directory(&task.environment).await?;
+    let task_dir = recording::task_recordings_dir(&env_state_dir, task.id);
+    if let Err(e) = create_dir_all(&task_dir).await {
+        tracing::warn!(
+            "Failed to create terminal recording dir {}: {}",
+            task_dir.display(),
+            e
+        );
+        return None;
+    }
+    let path = recording::recording_path(&task_dir, terminal_id);
+    match CastWriter::create(&path, cols, rows) {
+        Ok(writer) => Some(Arc::new(std::sync::Mutex::new(writer))),
+        Err(e) => {
+            tracing::warn!(
+                "Failed to start terminal recording {}: {}",
+                path.display(),
+                e
+            );
+            None
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::registry()
-        .with(fmt::layer())
-        .with(EnvFilter::from_default_env().add_directive("slopagent=info".parse().unwrap()))
-        .init();
+    let argv: Vec<String> = std::env::args().skip(1).collect();
+    let filter = EnvFilter::from_default_env().add_directive("slopagent=info".parse().unwrap());
+    let task_logs = TaskLogs::default();
+    if wants_json_logs(&argv) {
+        tracing_subscriber::registry()
+            .with(fmt::layer().json())
+            .with(task_logs.layer())
+            .with(filter)
+            .init();
+    } else {
+        tracing_subscriber::registry()
+            .with(fmt::layer())
+            .with(task_logs.layer())
+            .with(filter)
+            .init();
+    }
 
-    let mut args = std::env::args().skip(1);
+    let mut args = argv.into_iter();
     let mut server_url: Option<String> = None;
     let mut branch_model = "claude-haiku-4-5".to_string();
     let mut host_override: Option<String> = None;
     let mut repo_root: Option<PathBuf> = None;
     let mut discovery_max_depth: usize = 10;
     let mut discovery_max_repos: usize = 100;
+    let mut record_terminals = false;
+    let mut terminal_idle_timeout_secs: u64 = 0;
+    let mut debug_raw = false;
+    let mut claude_approvals = false;
+    let mut compress_output = false;
+    let mut heartbeat_timeout_secs: u64 = DEFAULT_HEARTBEAT_TIMEOUT_SECS;
+    let mut archive_retention_days: Option<u64> = None;
+    let mut max_diff_bytes = DEFAULT_MAX_DIFF_BYTES;
+    let mut max_output_bytes: Option<u64> = None;
+    let mut run_heartbeat_secs = DEFAULT_RUN_HEARTBEAT_SECS;
+    let mut run_timeout_secs: u64 = 0;
+    let mut spawn_retries = DEFAULT_SPAWN_RETRIES;
+    let mut spawn_retry_backoff_ms = DEFAULT_SPAWN_RETRY_BACKOFF_MS;
+    let mut reconnect_max_delay_secs = DEFAULT_RECONNECT_MAX_DELAY_SECS;
+    let mut start_rate_per_minute: Option<u32> = None;
+    let mut start_burst: u32 = 1;
+    let mut host_labels = HashMap::new();
 
     while let Some(arg) = args.next() {
         match arg.as_str() {
@@ -300,6 +521,165 @@ async fn main() {
                 std::process::exit(1);
             }
             "--name" | "--hostname" => host_override = args.next(),
+            "--label" => {
+                if let Some(value) = args.next() {
+                    match parse_host_label(&value) {
+                        Some((key, label)) => {
+                            host_labels.insert(key, label);
+                        }
+                        None => {
+                            tracing::error!(
+                                "Invalid --label value (expected KEY=VALUE): {}",
+                                value
+                            );
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+            "--record-terminals" => record_terminals = true,
+            "--terminal-idle-timeout-secs" => {
+                if let Some(value) = args.next() {
+                    match value.parse::<u64>() {
+                        Ok(parsed) => terminal_idle_timeout_secs = parsed,
+                        Err(_) => {
+                            tracing::error!(
+                                "Invalid --terminal-idle-timeout-secs value: {}",
+                                value
+                            );
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+            "--debug-raw" => debug_raw = true,
+            "--claude-approvals" => claude_approvals = true,
+            "--compress-output" => compress_output = true,
+            "--log-format" => {
+                // Already applied before logging was initialized.
+                let _ = args.next();
+            }
+            "--heartbeat-timeout-secs" => {
+                if let Some(value) = args.next() {
+                    match value.parse::<u64>() {
+                        Ok(parsed) => heartbeat_timeout_secs = parsed,
+                        Err(_) => {
+                            tracing::error!("Invalid --heartbeat-timeout-secs value: {}", value);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+            "--run-heartbeat-secs" => {
+                if let Some(value) = args.next() {
+                    match value.parse::<u64>() {
+                        Ok(parsed) => run_heartbeat_secs = parsed,
+                        Err(_) => {
+                            tracing::error!("Invalid --run-heartbeat-secs value: {}", value);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+            "--run-timeout-secs" => {
+                if let Some(value) = args.next() {
+                    match value.parse::<u64>() {
+                        Ok(parsed) => run_timeout_secs = parsed,
+                        Err(_) => {
+                            tracing::error!("Invalid --run-timeout-secs value: {}", value);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+            "--spawn-retries" => {
+                if let Some(value) = args.next() {
+                    match value.parse::<u32>() {
+                        Ok(parsed) => spawn_retries = parsed,
+                        Err(_) => {
+                            tracing::error!("Invalid --spawn-retries value: {}", value);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+            "--spawn-retry-backoff-ms" => {
+                if let Some(value) = args.next() {
+                    match value.parse::<u64>() {
+                        Ok(parsed) => spawn_retry_backoff_ms = parsed,
+                        Err(_) => {
+                            tracing::error!("Invalid --spawn-retry-backoff-ms value: {}", value);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+            "--reconnect-max-delay-secs" => {
+                if let Some(value) = args.next() {
+                    match value.parse::<u64>() {
+                        Ok(parsed) if parsed > 0 => reconnect_max_delay_secs = parsed,
+                        _ => {
+                            tracing::error!("Invalid --reconnect-max-delay-secs value: {}", value);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+            "--start-rate-per-minute" => {
+                if let Some(value) = args.next() {
+                    match value.parse::<u32>() {
+                        Ok(parsed) if parsed > 0 => start_rate_per_minute = Some(parsed),
+                        _ => {
+                            tracing::error!("Invalid --start-rate-per-minute value: {}", value);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+            "--start-burst" => {
+                if let Some(value) = args.next() {
+                    match value.parse::<u32>() {
+                        Ok(parsed) if parsed > 0 => start_burst = parsed,
+                        _ => {
+                            tracing::error!("Invalid --start-burst value: {}", value);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+            "--archive-retention-days" => {
+                if let Some(value) = args.next() {
+                    match value.parse::<u64>() {
+                        Ok(parsed) if parsed > 0 => archive_retention_days = Some(parsed),
+                        _ => {
+                            tracing::error!("Invalid --archive-retention-days value: {}", value);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+            "--max-diff-bytes" => {
+                if let Some(value) = args.next() {
+                    match value.parse::<usize>() {
+                        Ok(parsed) if parsed > 0 => max_diff_bytes = parsed,
+                        _ => {
+                            tracing::error!("Invalid --max-diff-bytes value: {}", value);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+            "--max-output-bytes" => {
+                if let Some(value) = args.next() {
+                    match value.parse::<u64>() {
+                        Ok(parsed) if parsed > 0 => max_output_bytes = Some(parsed),
+                        _ => {
+                            tracing::error!("Invalid --max-output-bytes value: {}", value);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
             "--no-password" => {
                 tracing::error!(
                     "--no-password is no longer supported; slopagent password is required"
@@ -312,9 +692,27 @@ async fn main() {
 Options:\n\
   REPO_ROOT                       Positional root scanned for repositories (required)\n\
   --name HOSTNAME                 Override host label shown in UI\n\
+  --label KEY=VALUE               Host attribute for task routing (repeatable)\n\
   --branch-model MODEL            Topic naming model (default: claude-haiku-4-5)\n\
   --discover-max-depth N          Max recursive discovery depth (default: 10)\n\
-  --discover-max-repos N          Max discovered repos total (default: 100)"
+  --discover-max-repos N          Max discovered repos total (default: 100)\n\
+  --record-terminals              Record terminal sessions as asciinema .cast files\n\
+  --terminal-idle-timeout-secs N  Close a terminal after N seconds without input (default: off)\n\
+  --debug-raw                     Also write raw agent stdout to task-<id>.raw.jsonl\n\
+  --claude-approvals              Ask for approval before each Claude tool call\n\
+  --compress-output               Gzip task-<id>.jsonl to task-<id>.jsonl.gz when a run finishes\n\
+  --heartbeat-timeout-secs N      Reconnect after N seconds without coordinator traffic (default: 60, 0 disables)\n\
+  --run-heartbeat-secs N          Send a heartbeat event after N quiet seconds in a run (default: 15, 0 disables)\n\
+  --run-timeout-secs N            Stop a run after N seconds and mark it timed out (default: off)\n\
+  --spawn-retries N               Retry an agent start that failed transiently N times (default: 2)\n\
+  --spawn-retry-backoff-ms N      Wait before the first start retry, doubling after (default: 1000)\n\
+  --reconnect-max-delay-secs N    Longest wait between coordinator reconnect attempts (default: 60)\n\
+  --start-rate-per-minute N       Limit agent starts and resumes to N per minute (default: unlimited)\n\
+  --start-burst N                 Starts allowed at once before the rate applies (default: 1)\n\
+  --archive-retention-days N      Delete archived conversations untouched for N days (default: keep forever)\n\
+  --max-diff-bytes N              Truncate each file in a task diff after N bytes (default: 1048576)\n\
+  --max-output-bytes N            Rotate task-<id>.jsonl to task-<id>.jsonl.1 past N bytes (default: unlimited)\n\
+  --log-format text|json          Log output format (default: text, or SLOPCODER_LOG_FORMAT)"
                 );
                 return;
             }
@@ -364,7 +762,13 @@ Options:\n\
     }
 
     let server_url = match server_url {
-        Some(url) => normalize_server_url(&url),
+        Some(url) => match normalize_server_url(&url) {
+            Ok(url) => url,
+            Err(e) => {
+                tracing::error!("Invalid --server {:?}: {}", url, e);
+                std::process::exit(1);
+            }
+        },
         None => {
             tracing::error!("Missing --server argument");
             std::process::exit(1);
@@ -394,36 +798,209 @@ Options:\n\
         }
     };
 
+    state.set_record_terminals(record_terminals).await;
+    state.set_task_logs(task_logs).await;
+    state
+        .set_terminal_idle_timeout(
+            (terminal_idle_timeout_secs > 0)
+                .then(|| Duration::from_secs(terminal_idle_timeout_secs)),
+        )
+        .await;
+    state.set_debug_raw(debug_raw).await;
+    state.set_claude_approvals(claude_approvals).await;
+    state.set_compress_output(compress_output).await;
+    state.set_max_diff_bytes(max_diff_bytes).await;
+    state.set_max_output_bytes(max_output_bytes).await;
+    state
+        .set_run_heartbeat(
+            (run_heartbeat_secs > 0).then(|| Duration::from_secs(run_heartbeat_secs)),
+        )
+        .await;
+    state
+        .set_spawn_retry(spawn_retries, Duration::from_millis(spawn_retry_backoff_ms))
+        .await;
+    state
+        .set_run_timeout((run_timeout_secs > 0).then(|| Duration::from_secs(run_timeout_secs)))
+        .await;
+    if let Some(rate) = start_rate_per_minute {
+        state
+            .set_start_limiter(Some(TokenBucket::new(f64::from(rate) / 60.0, start_burst)))
+            .await;
+    }
+    state.set_host_labels(host_labels).await;
+    if let Some(days) = archive_retention_days {
+        spawn_archive_pruner(state.clone(), days);
+    }
+
     let hostname = default_hostname();
     if let Some(display_name) = host_override.as_deref() {
         tracing::info!(
-            "slopagent hostname: {} (display override: {})",
-            hostname,
-            display_name
+            host = %display_name,
+            hostname = %hostname,
+            "slopagent hostname (display override)"
         );
     } else {
-        tracing::info!("slopagent hostname: {}", hostname);
+        tracing::info!(hostname = %hostname, "slopagent hostname");
     }
 
+    let agent_versions = probe_agent_versions(&state.get_agent_config().await).await;
+    tracing::info!(
+        "Installed agent CLIs: {}",
+        agent_versions
+            .iter()
+            .map(|version| version.agent.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let idle_timeout =
+        (heartbeat_timeout_secs > 0).then(|| Duration::from_secs(heartbeat_timeout_secs));
+
+    let mut backoff = ReconnectBackoff::new(
+        RECONNECT_BASE_DELAY,
+        Duration::from_secs(reconnect_max_delay_secs),
+    );
     loop {
-        match run_connection(
+        let started = std::time::Instant::now();
+        let result = run_connection(
             state.clone(),
             &server_url,
             password.clone(),
             hostname.clone(),
             host_override.clone(),
+            agent_versions.clone(),
+            idle_timeout,
         )
-        .await
-        {
+        .await;
+        if started.elapsed() >= RECONNECT_STABLE_AFTER {
+            backoff.reset();
+        }
+        let delay = backoff.next_delay(random_jitter());
+        match result {
             Ok(()) => {
-                tracing::warn!("Disconnected from coordinator; retrying in 2s");
+                tracing::warn!(
+                    "Disconnected from coordinator; retrying in {:.1}s",
+                    delay.as_secs_f64()
+                );
             }
             Err(e) => {
-                tracing::warn!("Connection error: {}; retrying in 2s", e);
+                tracing::warn!(
+                    "Connection error: {}; retrying in {:.1}s",
+                    e,
+                    delay.as_secs_f64()
+                );
+            }
+        }
+
+        sleep(delay).await;
+    }
+}
+
+/// Exponential reconnect delays, so many agents don't hammer a recovering coordinator in step.
+struct ReconnectBackoff {
+    base: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+impl ReconnectBackoff {
+    fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max: max.max(base),
+            attempt: 0,
+        }
+    }
+
+    /// `base * 2^attempt` capped at `max`, of which the upper half is scaled by `jitter`
+    /// (in `[0, 1)`), so the delay lands between half and all of the capped value.
+    fn next_delay(&mut self, jitter: f64) -> Duration {
+        let capped = self
+            .base
+            .saturating_mul(2u32.saturating_pow(self.attempt))
+            .min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+        capped / 2 + (capped / 2).mul_f64(jitter.clamp(0.0, 1.0))
+    }
+
+    fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+/// A value in `[0, 1)` from the leading 48 random bits of a v4 UUID; plenty for jitter.
+fn random_jitter() -> f64 {
+    (Uuid::new_v4().as_u128() >> 80) as f64 / (1u64 << 48) as f64
+}
+
+/// Periodically delete archived conversations older than `retention_days`.
+fn spawn_archive_pruner(state: AppState, retention_days: u64) {
+    let retention = Duration::from_secs(retention_days * 24 * 60 * 60);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(ARCHIVE_PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Some(cutoff) = std::time::SystemTime::now().checked_sub(retention) else {
+                continue;
+            };
+            let archive_root = archive_root_dir(&state.get_worktrees_directory().await);
+            let removed = prune_archives(&archive_root, cutoff).await;
+            if removed > 0 {
+                tracing::info!(removed, retention_days, "Pruned archived conversations");
+            }
+        }
+    });
+}
+
+/// Delete archived transcripts last modified before `cutoff`, with their raw logs and
+/// metadata. Returns how many conversations were removed.
+async fn prune_archives(archive_root: &Path, cutoff: std::time::SystemTime) -> usize {
+    let Ok(mut environments) = tokio::fs::read_dir(archive_root).await else {
+        return 0;
+    };
+    let mut removed = 0;
+    while let Ok(Some(environment)) = environments.next_entry().await {
+        let archive_dir = environment.path();
+        let Ok(mut entries) = tokio::fs::read_dir(&archive_dir).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let Some(task_id) = entry.file_name().to_str().and_then(parse_archive_file_name) else {
+                continue;
+            };
+            let Ok(modified) = entry.metadata().await.and_then(|meta| meta.modified()) else {
+                continue;
+            };
+            if modified >= cutoff {
+                continue;
+            }
+            if let Err(e) = remove_file(entry.path()).await {
+                tracing::warn!(path = %entry.path().display(), error = %e, "Failed to prune archived conversation");
+                continue;
+            }
+            let transcript = task_output_path(&archive_dir, task_id);
+            for segment in rotated_output_segments(&transcript) {
+                let _ = remove_file(segment).await;
             }
+            for companion in [
+                compressed_output_path(&transcript),
+                transcript,
+                task_raw_output_path(&archive_dir, task_id),
+                task_archive_metadata_path(&archive_dir, task_id),
+            ] {
+                let _ = remove_file(companion).await;
+            }
+            removed += 1;
         }
+    }
+    removed
+}
 
-        sleep(Duration::from_secs(2)).await;
+/// `--log-format json` wins over `SLOPCODER_LOG_FORMAT`; anything else means text.
+fn wants_json_logs(args: &[String]) -> bool {
+    match args.iter().position(|arg| arg == "--log-format") {
+        Some(index) => args.get(index + 1).is_some_and(|format| format == "json"),
+        None => std::env::var("SLOPCODER_LOG_FORMAT").is_ok_and(|format| format == "json"),
     }
 }
 
@@ -442,6 +1019,66 @@ fn prompt_password() -> Option<String> {
     }
 }
 
+/// Run `--version` for each agent CLI; agents that do not answer are disabled on this host.
+async fn probe_agent_versions(config: &AnyAgentConfig) -> Vec<AgentVersion> {
+    let mut versions = Vec::new();
+    for kind in AgentKind::ALL {
+        let binary = config.binary_path(kind);
+        let probe = Command::new(binary)
+            .arg("--version")
+            .stdin(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        match timeout(Duration::from_secs(10), probe).await {
+            Ok(Ok(output)) if output.status.success() => {
+                // Some CLIs print their version on stderr.
+                let version = parse_version_output(&String::from_utf8_lossy(&output.stdout))
+                    .or_else(|| parse_version_output(&String::from_utf8_lossy(&output.stderr)))
+                    .unwrap_or_else(|| "unknown".to_string());
+                tracing::info!(agent = kind.as_str(), version = %version, "Detected agent CLI");
+                versions.push(AgentVersion {
+                    agent: kind,
+                    version,
+                });
+            }
+            Ok(Ok(output)) => tracing::warn!(
+                agent = kind.as_str(),
+                binary,
+                status = %output.status,
+                "Agent CLI --version failed; disabling it on this host"
+            ),
+            Ok(Err(e)) => tracing::warn!(
+                agent = kind.as_str(),
+                binary,
+                error = %e,
+                "Agent CLI not available; disabling it on this host"
+            ),
+            Err(_) => tracing::warn!(
+                agent = kind.as_str(),
+                binary,
+                "Agent CLI --version timed out; disabling it on this host"
+            ),
+        }
+    }
+    versions
+}
+
+/// Pull a version out of `--version` output, e.g. `codex-cli 0.46.0` -> `0.46.0`.
+///
+/// Falls back to the first non-empty line when no dotted number is present.
+fn parse_version_output(output: &str) -> Option<String> {
+    let line = output
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())?;
+    let version = line
+        .split_whitespace()
+        .map(|token| token.trim_start_matches('v'))
+        .find(|token| token.starts_with(|c: char| c.is_ascii_digit()) && token.contains('.'))
+        .unwrap_or(line);
+    Some(version.to_string())
+}
+
 fn default_hostname() -> String {
     hostname::get()
         .ok()
@@ -450,12 +1087,50 @@ fn default_hostname() -> String {
         .unwrap_or_else(|| "unknown-host".to_string())
 }
 
-fn normalize_server_url(input: &str) -> String {
-    let trimmed = input.trim_end_matches('/');
-    if trimmed.ends_with("/agent/connect") {
-        return trimmed.to_string();
+/// Parse a `--label KEY=VALUE` pair; the key must be non-empty, the value may be.
+fn parse_host_label(input: &str) -> Option<(String, String)> {
+    let (key, value) = input.split_once('=')?;
+    let key = key.trim();
+    if key.is_empty() {
+        return None;
+    }
+    Some((key.to_string(), value.trim().to_string()))
+}
+
+/// Turn a `--server` value into the coordinator's agent websocket URL.
+///
+/// Accepts `http(s)://`, `ws(s)://` or a scheme-less `host[:port]` (treated as plain `ws`),
+/// maps HTTP schemes to their websocket counterparts, and appends `/agent/connect` unless
+/// the path already ends with it.
+fn normalize_server_url(input: &str) -> Result<String, String> {
+    let input = input.trim();
+    let (scheme, rest) = match input.split_once("://") {
+        Some((scheme, rest)) => match scheme.to_ascii_lowercase().as_str() {
+            "http" | "ws" => ("ws", rest),
+            "https" | "wss" => ("wss", rest),
+            other => return Err(format!("unsupported scheme `{}`", other)),
+        },
+        None => ("ws", input),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, ""),
+    };
+    if authority.is_empty() {
+        return Err("missing host".to_string());
+    }
+    let path = path.trim_end_matches('/');
+    let path = if path.ends_with("/agent/connect") {
+        path.to_string()
+    } else {
+        format!("{}/agent/connect", path)
+    };
+    let url = format!("{}://{}{}", scheme, authority, path);
+    match url.parse::<http::Uri>() {
+        Ok(uri) if uri.host().is_some_and(|host| !host.is_empty()) => Ok(url),
+        Ok(_) => Err("missing host".to_string()),
+        Err(e) => Err(e.to_string()),
     }
-    format!("{}/agent/connect", trimmed)
 }
 
 async fn run_connection(
@@ -464,6 +1139,8 @@ async fn run_connection(
     password: Option<String>,
     hostname: String,
     display_name: Option<String>,
+    agent_versions: Vec<AgentVersion>,
+    idle_timeout: Option<Duration>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let mut request = server_url.into_client_request()?;
     if let Some(password) = password {
@@ -482,31 +1159,54 @@ async fn run_connection(
             serde_json::to_string(&AgentEnvelope::Hello {
                 hostname,
                 display_name,
+                installed_agents: Some(agent_versions.iter().map(|v| v.agent).collect()),
+                agent_versions,
+                protocol_version: PROTOCOL_VERSION,
+                labels: state.host_labels().await,
+                running_tasks: state.running_task_ids().await,
             })?
             .into(),
         ))
         .await?;
 
-    let (out_tx, mut out_rx) = mpsc::unbounded_channel::<AgentEnvelope>();
+    let out_tx = state.outbound_sender().await;
+    let outbound = state.outbound_receiver().await;
     let terminal_manager = TerminalManager::new(out_tx.clone());
-    let writer = tokio::spawn(async move {
-        while let Some(envelope) = out_rx.recv().await {
-            let payload = match serde_json::to_string(&envelope) {
-                Ok(p) => p,
-                Err(e) => {
-                    tracing::warn!("Failed to serialize outgoing envelope: {}", e);
-                    continue;
-                }
-            };
-            if ws_sink.send(Message::Text(payload.into())).await.is_err() {
-                break;
-            }
+    if state.take_queue_resume().await {
+        for task_id in state.tasks_with_queued_prompts().await {
+            tokio::spawn(drain_prompt_queue(state.clone(), task_id, out_tx.clone()));
         }
-    });
+        // A dependency may have finished or been removed while the host was down.
+        for (task_id, _) in state.tasks_awaiting_dependency().await {
+            resolve_dependency(state.clone(), task_id, out_tx.clone()).await;
+        }
+    }
+    // Aborted however this function returns, releasing the queue for the next connection.
+    let _writer = AbortOnDrop(tokio::spawn(write_outbound(outbound, ws_sink)));
 
-    while let Some(message) = ws_stream.next().await {
+    loop {
+        // The coordinator pings periodically, so prolonged silence means a dead link.
+        let next = match idle_timeout {
+            Some(limit) => match timeout(limit, ws_stream.next()).await {
+                Ok(next) => next,
+                Err(_) => {
+                    return Err(format!(
+                        "no coordinator traffic for {}s; assuming connection is dead",
+                        limit.as_secs()
+                    )
+                    .into());
+                }
+            },
+            None => ws_stream.next().await,
+        };
+        let Some(message) = next else {
+            break;
+        };
         let message = message?;
-        if message.is_close() {
+        if let Message::Close(frame) = &message {
+            if let Some(frame) = frame {
+                tracing::warn!("Coordinator closed connection: {}", frame.reason);
+            }
             break;
         }
         if !message.is_text() {
@@ -527,32 +1227,31 @@ async fn run_connection(
                 request_id,
                 request,
             } => {
-                let state = state.clone();
-                let out_tx = out_tx.clone();
-                tokio::spawn(async move {
-                    let response = handle_request(state, request, out_tx.clone()).await;
-                    let outgoing = match response {
-                        Ok(response) => AgentEnvelope::Response {
-                            request_id,
-                            response,
-                        },
-                        Err(err) => AgentEnvelope::Error {
-                            request_id,
-                            status: err.status,
-                            error: err.error,
-                        },
-                    };
-                    let _ = out_tx.send(outgoing);
-                });
+                dispatch_request(
+                    state.clone(),
+                    &terminal_manager,
+                    request_id,
+                    request,
+                    out_tx.clone(),
+                )
+                .await;
             }
             AgentEnvelope::TerminalOpen {
                 terminal_id,
                 task_id,
+                rows,
+                cols,
             } => {
                 let manager = terminal_manager.clone();
                 let state = state.clone();
+                let size = PtySize {
+                    rows: rows.filter(|r| *r > 0).unwrap_or(DEFAULT_TERMINAL_ROWS),
+                    cols: cols.filter(|c| *c > 0).unwrap_or(DEFAULT_TERMINAL_COLS),
+                    pixel_width: 0,
+                    pixel_height: 0,
+                };
                 tokio::spawn(async move {
-                    manager.open(state, terminal_id, task_id).await;
+                    manager.open(state, terminal_id, task_id, size).await;
                 });
             }
             AgentEnvelope::TerminalInput { terminal_id, data } => {
@@ -577,27 +1276,138 @@ async fn run_connection(
                     manager.close(terminal_id).await;
                 });
             }
+            AgentEnvelope::Ping { nonce } => {
+                let _ = out_tx.send(AgentEnvelope::Pong { nonce });
+            }
+            AgentEnvelope::Shutdown => {
+                // Keep answering until the coordinator closes the socket so its in-flight
+                // requests resolve; runs get a grace period before they are interrupted.
+                tracing::info!("Coordinator is shutting down; winding down task runs");
+                tokio::spawn(wind_down_runs(state.clone(), SHUTDOWN_RUN_GRACE));
+            }
             _ => {
                 tracing::warn!("Ignoring unexpected envelope from coordinator");
             }
         }
     }
 
-    writer.abort();
     Ok(())
 }
 
-async fn handle_request(
+/// Give running tasks `grace` to finish after the coordinator announced its shutdown, then
+/// interrupt the rest. The run loop persists each as interrupted, so its session can be resumed
+/// once the coordinator is back. Returns how many runs were interrupted.
+async fn wind_down_runs(state: AppState, grace: Duration) -> usize {
+    let deadline = tokio::time::Instant::now() + grace;
+    while !state.running_task_ids().await.is_empty() && tokio::time::Instant::now() < deadline {
+        sleep(Duration::from_millis(200)).await;
+    }
+    let interrupted = state.send_interrupt_all().await;
+    if interrupted > 0 {
+        tracing::warn!(
+            interrupted,
+            "Interrupted runs still going after the coordinator shut down"
+        );
+    }
+    interrupted
+}
+
+/// Start answering one coordinator request; the response is sent on `out_tx` when it is ready.
+async fn dispatch_request(
     state: AppState,
+    terminal_manager: &TerminalManager,
+    request_id: RequestId,
     request: AgentRequest,
     out_tx: mpsc::UnboundedSender<AgentEnvelope>,
-) -> Result<AgentResponse, RpcError> {
+) {
+    if let AgentRequest::MoveTaskWorktree { task_id, .. } = &request {
+        // A shell left in the old directory would keep working in a path that is gone.
+        terminal_manager.close_task(*task_id).await;
+    }
+    tokio::spawn(async move {
+        let response = handle_request(state, request, out_tx.clone()).await;
+        let outgoing = match response {
+            Ok(response) => AgentEnvelope::Response {
+                request_id,
+                response,
+            },
+            Err(err) => {
+                tracing::debug!(
+                    request_id = %request_id,
+                    status = err.status,
+                    error = %err.error,
+                    "Request failed"
+                );
+                AgentEnvelope::Error {
+                    request_id,
+                    status: err.status,
+                    error: err.error,
+                }
+            }
+        };
+        let _ = out_tx.send(outgoing);
+    });
+}
+
+/// Aborts the wrapped task when dropped.
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Send queued envelopes to the coordinator until `sink` fails. The queue is locked while this
+/// runs and released when it ends or is aborted; an envelope leaves the queue only once sent, so
+/// one cut off mid-send goes out again (possibly twice) on the next connection.
+async fn write_outbound<S>(queue: Arc<Mutex<OutboundQueue>>, mut sink: S)
+where
+    S: Sink<Message> + Unpin,
+{
+    let mut queue = queue.lock_owned().await;
+    while let Some(envelope) = queue.peek().await {
+        let payload = match serde_json::to_string(envelope) {
+            Ok(p) => p,
+            Err(e) => {
+                tracing::warn!("Failed to serialize outgoing envelope: {}", e);
+                queue.mark_sent();
+                continue;
+            }
+        };
+        if sink.send(Message::Text(payload.into())).await.is_err() {
+            break;
+        }
+        queue.mark_sent();
+    }
+}
+
+async fn handle_request(
+    state: AppState,
+    request: AgentRequest,
+    out_tx: mpsc::UnboundedSender<AgentEnvelope>,
+) -> Result<AgentResponse, RpcError> {
     match request {
         AgentRequest::ListEnvironments => Ok(AgentResponse::Environments {
             environments: state.list_environments().await,
         }),
-        AgentRequest::CreateEnvironment { name } => create_environment(state, &name).await,
+        AgentRequest::ReloadEnvironments => {
+            let (added, removed) = state.reload_environments().await;
+            tracing::info!(
+                "Reloaded environments (added: {:?}, removed: {:?})",
+                added,
+                removed
+            );
+            Ok(AgentResponse::EnvironmentsReloaded { added, removed })
+        }
+        AgentRequest::CreateEnvironment { name, clone_url } => {
+            create_environment(state, &name, clone_url.as_deref()).await
+        }
         AgentRequest::ListBranches { environment } => list_branches(state, &environment).await,
+        AgentRequest::GetEnvironmentInfo { environment } => {
+            get_environment_info(state, &environment).await
+        }
+        AgentRequest::ListWorktrees { environment } => list_worktrees(state, &environment).await,
         AgentRequest::ListTasks => Ok(AgentResponse::Tasks {
             tasks: state.list_tasks().await,
         }),
@@ -606,24 +1416,195 @@ async fn handle_request(
         }),
         AgentRequest::CreateTask { request } => create_task(state, request, out_tx).await,
         AgentRequest::RenameTask { task_id, name } => rename_task(state, task_id, &name).await,
-        AgentRequest::SendPrompt { task_id, prompt } => {
-            send_prompt(state, task_id, prompt, out_tx).await
+        AgentRequest::SetTaskTags { task_id, tags } => {
+            let task = state
+                .set_task_tags(task_id, tags)
+                .await
+                .map_err(map_state_error)?;
+            Ok(AgentResponse::Task { task: Some(task) })
+        }
+        AgentRequest::SendPrompt {
+            task_id,
+            prompt,
+            attachments,
+            queue,
+        } => send_prompt(state, task_id, prompt, attachments, queue, out_tx).await,
+        AgentRequest::CancelQueuedPrompt { task_id, index } => {
+            match state
+                .cancel_queued_prompt(task_id, index)
+                .await
+                .map_err(map_state_error)?
+            {
+                Some(queued) => Ok(AgentResponse::QueuedPrompts { queued }),
+                None => Err(RpcError::new(
+                    StatusCode::NOT_FOUND,
+                    "Queued prompt not found",
+                )),
+            }
+        }
+        AgentRequest::CancelAllQueued { task_id } => {
+            state
+                .clear_prompt_queue(task_id)
+                .await
+                .map_err(map_state_error)?;
+            Ok(AgentResponse::QueuedPrompts { queued: 0 })
         }
         AgentRequest::GetTaskOutput {
             task_id,
             pagination,
         } => get_task_output(state, task_id, pagination).await,
-        AgentRequest::GetTaskDiff { task_id } => get_task_diff(state, task_id).await,
+        AgentRequest::GetTaskSummary { task_id } => get_task_summary(state, task_id).await,
+        AgentRequest::ExportTaskBundle { task_id } => export_task_bundle(state, task_id).await,
+        AgentRequest::GetTaskLogs { task_id, limit } => get_task_logs(state, task_id, limit).await,
+        AgentRequest::ExportTask { task_id, format } => export_task(state, task_id, format).await,
+        AgentRequest::GetTaskDiff {
+            task_id,
+            max_diff_bytes,
+        } => get_task_diff(state, task_id, max_diff_bytes).await,
+        AgentRequest::StreamTaskDiff { task_id, stream_id } => {
+            stream_task_diff(state, task_id, stream_id, out_tx).await
+        }
         AgentRequest::InterruptTask { task_id } => interrupt_task(state, task_id).await,
-        AgentRequest::MergeTask { task_id } => merge_task(state, task_id).await,
+        AgentRequest::InterruptAll => Ok(AgentResponse::InterruptedAll {
+            interrupted: state.send_interrupt_all().await,
+        }),
+        AgentRequest::RespondApproval {
+            task_id,
+            call_id,
+            approve,
+        } => respond_approval(state, task_id, call_id, approve).await,
+        AgentRequest::MergeTask {
+            task_id,
+            target,
+            skip_checks,
+        } => merge_task(state, task_id, target, skip_checks).await,
         AgentRequest::GetMergeReadiness { task_id } => get_merge_readiness(state, task_id).await,
+        AgentRequest::CherryPickTask {
+            task_id,
+            target,
+            commits,
+        } => cherry_pick_task(state, task_id, &target, commits).await,
+        AgentRequest::RevertMerge { task_id } => revert_merge(state, task_id).await,
         AgentRequest::ArchiveTask { task_id } => archive_task(state, task_id).await,
-        AgentRequest::DeleteTask { task_id, force } => delete_task(state, task_id, force).await,
+        AgentRequest::DeleteTask {
+            task_id,
+            force,
+            keep_branch,
+        } => {
+            let response = delete_task(state.clone(), task_id, force, keep_branch).await;
+            if response.is_ok() {
+                release_dependents(&state, task_id, &out_tx).await;
+            }
+            response
+        }
+        AgentRequest::PruneWorktrees { environment, force } => {
+            prune_worktrees(state, &environment, force).await
+        }
+        AgentRequest::DeleteCompletedTasks { environment, force } => {
+            let response = delete_completed_tasks(state.clone(), &environment, force).await;
+            if let Ok(AgentResponse::BulkDeleteResult { deleted, .. }) = &response {
+                for task_id in deleted {
+                    release_dependents(&state, *task_id, &out_tx).await;
+                }
+            }
+            response
+        }
+        AgentRequest::ResetTaskWorktree {
+            task_id,
+            keep_untracked,
+        } => reset_task_worktree(state, task_id, keep_untracked).await,
+        AgentRequest::MoveTaskWorktree { task_id, new_path } => {
+            move_task_worktree(state, task_id, new_path).await
+        }
+        AgentRequest::ApplyPatch { task_id, diff } => apply_patch(state, task_id, diff).await,
+        AgentRequest::CommitTask {
+            task_id,
+            message,
+            add_all,
+        } => commit_task(state, task_id, &message, add_all).await,
+        AgentRequest::StashTaskChanges { task_id } => stash_task_changes(state, task_id).await,
+        AgentRequest::UnstashTaskChanges { task_id } => unstash_task_changes(state, task_id).await,
+        AgentRequest::GetTaskCommits { task_id, limit } => {
+            get_task_commits(state, task_id, limit).await
+        }
+        AgentRequest::ReadWorktreeFile { task_id, path } => {
+            read_worktree_file(state, task_id, &path).await
+        }
+        AgentRequest::GetTerminalRecording { task_id } => {
+            get_terminal_recording(state, task_id).await
+        }
+        AgentRequest::GetTaskDiskUsage {
+            task_id,
+            exclude_git,
+        } => get_task_disk_usage(state, task_id, exclude_git).await,
+        AgentRequest::GetEnvironmentDiskUsage {
+            environment,
+            exclude_git,
+        } => get_environment_disk_usage(state, &environment, exclude_git).await,
+        AgentRequest::ListArchives { environment } => list_archives(state, &environment).await,
+        AgentRequest::GetArchivedOutput {
+            environment,
+            task_id,
+        } => get_archived_output(state, &environment, task_id).await,
+        AgentRequest::RestoreArchivedTask {
+            environment,
+            task_id,
+        } => restore_archived_task(state, &environment, task_id).await,
+        AgentRequest::Batch { requests } => handle_batch(state, requests, out_tx).await,
+    }
+}
+
+/// Box a nested `handle_request` call from a plain function, so the recursion through
+/// [`handle_batch`] has a known size and its `Send` bound does not depend on the async
+/// function that contains it.
+fn boxed_request(
+    state: AppState,
+    request: AgentRequest,
+    out_tx: mpsc::UnboundedSender<AgentEnvelope>,
+) -> Pin<Box<dyn Future<Output = Result<AgentResponse, RpcError>> + Send>> {
+    Box::pin(handle_request(state, request, out_tx))
+}
+
+/// Run the requests of a batch concurrently, answering in request order.
+async fn handle_batch(
+    state: AppState,
+    requests: Vec<AgentRequest>,
+    out_tx: mpsc::UnboundedSender<AgentEnvelope>,
+) -> Result<AgentResponse, RpcError> {
+    if requests
+        .iter()
+        .any(|request| matches!(request, AgentRequest::Batch { .. }))
+    {
+        return Err(RpcError::new(
+            StatusCode::BAD_REQUEST,
+            "Batches cannot be nested",
+        ));
     }
+    let results = futures::future::join_all(
+        requests
+            .into_iter()
+            .map(|request| boxed_request(state.clone(), request, out_tx.clone())),
+    )
+    .await;
+    Ok(AgentResponse::Batch {
+        results: results
+            .into_iter()
+            .map(|result| {
+                result.map_err(|err| BatchError {
+                    status: err.status,
+                    error: err.error,
+                })
+            })
+            .collect(),
+    })
 }
 
-async fn create_environment(state: AppState, raw_name: &str) -> Result<AgentResponse, RpcError> {
-    match state.create_environment(raw_name).await {
+async fn create_environment(
+    state: AppState,
+    raw_name: &str,
+    clone_url: Option<&str>,
+) -> Result<AgentResponse, RpcError> {
+    match state.create_environment(raw_name, clone_url).await {
         Ok(environment) => Ok(AgentResponse::Environment { environment }),
         Err(CreateEnvironmentError::NameRequired | CreateEnvironmentError::InvalidName) => {
             Err(RpcError::new(
@@ -642,6 +1623,12 @@ async fn create_environment(state: AppState, raw_name: &str) -> Result<AgentResp
         Err(CreateEnvironmentError::GitInit(e)) => {
             Err(RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e))
         }
+        Err(e @ CreateEnvironmentError::InvalidCloneUrl(_)) => {
+            Err(RpcError::new(StatusCode::BAD_REQUEST, e.to_string()))
+        }
+        Err(e @ CreateEnvironmentError::CloneFailed(_)) => {
+            Err(RpcError::new(StatusCode::BAD_GATEWAY, e.to_string()))
+        }
     }
 }
 
@@ -661,6 +1648,50 @@ async fn list_branches(state: AppState, name: &str) -> Result<AgentResponse, Rpc
     }
 }
 
+async fn get_environment_info(state: AppState, name: &str) -> Result<AgentResponse, RpcError> {
+    let Some(env) = state.find_environment(name).await else {
+        return Err(RpcError::new(
+            StatusCode::NOT_FOUND,
+            format!("Environment '{}' not found", name),
+        ));
+    };
+    let git_error =
+        |e: EnvironmentError| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    let current_branch = env.current_branch().await.map_err(git_error)?;
+    let branch_count = env.list_branches().await.map_err(git_error)?.len();
+    let active_tasks = state
+        .list_tasks()
+        .await
+        .iter()
+        .filter(|task| task.environment == env.name && task.is_running())
+        .count();
+    Ok(AgentResponse::EnvironmentInfo {
+        info: EnvironmentInfo {
+            name: env.name,
+            directory: env.directory,
+            current_branch,
+            branch_count,
+            active_tasks,
+        },
+    })
+}
+
+async fn list_worktrees(state: AppState, name: &str) -> Result<AgentResponse, RpcError> {
+    let Some(env) = state.find_environment(name).await else {
+        return Err(RpcError::new(
+            StatusCode::NOT_FOUND,
+            format!("Environment '{}' not found", name),
+        ));
+    };
+    match env.list_worktrees().await {
+        Ok(worktrees) => Ok(AgentResponse::Worktrees { worktrees }),
+        Err(e) => Err(RpcError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            e.to_string(),
+        )),
+    }
+}
+
 async fn create_task(
     state: AppState,
     req: AgentCreateTaskRequest,
@@ -672,6 +1703,43 @@ async fn create_task(
             format!("Environment '{}' not found", req.environment),
         ));
     };
+    if let Some(dependency) = req.depends_on {
+        if !req.attachments.is_empty() {
+            return Err(RpcError::new(
+                StatusCode::BAD_REQUEST,
+                "Tasks with a dependency cannot carry attachments",
+            ));
+        }
+        match state.get_task(dependency).await {
+            None => {
+                return Err(RpcError::new(
+                    StatusCode::NOT_FOUND,
+                    "Dependency task not found",
+                ))
+            }
+            Some(task) if task.status == TaskStatus::Failed => {
+                return Err(RpcError::new(
+                    StatusCode::CONFLICT,
+                    "Dependency task has failed",
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+    let settings = state.environment_settings(&env).await;
+    let agent = settings.resolve_agent(req.agent);
+    let attachments = decode_attachments(agent, &req.attachments)?;
+    let model = settings.resolve_model(agent, req.model);
+    let use_worktree = settings.resolve_use_worktree(req.use_worktree);
+    let subdir = match req.subdir.as_deref().map(validate_subdir).transpose()? {
+        Some(subdir) => {
+            // Check against the environment checkout first so a bad path fails before a
+            // worktree is created.
+            resolve_working_subdir(&env.directory, &subdir)?;
+            Some(subdir)
+        }
+        None => None,
+    };
 
     let task_name = match req.name.as_deref().map(str::trim).filter(|s| !s.is_empty()) {
         Some(name) => normalize_task_name(name).unwrap_or_else(|| "task".to_string()),
@@ -683,34 +1751,61 @@ async fn create_task(
         }
     };
 
-    let (workspace_kind, base_branch, merge_branch, worktree_path) = if req.use_worktree {
-        let base_branch = env.current_branch().await.map_err(|e| {
-            RpcError::new(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to resolve environment branch: {}", e),
-            )
-        })?;
-        let slug = topic_to_branch_slug(&task_name);
-        let suffix: String = Uuid::new_v4().to_string().chars().take(8).collect();
-        let merge_branch = format!("task/{}-{}", slug, suffix);
+    let (workspace_kind, base_branch, merge_branch, reused_branch, worktree_path) = if use_worktree
+    {
+        let base_branch = match req.base_branch.as_deref().map(str::trim) {
+            Some(branch) if !branch.is_empty() => {
+                let exists = env
+                    .branch_exists(branch)
+                    .await
+                    .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+                if !exists {
+                    return Err(RpcError::new(
+                        StatusCode::NOT_FOUND,
+                        format!("Base branch '{}' not found", branch),
+                    ));
+                }
+                branch.to_string()
+            }
+            _ => env.current_branch().await.map_err(|e| {
+                RpcError::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to resolve environment branch: {}", e),
+                )
+            })?,
+        };
         let worktrees_directory = state.get_worktrees_directory().await;
-        let worktree_path = match env
-            .create_worktree_from_base(&worktrees_directory, &base_branch, &merge_branch)
-            .await
-        {
-            Ok(path) => path,
-            Err(e) => {
-                let status = match e {
-                    slopcoder_core::environment::EnvironmentError::BranchExists(_)
-                    | slopcoder_core::environment::EnvironmentError::WorktreeExists(_) => {
-                        StatusCode::CONFLICT
-                    }
-                    _ => StatusCode::INTERNAL_SERVER_ERROR,
+        let existing_branch = req
+            .existing_branch
+            .as_deref()
+            .map(str::trim)
+            .filter(|branch| !branch.is_empty());
+        let (merge_branch, reused_branch, worktree_path) = match existing_branch {
+            Some(branch) => {
+                let worktree_path = if req.dry_run {
+                    env.check_branch_checkout_available(&worktrees_directory, branch)
+                        .await
+                } else {
+                    env.create_worktree(&worktrees_directory, branch).await
                 };
-                return Err(RpcError::new(
-                    status,
-                    format!("Failed to create worktree: {}", e),
-                ));
+                (
+                    branch.to_string(),
+                    true,
+                    worktree_path.map_err(worktree_error)?,
+                )
+            }
+            None => {
+                let slug = topic_to_branch_slug(&task_name);
+                let suffix: String = Uuid::new_v4().to_string().chars().take(8).collect();
+                let merge_branch = format!("task/{}-{}", slug, suffix);
+                let worktree_path = if req.dry_run {
+                    env.check_worktree_available(&worktrees_directory, &merge_branch)
+                        .await
+                } else {
+                    env.create_worktree_from_base(&worktrees_directory, &base_branch, &merge_branch)
+                        .await
+                };
+                (merge_branch, false, worktree_path.map_err(worktree_error)?)
             }
         };
 
@@ -718,6 +1813,7 @@ async fn create_task(
             TaskWorkspaceKind::Worktree,
             Some(base_branch),
             Some(merge_branch),
+            reused_branch,
             worktree_path,
         )
     } else {
@@ -725,12 +1821,26 @@ async fn create_task(
             TaskWorkspaceKind::Environment,
             None,
             None,
+            false,
             env.directory.clone(),
         )
     };
+    // A dependent task starts later, once its dependency has completed.
+    if workspace_kind == TaskWorkspaceKind::Environment && req.depends_on.is_none() {
+        ensure_environment_idle(&state, &env.name).await?;
+    }
+
+    if req.dry_run {
+        // The subdirectory was already checked against the environment checkout; the
+        // planned worktree doesn't exist yet.
+        return Ok(AgentResponse::CreatedTask {
+            id: TaskId::new(),
+            worktree_path: worktree_path.to_string_lossy().to_string(),
+        });
+    }
 
-    let task = Task::new(
-        req.agent.unwrap_or_default(),
+    let mut task = Task::new(
+        agent,
         req.environment,
         task_name,
         workspace_kind,
@@ -739,18 +1849,63 @@ async fn create_task(
         req.web_search,
         worktree_path.clone(),
     );
+    task.reused_branch = reused_branch;
+    task.set_tags(req.tags);
+    task.priority = req.priority;
+    task.model = model;
+    if let Some(subdir) = subdir {
+        // The base revision may lack a directory the environment checkout has.
+        match resolve_working_subdir(&worktree_path, &subdir) {
+            Ok(working_dir) => task.working_dir = Some(working_dir),
+            Err(e) => {
+                discard_task_worktree(&task, &env.directory).await;
+                return Err(e);
+            }
+        }
+    }
     let task_id = task.id;
+    let attachments = match write_attachments(&worktree_path, attachments).await {
+        Ok(attachments) => attachments,
+        Err(e) => {
+            discard_task_worktree(&task, &env.directory).await;
+            return Err(e);
+        }
+    };
+    let prompt = req.prompt;
+    // A dependent task keeps its first prompt queued until the dependency completes.
+    task.depends_on = req.depends_on;
+    if task.depends_on.is_some() {
+        task.queued_prompts.push(prompt.clone());
+    }
 
-    state
-        .insert_task(task)
-        .await
-        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if let Err(e) = state.insert_task(task.clone()).await {
+        discard_task_worktree(&task, &env.directory).await;
+        return Err(RpcError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            e.to_string(),
+        ));
+    }
 
-    let prompt = req.prompt;
-    let state_clone = state.clone();
-    tokio::spawn(async move {
-        run_agent(state_clone, task_id, prompt, None, out_tx).await;
-    });
+    if req.depends_on.is_some() {
+        // The dependency may have finished while this task was being set up.
+        resolve_dependency(state, task_id, out_tx).await;
+    } else {
+        let state_clone = state.clone();
+        tokio::spawn(async move {
+            let attachment_dir = attachment_dir(&attachments);
+            run_agent(
+                state_clone.clone(),
+                task_id,
+                prompt,
+                attachments,
+                None,
+                out_tx.clone(),
+            )
+            .await;
+            remove_attachments(attachment_dir).await;
+            drain_prompt_queue(state_clone, task_id, out_tx).await;
+        });
+    }
 
     Ok(AgentResponse::CreatedTask {
         id: task_id,
@@ -762,12 +1917,29 @@ async fn send_prompt(
     state: AppState,
     task_id: TaskId,
     prompt: String,
+    attachments: Vec<Attachment>,
+    queue: bool,
     out_tx: mpsc::UnboundedSender<AgentEnvelope>,
 ) -> Result<AgentResponse, RpcError> {
     let Some(task) = state.get_task(task_id).await else {
         return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
     };
 
+    if queue && task.is_running() {
+        if !attachments.is_empty() {
+            return Err(RpcError::new(
+                StatusCode::BAD_REQUEST,
+                "Queued prompts cannot carry attachments",
+            ));
+        }
+        match state.enqueue_prompt(task_id, prompt.clone()).await {
+            Ok(queued) => return Ok(AgentResponse::QueuedPrompts { queued }),
+            // The run finished in the meantime; start this prompt directly.
+            Err(StateError::TaskNotReady) => {}
+            Err(e) => return Err(map_state_error(e)),
+        }
+    }
+
     if !task.can_run() {
         return Err(RpcError::new(
             StatusCode::CONFLICT,
@@ -781,713 +1953,6426 @@ async fn send_prompt(
             "Task workspace no longer exists (may have been removed from CLI)",
         ));
     }
+    if task.workspace_kind == TaskWorkspaceKind::Environment {
+        ensure_environment_idle(&state, &task.environment).await?;
+    }
+    let attachments = decode_attachments(task.agent, &attachments)?;
+    let attachments = write_attachments(&task.worktree_path, attachments).await?;
 
     let session_id = task.session_id;
     let state_clone = state.clone();
     tokio::spawn(async move {
-        run_agent(state_clone, task_id, prompt, session_id, out_tx).await;
+        let attachment_dir = attachment_dir(&attachments);
+        run_agent(
+            state_clone.clone(),
+            task_id,
+            prompt,
+            attachments,
+            session_id,
+            out_tx.clone(),
+        )
+        .await;
+        remove_attachments(attachment_dir).await;
+        drain_prompt_queue(state_clone, task_id, out_tx).await;
     });
 
     Ok(AgentResponse::Ack)
 }
 
-async fn rename_task(
+/// Refuse to start an in-place run while another in-place task is running in the same
+/// environment; `start_task_run` enforces the same rule once the run is spawned.
+async fn ensure_environment_idle(state: &AppState, environment: &str) -> Result<(), RpcError> {
+    match state.running_in_place_task(environment).await {
+        Some(_) => Err(map_state_error(StateError::EnvironmentBusy(
+            environment.to_string(),
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Run the task's queued prompts one after another, stopping once a run doesn't complete
+/// successfully so a failure or interrupt leaves the rest of the queue for the user.
+async fn drain_prompt_queue(
     state: AppState,
     task_id: TaskId,
-    name: &str,
-) -> Result<AgentResponse, RpcError> {
-    let task = state
-        .rename_task(task_id, name)
-        .await
-        .map_err(|err| match err {
-            StateError::TaskNotFound(_) => RpcError::new(StatusCode::NOT_FOUND, "Task not found"),
-            StateError::InvalidTaskName => {
-                RpcError::new(StatusCode::BAD_REQUEST, "Task name is required")
+    out_tx: mpsc::UnboundedSender<AgentEnvelope>,
+) {
+    loop {
+        let Some(task) = state.get_task(task_id).await else {
+            return;
+        };
+        if task.status != TaskStatus::Completed {
+            return;
+        }
+        let prompt = match state.pop_queued_prompt(task_id).await {
+            Ok(Some(prompt)) => prompt,
+            Ok(None) => return,
+            Err(e) => {
+                tracing::warn!(task_id = %task_id, error = %e, "Failed to take queued prompt");
+                return;
             }
-            other => RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, other.to_string()),
-        })?;
-
-    Ok(AgentResponse::RenamedTask { task })
+        };
+        run_agent(
+            state.clone(),
+            task_id,
+            prompt,
+            Vec::new(),
+            task.session_id,
+            out_tx.clone(),
+        )
+        .await;
+    }
 }
 
-async fn interrupt_task(state: AppState, task_id: TaskId) -> Result<AgentResponse, RpcError> {
-    if state.send_interrupt(task_id).await {
-        Ok(AgentResponse::Ack)
-    } else {
-        Err(RpcError::new(
-            StatusCode::CONFLICT,
-            "Task is not running or interrupt channel not found",
-        ))
+/// Resolve the tasks waiting on `dependency` after it finished a run or was deleted.
+async fn release_dependents(
+    state: &AppState,
+    dependency: TaskId,
+    out_tx: &mpsc::UnboundedSender<AgentEnvelope>,
+) {
+    for (task_id, depends_on) in state.tasks_awaiting_dependency().await {
+        if depends_on == dependency {
+            resolve_dependency(state.clone(), task_id, out_tx.clone()).await;
+        }
     }
 }
 
-async fn get_task_output(
+/// Release `dependency`'s dependents on a separate task. `run_agent` uses this instead of
+/// awaiting [`release_dependents`], since a released dependent runs `run_agent` itself
+/// and the awaited future would then contain its own type.
+fn spawn_release_dependents(
+    state: &AppState,
+    dependency: TaskId,
+    out_tx: &mpsc::UnboundedSender<AgentEnvelope>,
+) {
+    let state = state.clone();
+    let out_tx = out_tx.clone();
+    tokio::spawn(async move {
+        release_dependents(&state, dependency, &out_tx).await;
+    });
+}
+
+/// Start a task held by `depends_on` once its dependency has completed successfully, or
+/// fail it when the dependency failed or no longer exists. A dependency that is pending,
+/// running or interrupted keeps the task waiting.
+async fn resolve_dependency(
     state: AppState,
     task_id: TaskId,
-    pagination: TaskOutputPageRequest,
-) -> Result<AgentResponse, RpcError> {
+    out_tx: mpsc::UnboundedSender<AgentEnvelope>,
+) {
     let Some(task) = state.get_task(task_id).await else {
-        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
+        return;
     };
-
-    let Some(env_dir) = state.get_environment_directory(&task.environment).await else {
-        return Err(RpcError::new(
-            StatusCode::NOT_FOUND,
-            "Environment not found",
-        ));
+    let Some(dependency) = task.depends_on else {
+        return;
+    };
+    if task.status != TaskStatus::Pending || !task.history.is_empty() {
+        return;
+    }
+    let reason = match state.get_task(dependency).await.map(|task| task.status) {
+        Some(TaskStatus::Completed) => {
+            tokio::spawn(async move {
+                // Taking the prompt claims the start, so a concurrent resolve finds nothing.
+                let prompt = match state.pop_queued_prompt(task_id).await {
+                    Ok(Some(prompt)) => prompt,
+                    Ok(None) => return,
+                    Err(e) => {
+                        tracing::warn!(task_id = %task_id, error = %e, "Failed to take dependent prompt");
+                        return;
+                    }
+                };
+                tracing::info!(task_id = %task_id, dependency = %dependency, "Dependency completed; starting task");
+                run_agent(
+                    state.clone(),
+                    task_id,
+                    prompt,
+                    Vec::new(),
+                    None,
+                    out_tx.clone(),
+                )
+                .await;
+                drain_prompt_queue(state, task_id, out_tx).await;
+            });
+            return;
+        }
+        Some(TaskStatus::Failed) => format!("Dependency task {} failed", dependency),
+        None => format!("Dependency task {} no longer exists", dependency),
+        Some(_) => return,
     };
 
-    let output_path = task_output_path(&env_dir, task_id);
-    let page = read_output_events_page(&output_path, pagination.before, pagination.limit)
-        .await
-        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-
-    Ok(AgentResponse::TaskOutput {
-        events: page.events,
-        total_events: page.total_events,
-        has_more_before: page.has_more_before,
-    })
+    match state.fail_waiting_task(task_id).await {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            tracing::warn!(task_id = %task_id, error = %e, "Failed to fail dependent task");
+            return;
+        }
+    }
+    tracing::info!(task_id = %task_id, reason = %reason, "Dependent task failed");
+    let event = AgentEvent::Error { message: reason };
+    if let Some(env_dir) = state.get_environment_directory(&task.environment).await {
+        let mut output_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(task_output_path(&env_dir, task_id))
+            .await
+            .ok();
+        append_output_event(&mut output_file, task_id, &event).await;
+    }
+    let _ = out_tx.send(AgentEnvelope::TaskEvent { task_id, event });
 }
 
-async fn get_task_diff(state: AppState, task_id: TaskId) -> Result<AgentResponse, RpcError> {
-    let Some(task) = state.get_task(task_id).await else {
-        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
-    };
-
-    let diff = load_git_diff(&task.worktree_path, task.base_branch.as_deref())
-        .await
-        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-
-    Ok(AgentResponse::TaskDiff {
-        staged: diff.staged,
-        unstaged: diff.unstaged,
-    })
+/// A validated attachment, ready to be written to the workspace.
+#[derive(Debug)]
+struct DecodedAttachment {
+    filename: String,
+    mime: String,
+    bytes: Vec<u8>,
 }
 
-async fn merge_task(state: AppState, task_id: TaskId) -> Result<AgentResponse, RpcError> {
-    let Some(task) = state.get_task(task_id).await else {
-        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
-    };
+/// Check prompt attachments against the agent, count, type and size limits and decode
+/// them, before any workspace or run is started.
+fn decode_attachments(
+    agent: AgentKind,
+    attachments: &[Attachment],
+) -> Result<Vec<DecodedAttachment>, RpcError> {
+    use base64::Engine as _;
 
-    let readiness = evaluate_merge_readiness(&state, &task).await?;
-    if !readiness.can_merge {
-        return Err(RpcError::new(
-            StatusCode::CONFLICT,
-            readiness
-                .reason
-                .unwrap_or_else(|| "Task cannot be merged right now.".to_string()),
-        ));
+    if attachments.is_empty() {
+        return Ok(Vec::new());
     }
-
-    let Some(env) = state.find_environment(&task.environment).await else {
+    if !agent.supports_attachments() {
         return Err(RpcError::new(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Environment not found",
+            StatusCode::BAD_REQUEST,
+            format!("Agent '{}' does not support attachments", agent.as_str()),
         ));
-    };
-    let Some(merge_branch) = task.merge_branch.as_deref() else {
+    }
+    if attachments.len() > MAX_ATTACHMENTS {
         return Err(RpcError::new(
             StatusCode::BAD_REQUEST,
-            "Task has no merge branch; only worktree tasks are mergeable",
+            format!("At most {} attachments are allowed", MAX_ATTACHMENTS),
         ));
+    }
+
+    attachments
+        .iter()
+        .map(|attachment| {
+            let filename = attachment.filename.trim();
+            let plain_name = Path::new(filename)
+                .file_name()
+                .and_then(|name| name.to_str());
+            if plain_name != Some(filename) || filename.starts_with('.') {
+                return Err(RpcError::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("Invalid attachment filename '{}'", attachment.filename),
+                ));
+            }
+            let mime = attachment.mime.trim().to_ascii_lowercase();
+            if !ATTACHMENT_MIME_TYPES.contains(&mime.as_str()) {
+                return Err(RpcError::new(
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "Unsupported attachment type '{}' for '{}'",
+                        attachment.mime, filename
+                    ),
+                ));
+            }
+            // Base64 is 4/3 of the decoded size; reject obviously oversized input unread.
+            if attachment.base64.len() / 4 * 3 > MAX_ATTACHMENT_BYTES + 3 {
+                return Err(attachment_too_large(filename));
+            }
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(attachment.base64.trim())
+                .map_err(|e| {
+                    RpcError::new(
+                        StatusCode::BAD_REQUEST,
+                        format!("Attachment '{}' is not valid base64: {}", filename, e),
+                    )
+                })?;
+            if bytes.len() > MAX_ATTACHMENT_BYTES {
+                return Err(attachment_too_large(filename));
+            }
+            Ok(DecodedAttachment {
+                filename: filename.to_string(),
+                mime,
+                bytes,
+            })
+        })
+        .collect()
+}
+
+fn attachment_too_large(filename: &str) -> RpcError {
+    RpcError::new(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!(
+            "Attachment '{}' exceeds {} bytes",
+            filename, MAX_ATTACHMENT_BYTES
+        ),
+    )
+}
+
+/// Write attachments to a fresh directory under [`ATTACHMENTS_DIR`] in the workspace. The
+/// directory ignores itself, so attachments never show up in the task diff.
+async fn write_attachments(
+    workspace: &Path,
+    attachments: Vec<DecodedAttachment>,
+) -> Result<Vec<AttachedFile>, RpcError> {
+    if attachments.is_empty() {
+        return Ok(Vec::new());
+    }
+    let internal =
+        |e: std::io::Error| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+
+    let root = workspace.join(ATTACHMENTS_DIR);
+    create_dir_all(&root).await.map_err(internal)?;
+    tokio::fs::write(root.join(".gitignore"), "*\n")
+        .await
+        .map_err(internal)?;
+    let dir = root.join(Uuid::new_v4().simple().to_string());
+    create_dir_all(&dir).await.map_err(internal)?;
+
+    let mut files = Vec::with_capacity(attachments.len());
+    for attachment in attachments {
+        let path = dir.join(&attachment.filename);
+        tokio::fs::write(&path, &attachment.bytes)
+            .await
+            .map_err(internal)?;
+        files.push(AttachedFile {
+            path,
+            mime: attachment.mime,
+        });
+    }
+    Ok(files)
+}
+
+/// The directory [`write_attachments`] made for one prompt, if it wrote any files.
+fn attachment_dir(files: &[AttachedFile]) -> Option<PathBuf> {
+    files
+        .first()
+        .and_then(|file| file.path.parent())
+        .map(Path::to_path_buf)
+}
+
+/// Delete a prompt's attachments once its run is over; the agent has read them by then.
+async fn remove_attachments(dir: Option<PathBuf>) {
+    let Some(dir) = dir else {
+        return;
     };
+    if let Err(e) = remove_dir_all(&dir).await {
+        tracing::warn!(path = %dir.display(), error = %e, "Failed to remove prompt attachments");
+    }
+}
 
-    let merge_output = Command::new("git")
-        .args(["merge", merge_branch])
-        .current_dir(&env.directory)
-        .output()
+async fn rename_task(
+    state: AppState,
+    task_id: TaskId,
+    name: &str,
+) -> Result<AgentResponse, RpcError> {
+    let task = state
+        .rename_task(task_id, name)
         .await
-        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .map_err(|err| match err {
+            StateError::TaskNotFound(_) => RpcError::new(StatusCode::NOT_FOUND, "Task not found"),
+            StateError::InvalidTaskName => {
+                RpcError::new(StatusCode::BAD_REQUEST, "Task name is required")
+            }
+            other => RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, other.to_string()),
+        })?;
 
-    if merge_output.status.success() {
-        let base = task.base_branch.as_deref().unwrap_or("current");
-        Ok(AgentResponse::MergeResult {
-            status: "merged".to_string(),
-            message: format!("Successfully merged {} into {}", merge_branch, base),
-        })
-    } else {
-        let stdout = String::from_utf8_lossy(&merge_output.stdout);
-        let _ = Command::new("git")
-            .args(["merge", "--abort"])
-            .current_dir(&env.directory)
-            .output()
-            .await;
+    Ok(AgentResponse::RenamedTask { task })
+}
 
+async fn interrupt_task(state: AppState, task_id: TaskId) -> Result<AgentResponse, RpcError> {
+    if state.send_interrupt(task_id).await {
+        Ok(AgentResponse::Ack)
+    } else {
         Err(RpcError::new(
             StatusCode::CONFLICT,
-            format!("Merge failed (reverted): {}", stdout.trim()),
+            "Task is not running or interrupt channel not found",
         ))
     }
 }
 
-struct MergeReadinessResult {
-    can_merge: bool,
-    reason: Option<String>,
-}
-
-async fn get_merge_readiness(state: AppState, task_id: TaskId) -> Result<AgentResponse, RpcError> {
+async fn respond_approval(
+    state: AppState,
+    task_id: TaskId,
+    call_id: String,
+    approve: bool,
+) -> Result<AgentResponse, RpcError> {
     let Some(task) = state.get_task(task_id).await else {
         return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
     };
-
-    let readiness = evaluate_merge_readiness(&state, &task).await?;
-    Ok(AgentResponse::MergeReadiness {
-        can_merge: readiness.can_merge,
-        reason: readiness.reason,
-    })
-}
-
-async fn evaluate_merge_readiness(
-    state: &AppState,
-    task: &Task,
-) -> Result<MergeReadinessResult, RpcError> {
-    if task.workspace_kind != TaskWorkspaceKind::Worktree {
-        return Ok(MergeReadinessResult {
-            can_merge: false,
-            reason: Some("Only isolated worktree tasks can be merged.".to_string()),
-        });
+    if !task.is_running() {
+        return Err(RpcError::new(StatusCode::CONFLICT, "Task is not running"));
     }
-
-    if task.is_running() {
-        return Ok(MergeReadinessResult {
-            can_merge: false,
-            reason: Some("Task is still running.".to_string()),
+    if !state
+        .get_agent_config()
+        .await
+        .supports_approvals(task.agent)
+    {
+        return Ok(AgentResponse::ApprovalResult {
+            status: "unsupported".to_string(),
         });
     }
-
-    if has_unstaged_changes(&task.worktree_path).await {
-        return Ok(MergeReadinessResult {
-            can_merge: false,
-            reason: Some(
-                "Task worktree has uncommitted or untracked changes. Commit or stash first."
-                    .to_string(),
-            ),
-        });
+    if !state.send_approval(task_id, call_id, approve).await {
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            "Task run is not accepting approvals",
+        ));
     }
+    Ok(AgentResponse::ApprovalResult {
+        status: "delivered".to_string(),
+    })
+}
 
-    let Some(merge_branch) = task.merge_branch.as_deref() else {
-        return Ok(MergeReadinessResult {
-            can_merge: false,
-            reason: Some("Task has no merge branch.".to_string()),
-        });
+async fn get_task_output(
+    state: AppState,
+    task_id: TaskId,
+    pagination: TaskOutputPageRequest,
+) -> Result<AgentResponse, RpcError> {
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
     };
 
-    let Some(env) = state.find_environment(&task.environment).await else {
-        return Ok(MergeReadinessResult {
-            can_merge: false,
-            reason: Some("Environment not found.".to_string()),
-        });
+    let Some(env_dir) = state.get_environment_directory(&task.environment).await else {
+        return Err(RpcError::new(
+            StatusCode::NOT_FOUND,
+            "Environment not found",
+        ));
     };
 
-    if has_unstaged_changes(&env.directory).await {
-        return Ok(MergeReadinessResult {
-            can_merge: false,
-            reason: Some(
-                "Environment repository has uncommitted or untracked changes.".to_string(),
-            ),
-        });
-    }
-
-    let merge_tree = Command::new("git")
-        .args(["merge-tree", "--write-tree", "HEAD", merge_branch])
-        .current_dir(&env.directory)
-        .output()
+    let output_path = task_output_path(&env_dir, task_id);
+    let page = read_output_events_page(&output_path, pagination.before, pagination.limit)
         .await
         .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
-    if merge_tree.status.success() {
-        return Ok(MergeReadinessResult {
-            can_merge: true,
-            reason: None,
-        });
-    }
-
-    let stderr = String::from_utf8_lossy(&merge_tree.stderr)
-        .trim()
-        .to_string();
-    let stdout = String::from_utf8_lossy(&merge_tree.stdout)
-        .trim()
-        .to_string();
-    let detail = if !stderr.is_empty() {
-        stderr
-    } else if !stdout.is_empty() {
-        stdout
-    } else {
-        "Merge would conflict.".to_string()
-    };
-
-    Ok(MergeReadinessResult {
-        can_merge: false,
-        reason: Some(format!("Merge precheck failed: {}", detail)),
+    Ok(AgentResponse::TaskOutput {
+        events: page.events,
+        total_events: page.total_events,
+        has_more_before: page.has_more_before,
     })
 }
 
-async fn archive_task(state: AppState, task_id: TaskId) -> Result<AgentResponse, RpcError> {
+async fn get_task_summary(state: AppState, task_id: TaskId) -> Result<AgentResponse, RpcError> {
     let Some(task) = state.get_task(task_id).await else {
         return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
     };
 
-    if task.is_running() {
+    let Some(env_dir) = state.get_environment_directory(&task.environment).await else {
         return Err(RpcError::new(
-            StatusCode::CONFLICT,
-            "Stop the running task before archiving.",
+            StatusCode::NOT_FOUND,
+            "Environment not found",
         ));
-    }
+    };
 
-    let archived_path = archive_task_output(&state, &task).await?;
-    state
-        .remove_task(task_id)
+    let summary = read_output_summary(&task_output_path(&env_dir, task_id))
         .await
         .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
-    let message = match archived_path {
-        Some(path) => format!("Archived conversation to {}", path.display()),
-        None => "Task removed; no conversation file was found to archive.".to_string(),
-    };
-    Ok(AgentResponse::ArchiveResult {
-        status: "archived".to_string(),
-        message,
+    Ok(AgentResponse::TaskSummary {
+        last_message: summary.last_message,
+        usage: summary.usage,
+        outcome: task.history.last().and_then(|run| run.outcome),
     })
 }
 
-async fn delete_task(
-    state: AppState,
-    task_id: TaskId,
-    force: bool,
-) -> Result<AgentResponse, RpcError> {
+/// Collect a support bundle. Parts that fail (e.g. the diff of a removed worktree) are
+/// listed in `errors` instead of failing the whole request.
+async fn export_task_bundle(state: AppState, task_id: TaskId) -> Result<AgentResponse, RpcError> {
     let Some(task) = state.get_task(task_id).await else {
         return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
     };
+    let mut errors = Vec::new();
 
-    if task.workspace_kind != TaskWorkspaceKind::Worktree {
-        return Err(RpcError::new(
-            StatusCode::BAD_REQUEST,
-            "Delete is only supported for isolated worktree tasks",
-        ));
-    }
-    if task.is_running() {
-        return Err(RpcError::new(
-            StatusCode::CONFLICT,
-            "Stop the running task before deleting its worktree.",
-        ));
-    }
-
-    let env = state
-        .find_environment(&task.environment)
-        .await
-        .ok_or_else(|| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, "Environment not found"))?;
+    let events = match state.get_environment_directory(&task.environment).await {
+        Some(env_dir) => match read_all_output_events(&task_output_path(&env_dir, task_id)).await {
+            Ok(events) => events,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                errors.push(format!("events: {}", e));
+                Vec::new()
+            }
+        },
+        None => {
+            errors.push("events: Environment not found".to_string());
+            Vec::new()
+        }
+    };
 
-    prune_task_worktree(&task, &env.directory, force).await?;
+    let diff = match get_task_diff(state.clone(), task_id, None).await {
+        Ok(AgentResponse::TaskDiff {
+            staged,
+            unstaged,
+            truncated,
+        }) => Some(BundleDiff {
+            staged,
+            unstaged,
+            truncated,
+        }),
+        Ok(_) => None,
+        Err(e) => {
+            errors.push(format!("diff: {}", e.error));
+            None
+        }
+    };
 
-    if let Some(branch) = task.merge_branch.as_deref() {
-        let branch_args = if force {
-            vec!["branch", "-D", branch]
-        } else {
-            vec!["branch", "-d", branch]
-        };
-        let _ = Command::new("git")
-            .args(branch_args)
-            .current_dir(&env.directory)
-            .output()
-            .await;
+    let commits = match get_task_commits(state.clone(), task_id, Some(MAX_COMMIT_LIMIT)).await {
+        Ok(AgentResponse::TaskCommits { commits }) => commits,
+        Ok(_) => Vec::new(),
+        Err(e) => {
+            errors.push(format!("commits: {}", e.error));
+            Vec::new()
+        }
+    };
+
+    // The settings `run_agent` would launch the task's agent with.
+    let mut agent_config = state.get_agent_config().await;
+    if let Some(model) = task.model.clone() {
+        agent_config.set_model(task.agent, model);
+    }
+    if let Some(env) = state.find_environment(&task.environment).await {
+        agent_config.extend_env(&state.environment_settings(&env).await.env);
     }
 
-    let archived_path = archive_task_output(&state, &task).await?;
-    state
-        .remove_task(task_id)
+    Ok(AgentResponse::TaskBundle {
+        bundle: Box::new(TaskBundle {
+            agent_config: agent_config.redacted_summary(task.agent),
+            task,
+            events,
+            diff,
+            commits,
+            errors,
+        }),
+    })
+}
+
+async fn get_task_logs(
+    state: AppState,
+    task_id: TaskId,
+    limit: Option<usize>,
+) -> Result<AgentResponse, RpcError> {
+    if state.get_task(task_id).await.is_none() {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
+    }
+    let lines = state
+        .task_logs()
         .await
-        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .recent(&task_id.to_string(), limit.unwrap_or(TASK_LOG_LINES));
+    Ok(AgentResponse::TaskLogs { lines })
+}
 
-    let message = match archived_path {
-        Some(path) => format!(
-            "Deleted worktree and archived conversation to {}",
-            path.display()
-        ),
-        None => "Deleted worktree. No conversation file was found to archive.".to_string(),
+async fn export_task(
+    state: AppState,
+    task_id: TaskId,
+    format: ExportFormat,
+) -> Result<AgentResponse, RpcError> {
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
     };
 
-    Ok(AgentResponse::DeleteResult {
-        status: "deleted".to_string(),
-        message,
+    let Some(env_dir) = state.get_environment_directory(&task.environment).await else {
+        return Err(RpcError::new(
+            StatusCode::NOT_FOUND,
+            "Environment not found",
+        ));
+    };
+
+    let events = match read_all_output_events(&task_output_path(&env_dir, task_id)).await {
+        Ok(events) => events,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => {
+            return Err(RpcError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                e.to_string(),
+            ))
+        }
+    };
+
+    Ok(AgentResponse::TaskExport {
+        format,
+        content: render_task(format, &task, &events),
     })
 }
 
-async fn prune_task_worktree(task: &Task, repo_dir: &Path, force: bool) -> Result<(), RpcError> {
-    let mut args = vec!["worktree", "remove"];
-    if force {
-        args.push("-f");
+async fn get_task_diff(
+    state: AppState,
+    task_id: TaskId,
+    max_diff_bytes: Option<usize>,
+) -> Result<AgentResponse, RpcError> {
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
+    };
+    if max_diff_bytes == Some(0) {
+        return Err(RpcError::new(
+            StatusCode::BAD_REQUEST,
+            "max_diff_bytes must be greater than 0",
+        ));
     }
-    let worktree_path = task.worktree_path.to_string_lossy().to_string();
-    args.push(&worktree_path);
+    let max_diff_bytes = match max_diff_bytes {
+        Some(max) => max,
+        None => state.max_diff_bytes().await,
+    };
 
-    let output = Command::new("git")
-        .args(args)
-        .current_dir(repo_dir)
-        .output()
+    let diff = load_git_diff(&task.worktree_path, task.base_branch.as_deref())
         .await
         .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let (staged, staged_truncated) = truncate_file_diffs(&diff.staged, max_diff_bytes);
+    let (unstaged, unstaged_truncated) = truncate_file_diffs(&diff.unstaged, max_diff_bytes);
 
-    if output.status.success() {
-        return Ok(());
-    }
+    Ok(AgentResponse::TaskDiff {
+        staged,
+        unstaged,
+        truncated: staged_truncated || unstaged_truncated,
+    })
+}
 
-    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
-    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
-    let detail = if !stderr.is_empty() {
-        stderr
-    } else if !stdout.is_empty() {
-        stdout
-    } else {
-        "unknown git error".to_string()
+async fn stream_task_diff(
+    state: AppState,
+    task_id: TaskId,
+    stream_id: Uuid,
+    out_tx: mpsc::UnboundedSender<AgentEnvelope>,
+) -> Result<AgentResponse, RpcError> {
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
     };
-    let lower = detail.to_ascii_lowercase();
-    if !force
-        && (lower.contains("untracked") || lower.contains("modified") || lower.contains("--force"))
-    {
-        return Err(RpcError::new(
-            StatusCode::CONFLICT,
-            format!(
-                "Prune failed: {}. Use force prune to remove it anyway.",
-                detail
-            ),
-        ));
-    }
 
-    Err(RpcError::new(
-        StatusCode::INTERNAL_SERVER_ERROR,
-        format!("Failed to remove worktree: {}", detail),
-    ))
+    let slots = state.diff_chunk_slots().await;
+    tokio::spawn(async move {
+        let error = stream_git_diff(
+            &task.worktree_path,
+            task.base_branch.as_deref(),
+            stream_id,
+            &out_tx,
+            &slots,
+            DIFF_CHUNK_BYTES,
+        )
+        .await
+        .err()
+        .map(|e| e.to_string());
+        if let Some(error) = &error {
+            tracing::warn!(task_id = %task_id, error = %error, "Diff stream failed");
+        }
+        let _ = out_tx.send(AgentEnvelope::DiffEnd { stream_id, error });
+    });
+    Ok(AgentResponse::Ack)
 }
 
-async fn archive_task_output(state: &AppState, task: &Task) -> Result<Option<PathBuf>, RpcError> {
-    let Some(env_state_dir) = state.get_environment_directory(&task.environment).await else {
+async fn merge_task(
+    state: AppState,
+    task_id: TaskId,
+    target: Option<String>,
+    skip_checks: bool,
+) -> Result<AgentResponse, RpcError> {
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
+    };
+
+    let Some(env) = state.find_environment(&task.environment).await else {
         return Err(RpcError::new(
-            StatusCode::NOT_FOUND,
-            "Environment state directory not found",
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Environment not found",
         ));
     };
 
-    let source = task_output_path(&env_state_dir, task.id);
-    if !source.exists() {
-        return Ok(None);
-    }
-
-    let worktrees_root = state.get_worktrees_directory().await;
-    let archive_dir = worktrees_root
-        .join(".slopcoder-state")
-        .join("archive")
-        .join(sanitize_for_path(&task.environment));
-    create_dir_all(&archive_dir)
-        .await
-        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-
-    let destination = archive_dir.join(format!("task-{}.jsonl", task.id));
-    if destination.exists() {
-        remove_file(&destination)
+    // Without an explicit target, merge into the branch the task was started from rather than
+    // whatever the environment happens to have checked out.
+    let target = target
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .or(task.base_branch.as_deref());
+    if let Some(target) = target {
+        let exists = env
+            .branch_exists(target)
             .await
             .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        if !exists {
+            return Err(RpcError::new(
+                StatusCode::NOT_FOUND,
+                format!("Target branch '{}' not found", target),
+            ));
+        }
     }
 
-    match rename(&source, &destination).await {
-        Ok(_) => Ok(Some(destination)),
-        Err(_) => {
-            copy(&source, &destination)
-                .await
-                .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-            remove_file(&source)
-                .await
-                .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-            Ok(Some(destination))
-        }
+    let checks = if skip_checks {
+        MergeChecks::Skip
+    } else {
+        MergeChecks::Fresh
+    };
+    let readiness = evaluate_merge_readiness(&state, &task, target, checks).await?;
+    if !readiness.can_merge {
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            readiness
+                .reason
+                .unwrap_or_else(|| "Task cannot be merged right now.".to_string()),
+        ));
     }
-}
 
-async fn run_agent(
-    state: AppState,
-    task_id: TaskId,
-    prompt: String,
-    session_id: Option<Uuid>,
-    event_tx: mpsc::UnboundedSender<AgentEnvelope>,
-) {
-    let task = match state.get_task(task_id).await {
-        Some(t) => t,
-        None => {
-            tracing::error!("Task {} not found", task_id);
-            return;
-        }
+    let Some(merge_branch) = task.merge_branch.as_deref() else {
+        return Err(RpcError::new(
+            StatusCode::BAD_REQUEST,
+            "Task has no merge branch; only worktree tasks are mergeable",
+        ));
     };
 
-    let mut output_file = match state.get_environment_directory(&task.environment).await {
-        Some(env_dir) => {
-            let output_path = task_output_path(&env_dir, task_id);
-            match OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(&output_path)
+    // The readiness precheck runs unlocked; everything from here touches the shared checkout.
+    let _env_lock = state.lock_environment(&task.environment).await;
+    let original_branch = match target {
+        Some(target) => {
+            let current = env
+                .current_branch()
                 .await
-            {
-                Ok(file) => Some(file),
-                Err(e) => {
-                    tracing::warn!("Failed to open output log {}: {}", output_path.display(), e);
-                    None
+                .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            if current == target {
+                None
+            } else {
+                if has_unstaged_changes(&env.directory).await {
+                    return Err(RpcError::new(
+                        StatusCode::CONFLICT,
+                        format!(
+                            "Environment repository is on '{}' with uncommitted changes; cannot check out '{}' to merge into it.",
+                            current, target
+                        ),
+                    ));
                 }
+                checkout_branch(&env.directory, target)
+                    .await
+                    .map_err(|e| RpcError::new(StatusCode::CONFLICT, e))?;
+                Some(current)
             }
         }
         None => None,
     };
 
-    if let Err(e) = state.start_task_run(task_id, prompt.clone()).await {
-        tracing::error!("Failed to start task run for {}: {}", task_id, e);
-        return;
-    }
+    let merge_output = Command::new("git")
+        .args(["merge", merge_branch])
+        .current_dir(&env.directory)
+        .output()
+        .await;
 
-    let mut interrupt_rx = state.register_interrupt_channel(task_id).await;
-    let agent_config = state.get_agent_config().await;
-    if task.web_search && task.agent != AgentKind::Codex {
-        tracing::warn!(
-            "Task {} requested web search, but '{}' does not currently support it in slopcoder",
-            task_id,
-            format!("{:?}", task.agent).to_lowercase()
-        );
-    }
+    let result = match merge_output {
+        Ok(output) if output.status.success() => {
+            let into = target.unwrap_or("current");
+            Ok(AgentResponse::MergeResult {
+                status: "merged".to_string(),
+                message: format!("Successfully merged {} into {}", merge_branch, into),
+            })
+        }
+        Ok(output) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let _ = Command::new("git")
+                .args(["merge", "--abort"])
+                .current_dir(&env.directory)
+                .output()
+                .await;
 
-    let prompt_event = AgentEvent::PromptSent {
-        prompt: prompt.clone(),
+            Err(RpcError::new(
+                StatusCode::CONFLICT,
+                format!("Merge failed (reverted): {}", stdout.trim()),
+            ))
+        }
+        Err(e) => Err(RpcError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            e.to_string(),
+        )),
     };
-    if let Some(file) = output_file.as_mut() {
-        if let Ok(line) = serde_json::to_string(&prompt_event) {
-            if file.write_all(line.as_bytes()).await.is_err()
-                || file.write_all(b"\n").await.is_err()
-            {
-                output_file = None;
-            }
+
+    if let Some(original_branch) = original_branch.as_deref() {
+        if let Err(e) = checkout_branch(&env.directory, original_branch).await {
+            tracing::warn!(
+                "Failed to restore branch '{}' in {}: {}",
+                original_branch,
+                env.directory.display(),
+                e
+            );
         }
     }
-    let _ = event_tx.send(AgentEnvelope::TaskEvent {
-        task_id,
-        event: prompt_event,
-    });
 
-    let agent_result = if let Some(sid) = session_id {
-        resume_anyagent(
-            task.agent,
-            &agent_config,
-            &task.worktree_path,
-            sid,
-            &prompt,
-            task.web_search,
-        )
-        .await
-    } else {
-        spawn_anyagent(
-            task.agent,
-            &agent_config,
-            &task.worktree_path,
-            &prompt,
-            task.web_search,
-        )
-        .await
-    };
+    result
+}
 
-    let mut agent = match agent_result {
-        Ok(a) => a,
-        Err(e) => {
-            tracing::error!("Failed to spawn agent: {}", e);
-            let _ = state.complete_task_run(task_id, false).await;
-            return;
-        }
-    };
+async fn cherry_pick_task(
+    state: AppState,
+    task_id: TaskId,
+    target: &str,
+    commits: Vec<String>,
+) -> Result<AgentResponse, RpcError> {
+    let target = target.trim();
+    if target.is_empty() {
+        return Err(RpcError::new(
+            StatusCode::BAD_REQUEST,
+            "Target branch is required",
+        ));
+    }
+    if commits.is_empty() {
+        return Err(RpcError::new(
+            StatusCode::BAD_REQUEST,
+            "No commits to cherry-pick",
+        ));
+    }
+    if let Some(sha) = commits
+        .iter()
+        .find(|sha| sha.is_empty() || !sha.chars().all(|c| c.is_ascii_hexdigit()))
+    {
+        return Err(RpcError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid commit SHA '{}'", sha),
+        ));
+    }
 
-    let mut interrupted = false;
-    loop {
-        tokio::select! {
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
+    };
+    if task.is_running() {
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            "Stop the running task before cherry-picking.",
+        ));
+    }
+    let Some(merge_branch) = task.merge_branch.as_deref() else {
+        return Err(RpcError::new(
+            StatusCode::BAD_REQUEST,
+            "Task has no branch; only worktree tasks can be cherry-picked",
+        ));
+    };
+    let Some(env) = state.find_environment(&task.environment).await else {
+        return Err(RpcError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Environment not found",
+        ));
+    };
+
+    let exists = env
+        .branch_exists(target)
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !exists {
+        return Err(RpcError::new(
+            StatusCode::NOT_FOUND,
+            format!("Target branch '{}' not found", target),
+        ));
+    }
+    for sha in &commits {
+        let on_branch = Command::new("git")
+            .args(["merge-base", "--is-ancestor", sha, merge_branch])
+            .current_dir(&env.directory)
+            .output()
+            .await
+            .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        if !on_branch.status.success() {
+            return Err(RpcError::new(
+                StatusCode::BAD_REQUEST,
+                format!("Commit {} is not on task branch '{}'", sha, merge_branch),
+            ));
+        }
+    }
+    let _env_lock = state.lock_environment(&task.environment).await;
+    if has_unstaged_changes(&env.directory).await {
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            format!(
+                "Environment repository has uncommitted changes; cannot check out '{}'.",
+                target
+            ),
+        ));
+    }
+
+    let current = env
+        .current_branch()
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let original_branch = if current == target {
+        None
+    } else {
+        checkout_branch(&env.directory, target)
+            .await
+            .map_err(|e| RpcError::new(StatusCode::CONFLICT, e))?;
+        Some(current)
+    };
+
+    let mut applied = Vec::new();
+    let mut conflict = None;
+    for sha in commits {
+        let output = Command::new("git")
+            .args(["cherry-pick", &sha])
+            .current_dir(&env.directory)
+            .output()
+            .await;
+        match output {
+            Ok(output) if output.status.success() => applied.push(sha),
+            other => {
+                let detail = match other {
+                    Ok(output) => String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                    Err(e) => e.to_string(),
+                };
+                // Leave the environment exactly as the last successful pick left it.
+                let _ = Command::new("git")
+                    .args(["cherry-pick", "--abort"])
+                    .current_dir(&env.directory)
+                    .output()
+                    .await;
+                conflict = Some((sha, detail));
+                break;
+            }
+        }
+    }
+
+    if let Some(original_branch) = original_branch.as_deref() {
+        if let Err(e) = checkout_branch(&env.directory, original_branch).await {
+            tracing::warn!(
+                "Failed to restore branch '{}' in {}: {}",
+                original_branch,
+                env.directory.display(),
+                e
+            );
+        }
+    }
+
+    Ok(match conflict {
+        None => AgentResponse::CherryPickResult {
+            status: "picked".to_string(),
+            message: format!("Cherry-picked {} commit(s) onto {}", applied.len(), target),
+            applied,
+            conflict: None,
+        },
+        Some((sha, detail)) => AgentResponse::CherryPickResult {
+            status: "conflict".to_string(),
+            message: format!(
+                "Cherry-pick of {} onto {} failed (aborted after {} commit(s)): {}",
+                sha,
+                target,
+                applied.len(),
+                detail
+            ),
+            applied,
+            conflict: Some(sha),
+        },
+    })
+}
+
+/// Undo a task's merge into its base branch with new commits on that branch.
+///
+/// A merge commit is reverted with `-m 1`. A fast-forward left none, so the commits it
+/// brought in are reverted as a range starting where the base branch's reflog last
+/// pointed before the task tip.
+async fn revert_merge(state: AppState, task_id: TaskId) -> Result<AgentResponse, RpcError> {
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
+    };
+    let (Some(base_branch), Some(merge_branch)) =
+        (task.base_branch.as_deref(), task.merge_branch.as_deref())
+    else {
+        return Err(RpcError::new(
+            StatusCode::BAD_REQUEST,
+            "Task has no branch; only worktree tasks can be reverted",
+        ));
+    };
+    let Some(env) = state.find_environment(&task.environment).await else {
+        return Err(RpcError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Environment not found",
+        ));
+    };
+    let repo = env.directory.as_path();
+
+    let _env_lock = state.lock_environment(&task.environment).await;
+    if has_unstaged_changes(repo).await {
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            "Environment repository has uncommitted changes; cannot revert.",
+        ));
+    }
+
+    let tip = git_stdout(
+        repo,
+        &[
+            "rev-parse",
+            "--verify",
+            &format!("{}^{{commit}}", merge_branch),
+        ],
+    )
+    .await?
+    .trim()
+    .to_string();
+    if !is_ancestor(repo, &tip, base_branch).await? {
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            format!("'{}' is not merged into '{}'", merge_branch, base_branch),
+        ));
+    }
+
+    let revert_args = match find_merge_commit(repo, &tip, base_branch).await? {
+        Some(merge_commit) => vec!["-m".to_string(), "1".to_string(), merge_commit],
+        None => {
+            let Some(before) = find_pre_merge_head(repo, &tip, base_branch).await? else {
+                return Err(RpcError::new(
+                    StatusCode::CONFLICT,
+                    format!(
+                        "Cannot find where '{}' was before '{}' was fast-forwarded into it",
+                        base_branch, merge_branch
+                    ),
+                ));
+            };
+            vec![format!("{}..{}", before, tip)]
+        }
+    };
+
+    let current = env
+        .current_branch()
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let original_branch = if current == base_branch {
+        None
+    } else {
+        checkout_branch(repo, base_branch)
+            .await
+            .map_err(|e| RpcError::new(StatusCode::CONFLICT, e))?;
+        Some(current)
+    };
+
+    let revert_output = Command::new("git")
+        .args(["revert", "--no-edit"])
+        .args(&revert_args)
+        .current_dir(repo)
+        .output()
+        .await;
+    let result = match revert_output {
+        Ok(output) if output.status.success() => git_stdout(repo, &["rev-parse", "HEAD"])
+            .await
+            .map(|sha| AgentResponse::RevertResult {
+                sha: sha.trim().to_string(),
+                message: format!("Reverted {} on {}", merge_branch, base_branch),
+            }),
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let _ = Command::new("git")
+                .args(["revert", "--abort"])
+                .current_dir(repo)
+                .output()
+                .await;
+            Err(RpcError::new(
+                StatusCode::CONFLICT,
+                format!("Revert failed (aborted): {}", stderr.trim()),
+            ))
+        }
+        Err(e) => Err(RpcError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            e.to_string(),
+        )),
+    };
+
+    if let Some(original_branch) = original_branch.as_deref() {
+        if let Err(e) = checkout_branch(repo, original_branch).await {
+            tracing::warn!(
+                "Failed to restore branch '{}' in {}: {}",
+                original_branch,
+                repo.display(),
+                e
+            );
+        }
+    }
+
+    result
+}
+
+/// The merge commit on `branch`'s first-parent history that brought in `tip`, if any.
+async fn find_merge_commit(
+    repo: &Path,
+    tip: &str,
+    branch: &str,
+) -> Result<Option<String>, RpcError> {
+    let range = format!("{}..{}", tip, branch);
+    let merges = git_stdout(
+        repo,
+        &[
+            "log",
+            "--merges",
+            "--first-parent",
+            "--format=%H %P",
+            &range,
+        ],
+    )
+    .await?;
+    // Newest first: later merges already have `tip` behind their first parent.
+    for line in merges.lines() {
+        let mut shas = line.split_whitespace();
+        let (Some(sha), Some(first_parent)) = (shas.next(), shas.next()) else {
+            continue;
+        };
+        if is_ancestor(repo, tip, first_parent).await? {
+            continue;
+        }
+        for parent in shas {
+            if is_ancestor(repo, tip, parent).await? {
+                return Ok(Some(sha.to_string()));
+            }
+        }
+        return Ok(None);
+    }
+    Ok(None)
+}
+
+/// The newest reflog position of `branch` that does not contain `tip`, i.e. where the branch
+/// stood before `tip` was fast-forwarded into it.
+async fn find_pre_merge_head(
+    repo: &Path,
+    tip: &str,
+    branch: &str,
+) -> Result<Option<String>, RpcError> {
+    let reflog = git_stdout(repo, &["log", "--walk-reflogs", "--format=%H", branch]).await?;
+    for sha in reflog.lines().map(str::trim).filter(|sha| !sha.is_empty()) {
+        if !is_ancestor(repo, tip, sha).await? {
+            return Ok(is_ancestor(repo, sha, tip).await?.then(|| sha.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+async fn is_ancestor(repo: &Path, ancestor: &str, descendant: &str) -> Result<bool, RpcError> {
+    let output = Command::new("git")
+        .args(["merge-base", "--is-ancestor", ancestor, descendant])
+        .current_dir(repo)
+        .output()
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(output.status.success())
+}
+
+async fn checkout_branch(repo_dir: &Path, branch: &str) -> Result<(), String> {
+    let output = Command::new("git")
+        .args(["checkout", branch])
+        .current_dir(repo_dir)
+        .output()
+        .await
+        .map_err(|e| e.to_string())?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    Err(format!("Failed to check out '{}': {}", branch, stderr))
+}
+
+/// Whether merge readiness runs the environment's `merge_check_command`.
+#[derive(Clone, Copy)]
+enum MergeChecks {
+    Skip,
+    /// Reuse the outcome recorded for the worktree's current HEAD, running the check only when
+    /// there is none; for readiness polls.
+    Cached,
+    /// Always run the check; for the merge itself.
+    Fresh,
+}
+
+struct MergeReadinessResult {
+    can_merge: bool,
+    reason: Option<String>,
+    conflicts: Vec<String>,
+    /// Files blocking the merge when the reason is uncommitted changes.
+    dirty_files: Vec<String>,
+}
+
+async fn get_merge_readiness(state: AppState, task_id: TaskId) -> Result<AgentResponse, RpcError> {
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
+    };
+
+    let readiness = evaluate_merge_readiness(
+        &state,
+        &task,
+        task.base_branch.as_deref(),
+        MergeChecks::Cached,
+    )
+    .await?;
+    Ok(AgentResponse::MergeReadiness {
+        can_merge: readiness.can_merge,
+        reason: readiness.reason,
+        conflicts: readiness.conflicts,
+        dirty_files: readiness.dirty_files,
+    })
+}
+
+async fn evaluate_merge_readiness(
+    state: &AppState,
+    task: &Task,
+    target: Option<&str>,
+    checks: MergeChecks,
+) -> Result<MergeReadinessResult, RpcError> {
+    if task.workspace_kind != TaskWorkspaceKind::Worktree {
+        return Ok(MergeReadinessResult {
+            can_merge: false,
+            reason: Some("Only isolated worktree tasks can be merged.".to_string()),
+            conflicts: Vec::new(),
+            dirty_files: Vec::new(),
+        });
+    }
+
+    if task.is_running() {
+        return Ok(MergeReadinessResult {
+            can_merge: false,
+            reason: Some("Task is still running.".to_string()),
+            conflicts: Vec::new(),
+            dirty_files: Vec::new(),
+        });
+    }
+
+    if has_unstaged_changes(&task.worktree_path).await {
+        return Ok(MergeReadinessResult {
+            can_merge: false,
+            reason: Some(
+                "Task worktree has uncommitted or untracked changes. Commit or stash first."
+                    .to_string(),
+            ),
+            conflicts: Vec::new(),
+            dirty_files: dirty_files(&task.worktree_path).await,
+        });
+    }
+
+    let Some(merge_branch) = task.merge_branch.as_deref() else {
+        return Ok(MergeReadinessResult {
+            can_merge: false,
+            reason: Some("Task has no merge branch.".to_string()),
+            conflicts: Vec::new(),
+            dirty_files: Vec::new(),
+        });
+    };
+
+    let Some(env) = state.find_environment(&task.environment).await else {
+        return Ok(MergeReadinessResult {
+            can_merge: false,
+            reason: Some("Environment not found.".to_string()),
+            conflicts: Vec::new(),
+            dirty_files: Vec::new(),
+        });
+    };
+
+    if has_unstaged_changes(&env.directory).await {
+        let current = env.current_branch().await.ok();
+        let reason = match (current, target) {
+            (Some(current), Some(target)) if current != target => format!(
+                "Environment repository is on '{}', not '{}', and has uncommitted or untracked changes.",
+                current, target
+            ),
+            _ => "Environment repository has uncommitted or untracked changes.".to_string(),
+        };
+        return Ok(MergeReadinessResult {
+            can_merge: false,
+            reason: Some(reason),
+            conflicts: Vec::new(),
+            dirty_files: dirty_files(&env.directory).await,
+        });
+    }
+
+    let merge_tree = Command::new("git")
+        .args([
+            "merge-tree",
+            "--write-tree",
+            target.unwrap_or("HEAD"),
+            merge_branch,
+        ])
+        .current_dir(&env.directory)
+        .output()
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    if merge_tree.status.success() {
+        let check_command = match checks {
+            MergeChecks::Skip => None,
+            MergeChecks::Cached | MergeChecks::Fresh => {
+                state.environment_settings(&env).await.merge_check_command
+            }
+        };
+        if let Some(command) = check_command.filter(|command| !command.trim().is_empty()) {
+            if let Err(reason) = merge_check_outcome(state, task, &command, checks).await {
+                return Ok(MergeReadinessResult {
+                    can_merge: false,
+                    reason: Some(reason),
+                    conflicts: Vec::new(),
+                    dirty_files: Vec::new(),
+                });
+            }
+        }
+        return Ok(MergeReadinessResult {
+            can_merge: true,
+            reason: None,
+            conflicts: Vec::new(),
+            dirty_files: Vec::new(),
+        });
+    }
+
+    let stderr = String::from_utf8_lossy(&merge_tree.stderr)
+        .trim()
+        .to_string();
+    let stdout = String::from_utf8_lossy(&merge_tree.stdout)
+        .trim()
+        .to_string();
+    let conflicts = parse_merge_tree_conflicts(&stdout);
+    let detail = if !stderr.is_empty() {
+        stderr
+    } else if !stdout.is_empty() {
+        stdout
+    } else {
+        "Merge would conflict.".to_string()
+    };
+
+    Ok(MergeReadinessResult {
+        can_merge: false,
+        reason: Some(format!("Merge precheck failed: {}", detail)),
+        conflicts,
+        dirty_files: Vec::new(),
+    })
+}
+
+/// Modified, staged and untracked paths in `dir`, for showing what blocks a merge.
+async fn dirty_files(dir: &Path) -> Vec<String> {
+    match git_stdout(
+        dir,
+        &["status", "--porcelain", "-z", "--untracked-files=all"],
+    )
+    .await
+    {
+        Ok(output) => parse_porcelain_paths(&output),
+        Err(e) => {
+            tracing::warn!(dir = %dir.display(), error = %e.error, "Failed to list dirty files");
+            Vec::new()
+        }
+    }
+}
+
+/// Paths from `git status --porcelain -z`; renames and copies report their new path.
+fn parse_porcelain_paths(output: &str) -> Vec<String> {
+    let mut paths = Vec::new();
+    let mut entries = output.split('\0').filter(|entry| entry.len() > 3);
+    while let Some(entry) = entries.next() {
+        let (status, path) = entry.split_at(3);
+        paths.push(path.to_string());
+        // The original path of a rename or copy follows as its own entry.
+        if status.contains(['R', 'C']) {
+            entries.next();
+        }
+    }
+    paths
+}
+
+/// Outcome of `command` for the task's clean worktree. The result is recorded against the
+/// worktree HEAD, and [`MergeChecks::Cached`] reuses it until HEAD or the command changes.
+async fn merge_check_outcome(
+    state: &AppState,
+    task: &Task,
+    command: &str,
+    checks: MergeChecks,
+) -> Result<(), String> {
+    let head = git_stdout(&task.worktree_path, &["rev-parse", "HEAD"])
+        .await
+        .map(|head| head.trim().to_string())
+        .ok();
+    if let (MergeChecks::Cached, Some(head)) = (checks, head.as_deref()) {
+        if let Some(result) = state.cached_merge_check(task.id, command, head).await {
+            return result;
+        }
+    }
+    let result = run_merge_check(command, &task.worktree_path, MERGE_CHECK_TIMEOUT).await;
+    if let Some(head) = head {
+        state
+            .record_merge_check(task.id, command.to_string(), head, result.clone())
+            .await;
+    }
+    result
+}
+
+/// Run an environment's `merge_check_command` in `dir`; the error is the reason shown to the user.
+async fn run_merge_check(command: &str, dir: &Path, limit: Duration) -> Result<(), String> {
+    let output = run_shell_command(command, dir, limit)
+        .await
+        .map_err(|e| format!("Merge check `{}` {}", command, e))?;
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let combined = combined_output(&output);
+    let lines: Vec<&str> = combined.trim_end().lines().collect();
+    let tail = lines[lines.len().saturating_sub(MERGE_CHECK_OUTPUT_LINES)..].join("\n");
+    let status = output
+        .status
+        .code()
+        .map(|code| format!("exit code {}", code))
+        .unwrap_or_else(|| "a signal".to_string());
+    Err(format!(
+        "Merge check `{}` failed with {}:\n{}",
+        command, status, tail
+    ))
+}
+
+/// Run a user-configured command with `sh -c` in `dir`, killing it after `limit`.
+async fn run_shell_command(
+    command: &str,
+    dir: &Path,
+    limit: Duration,
+) -> Result<std::process::Output, String> {
+    let child = Command::new("sh")
+        .args(["-c", command])
+        .current_dir(dir)
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    match timeout(limit, child).await {
+        Ok(Ok(output)) => Ok(output),
+        Ok(Err(e)) => Err(format!("could not start: {}", e)),
+        Err(_) => Err(format!("timed out after {}s.", limit.as_secs())),
+    }
+}
+
+fn combined_output(output: &std::process::Output) -> String {
+    format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    )
+}
+
+/// Run the environment's `post_run_command`, if any, as a `command_execution` item.
+///
+/// The command's exit status is recorded on the item; a failure never fails the task.
+async fn run_post_run_command(state: &AppState, task: &Task) -> Option<AgentEvent> {
+    let env = state.find_environment(&task.environment).await?;
+    let command = state
+        .environment_settings(&env)
+        .await
+        .post_run_command
+        .filter(|command| !command.trim().is_empty())?;
+    let (output, exit_code) =
+        match run_shell_command(&command, &task.worktree_path, POST_RUN_COMMAND_TIMEOUT).await {
+            Ok(output) => (combined_output(&output), output.status.code()),
+            Err(e) => (format!("Post-run command {}", e), None),
+        };
+    if exit_code != Some(0) {
+        tracing::warn!(task_id = %task.id, command = %command, ?exit_code, "Post-run command failed");
+    }
+    let item = CompletedItem {
+        id: Uuid::new_v4().to_string(),
+        item_type: "command_execution".to_string(),
+        text: None,
+        name: None,
+        arguments: None,
+        call_id: None,
+        output: Some(output),
+        duration_ms: None,
+        truncated: false,
+        extra: serde_json::json!({
+            "command": command,
+            "exit_code": exit_code,
+            "source": "post_run_command",
+        }),
+    };
+    Some(AgentEvent::ItemCompleted { item }.normalize())
+}
+
+/// Extract conflicted paths from `git merge-tree --write-tree` output.
+///
+/// The first line is the resulting tree OID, followed by one
+/// `<mode> <object> <stage>\t<path>` line per conflicted stage and then a blank
+/// line before the informational messages.
+fn parse_merge_tree_conflicts(stdout: &str) -> Vec<String> {
+    let mut conflicts: Vec<String> = Vec::new();
+    for line in stdout.lines().skip(1) {
+        if line.trim().is_empty() {
+            break;
+        }
+        let Some((_, path)) = line.split_once('\t') else {
+            continue;
+        };
+        if !conflicts.iter().any(|existing| existing == path) {
+            conflicts.push(path.to_string());
+        }
+    }
+    conflicts
+}
+
+/// Size of `path`, reusing a recent result for the same path.
+async fn workspace_disk_usage(
+    state: &AppState,
+    path: &Path,
+    exclude_git: bool,
+) -> Result<u64, RpcError> {
+    if let Some(bytes) = state.cached_disk_usage(path, exclude_git).await {
+        return Ok(bytes);
+    }
+    let bytes = disk_usage::dir_size(path, exclude_git)
+        .await
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => RpcError::new(
+                StatusCode::GONE,
+                "Task workspace no longer exists (may have been removed from CLI)",
+            ),
+            _ => RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        })?;
+    state
+        .store_disk_usage(path.to_path_buf(), exclude_git, bytes)
+        .await;
+    Ok(bytes)
+}
+
+async fn get_task_disk_usage(
+    state: AppState,
+    task_id: TaskId,
+    exclude_git: bool,
+) -> Result<AgentResponse, RpcError> {
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
+    };
+    let bytes = workspace_disk_usage(&state, &task.worktree_path, exclude_git).await?;
+    Ok(AgentResponse::DiskUsage { task_id, bytes })
+}
+
+/// Sum the isolated worktrees of an environment. In-place tasks share the environment
+/// checkout, so they are left out.
+async fn get_environment_disk_usage(
+    state: AppState,
+    environment: &str,
+    exclude_git: bool,
+) -> Result<AgentResponse, RpcError> {
+    if state.find_environment(environment).await.is_none() {
+        return Err(RpcError::new(
+            StatusCode::NOT_FOUND,
+            format!("Environment '{}' not found", environment),
+        ));
+    }
+
+    let mut tasks = Vec::new();
+    for task in state.list_tasks().await {
+        if task.environment != environment
+            || task.workspace_kind != TaskWorkspaceKind::Worktree
+            || task.history_only
+        {
+            continue;
+        }
+        match workspace_disk_usage(&state, &task.worktree_path, exclude_git).await {
+            Ok(bytes) => tasks.push(TaskDiskUsage {
+                task_id: task.id,
+                bytes,
+            }),
+            Err(e) => {
+                tracing::debug!(task_id = %task.id, error = %e.error, "Skipping task disk usage");
+            }
+        }
+    }
+    tasks.sort_by_key(|task| std::cmp::Reverse(task.bytes));
+    let total_bytes = tasks.iter().map(|task| task.bytes).sum();
+    Ok(AgentResponse::EnvironmentDiskUsage { total_bytes, tasks })
+}
+
+async fn get_terminal_recording(
+    state: AppState,
+    task_id: TaskId,
+) -> Result<AgentResponse, RpcError> {
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
+    };
+    let Some(env_state_dir) = state.get_environment_directory(&task.environment).await else {
+        return Err(RpcError::new(
+            StatusCode::NOT_FOUND,
+            "Environment not found",
+        ));
+    };
+
+    let task_dir = recording::task_recordings_dir(&env_state_dir, task_id);
+    let mut latest: Option<(std::time::SystemTime, u64, PathBuf)> = None;
+    if let Ok(mut entries) = tokio::fs::read_dir(&task_dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("cast") {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            let Ok(modified) = metadata.modified() else {
+                continue;
+            };
+            if !matches!(&latest, Some((best, _, _)) if *best >= modified) {
+                latest = Some((modified, metadata.len(), path));
+            }
+        }
+    }
+
+    let Some((_, size, path)) = latest else {
+        return Err(RpcError::new(
+            StatusCode::NOT_FOUND,
+            "No terminal recording found for this task (is slopagent running with --record-terminals?)",
+        ));
+    };
+    if size > MAX_RECORDING_BYTES {
+        return Err(RpcError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "Terminal recording is {} bytes; playback is limited to {} bytes ({})",
+                size,
+                MAX_RECORDING_BYTES,
+                path.display()
+            ),
+        ));
+    }
+    let content = tokio::fs::read_to_string(&path)
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(AgentResponse::TerminalRecording {
+        path: path.to_string_lossy().to_string(),
+        content,
+    })
+}
+
+async fn archive_task(state: AppState, task_id: TaskId) -> Result<AgentResponse, RpcError> {
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
+    };
+
+    if task.is_running() {
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            "Stop the running task before archiving.",
+        ));
+    }
+
+    let archived_path = archive_task_output(&state, &task).await?;
+    state
+        .remove_task(task_id)
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let message = match archived_path {
+        Some(path) => format!("Archived conversation to {}", path.display()),
+        None => "Task removed; no conversation file was found to archive.".to_string(),
+    };
+    Ok(AgentResponse::ArchiveResult {
+        status: "archived".to_string(),
+        message,
+    })
+}
+
+async fn list_archives(state: AppState, environment: &str) -> Result<AgentResponse, RpcError> {
+    let archive_dir = task_archive_dir(&state.get_worktrees_directory().await, environment);
+    let mut archives: Vec<ArchivedTask> = Vec::new();
+    if let Ok(mut entries) = tokio::fs::read_dir(&archive_dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let Some(task_id) = entry.file_name().to_str().and_then(parse_archive_file_name) else {
+                continue;
+            };
+            let Ok(modified) = entry.metadata().await.and_then(|m| m.modified()) else {
+                continue;
+            };
+            // A transcript can briefly exist in both plain and gzipped form.
+            if let Some(existing) = archives
+                .iter_mut()
+                .find(|archive| archive.task_id == task_id)
+            {
+                existing.archived_at = existing.archived_at.max(modified.into());
+                continue;
+            }
+            archives.push(ArchivedTask {
+                task_id,
+                archived_at: modified.into(),
+            });
+        }
+    }
+    archives.sort_by_key(|archive| std::cmp::Reverse(archive.archived_at));
+    Ok(AgentResponse::Archives { archives })
+}
+
+async fn get_archived_output(
+    state: AppState,
+    environment: &str,
+    task_id: TaskId,
+) -> Result<AgentResponse, RpcError> {
+    let archive_dir = task_archive_dir(&state.get_worktrees_directory().await, environment);
+    let path = task_output_path(&archive_dir, task_id);
+    if !output_log_exists(&path) {
+        return Err(RpcError::new(
+            StatusCode::NOT_FOUND,
+            "Archived conversation not found",
+        ));
+    }
+    let events = read_all_output_events(&path)
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(AgentResponse::ArchivedOutput { task_id, events })
+}
+
+async fn restore_archived_task(
+    state: AppState,
+    environment: &str,
+    task_id: TaskId,
+) -> Result<AgentResponse, RpcError> {
+    if state.get_task(task_id).await.is_some() {
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            "Task is already active",
+        ));
+    }
+
+    let archive_dir = task_archive_dir(&state.get_worktrees_directory().await, environment);
+    let archived = task_output_path(&archive_dir, task_id);
+    if !output_log_exists(&archived) {
+        return Err(RpcError::new(
+            StatusCode::NOT_FOUND,
+            "Archived conversation not found",
+        ));
+    }
+
+    let metadata_path = task_archive_metadata_path(&archive_dir, task_id);
+    let archived_task = match tokio::fs::read(&metadata_path).await {
+        Ok(bytes) => serde_json::from_slice::<Task>(&bytes).ok(),
+        Err(_) => None,
+    };
+    let mut task = match archived_task {
+        Some(task) => task,
+        None => {
+            let events = read_all_output_events(&archived)
+                .await
+                .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            reconstruct_archived_task(&state, environment, task_id, &events).await
+        }
+    };
+    task.status = TaskStatus::Completed;
+    // Without its workspace the task can still be read, just not prompted again.
+    task.history_only = !task.worktree_path.is_dir();
+
+    state
+        .insert_task(task.clone())
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let Some(env_state_dir) = state.get_environment_directory(&task.environment).await else {
+        return Err(RpcError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Environment state directory not found",
+        ));
+    };
+    create_dir_all(&env_state_dir)
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    move_output_log(&archived, &task_output_path(&env_state_dir, task_id)).await?;
+    let raw_archived = task_raw_output_path(&archive_dir, task_id);
+    if raw_archived.exists() {
+        move_file_replacing(
+            &raw_archived,
+            &task_raw_output_path(&env_state_dir, task_id),
+        )
+        .await?;
+    }
+    let _ = remove_file(&metadata_path).await;
+
+    Ok(AgentResponse::RestoredTask { task })
+}
+
+/// Best-effort task record for archives that predate saved task metadata.
+async fn reconstruct_archived_task(
+    state: &AppState,
+    environment: &str,
+    task_id: TaskId,
+    events: &[AgentEvent],
+) -> Task {
+    let directory = state
+        .find_environment(environment)
+        .await
+        .map(|env| env.directory)
+        .unwrap_or_default();
+    let prompts: Vec<&str> = events
+        .iter()
+        .filter_map(|event| match event {
+            AgentEvent::PromptSent { prompt } => Some(prompt.as_str()),
+            _ => None,
+        })
+        .collect();
+    let name = prompts
+        .first()
+        .map(|prompt| fallback_topic_name(prompt))
+        .unwrap_or_else(|| "restored task".to_string());
+
+    let mut task = Task::new(
+        AgentKind::default(),
+        environment.to_string(),
+        name,
+        TaskWorkspaceKind::Environment,
+        None,
+        None,
+        false,
+        directory,
+    );
+    task.id = task_id;
+    task.session_id = events.iter().rev().find_map(AgentEvent::session_id);
+    task.history = prompts
+        .into_iter()
+        .map(|prompt| {
+            let mut run = PromptRun::new(prompt.to_string());
+            run.finish(TaskOutcome::Completed);
+            run
+        })
+        .collect();
+    task
+}
+
+/// `task-<uuid>.jsonl` (or `.jsonl.gz`) -> task id; raw logs and other files are ignored.
+fn parse_archive_file_name(name: &str) -> Option<TaskId> {
+    let name = name.strip_suffix(".gz").unwrap_or(name);
+    let id = name.strip_prefix("task-")?.strip_suffix(".jsonl")?;
+    Uuid::parse_str(id).ok().map(TaskId)
+}
+
+async fn delete_task(
+    state: AppState,
+    task_id: TaskId,
+    force: bool,
+    keep_branch: bool,
+) -> Result<AgentResponse, RpcError> {
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
+    };
+
+    if task.workspace_kind != TaskWorkspaceKind::Worktree {
+        return Err(RpcError::new(
+            StatusCode::BAD_REQUEST,
+            "Delete is only supported for isolated worktree tasks",
+        ));
+    }
+    if task.is_running() {
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            "Stop the running task before deleting its worktree.",
+        ));
+    }
+
+    let env = state
+        .find_environment(&task.environment)
+        .await
+        .ok_or_else(|| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, "Environment not found"))?;
+
+    prune_task_worktree(&task, &env.directory, force).await?;
+
+    // Never delete a branch the user had before the task; only the task's own branch goes.
+    let keep_branch = keep_branch || task.reused_branch;
+    let kept_branch = task.merge_branch.as_deref().filter(|_| keep_branch);
+    if let Some(branch) = task.merge_branch.as_deref().filter(|_| !keep_branch) {
+        let branch_args = if force {
+            vec!["branch", "-D", branch]
+        } else {
+            vec!["branch", "-d", branch]
+        };
+        let _ = Command::new("git")
+            .args(branch_args)
+            .current_dir(&env.directory)
+            .output()
+            .await;
+    }
+
+    let archived_path = archive_task_output(&state, &task).await?;
+    state
+        .remove_task(task_id)
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let deleted = match kept_branch {
+        Some(branch) => format!("Deleted worktree (kept branch '{}')", branch),
+        None => "Deleted worktree".to_string(),
+    };
+    let message = match archived_path {
+        Some(path) => format!(
+            "{} and archived conversation to {}",
+            deleted,
+            path.display()
+        ),
+        None => format!("{}. No conversation file was found to archive.", deleted),
+    };
+
+    Ok(AgentResponse::DeleteResult {
+        status: "deleted".to_string(),
+        message,
+    })
+}
+
+/// Delete every worktree task in the environment that is not running. Running tasks and
+/// tasks whose delete fails are reported as skipped rather than failing the batch.
+async fn delete_completed_tasks(
+    state: AppState,
+    environment: &str,
+    force: bool,
+) -> Result<AgentResponse, RpcError> {
+    if state.find_environment(environment).await.is_none() {
+        return Err(RpcError::new(
+            StatusCode::NOT_FOUND,
+            format!("Environment '{}' not found", environment),
+        ));
+    }
+
+    let mut deleted = Vec::new();
+    let mut skipped = Vec::new();
+    for task in state.list_tasks().await {
+        if task.environment != environment || task.workspace_kind != TaskWorkspaceKind::Worktree {
+            continue;
+        }
+        if task.is_running() {
+            skipped.push(SkippedTask {
+                task_id: task.id,
+                reason: "Task is running".to_string(),
+            });
+            continue;
+        }
+        match delete_task(state.clone(), task.id, force, false).await {
+            Ok(_) => deleted.push(task.id),
+            Err(e) => skipped.push(SkippedTask {
+                task_id: task.id,
+                reason: e.error,
+            }),
+        }
+    }
+
+    Ok(AgentResponse::BulkDeleteResult { deleted, skipped })
+}
+
+/// Prune stale worktree metadata, then find linked worktrees under the worktrees directory that
+/// no task references by path or branch; with `force`, remove the unlocked ones.
+async fn prune_worktrees(
+    state: AppState,
+    environment: &str,
+    force: bool,
+) -> Result<AgentResponse, RpcError> {
+    let Some(env) = state.find_environment(environment).await else {
+        return Err(RpcError::new(
+            StatusCode::NOT_FOUND,
+            format!("Environment '{}' not found", environment),
+        ));
+    };
+
+    let output = Command::new("git")
+        .args(["worktree", "prune", "--verbose"])
+        .current_dir(&env.directory)
+        .output()
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !output.status.success() {
+        return Err(RpcError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    let mut pruned = parse_pruned_worktrees(&String::from_utf8_lossy(&output.stderr));
+    pruned.extend(parse_pruned_worktrees(&String::from_utf8_lossy(
+        &output.stdout,
+    )));
+
+    let worktrees = env
+        .list_worktrees()
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let tasks = state.list_tasks().await;
+    let referenced_paths: HashSet<PathBuf> = tasks
+        .iter()
+        .map(|task| canonical_or_raw(&task.worktree_path))
+        .collect();
+    let referenced_branches: HashSet<&str> = tasks
+        .iter()
+        .filter_map(|task| task.merge_branch.as_deref())
+        .collect();
+    let worktrees_root = canonical_or_raw(&state.get_worktrees_directory().await);
+    let main_checkout = canonical_or_raw(&env.directory);
+
+    let mut orphaned = Vec::new();
+    let mut removed = Vec::new();
+    for worktree in worktrees {
+        let path = canonical_or_raw(&worktree.path);
+        let referenced = referenced_paths.contains(&path)
+            || worktree
+                .branch
+                .as_deref()
+                .is_some_and(|branch| referenced_branches.contains(branch));
+        if worktree.is_bare
+            || path == main_checkout
+            || !path.starts_with(&worktrees_root)
+            || referenced
+        {
+            continue;
+        }
+        if force && !worktree.locked {
+            let target = worktree.path.to_string_lossy();
+            match git_stdout(&env.directory, &["worktree", "remove", "--force", &target]).await {
+                Ok(_) => removed.push(worktree.path.clone()),
+                Err(e) => {
+                    tracing::warn!(path = %worktree.path.display(), error = %e.error, "Failed to remove orphaned worktree");
+                }
+            }
+        }
+        orphaned.push(worktree.path);
+    }
+
+    Ok(AgentResponse::WorktreesPruned {
+        pruned,
+        orphaned,
+        removed,
+    })
+}
+
+/// Admin names from `git worktree prune --verbose` lines like
+/// `Removing worktrees/<name>: gitdir file points to non-existent location`.
+fn parse_pruned_worktrees(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.strip_prefix("Removing "))
+        .filter_map(|rest| rest.split_once(':').map(|(entry, _)| entry))
+        .map(|entry| {
+            entry
+                .strip_prefix("worktrees/")
+                .unwrap_or(entry)
+                .to_string()
+        })
+        .collect()
+}
+
+fn canonical_or_raw(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+async fn reset_task_worktree(
+    state: AppState,
+    task_id: TaskId,
+    keep_untracked: bool,
+) -> Result<AgentResponse, RpcError> {
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
+    };
+
+    if task.workspace_kind != TaskWorkspaceKind::Worktree {
+        return Err(RpcError::new(
+            StatusCode::BAD_REQUEST,
+            "Reset is only supported for isolated worktree tasks",
+        ));
+    }
+    if task.is_running() {
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            "Stop the running task before resetting its worktree.",
+        ));
+    }
+    let Some(base_branch) = task.base_branch.as_deref() else {
+        return Err(RpcError::new(
+            StatusCode::BAD_REQUEST,
+            "Task has no base branch to reset to",
+        ));
+    };
+
+    let env = state
+        .find_environment(&task.environment)
+        .await
+        .ok_or_else(|| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, "Environment not found"))?;
+
+    if !is_linked_worktree(&env.directory, &task.worktree_path).await? {
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            format!(
+                "{} is not a worktree of environment '{}'; refusing to reset.",
+                task.worktree_path.display(),
+                task.environment
+            ),
+        ));
+    }
+
+    let changed = git_stdout(&task.worktree_path, &["diff", "--name-only", base_branch]).await?;
+    let mut files_reset = changed.lines().filter(|l| !l.trim().is_empty()).count();
+
+    git_stdout(&task.worktree_path, &["reset", "--hard", base_branch]).await?;
+
+    if !keep_untracked {
+        let cleaned = git_stdout(&task.worktree_path, &["clean", "-fd"]).await?;
+        files_reset += cleaned
+            .lines()
+            .filter(|l| l.starts_with("Removing "))
+            .count();
+    }
+
+    Ok(AgentResponse::ResetResult { files_reset })
+}
+
+async fn move_task_worktree(
+    state: AppState,
+    task_id: TaskId,
+    new_path: PathBuf,
+) -> Result<AgentResponse, RpcError> {
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
+    };
+
+    if task.workspace_kind != TaskWorkspaceKind::Worktree {
+        return Err(RpcError::new(
+            StatusCode::BAD_REQUEST,
+            "Only isolated worktree tasks can be moved",
+        ));
+    }
+    if task.is_running() {
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            "Stop the running task before moving its worktree.",
+        ));
+    }
+    if !new_path.is_absolute() {
+        return Err(RpcError::new(
+            StatusCode::BAD_REQUEST,
+            "Destination must be an absolute path",
+        ));
+    }
+    if tokio::fs::symlink_metadata(&new_path).await.is_ok() {
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            format!("{} already exists", new_path.display()),
+        ));
+    }
+
+    let env = state
+        .find_environment(&task.environment)
+        .await
+        .ok_or_else(|| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, "Environment not found"))?;
+
+    if !is_linked_worktree(&env.directory, &task.worktree_path).await? {
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            format!(
+                "{} is not a worktree of environment '{}'; refusing to move.",
+                task.worktree_path.display(),
+                task.environment
+            ),
+        ));
+    }
+
+    if let Some(parent) = new_path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    }
+    let old_path = task.worktree_path.to_string_lossy().to_string();
+    let target = new_path.to_string_lossy().to_string();
+    git_stdout(&env.directory, &["worktree", "move", &old_path, &target]).await?;
+
+    let task = state
+        .set_task_worktree_path(task_id, new_path)
+        .await
+        .map_err(map_state_error)?;
+    Ok(AgentResponse::WorktreeMoved {
+        worktree_path: task.worktree_path.to_string_lossy().to_string(),
+    })
+}
+
+async fn apply_patch(
+    state: AppState,
+    task_id: TaskId,
+    diff: String,
+) -> Result<AgentResponse, RpcError> {
+    if diff.trim().is_empty() {
+        return Err(RpcError::new(StatusCode::BAD_REQUEST, "Patch is empty"));
+    }
+
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
+    };
+    if task.is_running() {
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            "Stop the running task before applying a patch.",
+        ));
+    }
+    if !state.validate_task_worktree(task_id).await {
+        return Err(RpcError::new(
+            StatusCode::GONE,
+            "Task workspace no longer exists (may have been removed from CLI)",
+        ));
+    }
+
+    let patch_path = std::env::temp_dir().join(format!("slopcoder-patch-{}.diff", Uuid::new_v4()));
+    let mut body = diff;
+    if !body.ends_with('\n') {
+        body.push('\n');
+    }
+    tokio::fs::write(&patch_path, body)
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let output = Command::new("git")
+        .args(["apply", "--3way"])
+        .arg(&patch_path)
+        .current_dir(&task.worktree_path)
+        .output()
+        .await;
+    let _ = remove_file(&patch_path).await;
+    let output =
+        output.map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    if output.status.success() {
+        return Ok(AgentResponse::Ack);
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    Err(RpcError::new(
+        StatusCode::CONFLICT,
+        format!("git apply failed: {}", stderr),
+    ))
+}
+
+async fn commit_task(
+    state: AppState,
+    task_id: TaskId,
+    message: &str,
+    add_all: bool,
+) -> Result<AgentResponse, RpcError> {
+    let message = message.trim();
+    if message.is_empty() {
+        return Err(RpcError::new(
+            StatusCode::BAD_REQUEST,
+            "Commit message is required",
+        ));
+    }
+
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
+    };
+    if task.is_running() {
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            "Stop the running task before committing.",
+        ));
+    }
+    if !state.validate_task_worktree(task_id).await {
+        return Err(RpcError::new(
+            StatusCode::GONE,
+            "Task workspace no longer exists (may have been removed from CLI)",
+        ));
+    }
+
+    let _env_lock = state.lock_environment(&task.environment).await;
+    if add_all {
+        git_stdout(&task.worktree_path, &["add", "-A"]).await?;
+    }
+
+    let staged = Command::new("git")
+        .args(["diff", "--cached", "--quiet"])
+        .current_dir(&task.worktree_path)
+        .output()
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if staged.status.success() {
+        return Ok(AgentResponse::CommitResult {
+            status: "nothing_to_commit".to_string(),
+            sha: None,
+        });
+    }
+
+    git_stdout(&task.worktree_path, &["commit", "-m", message]).await?;
+    let sha = git_stdout(&task.worktree_path, &["rev-parse", "HEAD"]).await?;
+
+    Ok(AgentResponse::CommitResult {
+        status: "committed".to_string(),
+        sha: Some(sha.trim().to_string()),
+    })
+}
+
+/// Stash message marking a task's entry; worktrees share one stash list, so pops match on it.
+fn task_stash_message(task_id: TaskId) -> String {
+    format!("slopcoder task {}", task_id)
+}
+
+/// Newest stash ref (`stash@{n}`) made for `task_id`, from `git stash list --format=%gd%x1f%s`.
+fn find_task_stash(list: &str, task_id: TaskId) -> Option<String> {
+    let marker = task_stash_message(task_id);
+    list.lines().find_map(|line| {
+        let (stash_ref, subject) = line.split_once('\x1f')?;
+        subject.ends_with(&marker).then(|| stash_ref.to_string())
+    })
+}
+
+async fn stash_task_changes(state: AppState, task_id: TaskId) -> Result<AgentResponse, RpcError> {
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
+    };
+    if task.is_running() {
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            "Stop the running task before stashing.",
+        ));
+    }
+    if !state.validate_task_worktree(task_id).await {
+        return Err(RpcError::new(
+            StatusCode::GONE,
+            "Task workspace no longer exists (may have been removed from CLI)",
+        ));
+    }
+
+    if !has_unstaged_changes(&task.worktree_path).await {
+        return Ok(AgentResponse::StashResult {
+            status: "nothing_to_stash".to_string(),
+            message: "Task workspace has no changes to stash.".to_string(),
+        });
+    }
+
+    let message = task_stash_message(task_id);
+    git_stdout(
+        &task.worktree_path,
+        &["stash", "push", "--include-untracked", "-m", &message],
+    )
+    .await?;
+    Ok(AgentResponse::StashResult {
+        status: "stashed".to_string(),
+        message: format!("Stashed changes as '{}'", message),
+    })
+}
+
+async fn unstash_task_changes(state: AppState, task_id: TaskId) -> Result<AgentResponse, RpcError> {
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
+    };
+    if task.is_running() {
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            "Stop the running task before unstashing.",
+        ));
+    }
+    if !state.validate_task_worktree(task_id).await {
+        return Err(RpcError::new(
+            StatusCode::GONE,
+            "Task workspace no longer exists (may have been removed from CLI)",
+        ));
+    }
+
+    let list = git_stdout(
+        &task.worktree_path,
+        &["stash", "list", "--format=%gd%x1f%s"],
+    )
+    .await?;
+    let Some(stash_ref) = find_task_stash(&list, task_id) else {
+        return Err(RpcError::new(
+            StatusCode::NOT_FOUND,
+            "No stashed changes found for this task",
+        ));
+    };
+
+    let output = Command::new("git")
+        .args(["stash", "pop", &stash_ref])
+        .current_dir(&task.worktree_path)
+        .output()
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !output.status.success() {
+        // git keeps the stash entry when the pop conflicts, so nothing is lost.
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            format!("git stash pop failed: {}", stderr),
+        ));
+    }
+    Ok(AgentResponse::StashResult {
+        status: "unstashed".to_string(),
+        message: format!("Restored changes from {}", stash_ref),
+    })
+}
+
+const DEFAULT_COMMIT_LIMIT: usize = 50;
+const MAX_COMMIT_LIMIT: usize = 500;
+/// `git log` format parsed by [`parse_git_log`]: unit-separated fields, one commit per line.
+const GIT_LOG_FORMAT: &str = "--pretty=format:%H%x1f%an%x1f%aI%x1f%s";
+
+async fn get_task_commits(
+    state: AppState,
+    task_id: TaskId,
+    limit: Option<usize>,
+) -> Result<AgentResponse, RpcError> {
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
+    };
+
+    // In-place tasks have no branch of their own, so there is nothing to list.
+    let base_branch = match (task.workspace_kind, task.base_branch.as_deref()) {
+        (TaskWorkspaceKind::Worktree, Some(base_branch)) => base_branch,
+        _ => {
+            return Ok(AgentResponse::TaskCommits {
+                commits: Vec::new(),
+            })
+        }
+    };
+
+    let limit = limit
+        .unwrap_or(DEFAULT_COMMIT_LIMIT)
+        .clamp(1, MAX_COMMIT_LIMIT)
+        .to_string();
+    let range = format!("{}..HEAD", base_branch);
+    let stdout = git_stdout(
+        &task.worktree_path,
+        &["log", GIT_LOG_FORMAT, "-n", &limit, &range],
+    )
+    .await?;
+
+    Ok(AgentResponse::TaskCommits {
+        commits: parse_git_log(&stdout),
+    })
+}
+
+fn parse_git_log(stdout: &str) -> Vec<CommitInfo> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, '\u{1f}');
+            let sha = fields.next()?.trim();
+            let author = fields.next()?;
+            let timestamp = chrono::DateTime::parse_from_rfc3339(fields.next()?).ok()?;
+            let subject = fields.next().unwrap_or_default();
+            if sha.is_empty() {
+                return None;
+            }
+            Some(CommitInfo {
+                sha: sha.to_string(),
+                author: author.to_string(),
+                timestamp: timestamp.with_timezone(&chrono::Utc),
+                subject: subject.to_string(),
+            })
+        })
+        .collect()
+}
+
+const MAX_WORKTREE_FILE_BYTES: u64 = 1024 * 1024;
+
+async fn read_worktree_file(
+    state: AppState,
+    task_id: TaskId,
+    path: &str,
+) -> Result<AgentResponse, RpcError> {
+    let Some(task) = state.get_task(task_id).await else {
+        return Err(RpcError::new(StatusCode::NOT_FOUND, "Task not found"));
+    };
+
+    let full_path = resolve_worktree_file(&task.worktree_path, path)?;
+    let metadata = tokio::fs::metadata(&full_path)
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if metadata.is_dir() {
+        return Err(RpcError::new(
+            StatusCode::BAD_REQUEST,
+            format!("'{}' is a directory", path),
+        ));
+    }
+
+    let size = metadata.len();
+    if size > MAX_WORKTREE_FILE_BYTES {
+        return Ok(AgentResponse::WorktreeFile {
+            path: path.to_string(),
+            size,
+            binary: false,
+            too_large: true,
+            content: None,
+        });
+    }
+
+    let bytes = tokio::fs::read(&full_path)
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let content = if bytes.contains(&0) {
+        None
+    } else {
+        String::from_utf8(bytes).ok()
+    };
+
+    Ok(AgentResponse::WorktreeFile {
+        path: path.to_string(),
+        size,
+        binary: content.is_none(),
+        too_large: false,
+        content,
+    })
+}
+
+/// Resolve `relative` inside `root`, rejecting paths that escape it (including via symlinks).
+fn resolve_worktree_file(root: &Path, relative: &str) -> Result<PathBuf, RpcError> {
+    let relative = relative.trim();
+    if relative.is_empty() || Path::new(relative).is_absolute() {
+        return Err(RpcError::new(
+            StatusCode::BAD_REQUEST,
+            "Path must be relative to the task workspace",
+        ));
+    }
+
+    let root = root.canonicalize().map_err(|_| {
+        RpcError::new(
+            StatusCode::GONE,
+            "Task workspace no longer exists (may have been removed from CLI)",
+        )
+    })?;
+    let resolved = root.join(relative).canonicalize().map_err(|_| {
+        RpcError::new(
+            StatusCode::NOT_FOUND,
+            format!("File '{}' not found", relative),
+        )
+    })?;
+    if !resolved.starts_with(&root) {
+        return Err(RpcError::new(
+            StatusCode::BAD_REQUEST,
+            "Path escapes the task workspace",
+        ));
+    }
+    Ok(resolved)
+}
+
+/// Map a worktree setup failure to an RPC error; taken branches and paths are conflicts.
+fn worktree_error(e: EnvironmentError) -> RpcError {
+    let status = match e {
+        EnvironmentError::BranchExists(_)
+        | EnvironmentError::WorktreeExists(_)
+        | EnvironmentError::BranchCheckedOut { .. } => StatusCode::CONFLICT,
+        EnvironmentError::BranchNotFound(_) => StatusCode::NOT_FOUND,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    RpcError::new(status, format!("Failed to create worktree: {}", e))
+}
+
+/// Normalize a requested agent subdirectory, rejecting absolute paths and `..` components.
+/// An empty or `.` path normalizes to the workspace root.
+fn validate_subdir(subdir: &str) -> Result<PathBuf, RpcError> {
+    let mut normalized = PathBuf::new();
+    for component in Path::new(subdir.trim()).components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(RpcError::new(
+                    StatusCode::BAD_REQUEST,
+                    "Subdirectory must stay inside the task workspace",
+                ));
+            }
+        }
+    }
+    Ok(normalized)
+}
+
+/// Resolve a validated subdirectory inside `root`, following symlinks, and require a directory.
+fn resolve_working_subdir(root: &Path, subdir: &Path) -> Result<PathBuf, RpcError> {
+    if subdir.as_os_str().is_empty() {
+        return Ok(root.to_path_buf());
+    }
+    let resolved = resolve_worktree_file(root, &subdir.to_string_lossy())?;
+    if !resolved.is_dir() {
+        return Err(RpcError::new(
+            StatusCode::BAD_REQUEST,
+            format!("'{}' is not a directory", subdir.display()),
+        ));
+    }
+    Ok(resolved)
+}
+
+/// Whether `worktree_path` is a linked worktree (not the main checkout) of `repo_dir`.
+async fn is_linked_worktree(repo_dir: &Path, worktree_path: &Path) -> Result<bool, RpcError> {
+    let listing = git_stdout(repo_dir, &["worktree", "list", "--porcelain"]).await?;
+    let Ok(target) = worktree_path.canonicalize() else {
+        return Ok(false);
+    };
+    let main = repo_dir.canonicalize().ok();
+    Ok(parse_worktree_list(&listing)
+        .into_iter()
+        .filter_map(|path| path.canonicalize().ok())
+        .any(|path| path == target && Some(&path) != main.as_ref()))
+}
+
+/// Extract worktree paths from `git worktree list --porcelain` output.
+fn parse_worktree_list(stdout: &str) -> Vec<PathBuf> {
+    stdout
+        .lines()
+        .filter_map(|line| line.strip_prefix("worktree "))
+        .map(PathBuf::from)
+        .collect()
+}
+
+async fn git_stdout(dir: &Path, args: &[&str]) -> Result<String, RpcError> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(RpcError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("git {} failed: {}", args.join(" "), stderr),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Undo the worktree `create_task` just made for `task` when a later setup step fails: remove
+/// it and, unless the task reused an existing branch, the branch created with it.
+async fn discard_task_worktree(task: &Task, repo_dir: &Path) {
+    if task.workspace_kind != TaskWorkspaceKind::Worktree {
+        return;
+    }
+    if let Err(e) = prune_task_worktree(task, repo_dir, true).await {
+        tracing::warn!(task_id = %task.id, error = %e.error, "Failed to remove abandoned worktree");
+    }
+    if let Some(branch) = task.merge_branch.as_deref().filter(|_| !task.reused_branch) {
+        let _ = Command::new("git")
+            .args(["branch", "-D", branch])
+            .current_dir(repo_dir)
+            .output()
+            .await;
+    }
+}
+
+async fn prune_task_worktree(task: &Task, repo_dir: &Path, force: bool) -> Result<(), RpcError> {
+    let mut args = vec!["worktree", "remove"];
+    if force {
+        args.push("-f");
+    }
+    let worktree_path = task.worktree_path.to_string_lossy().to_string();
+    args.push(&worktree_path);
+
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(repo_dir)
+        .output()
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let detail = if !stderr.is_empty() {
+        stderr
+    } else if !stdout.is_empty() {
+        stdout
+    } else {
+        "unknown git error".to_string()
+    };
+    let lower = detail.to_ascii_lowercase();
+    if !force
+        && (lower.contains("untracked") || lower.contains("modified") || lower.contains("--force"))
+    {
+        return Err(RpcError::new(
+            StatusCode::CONFLICT,
+            format!(
+                "Prune failed: {}. Use force prune to remove it anyway.",
+                detail
+            ),
+        ));
+    }
+
+    Err(RpcError::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("Failed to remove worktree: {}", detail),
+    ))
+}
+
+async fn archive_task_output(state: &AppState, task: &Task) -> Result<Option<PathBuf>, RpcError> {
+    let Some(env_state_dir) = state.get_environment_directory(&task.environment).await else {
+        return Err(RpcError::new(
+            StatusCode::NOT_FOUND,
+            "Environment state directory not found",
+        ));
+    };
+
+    let source = task_output_path(&env_state_dir, task.id);
+    let raw_source = task_raw_output_path(&env_state_dir, task.id);
+    if !output_log_exists(&source) && !raw_source.exists() {
+        return Ok(None);
+    }
+
+    let archive_dir = task_archive_dir(&state.get_worktrees_directory().await, &task.environment);
+    create_dir_all(&archive_dir)
+        .await
+        .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    if raw_source.exists() {
+        let raw_destination = task_raw_output_path(&archive_dir, task.id);
+        move_file_replacing(&raw_source, &raw_destination).await?;
+    }
+    if !output_log_exists(&source) {
+        return Ok(None);
+    }
+    let destination = task_output_path(&archive_dir, task.id);
+    move_output_log(&source, &destination).await?;
+
+    // Kept so the task can be restored with its agent, branches, and session intact.
+    let metadata_path = task_archive_metadata_path(&archive_dir, task.id);
+    match serde_json::to_vec_pretty(task) {
+        Ok(bytes) => {
+            if let Err(e) = tokio::fs::write(&metadata_path, bytes).await {
+                tracing::warn!(task_id = %task.id, error = %e, "Failed to write archived task metadata");
+            }
+        }
+        Err(e) => {
+            tracing::warn!(task_id = %task.id, error = %e, "Failed to serialize archived task")
+        }
+    }
+    Ok(Some(destination))
+}
+
+fn archive_root_dir(worktrees_root: &Path) -> PathBuf {
+    worktrees_root.join(".slopcoder-state").join("archive")
+}
+
+fn task_archive_dir(worktrees_root: &Path, environment: &str) -> PathBuf {
+    archive_root_dir(worktrees_root).join(environment_dir_name(environment))
+}
+
+fn task_archive_metadata_path(archive_dir: &Path, task_id: TaskId) -> PathBuf {
+    archive_dir.join(format!("task-{}.meta.json", task_id))
+}
+
+/// Move `source` to `destination`, replacing it and falling back to copy across filesystems.
+async fn move_file_replacing(source: &Path, destination: &Path) -> Result<(), RpcError> {
+    if destination.exists() {
+        remove_file(destination)
+            .await
+            .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    }
+
+    if rename(source, destination).await.is_err() {
+        copy(source, destination)
+            .await
+            .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        remove_file(source)
+            .await
+            .map_err(|e| RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    }
+    Ok(())
+}
+
+#[tracing::instrument(name = "run_agent", skip_all, fields(task_id = %task_id))]
+async fn run_agent(
+    state: AppState,
+    task_id: TaskId,
+    prompt: String,
+    attachments: Vec<AttachedFile>,
+    session_id: Option<Uuid>,
+    event_tx: mpsc::UnboundedSender<AgentEnvelope>,
+) {
+    let task = match state.get_task(task_id).await {
+        Some(t) => t,
+        None => {
+            tracing::error!(task_id = %task_id, "Task not found");
+            return;
+        }
+    };
+
+    let env_dir = state.get_environment_directory(&task.environment).await;
+    let output_path = env_dir
+        .as_deref()
+        .map(|env_dir| task_output_path(env_dir, task_id));
+    let max_output_bytes = state.max_output_bytes().await;
+    let mut output_file = match output_path.as_deref() {
+        Some(output_path) => {
+            if let Err(e) = expand_output_log(output_path).await {
+                tracing::warn!(task_id = %task_id, error = %e, "Failed to decompress output log");
+            }
+            match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(output_path)
+                .await
+            {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    tracing::warn!("Failed to open output log {}: {}", output_path.display(), e);
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    if let Err(e) = state.start_task_run(task_id, prompt.clone()).await {
+        tracing::error!(task_id = %task_id, error = %e, "Failed to start task run");
+        return;
+    }
+
+    let mut interrupt_rx = state.register_interrupt_channel(task_id).await;
+    let mut agent_config = state.get_agent_config().await;
+    let mut approval_rx = if agent_config.supports_approvals(task.agent) {
+        Some(state.register_approval_channel(task_id).await)
+    } else {
+        None
+    };
+    if let Some(model) = task.model.clone() {
+        agent_config.set_model(task.agent, model);
+    }
+    let mut redactor = SecretRedactor::default();
+    let mut instructions = None;
+    if let Some(env) = state.find_environment(&task.environment).await {
+        let settings = state.environment_settings(&env).await;
+        instructions = settings.instructions.clone();
+        if !settings.env.is_empty() {
+            tracing::info!(
+                task_id = %task_id,
+                env = %redact_env(&settings.env),
+                "Passing environment variables to agent"
+            );
+            agent_config.extend_env(&settings.env);
+        }
+        redactor = SecretRedactor::new(&settings.redact_patterns, settings.env.into_values());
+    }
+    if state.debug_raw().await {
+        agent_config.set_raw_output(env_dir.as_deref().map(|env_dir| RawOutputLog {
+            path: task_raw_output_path(env_dir, task_id),
+            redactor: redactor.clone(),
+        }));
+    }
+    let mut agent_prompt = agent_config.attach_files(task.agent, &prompt, &attachments);
+    // The CLI resumes from the last message it persisted, so the crashed turn's unfinished
+    // tool calls are dropped rather than continued.
+    let resumes_crashed_turn = session_id.is_some() && task.last_run_crashed();
+    if resumes_crashed_turn {
+        agent_prompt = format!("{}{}", CRASHED_TURN_NOTICE, agent_prompt);
+    }
+    if let Some(instructions) = instructions.as_deref() {
+        agent_prompt = agent_config.add_instructions(task.agent, &agent_prompt, instructions);
+    }
+    if task.web_search && task.agent != AgentKind::Codex {
+        tracing::warn!(
+            task_id = %task_id,
+            agent = task.agent.as_str(),
+            "Web search requested, but this agent does not currently support it in slopcoder"
+        );
+    }
+
+    let prompt_event = AgentEvent::PromptSent {
+        prompt: prompt.clone(),
+    };
+    append_output_event(&mut output_file, task_id, &prompt_event).await;
+    let _ = event_tx.send(AgentEnvelope::TaskEvent {
+        task_id,
+        event: prompt_event,
+    });
+    if resumes_crashed_turn {
+        tracing::info!(task_id = %task_id, "Resuming session after a crashed run");
+        let resumed_event = AgentEvent::BackgroundEvent {
+            event: Some("resumed_after_crash".to_string()),
+            extra: serde_json::json!({
+                "message": "Resuming the session; the turn interrupted by the restart was abandoned",
+            }),
+        };
+        append_output_event(&mut output_file, task_id, &resumed_event).await;
+        let _ = event_tx.send(AgentEnvelope::TaskEvent {
+            task_id,
+            event: resumed_event,
+        });
+    }
+
+    if let Some(limiter) = state.start_limiter().await {
+        let ticket = limiter.reserve(task.priority);
+        if let Some(wait) = ticket.queued_wait() {
+            let wait_ms = u64::try_from(wait.as_millis()).unwrap_or(u64::MAX);
+            tracing::info!(task_id = %task_id, wait_ms, "Waiting on agent start rate limit");
+            let waiting_event = AgentEvent::BackgroundEvent {
+                event: Some("rate_limited".to_string()),
+                extra: serde_json::json!({
+                    "message": "Waiting on the host's agent start rate limit",
+                    "wait_ms": wait_ms,
+                }),
+            };
+            append_output_event(&mut output_file, task_id, &waiting_event).await;
+            let _ = event_tx.send(AgentEnvelope::TaskEvent {
+                task_id,
+                event: waiting_event,
+            });
+            // An interrupt drops the ticket, handing its token to the next queued start.
+            tokio::select! {
+                _ = ticket.ready() => {}
+                _ = &mut interrupt_rx => {
+                    if let Err(e) = state.interrupt_task_run(task_id).await {
+                        tracing::warn!(task_id = %task_id, error = %e, "Failed to persist interrupt");
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    // Only starting the agent is retried; a run that already produced events never is.
+    let (spawn_retries, spawn_retry_backoff) = state.spawn_retry().await;
+    let mut attempt: u32 = 0;
+    let agent_result = loop {
+        let result = if let Some(sid) = session_id {
+            resume_anyagent(
+                task.agent,
+                &agent_config,
+                task.agent_working_dir(),
+                sid,
+                &agent_prompt,
+                task.web_search,
+            )
+            .await
+        } else {
+            spawn_anyagent(
+                task.agent,
+                &agent_config,
+                task.agent_working_dir(),
+                &agent_prompt,
+                task.web_search,
+            )
+            .await
+        };
+        let error = match result {
+            Err(e) if e.is_transient_spawn_failure() && attempt < spawn_retries => e,
+            result => break result,
+        };
+        let wait = spawn_retry_backoff.saturating_mul(1 << attempt.min(16));
+        attempt += 1;
+        let wait_ms = u64::try_from(wait.as_millis()).unwrap_or(u64::MAX);
+        tracing::warn!(task_id = %task_id, attempt, wait_ms, error = %error, "Agent failed to start; retrying");
+        let retry_event = AgentEvent::BackgroundEvent {
+            event: Some("spawn_retry".to_string()),
+            extra: serde_json::json!({
+                "message": format!("Agent failed to start ({}); retry {} of {}", error, attempt, spawn_retries),
+                "attempt": attempt,
+                "retries": spawn_retries,
+                "wait_ms": wait_ms,
+            }),
+        };
+        append_output_event(&mut output_file, task_id, &retry_event).await;
+        let _ = event_tx.send(AgentEnvelope::TaskEvent {
+            task_id,
+            event: retry_event,
+        });
+        tokio::select! {
+            _ = sleep(wait) => {}
+            _ = &mut interrupt_rx => {
+                if let Err(e) = state.interrupt_task_run(task_id).await {
+                    tracing::warn!(task_id = %task_id, error = %e, "Failed to persist interrupt");
+                }
+                return;
+            }
+        }
+    };
+
+    let mut agent = match agent_result {
+        Ok(a) => a,
+        Err(e) => {
+            tracing::error!(task_id = %task_id, error = %e, "Failed to spawn agent");
+            let message = match e {
+                AgentError::ProcessError(message) => message,
+                other => other.to_string(),
+            };
+            let error_event = redactor.redact_event(AgentEvent::Error { message });
+            append_output_event(&mut output_file, task_id, &error_event).await;
+            let _ = event_tx.send(AgentEnvelope::TaskEvent {
+                task_id,
+                event: error_event,
+            });
+            let _ = state
+                .complete_task_run(task_id, TaskOutcome::ToolError, None, None)
+                .await;
+            finish_output_log(&state, task_id, output_file, env_dir.as_deref()).await;
+            spawn_release_dependents(&state, task_id, &event_tx);
+            return;
+        }
+    };
+
+    let mut interrupted = false;
+    let mut timed_out = false;
+    let mut tool_timer = ToolCallTimer::default();
+    let heartbeat = state.run_heartbeat().await;
+    let mut last_event = tokio::time::Instant::now();
+    let mut next_heartbeat = heartbeat.map(|interval| last_event + interval);
+    let run_timeout = state.run_timeout().await;
+    let deadline = run_timeout.map(|limit| last_event + limit);
+    loop {
+        tokio::select! {
             result = agent.next_event() => {
                 match result {
                     Some(Ok(event)) => {
+                        last_event = tokio::time::Instant::now();
+                        next_heartbeat = heartbeat.map(|interval| last_event + interval);
                         if let Some(sid) = event.session_id() {
                             if let Err(e) = state.set_task_session_id(task_id, sid).await {
-                                tracing::warn!("Failed to save session ID: {}", e);
+                                tracing::warn!(task_id = %task_id, error = %e, "Failed to save session ID");
                             }
                         }
-                        if let Some(file) = output_file.as_mut() {
-                            match serde_json::to_string(&event) {
-                                Ok(line) => {
-                                    if file.write_all(line.as_bytes()).await.is_err()
-                                        || file.write_all(b"\n").await.is_err()
-                                    {
-                                        output_file = None;
-                                    }
-                                }
-                                Err(e) => tracing::warn!("Failed to serialize event for {}: {}", task_id, e),
+                        let mut event = redactor.redact_event(event);
+                        tool_timer.observe(&mut event);
+                        let file_edits = event.item().map(file_edit::file_edits).unwrap_or_default();
+                        append_output_event(&mut output_file, task_id, &event).await;
+                        let _ = event_tx.send(AgentEnvelope::TaskEvent { task_id, event });
+                        for event in file_edits {
+                            append_output_event(&mut output_file, task_id, &event).await;
+                            let _ = event_tx.send(AgentEnvelope::TaskEvent { task_id, event });
+                        }
+                        if let (Some(path), Some(max_bytes)) = (output_path.as_deref(), max_output_bytes) {
+                            if let Some(event) = rotate_output_log(&mut output_file, path, max_bytes, task_id).await {
+                                append_output_event(&mut output_file, task_id, &event).await;
+                                let _ = event_tx.send(AgentEnvelope::TaskEvent { task_id, event });
                             }
                         }
-                        let _ = event_tx.send(AgentEnvelope::TaskEvent { task_id, event });
                     }
-                    Some(Err(e)) => tracing::warn!("Error reading event: {}", e),
-                    None => break,
+                    Some(Err(e)) => tracing::warn!(task_id = %task_id, error = %e, "Error reading event"),
+                    None => break,
+                }
+            }
+            _ = sleep_until_due(next_heartbeat) => {
+                next_heartbeat = heartbeat.map(|interval| tokio::time::Instant::now() + interval);
+                // Broadcast only: heartbeats would bloat the task log.
+                let _ = event_tx.send(AgentEnvelope::TaskEvent {
+                    task_id,
+                    event: AgentEvent::Heartbeat {
+                        elapsed_secs: last_event.elapsed().as_secs(),
+                    },
+                });
+            }
+            Some((call_id, approve)) = next_approval(&mut approval_rx) => {
+                if let Err(e) = agent.respond_approval(&call_id, approve).await {
+                    tracing::warn!(task_id = %task_id, call_id = %call_id, error = %e, "Failed to deliver approval");
+                }
+            }
+            _ = &mut interrupt_rx => {
+                interrupted = true;
+                if let Err(e) = agent.kill().await {
+                    tracing::warn!(task_id = %task_id, error = %e, "Failed to kill agent");
+                }
+                break;
+            }
+            _ = sleep_until_due(deadline) => {
+                timed_out = true;
+                if let Err(e) = agent.kill().await {
+                    tracing::warn!(task_id = %task_id, error = %e, "Failed to kill timed-out agent");
+                }
+                break;
+            }
+        }
+    }
+
+    if interrupted {
+        if let Err(e) = state.interrupt_task_run(task_id).await {
+            tracing::warn!(task_id = %task_id, error = %e, "Failed to persist interrupt");
+        }
+    } else {
+        let (outcome, exit_code, signal) = if timed_out {
+            let limit = run_timeout.unwrap_or_default();
+            let event = AgentEvent::Error {
+                message: format!("Run timed out after {}s", limit.as_secs()),
+            };
+            append_output_event(&mut output_file, task_id, &event).await;
+            let _ = event_tx.send(AgentEnvelope::TaskEvent { task_id, event });
+            (TaskOutcome::TimedOut, None, None)
+        } else {
+            match agent.wait().await {
+                Ok(r) => {
+                    if let Err(e) = state.set_task_session_id(task_id, r.session_id).await {
+                        tracing::warn!("Failed to save session ID: {}", e);
+                    }
+                    let outcome = if r.success {
+                        TaskOutcome::Completed
+                    } else {
+                        TaskOutcome::Failed
+                    };
+                    (outcome, r.exit_code, r.signal)
+                }
+                Err(_) => (TaskOutcome::ToolError, None, None),
+            }
+        };
+
+        if outcome == TaskOutcome::Completed {
+            if let Some(event) = run_post_run_command(&state, &task).await {
+                let event = redactor.redact_event(event);
+                append_output_event(&mut output_file, task_id, &event).await;
+                let _ = event_tx.send(AgentEnvelope::TaskEvent { task_id, event });
+            }
+        }
+
+        if let Err(e) = state
+            .complete_task_run(task_id, outcome, exit_code, signal)
+            .await
+        {
+            tracing::warn!(task_id = %task_id, error = %e, "Failed to persist completion");
+        }
+        finish_output_log(&state, task_id, output_file, env_dir.as_deref()).await;
+        spawn_release_dependents(&state, task_id, &event_tx);
+    }
+}
+
+/// Start times of tool calls still waiting for their output, keyed by `call_id`.
+#[derive(Default)]
+struct ToolCallTimer {
+    started: HashMap<String, std::time::Instant>,
+}
+
+impl ToolCallTimer {
+    /// Note when a tool call is seen and stamp its matching output with the elapsed time.
+    fn observe(&mut self, event: &mut AgentEvent) {
+        let AgentEvent::ItemCompleted { item } = event else {
+            return;
+        };
+        let Some(call_id) = item.call_id.as_deref() else {
+            return;
+        };
+        if item.is_tool_call() {
+            self.started
+                .insert(call_id.to_string(), std::time::Instant::now());
+        } else if item.is_tool_output() {
+            if let Some(started) = self.started.remove(call_id) {
+                item.duration_ms =
+                    Some(u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX));
+            }
+        }
+    }
+}
+
+/// Sleep until `due`, or forever when there is nothing due (heartbeats or run timeout off).
+async fn sleep_until_due(due: Option<tokio::time::Instant>) {
+    match due {
+        Some(due) => tokio::time::sleep_until(due).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Next approval answer for the run, or never when the agent takes none.
+async fn next_approval(
+    approval_rx: &mut Option<tokio::sync::mpsc::UnboundedReceiver<(String, bool)>>,
+) -> Option<(String, bool)> {
+    match approval_rx {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Close the task's output log and, with `--compress-output`, gzip it once the task is idle.
+async fn finish_output_log(
+    state: &AppState,
+    task_id: TaskId,
+    output_file: Option<File>,
+    env_dir: Option<&Path>,
+) {
+    if let Some(mut file) = output_file {
+        let _ = file.flush().await;
+    }
+    let Some(env_dir) = env_dir else {
+        return;
+    };
+    if !state.compress_output().await
+        || state
+            .get_task(task_id)
+            .await
+            .is_none_or(|task| task.is_running())
+    {
+        return;
+    }
+    if let Err(e) = compress_output_log(&task_output_path(env_dir, task_id)).await {
+        tracing::warn!(task_id = %task_id, error = %e, "Failed to compress output log");
+    }
+}
+
+/// Append one event to the task's JSONL log; a failed write stops further logging.
+async fn append_output_event(output_file: &mut Option<File>, task_id: TaskId, event: &AgentEvent) {
+    let Some(file) = output_file.as_mut() else {
+        return;
+    };
+    match serde_json::to_string(event) {
+        Ok(line) => {
+            if file.write_all(line.as_bytes()).await.is_err()
+                || file.write_all(b"\n").await.is_err()
+            {
+                *output_file = None;
+            }
+        }
+        Err(e) => tracing::warn!(task_id = %task_id, error = %e, "Failed to serialize event"),
+    }
+}
+
+fn task_output_path(env_dir: &Path, task_id: TaskId) -> PathBuf {
+    env_dir.join(format!("task-{}.jsonl", task_id))
+}
+
+/// Most rotated segments kept per output log; rotating past this drops the oldest.
+const MAX_OUTPUT_SEGMENTS: usize = 10;
+
+/// Rotated segment `n` of an output log (`task-<id>.jsonl.<n>`); `.1` is the most recent.
+fn rotated_output_path(path: &Path, segment: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", segment));
+    PathBuf::from(name)
+}
+
+/// Existing rotated segments of an output log, oldest first.
+fn rotated_output_segments(path: &Path) -> Vec<PathBuf> {
+    let mut segments: Vec<PathBuf> = (1..)
+        .map(|segment| rotated_output_path(path, segment))
+        .take_while(|segment| segment.exists())
+        .collect();
+    segments.reverse();
+    segments
+}
+
+/// Once the log passes `max_bytes`, shift it to `.1` (older segments move up one) and
+/// continue in a fresh file. Returns the event announcing the rotation.
+async fn rotate_output_log(
+    output_file: &mut Option<File>,
+    path: &Path,
+    max_bytes: u64,
+    task_id: TaskId,
+) -> Option<AgentEvent> {
+    let file = output_file.as_mut()?;
+    let rotated_bytes = file.metadata().await.ok()?.len();
+    if rotated_bytes <= max_bytes {
+        return None;
+    }
+    let _ = file.flush().await;
+    if let Err(e) = shift_output_segments(path).await {
+        tracing::warn!(task_id = %task_id, error = %e, "Failed to rotate output log");
+        return None;
+    }
+    *output_file = match OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+    {
+        Ok(file) => Some(file),
+        Err(e) => {
+            tracing::warn!("Failed to reopen output log {}: {}", path.display(), e);
+            None
+        }
+    };
+    tracing::info!(task_id = %task_id, rotated_bytes, "Rotated task output log");
+    Some(AgentEvent::BackgroundEvent {
+        event: Some("output_rotated".to_string()),
+        extra: serde_json::json!({
+            "message": "Task output log reached its size cap and was rotated",
+            "rotated_bytes": rotated_bytes,
+        }),
+    })
+}
+
+/// Rename `path` to `path.1`, moving each existing `path.<n>` to `path.<n + 1>` first and
+/// deleting the oldest segments that would go past [`MAX_OUTPUT_SEGMENTS`].
+async fn shift_output_segments(path: &Path) -> io::Result<()> {
+    let mut segments = rotated_output_segments(path);
+    let excess = (segments.len() + 1).saturating_sub(MAX_OUTPUT_SEGMENTS);
+    for segment in segments.drain(..excess) {
+        remove_file(segment).await?;
+    }
+    let count = segments.len();
+    for (index, segment) in segments.iter().enumerate() {
+        tokio::fs::rename(segment, rotated_output_path(path, count - index + 1)).await?;
+    }
+    tokio::fs::rename(path, rotated_output_path(path, 1)).await
+}
+
+/// Gzipped form of an output log (`task-<id>.jsonl.gz`), written by `--compress-output`.
+fn compressed_output_path(path: &Path) -> PathBuf {
+    path.with_extension("jsonl.gz")
+}
+
+fn output_log_exists(path: &Path) -> bool {
+    path.exists() || compressed_output_path(path).exists() || rotated_output_path(path, 1).exists()
+}
+
+/// Open an output log for reading, rotated segments first so events stay in order.
+async fn open_output_log(path: &Path) -> io::Result<Box<dyn AsyncBufRead + Unpin + Send>> {
+    let segments = rotated_output_segments(path);
+    if segments.is_empty() {
+        return open_output_segment(path).await;
+    }
+    let mut reader: Box<dyn AsyncRead + Unpin + Send> = Box::new(tokio::io::empty());
+    for segment in segments {
+        reader = Box::new(reader.chain(File::open(segment).await?));
+    }
+    match open_output_segment(path).await {
+        Ok(current) => reader = Box::new(reader.chain(current)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    Ok(Box::new(BufReader::new(reader)))
+}
+
+/// Open the current segment of an output log, falling back to its gzipped form.
+async fn open_output_segment(path: &Path) -> io::Result<Box<dyn AsyncBufRead + Unpin + Send>> {
+    match File::open(path).await {
+        Ok(file) => return Ok(Box::new(BufReader::new(file))),
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        Err(_) => {}
+    }
+    let compressed = tokio::fs::read(compressed_output_path(path)).await?;
+    let decoded = tokio::task::spawn_blocking(move || {
+        let mut decoded = Vec::new();
+        GzDecoder::new(compressed.as_slice()).read_to_end(&mut decoded)?;
+        Ok::<_, io::Error>(decoded)
+    })
+    .await
+    .map_err(io::Error::other)??;
+    Ok(Box::new(io::Cursor::new(decoded)))
+}
+
+/// Replace `path` with `path.gz`. Skipped when the log is missing or already compressed.
+async fn compress_output_log(path: &Path) -> io::Result<bool> {
+    let Ok(modified) = tokio::fs::metadata(path).await.and_then(|m| m.modified()) else {
+        return Ok(false);
+    };
+    let compressed = compressed_output_path(path);
+    if let Ok(compressed_modified) = tokio::fs::metadata(&compressed)
+        .await
+        .and_then(|m| m.modified())
+    {
+        if compressed_modified >= modified {
+            return Ok(false);
+        }
+    }
+    let source = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let partial = compressed.with_extension("gz.tmp");
+        let mut input = std::fs::File::open(&source)?;
+        let mut encoder = GzEncoder::new(std::fs::File::create(&partial)?, Compression::default());
+        io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?.sync_all()?;
+        std::fs::rename(&partial, &compressed)?;
+        std::fs::remove_file(&source)
+    })
+    .await
+    .map_err(io::Error::other)??;
+    Ok(true)
+}
+
+/// Turn a gzipped log back into plain JSONL so a new run can append to it.
+async fn expand_output_log(path: &Path) -> io::Result<()> {
+    let compressed = compressed_output_path(path);
+    if path.exists() || !compressed.exists() {
+        return Ok(());
+    }
+    let mut reader = open_output_segment(path).await?;
+    let mut decoded = Vec::new();
+    reader.read_to_end(&mut decoded).await?;
+    tokio::fs::write(path, decoded).await?;
+    remove_file(&compressed).await
+}
+
+/// Move an output log in whichever forms exist.
+async fn move_output_log(source: &Path, destination: &Path) -> Result<(), RpcError> {
+    if source.exists() {
+        move_file_replacing(source, destination).await?;
+    }
+    let compressed = compressed_output_path(source);
+    if compressed.exists() {
+        move_file_replacing(&compressed, &compressed_output_path(destination)).await?;
+    }
+    for segment in 1..=rotated_output_segments(source).len() {
+        move_file_replacing(
+            &rotated_output_path(source, segment),
+            &rotated_output_path(destination, segment),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Unparsed agent stdout, written only with `--debug-raw`.
+fn task_raw_output_path(env_dir: &Path, task_id: TaskId) -> PathBuf {
+    env_dir.join(format!("task-{}.raw.jsonl", task_id))
+}
+
+async fn read_all_output_events(path: &Path) -> Result<Vec<AgentEvent>, std::io::Error> {
+    let mut lines = open_output_log(path).await?.lines();
+    let mut events = Vec::new();
+    while let Some(line) = lines.next_line().await? {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if let Ok(event) = serde_json::from_str::<AgentEvent>(trimmed) {
+            events.push(event.normalize());
+        }
+    }
+    Ok(events)
+}
+
+/// Bytes read per step when scanning an output log backwards.
+const OUTPUT_TAIL_CHUNK_BYTES: u64 = 64 * 1024;
+
+#[derive(Debug, Default)]
+struct OutputSummary {
+    last_message: Option<String>,
+    usage: Option<UsageStats>,
+}
+
+impl OutputSummary {
+    /// Take what `event` adds when scanning newest first; true once nothing is missing.
+    fn record(&mut self, event: &AgentEvent) -> bool {
+        match event {
+            AgentEvent::ItemCompleted { item }
+                if item.is_agent_message() && self.last_message.is_none() =>
+            {
+                self.last_message = item.text.clone();
+            }
+            AgentEvent::TurnCompleted { usage: Some(usage) } if self.usage.is_none() => {
+                self.usage = Some(usage.clone());
+            }
+            _ => {}
+        }
+        self.last_message.is_some() && self.usage.is_some()
+    }
+}
+
+/// Last agent message and latest turn usage in an output log.
+///
+/// A plain log is read backwards a chunk at a time, stopping once both are found, so a
+/// long conversation is not parsed in full. Gzipped logs, and rotated logs whose current
+/// segment has no agent message, fall back to reading every event.
+async fn read_output_summary(path: &Path) -> io::Result<OutputSummary> {
+    match File::open(path).await {
+        Ok(file) => {
+            let mut summary = OutputSummary::default();
+            scan_output_tail(file, &mut summary).await?;
+            if summary.last_message.is_some() || !rotated_output_path(path, 1).exists() {
+                return Ok(summary);
+            }
+        }
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        Err(_) => {}
+    }
+    if !output_log_exists(path) {
+        return Ok(OutputSummary::default());
+    }
+    let mut summary = OutputSummary::default();
+    for event in read_all_output_events(path).await?.iter().rev() {
+        if summary.record(event) {
+            break;
+        }
+    }
+    Ok(summary)
+}
+
+async fn scan_output_tail(mut file: File, summary: &mut OutputSummary) -> io::Result<()> {
+    let mut end = file.metadata().await?.len();
+    // Start of a line cut by the chunk boundary; completed by the next (earlier) chunk.
+    let mut carry = Vec::new();
+    while end > 0 {
+        let start = end.saturating_sub(OUTPUT_TAIL_CHUNK_BYTES);
+        let mut chunk = vec![0; (end - start) as usize];
+        file.seek(io::SeekFrom::Start(start)).await?;
+        file.read_exact(&mut chunk).await?;
+        chunk.extend_from_slice(&carry);
+        end = start;
+
+        let complete_from = if start == 0 {
+            0
+        } else {
+            match chunk.iter().position(|b| *b == b'\n') {
+                Some(index) => index + 1,
+                None => {
+                    carry = chunk;
+                    continue;
+                }
+            }
+        };
+        for line in chunk[complete_from..].rsplit(|b| *b == b'\n') {
+            let Ok(event) = serde_json::from_slice::<AgentEvent>(line) else {
+                continue;
+            };
+            if summary.record(&event.normalize()) {
+                return Ok(());
+            }
+        }
+        chunk.truncate(complete_from);
+        carry = chunk;
+    }
+    Ok(())
+}
+
+struct OutputEventsPage {
+    events: Vec<AgentEvent>,
+    total_events: usize,
+    has_more_before: bool,
+}
+
+async fn read_output_events_page(
+    path: &Path,
+    before: usize,
+    limit: usize,
+) -> Result<OutputEventsPage, std::io::Error> {
+    if !output_log_exists(path) {
+        return Ok(OutputEventsPage {
+            events: Vec::new(),
+            total_events: 0,
+            has_more_before: false,
+        });
+    }
+
+    let limit = limit.max(1);
+    let window_size = before.saturating_add(limit);
+    let mut lines = open_output_log(path).await?.lines();
+    let mut window = std::collections::VecDeque::with_capacity(window_size);
+    let mut total_events = 0usize;
+
+    while let Some(line) = lines.next_line().await? {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if let Ok(event) = serde_json::from_str::<AgentEvent>(trimmed) {
+            total_events += 1;
+            if window.len() == window_size {
+                window.pop_front();
+            }
+            window.push_back(event.normalize());
+        }
+    }
+
+    let available_before = total_events.saturating_sub(before);
+    let take_count = available_before.min(limit);
+    let drop_count = window
+        .len()
+        .saturating_sub(before.saturating_add(take_count));
+    let events = window
+        .into_iter()
+        .skip(drop_count)
+        .take(take_count)
+        .collect::<Vec<_>>();
+
+    Ok(OutputEventsPage {
+        events,
+        total_events,
+        has_more_before: total_events > before.saturating_add(take_count),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        append_output_event, archive_task_output, cherry_pick_task, compress_output_log,
+        compressed_output_path, create_environment, create_task, delete_completed_tasks,
+        dispatch_request, expand_output_log, find_task_stash, get_terminal_recording,
+        handle_request, interrupt_task, list_archives, load_git_diff, merge_task,
+        move_task_worktree, normalize_server_url, parse_archive_file_name, parse_git_log,
+        parse_host_label, parse_merge_tree_conflicts, parse_version_output, parse_worktree_list,
+        prune_archives, prune_worktrees, random_jitter, read_all_output_events,
+        read_output_events_page, read_output_summary, resolve_working_subdir,
+        resolve_worktree_file, respond_approval, restore_archived_task, revert_merge,
+        rotate_output_log, rotated_output_path, run_merge_check, send_prompt, stash_task_changes,
+        stream_git_diff, task_output_path, truncate_file_diffs, unstash_task_changes,
+        validate_subdir, wind_down_runs, write_outbound, AppState, ReconnectBackoff,
+        TerminalManager, ATTACHMENTS_DIR, MAX_OUTPUT_SEGMENTS, MAX_RECORDING_BYTES,
+    };
+    use crate::recording;
+    use crate::state::StateError;
+    use slopcoder_core::{
+        agent_rpc::{
+            AgentCreateTaskRequest, AgentEnvelope, AgentRequest, AgentResponse, Attachment,
+            DiffSection, RequestId,
+        },
+        anyagent::AgentKind,
+        environment::{Environment, EnvironmentConfig},
+        task::{Task, TaskId, TaskOutcome, TaskStatus, TaskWorkspaceKind},
+        AgentEvent,
+    };
+    use std::path::{Path, PathBuf};
+    use tempfile::NamedTempFile;
+    use tokio::fs;
+
+    async fn write_events(lines: &[&str]) -> NamedTempFile {
+        let file = NamedTempFile::new().expect("temp file");
+        let body = format!("{}\n", lines.join("\n"));
+        fs::write(file.path(), body).await.expect("write events");
+        file
+    }
+
+    #[tokio::test]
+    async fn read_output_events_page_returns_latest_window() {
+        let file = write_events(&[
+            r#"{"type":"prompt.sent","prompt":"one"}"#,
+            r#"{"type":"prompt.sent","prompt":"two"}"#,
+            r#"{"type":"prompt.sent","prompt":"three"}"#,
+            r#"{"type":"prompt.sent","prompt":"four"}"#,
+        ])
+        .await;
+
+        let page = read_output_events_page(file.path(), 0, 2)
+            .await
+            .expect("page");
+
+        assert_eq!(page.total_events, 4);
+        assert!(page.has_more_before);
+        let prompts = page
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                AgentEvent::PromptSent { prompt } => Some(prompt.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(prompts, vec!["three", "four"]);
+    }
+
+    #[tokio::test]
+    async fn read_output_events_page_returns_older_window() {
+        let file = write_events(&[
+            r#"{"type":"prompt.sent","prompt":"one"}"#,
+            r#"{"type":"prompt.sent","prompt":"two"}"#,
+            r#"{"type":"prompt.sent","prompt":"three"}"#,
+            r#"{"type":"prompt.sent","prompt":"four"}"#,
+            r#"{"type":"prompt.sent","prompt":"five"}"#,
+        ])
+        .await;
+
+        let page = read_output_events_page(file.path(), 2, 2)
+            .await
+            .expect("page");
+
+        assert_eq!(page.total_events, 5);
+        assert!(page.has_more_before);
+        let prompts = page
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                AgentEvent::PromptSent { prompt } => Some(prompt.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(prompts, vec!["two", "three"]);
+    }
+
+    #[tokio::test]
+    async fn read_output_summary_finds_last_message_across_chunks() {
+        // The large tool output pushes the final message out of the last tail chunk.
+        let tool_output = format!(
+            r#"{{"type":"item.completed","item":{{"id":"item_4","type":"tool_output","output":"{}"}}}}"#,
+            "x".repeat(100 * 1024)
+        );
+        let file = write_events(&[
+            r#"{"type":"prompt.sent","prompt":"fix it"}"#,
+            r#"{"type":"item.completed","item":{"id":"item_1","type":"agent_message","text":"Looking."}}"#,
+            r#"{"type":"turn.completed","usage":{"input_tokens":10,"output_tokens":3}}"#,
+            r#"{"type":"prompt.sent","prompt":"and test it"}"#,
+            r#"{"type":"item.completed","item":{"id":"item_3","type":"agent_message","text":"Fixed and tested."}}"#,
+            &tool_output,
+            r#"{"type":"turn.completed","usage":{"input_tokens":20,"output_tokens":7}}"#,
+        ])
+        .await;
+
+        let summary = read_output_summary(file.path()).await.expect("summary");
+        assert_eq!(summary.last_message.as_deref(), Some("Fixed and tested."));
+        let usage = summary.usage.expect("usage");
+        assert_eq!(usage.input_tokens, Some(20));
+        assert_eq!(usage.output_tokens, Some(7));
+    }
+
+    #[tokio::test]
+    async fn read_output_summary_without_agent_message() {
+        let file = write_events(&[r#"{"type":"prompt.sent","prompt":"hello"}"#]).await;
+
+        let summary = read_output_summary(file.path()).await.expect("summary");
+        assert!(summary.last_message.is_none());
+        assert!(summary.usage.is_none());
+    }
+
+    #[test]
+    fn parse_merge_tree_conflicts_lists_unique_paths() {
+        let stdout = "4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+100644 257cc5642cb1a054f08cc83f2d943e56fd3ebe99 1\tsrc/lib.rs\n\
+100644 5716ca5987cbf97d6bb54920bea6adde242d87e6 2\tsrc/lib.rs\n\
+100644 76018072e09c5d31c8c6e3113b8aa0fe625195ca 3\tsrc/lib.rs\n\
+100644 257cc5642cb1a054f08cc83f2d943e56fd3ebe99 2\tREADME.md\n\
+\n\
+Auto-merging src/lib.rs\n\
+CONFLICT (content): Merge conflict in src/lib.rs\n";
+
+        assert_eq!(
+            parse_merge_tree_conflicts(stdout),
+            vec!["src/lib.rs".to_string(), "README.md".to_string()]
+        );
+    }
+
+    #[test]
+    fn parse_merge_tree_conflicts_handles_clean_output() {
+        assert!(parse_merge_tree_conflicts("4b825dc642cb6eb9a060e54bf8d69288fbee4904").is_empty());
+    }
+
+    #[test]
+    fn parse_git_log_reads_unit_separated_fields() {
+        let stdout = "abc123\u{1f}Ada Lovelace\u{1f}2024-05-01T12:30:00+02:00\u{1f}Fix login: handle \u{1f} in names
+def456\u{1f}Grace Hopper\u{1f}2024-04-30T08:00:00Z\u{1f}Initial import";
+
+        let commits = parse_git_log(stdout);
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].sha, "abc123");
+        assert_eq!(commits[0].author, "Ada Lovelace");
+        assert_eq!(
+            commits[0].timestamp.to_rfc3339(),
+            "2024-05-01T10:30:00+00:00"
+        );
+        assert_eq!(commits[0].subject, "Fix login: handle \u{1f} in names");
+        assert_eq!(commits[1].subject, "Initial import");
+    }
+
+    #[test]
+    fn parse_git_log_skips_malformed_lines() {
+        let stdout = "\nnot a commit line\nabc123\u{1f}Ada\u{1f}not-a-date\u{1f}Subject\n";
+        assert!(parse_git_log(stdout).is_empty());
+    }
+
+    #[test]
+    fn parse_archive_file_name_accepts_only_transcripts() {
+        let id = "6c0b0f60-d9b0-4ee7-9f12-6de09fbfc6d5";
+        assert_eq!(
+            parse_archive_file_name(&format!("task-{}.jsonl", id)).map(|t| t.to_string()),
+            Some(id.to_string())
+        );
+        assert!(parse_archive_file_name(&format!("task-{}.raw.jsonl", id)).is_none());
+        assert!(parse_archive_file_name("task-not-a-uuid.jsonl").is_none());
+        assert!(parse_archive_file_name("tasks.yaml").is_none());
+    }
+
+    #[tokio::test]
+    async fn merge_check_reports_failures_and_timeouts() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        let limit = std::time::Duration::from_secs(5);
+        fs::write(temp.path().join("Makefile.ok"), "")
+            .await
+            .unwrap();
+
+        run_merge_check("test -f Makefile.ok", temp.path(), limit)
+            .await
+            .expect("passing check");
+
+        let reason = run_merge_check(
+            "echo building; echo 'error: E0308' >&2; exit 3",
+            temp.path(),
+            limit,
+        )
+        .await
+        .expect_err("failing check");
+        assert!(reason.contains("exit code 3"), "{}", reason);
+        assert!(reason.contains("building\nerror: E0308"), "{}", reason);
+
+        let reason = run_merge_check(
+            "sleep 5",
+            temp.path(),
+            std::time::Duration::from_millis(100),
+        )
+        .await
+        .expect_err("hung check");
+        assert!(reason.contains("timed out"), "{}", reason);
+    }
+
+    #[tokio::test]
+    async fn merge_readiness_reruns_the_check_only_when_head_changes() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        let runs = temp.path().join("check-runs");
+        fs::write(
+            env_dir.join(".slopcoder.yaml"),
+            format!("merge_check_command: echo run >> {}\n", runs.display()),
+        )
+        .await
+        .expect("write settings");
+        git(&env_dir, &["add", ".slopcoder.yaml"]).await;
+        git(&env_dir, &["commit", "-m", "merge check"]).await;
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+        let request = AgentCreateTaskRequest {
+            agent: Some(AgentKind::Mock),
+            ..worktree_request("Checked")
+        };
+        let AgentResponse::CreatedTask { id, worktree_path } =
+            create_task(state.clone(), request, out_tx.clone())
+                .await
+                .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+        wait_until_stopped(&state, id, 1).await;
+
+        let check_runs = || async {
+            let readiness = handle_request(
+                state.clone(),
+                AgentRequest::GetMergeReadiness { task_id: id },
+                out_tx.clone(),
+            )
+            .await
+            .expect("readiness");
+            assert!(matches!(
+                readiness,
+                AgentResponse::MergeReadiness {
+                    can_merge: true,
+                    ..
+                }
+            ));
+            fs::read_to_string(&runs)
+                .await
+                .unwrap_or_default()
+                .lines()
+                .count()
+        };
+        assert_eq!(check_runs().await, 1);
+        assert_eq!(check_runs().await, 1);
+
+        let worktree = PathBuf::from(&worktree_path);
+        git(&worktree, &["commit", "--allow-empty", "-m", "more work"]).await;
+        assert_eq!(check_runs().await, 2);
+    }
+
+    #[tokio::test]
+    async fn compressed_output_log_round_trips() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        let path = task_output_path(temp.path(), slopcoder_core::task::TaskId::new());
+        let lines = [
+            r#"{"type":"prompt.sent","prompt":"one"}"#,
+            r#"{"type":"turn.started"}"#,
+            r#"{"type":"item.completed","item":{"id":"m1","type":"agent_message","text":"done"}}"#,
+        ];
+        fs::write(&path, format!("{}\n", lines.join("\n")))
+            .await
+            .expect("write log");
+        let as_json = |events: &[AgentEvent]| serde_json::to_value(events).expect("serialize");
+        let plain = as_json(&read_all_output_events(&path).await.expect("read plain"));
+
+        assert!(compress_output_log(&path).await.expect("compress"));
+        let compressed = compressed_output_path(&path);
+        assert!(!path.exists());
+        assert!(compressed.exists());
+        assert_eq!(
+            as_json(&read_all_output_events(&path).await.expect("read gz")),
+            plain
+        );
+        let page = read_output_events_page(&path, 0, 2).await.expect("page");
+        assert_eq!(page.total_events, 3);
+        assert_eq!(as_json(&page.events)[0], plain[1]);
+        // Nothing newer than the gz to compress.
+        assert!(!compress_output_log(&path).await.expect("recompress"));
+
+        expand_output_log(&path).await.expect("expand");
+        assert!(!compressed.exists());
+        assert_eq!(
+            as_json(&read_all_output_events(&path).await.expect("read")),
+            plain
+        );
+    }
+
+    #[tokio::test]
+    async fn output_log_rotates_past_cap_and_reads_back_in_order() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        let task_id = TaskId::new();
+        let path = task_output_path(temp.path(), task_id);
+        let mut output_file = Some(
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await
+                .expect("open log"),
+        );
+
+        let mut rotations = 0;
+        for index in 0..10 {
+            let event = AgentEvent::PromptSent {
+                prompt: format!("prompt {}", index),
+            };
+            append_output_event(&mut output_file, task_id, &event).await;
+            if let Some(rotated) = rotate_output_log(&mut output_file, &path, 64, task_id).await {
+                assert!(matches!(
+                    &rotated,
+                    AgentEvent::BackgroundEvent { event: Some(kind), .. } if kind == "output_rotated"
+                ));
+                rotations += 1;
+            }
+        }
+        assert!(rotations >= 3, "{} rotations", rotations);
+        assert!(rotated_output_path(&path, rotations).exists());
+        assert!(!rotated_output_path(&path, rotations + 1).exists());
+        for segment in 1..=rotations {
+            let size = std::fs::metadata(rotated_output_path(&path, segment))
+                .expect("segment")
+                .len();
+            assert!(
+                size > 64 && size < 128,
+                "segment {} is {} bytes",
+                segment,
+                size
+            );
+        }
+
+        let prompts: Vec<_> = read_all_output_events(&path)
+            .await
+            .expect("read")
+            .into_iter()
+            .map(|event| match event {
+                AgentEvent::PromptSent { prompt } => prompt,
+                other => panic!("unexpected event: {:?}", other),
+            })
+            .collect();
+        let expected: Vec<_> = (0..10).map(|index| format!("prompt {}", index)).collect();
+        assert_eq!(prompts, expected);
+
+        let page = read_output_events_page(&path, 0, 3).await.expect("page");
+        assert_eq!(page.total_events, 10);
+        assert!(page.has_more_before);
+        assert!(matches!(
+            &page.events[0],
+            AgentEvent::PromptSent { prompt } if prompt == "prompt 7"
+        ));
+    }
+
+    #[tokio::test]
+    async fn output_log_rotation_keeps_at_most_the_segment_cap() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        let task_id = TaskId::new();
+        let path = task_output_path(temp.path(), task_id);
+        for segment in 1..=MAX_OUTPUT_SEGMENTS {
+            fs::write(
+                rotated_output_path(&path, segment),
+                format!("{}\n", segment),
+            )
+            .await
+            .unwrap();
+        }
+        fs::write(&path, "x".repeat(128)).await.unwrap();
+        let mut output_file = Some(
+            fs::OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .await
+                .expect("open log"),
+        );
+
+        assert!(rotate_output_log(&mut output_file, &path, 64, task_id)
+            .await
+            .is_some());
+        assert!(!rotated_output_path(&path, MAX_OUTPUT_SEGMENTS + 1).exists());
+        // The oldest segment was dropped; the rest moved up one.
+        let oldest = fs::read_to_string(rotated_output_path(&path, MAX_OUTPUT_SEGMENTS))
+            .await
+            .unwrap();
+        assert_eq!(oldest, format!("{}\n", MAX_OUTPUT_SEGMENTS - 1));
+        assert_eq!(
+            fs::read_to_string(rotated_output_path(&path, 1))
+                .await
+                .unwrap(),
+            "x".repeat(128)
+        );
+    }
+
+    #[tokio::test]
+    async fn prune_archives_removes_only_expired_conversations() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        let archive_dir = temp.path().join("demo");
+        std::fs::create_dir_all(&archive_dir).expect("create archive dir");
+        let old = "6c0b0f60-d9b0-4ee7-9f12-6de09fbfc6d5";
+        let fresh = "0b6f6d7e-4f8a-4c52-9c1e-2f0f5a1d3b21";
+        for id in [old, fresh] {
+            std::fs::write(archive_dir.join(format!("task-{}.jsonl", id)), "{}\n").unwrap();
+            std::fs::write(archive_dir.join(format!("task-{}.meta.json", id)), "{}").unwrap();
+        }
+        std::fs::write(archive_dir.join(format!("task-{}.raw.jsonl", old)), "{}\n").unwrap();
+
+        let now = std::time::SystemTime::now();
+        let ten_days = std::time::Duration::from_secs(10 * 24 * 60 * 60);
+        std::fs::File::options()
+            .write(true)
+            .open(archive_dir.join(format!("task-{}.jsonl", old)))
+            .and_then(|file| file.set_modified(now - ten_days))
+            .expect("backdate transcript");
+
+        let cutoff = now - std::time::Duration::from_secs(7 * 24 * 60 * 60);
+        assert_eq!(prune_archives(temp.path(), cutoff).await, 1);
+
+        let remaining: Vec<String> = std::fs::read_dir(&archive_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(remaining.len(), 2);
+        assert!(remaining.iter().all(|name| name.contains(fresh)));
+        assert_eq!(
+            prune_archives(&temp.path().join("missing"), cutoff).await,
+            0
+        );
+    }
+
+    #[test]
+    fn reconnect_backoff_grows_with_jitter_up_to_the_cap() {
+        let secs = |secs: u64| std::time::Duration::from_secs(secs);
+        let mut backoff = ReconnectBackoff::new(secs(2), secs(60));
+        let delays: Vec<_> = (0..8)
+            .map(|_| backoff.next_delay(0.999).as_secs())
+            .collect();
+        assert_eq!(delays, vec![1, 3, 7, 15, 31, 59, 59, 59]);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(0.0), secs(1));
+        assert_eq!(backoff.next_delay(0.5), secs(3));
+
+        let mut backoff = ReconnectBackoff::new(secs(2), secs(60));
+        let mut previous_cap = std::time::Duration::ZERO;
+        for _ in 0..10 {
+            let jitter = random_jitter();
+            assert!((0.0..1.0).contains(&jitter));
+            let delay = backoff.next_delay(jitter);
+            let cap = (previous_cap * 2).clamp(secs(2), secs(60));
+            assert!(
+                delay >= cap / 2 && delay <= cap,
+                "{:?} outside {:?}",
+                delay,
+                cap
+            );
+            previous_cap = cap;
+        }
+    }
+
+    #[tokio::test]
+    async fn outbound_envelope_survives_a_failed_send() {
+        use futures::StreamExt;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, _env_dir) = state_with_committed_repo(&temp).await;
+        let out_tx = state.outbound_sender().await;
+        for nonce in [1, 2] {
+            out_tx.send(AgentEnvelope::Pong { nonce }).expect("queue");
+        }
+
+        let broken = Box::pin(futures::sink::unfold((), |(), _: Message| async {
+            Err::<(), _>("connection reset")
+        }));
+        write_outbound(state.outbound_receiver().await, broken).await;
+
+        let (sink, mut sent) = futures::channel::mpsc::unbounded();
+        let writer = tokio::spawn(write_outbound(state.outbound_receiver().await, sink));
+        for expected in [1, 2] {
+            let message = sent.next().await.expect("sent");
+            let envelope: AgentEnvelope =
+                serde_json::from_str(message.to_text().expect("text")).expect("envelope");
+            assert!(
+                matches!(envelope, AgentEnvelope::Pong { nonce } if nonce == expected),
+                "{envelope:?}"
+            );
+        }
+        writer.abort();
+    }
+
+    #[tokio::test]
+    async fn connection_error_releases_the_outbound_queue() {
+        use futures::StreamExt;
+        use tokio::io::AsyncWriteExt;
+
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, _env_dir) = state_with_committed_repo(&temp).await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let url = format!("ws://{}", listener.local_addr().expect("addr"));
+        let coordinator = tokio::spawn(async move {
+            let (mut tcp, _) = listener.accept().await.expect("accept");
+            let mut ws = tokio_tungstenite::accept_async(&mut tcp)
+                .await
+                .expect("handshake");
+            ws.next().await.expect("hello").expect("hello frame");
+            drop(ws);
+            // A frame with a reserved opcode fails the agent's read.
+            tcp.write_all(&[0x8f, 0x00]).await.expect("write");
+            tcp
+        });
+
+        let result = super::run_connection(
+            state.clone(),
+            &url,
+            None,
+            "host".to_string(),
+            None,
+            Vec::new(),
+            None,
+        )
+        .await;
+        assert!(result.is_err());
+        let _tcp = coordinator.await.expect("coordinator");
+        tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            state.outbound_receiver().await.lock_owned(),
+        )
+        .await
+        .expect("outbound queue still held by the dead connection");
+    }
+
+    #[test]
+    fn normalize_server_url_maps_schemes_and_appends_the_path_once() {
+        let normalized = |input: &str| normalize_server_url(input).unwrap();
+        assert_eq!(normalized("https://x"), "wss://x/agent/connect");
+        assert_eq!(normalized("http://x/"), "ws://x/agent/connect");
+        assert_eq!(normalized("x:8080"), "ws://x:8080/agent/connect");
+        assert_eq!(
+            normalized("wss://x:8443/slop/agent/connect/"),
+            "wss://x:8443/slop/agent/connect"
+        );
+        assert_eq!(normalized("ws://x/agent/connect"), "ws://x/agent/connect");
+        assert_eq!(
+            normalized("HTTPS://x/prefix"),
+            "wss://x/prefix/agent/connect"
+        );
+
+        assert!(normalize_server_url("ftp://x").is_err());
+        assert!(normalize_server_url("https://").is_err());
+        assert!(normalize_server_url("/agent/connect").is_err());
+        assert!(normalize_server_url("bad host:80").is_err());
+    }
+
+    #[test]
+    fn parse_host_label_splits_on_first_equals() {
+        assert_eq!(
+            parse_host_label("region=us-east"),
+            Some(("region".to_string(), "us-east".to_string()))
+        );
+        assert_eq!(
+            parse_host_label(" gpu = a100=80gb "),
+            Some(("gpu".to_string(), "a100=80gb".to_string()))
+        );
+        assert_eq!(
+            parse_host_label("os="),
+            Some(("os".to_string(), String::new()))
+        );
+        assert_eq!(parse_host_label("=us"), None);
+        assert_eq!(parse_host_label("region"), None);
+    }
+
+    #[test]
+    fn parse_version_output_extracts_version_token() {
+        assert_eq!(
+            parse_version_output("codex-cli 0.46.0\n").as_deref(),
+            Some("0.46.0")
+        );
+        assert_eq!(
+            parse_version_output("\n1.0.120 (Claude Code)\n").as_deref(),
+            Some("1.0.120")
+        );
+        assert_eq!(parse_version_output("v2.1.3").as_deref(), Some("2.1.3"));
+        assert_eq!(
+            parse_version_output("nightly build\n").as_deref(),
+            Some("nightly build")
+        );
+        assert_eq!(parse_version_output("  \n"), None);
+    }
+
+    #[test]
+    fn resolve_worktree_file_rejects_traversal() {
+        let root = tempfile::tempdir().expect("temp dir");
+        let worktree = root.path().join("worktree");
+        std::fs::create_dir_all(worktree.join("src")).expect("create worktree");
+        std::fs::write(worktree.join("src/lib.rs"), "fn main() {}").expect("write file");
+        std::fs::write(root.path().join("secret.txt"), "secret").expect("write secret");
+
+        let resolved = resolve_worktree_file(&worktree, "src/../src/lib.rs").expect("inside");
+        assert!(resolved.ends_with("src/lib.rs"));
+
+        let escaped = resolve_worktree_file(&worktree, "../secret.txt").expect_err("escape");
+        assert_eq!(escaped.status, 400);
+        let absolute = resolve_worktree_file(&worktree, "/etc/passwd").expect_err("absolute");
+        assert_eq!(absolute.status, 400);
+        let missing = resolve_worktree_file(&worktree, "src/missing.rs").expect_err("missing");
+        assert_eq!(missing.status, 404);
+    }
+
+    #[test]
+    fn subdir_rejects_traversal() {
+        for subdir in ["../outside", "packages/../../outside", "/etc"] {
+            let err = validate_subdir(subdir).expect_err(subdir);
+            assert_eq!(err.status, 400);
+        }
+        assert_eq!(
+            validate_subdir("./packages/api/").unwrap(),
+            PathBuf::from("packages/api")
+        );
+        assert_eq!(validate_subdir(".").unwrap(), PathBuf::new());
+
+        let root = tempfile::tempdir().expect("temp dir");
+        let worktree = root.path().join("worktree");
+        std::fs::create_dir_all(worktree.join("packages/api")).expect("create subdir");
+        std::fs::write(worktree.join("README.md"), "readme").expect("write file");
+        let resolved =
+            resolve_working_subdir(&worktree, Path::new("packages/api")).expect("inside");
+        assert!(resolved.ends_with("packages/api"));
+        let not_dir = resolve_working_subdir(&worktree, Path::new("README.md")).expect_err("file");
+        assert_eq!(not_dir.status, 400);
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.path(), worktree.join("escape")).expect("symlink");
+            let escaped =
+                resolve_working_subdir(&worktree, Path::new("escape")).expect_err("escape");
+            assert_eq!(escaped.status, 400);
+        }
+    }
+
+    #[test]
+    fn parse_worktree_list_reads_porcelain_paths() {
+        let stdout = "worktree /repo/main
+HEAD 1111111111111111111111111111111111111111
+branch refs/heads/main
+
+worktree /worktrees/fix-login
+HEAD 2222222222222222222222222222222222222222
+branch refs/heads/fix-login
+";
+
+        assert_eq!(
+            parse_worktree_list(stdout),
+            vec![
+                std::path::PathBuf::from("/repo/main"),
+                std::path::PathBuf::from("/worktrees/fix-login"),
+            ]
+        );
+    }
+
+    async fn git(dir: &Path, args: &[&str]) {
+        let status = tokio::process::Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .status()
+            .await
+            .expect("git");
+        assert!(status.success(), "git {:?}", args);
+    }
+
+    /// State with one environment `env` backed by a repo holding a single commit.
+    async fn state_with_committed_repo(temp: &tempfile::TempDir) -> (AppState, PathBuf) {
+        let env_dir = temp.path().join("env");
+        fs::create_dir_all(&env_dir).await.expect("env dir");
+        git(&env_dir, &["init"]).await;
+        git(&env_dir, &["commit", "--allow-empty", "-m", "init"]).await;
+
+        let config = EnvironmentConfig {
+            environments_root: temp.path().join("environments"),
+            worktrees_directory: temp.path().join("worktrees"),
+            environments: vec![Environment {
+                name: "env".to_string(),
+                directory: env_dir.clone(),
+            }],
+        };
+        let state = AppState::new(config, None, 10, 100, "model".to_string())
+            .await
+            .expect("state");
+        (state, env_dir)
+    }
+
+    /// Where runs log the task's output: the environment's state directory, not the repo.
+    async fn task_log_path(state: &AppState, id: TaskId) -> PathBuf {
+        let env_state_dir = state
+            .get_environment_directory("env")
+            .await
+            .expect("env state dir");
+        task_output_path(&env_state_dir, id)
+    }
+
+    const MOCK_SESSION_STARTED: &str =
+        r#"{"type":"session.started","session_id":"0b6f6d7e-4f8a-4c52-9c1e-2f0f5a1d3b21"}"#;
+
+    /// A mock script whose run parks on an approval request until it is answered.
+    const PARKED_MOCK_SCRIPT: [&str; 2] = [
+        MOCK_SESSION_STARTED,
+        r#"{"type":"approval.requested","call_id":"call_1","tool":"shell"}"#,
+    ];
+
+    /// Writes the mock agent's script into `dir`, one JSON event per line.
+    async fn write_mock_script(dir: &Path, lines: &[&str]) {
+        let mut script = lines.join("\n");
+        script.push('\n');
+        fs::write(dir.join("mock-agent.jsonl"), script)
+            .await
+            .expect("write script");
+    }
+
+    /// Waits until the task's spawned run has started.
+    async fn wait_until_running(state: &AppState, id: TaskId) {
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while !state.get_task(id).await.expect("task").is_running() {
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("run started");
+    }
+
+    /// An idle in-place task, inserted without starting an agent run.
+    async fn in_place_task(state: &AppState, env_dir: &Path) -> TaskId {
+        let task = Task::new(
+            AgentKind::Mock,
+            "env".to_string(),
+            "terminal".to_string(),
+            TaskWorkspaceKind::Environment,
+            None,
+            None,
+            false,
+            env_dir.to_path_buf(),
+        );
+        let task_id = task.id;
+        state.insert_task(task).await.expect("insert");
+        task_id
+    }
+
+    fn terminal_size() -> portable_pty::PtySize {
+        portable_pty::PtySize {
+            rows: 24,
+            cols: 80,
+            pixel_width: 0,
+            pixel_height: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn idle_terminal_is_closed_while_active_one_stays_open() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        state
+            .set_terminal_idle_timeout(Some(std::time::Duration::from_millis(300)))
+            .await;
+        let task_id = in_place_task(&state, &env_dir).await;
+
+        let (out_tx, mut out_rx) = tokio::sync::mpsc::unbounded_channel();
+        let manager = TerminalManager::new(out_tx);
+        let idle = uuid::Uuid::new_v4();
+        let active = uuid::Uuid::new_v4();
+        manager
+            .open(state.clone(), idle, task_id, terminal_size())
+            .await;
+        manager
+            .open(state.clone(), active, task_id, terminal_size())
+            .await;
+
+        // Keep typing into `active` until `idle` is reported closed.
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                manager.input(active, b"\n".to_vec()).await;
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                while let Ok(envelope) = out_rx.try_recv() {
+                    if let AgentEnvelope::TerminalClosed { terminal_id } = envelope {
+                        assert_eq!(terminal_id, idle, "active terminal was closed");
+                        return;
+                    }
+                }
+            }
+        })
+        .await
+        .expect("idle terminal closed");
+
+        assert!(!manager.sessions.lock().await.contains_key(&idle));
+        assert!(manager.sessions.lock().await.contains_key(&active));
+        manager.close(active).await;
+    }
+
+    #[tokio::test]
+    async fn terminal_init_command_output_reaches_the_stream() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        fs::write(
+            env_dir.join(".slopcoder.yaml"),
+            "terminal_shell: /bin/sh\nterminal_init_command: echo init-$((6 * 7))\n",
+        )
+        .await
+        .expect("write settings");
+        let task_id = in_place_task(&state, &env_dir).await;
+
+        let (out_tx, mut out_rx) = tokio::sync::mpsc::unbounded_channel();
+        let manager = TerminalManager::new(out_tx);
+        let terminal_id = uuid::Uuid::new_v4();
+        manager
+            .open(state, terminal_id, task_id, terminal_size())
+            .await;
+
+        // The echoed input shows `$((6 * 7))`; only the shell running it prints `init-42`.
+        let mut output = String::new();
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while !output.contains("init-42") {
+                match out_rx.recv().await.expect("channel open") {
+                    AgentEnvelope::TerminalData { data, .. } => {
+                        output.push_str(&String::from_utf8_lossy(&data));
+                    }
+                    AgentEnvelope::TerminalError { error, .. } => panic!("{}", error),
+                    _ => {}
+                }
+            }
+        })
+        .await
+        .expect("init output");
+        manager.close(terminal_id).await;
+    }
+
+    #[tokio::test]
+    async fn create_environment_clones_from_a_bare_repo() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, _env_dir) = state_with_committed_repo(&temp).await;
+        let seed = temp.path().join("seed");
+        fs::create_dir_all(&seed).await.expect("seed dir");
+        git(&seed, &["init"]).await;
+        fs::write(seed.join("README.md"), "seeded\n")
+            .await
+            .expect("write readme");
+        git(&seed, &["add", "README.md"]).await;
+        git(&seed, &["commit", "-m", "seed"]).await;
+        git(temp.path(), &["clone", "--bare", "seed", "remote.git"]).await;
+        let url = format!("file://{}", temp.path().join("remote.git").display());
+
+        let AgentResponse::Environment { environment } =
+            create_environment(state.clone(), "cloned", Some(&url))
+                .await
+                .expect("clone environment")
+        else {
+            panic!("unexpected response");
+        };
+        assert_eq!(
+            fs::read_to_string(environment.directory.join("README.md"))
+                .await
+                .expect("cloned readme"),
+            "seeded\n"
+        );
+        assert!(state.find_environment(&environment.name).await.is_some());
+
+        let error = create_environment(state.clone(), "bad", Some("ext::sh -c touch% /tmp/x"))
+            .await
+            .expect_err("ext transport");
+        assert_eq!(error.status, 400);
+        let missing = format!("file://{}", temp.path().join("missing.git").display());
+        let error = create_environment(state.clone(), "missing", Some(&missing))
+            .await
+            .expect_err("missing remote");
+        assert_eq!(error.status, 502);
+        assert!(!temp.path().join("environments").join("missing").exists());
+    }
+
+    fn worktree_request(name: &str) -> AgentCreateTaskRequest {
+        AgentCreateTaskRequest {
+            environment: "env".to_string(),
+            name: Some(name.to_string()),
+            use_worktree: Some(true),
+            web_search: false,
+            prompt: "do things".to_string(),
+            agent: Some(AgentKind::Codex),
+            model: None,
+            subdir: None,
+            base_branch: None,
+            existing_branch: None,
+            tags: Vec::new(),
+            priority: 0,
+            dry_run: false,
+            attachments: Vec::new(),
+            depends_on: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn post_run_command_output_is_logged_without_failing_task() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        write_mock_script(
+            &env_dir,
+            &[
+                MOCK_SESSION_STARTED,
+                r#"{"type":"item.completed","item":{"id":"m1","type":"agent_message","text":"done"}}"#,
+            ],
+        )
+        .await;
+        fs::write(
+            env_dir.join(".slopcoder.yaml"),
+            "post_run_command: echo formatted; exit 2\n",
+        )
+        .await
+        .expect("write settings");
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let request = AgentCreateTaskRequest {
+            use_worktree: Some(false),
+            agent: Some(AgentKind::Mock),
+            ..worktree_request("Format")
+        };
+        let AgentResponse::CreatedTask { id, .. } = create_task(state.clone(), request, out_tx)
+            .await
+            .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+
+        let output_path = task_log_path(&state, id).await;
+        let mut post_run = None;
+        for _ in 0..100 {
+            let events = read_all_output_events(&output_path)
+                .await
+                .unwrap_or_default();
+            post_run = events.into_iter().find_map(|event| match event {
+                AgentEvent::ItemCompleted { item }
+                    if item.extra["source"] == "post_run_command" =>
+                {
+                    Some(item)
+                }
+                _ => None,
+            });
+            if post_run.is_some() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+
+        let item = post_run.expect("post-run item logged");
+        assert_eq!(item.item_type, "command_execution");
+        assert_eq!(item.extra["exit_code"], 2);
+        assert_eq!(item.output.as_deref(), Some("formatted\n"));
+        let task = wait_until_stopped(&state, id, 1).await;
+        assert_eq!(task.status, slopcoder_core::task::TaskStatus::Completed);
+    }
+
+    #[tokio::test]
+    async fn secrets_are_redacted_from_logged_and_broadcast_output() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        write_mock_script(
+            &env_dir,
+            &[
+                MOCK_SESSION_STARTED,
+                r#"{"type":"item.completed","item":{"id":"o1","type":"tool_output","output":"API_TOKEN=tok-injected-value\nkey sk-live0123456789abcdef"}}"#,
+            ],
+        )
+        .await;
+        fs::write(
+            env_dir.join(".slopcoder.yaml"),
+            "env:\n  API_TOKEN: tok-injected-value\nredact_patterns:\n  - 'sk-live[0-9a-f]+'\n",
+        )
+        .await
+        .expect("write settings");
+        let (out_tx, mut out_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let request = AgentCreateTaskRequest {
+            use_worktree: Some(false),
+            agent: Some(AgentKind::Mock),
+            ..worktree_request("Redact")
+        };
+        let AgentResponse::CreatedTask { id, .. } = create_task(state.clone(), request, out_tx)
+            .await
+            .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+
+        let expected = "API_TOKEN=***\nkey ***";
+        let broadcast = loop {
+            let envelope = tokio::time::timeout(std::time::Duration::from_secs(5), out_rx.recv())
+                .await
+                .expect("event broadcast")
+                .expect("channel open");
+            if let AgentEnvelope::TaskEvent {
+                event: AgentEvent::ItemCompleted { item },
+                ..
+            } = envelope
+            {
+                break item;
+            }
+        };
+        assert_eq!(broadcast.output.as_deref(), Some(expected));
+
+        wait_until_stopped(&state, id, 1).await;
+        let logged = read_all_output_events(&task_log_path(&state, id).await)
+            .await
+            .expect("events")
+            .into_iter()
+            .find_map(|event| match event {
+                AgentEvent::ItemCompleted { item } => Some(item),
+                _ => None,
+            })
+            .expect("logged item");
+        assert_eq!(logged.output.as_deref(), Some(expected));
+    }
+
+    #[tokio::test]
+    async fn tool_output_carries_duration_since_its_call() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        write_mock_script(
+            &env_dir,
+            &[
+                MOCK_SESSION_STARTED,
+                r#"{"type":"item.completed","item":{"id":"c1","type":"tool_call","name":"shell","call_id":"call_1"}}"#,
+                r#"{"type":"item.completed","item":{"id":"o1","type":"tool_output","call_id":"call_1","output":"ok"}}"#,
+                r#"{"type":"item.completed","item":{"id":"o2","type":"tool_output","call_id":"call_2","output":"orphan"}}"#,
+            ],
+        )
+        .await;
+        let (out_tx, mut out_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let request = AgentCreateTaskRequest {
+            use_worktree: Some(false),
+            agent: Some(AgentKind::Mock),
+            ..worktree_request("Timing")
+        };
+        let AgentResponse::CreatedTask { id, .. } = create_task(state.clone(), request, out_tx)
+            .await
+            .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+
+        let mut broadcast = Vec::new();
+        while broadcast.len() < 3 {
+            let envelope = tokio::time::timeout(std::time::Duration::from_secs(5), out_rx.recv())
+                .await
+                .expect("event broadcast")
+                .expect("channel open");
+            if let AgentEnvelope::TaskEvent {
+                event: AgentEvent::ItemCompleted { item },
+                ..
+            } = envelope
+            {
+                broadcast.push(item);
+            }
+        }
+        assert_eq!(broadcast[0].duration_ms, None);
+        assert!(broadcast[1].duration_ms.is_some());
+        assert_eq!(broadcast[2].duration_ms, None);
+
+        wait_until_stopped(&state, id, 1).await;
+        let logged: Vec<_> = read_all_output_events(&task_log_path(&state, id).await)
+            .await
+            .expect("events")
+            .into_iter()
+            .filter_map(|event| match event {
+                AgentEvent::ItemCompleted { item } => Some(item.duration_ms),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(logged, vec![None, broadcast[1].duration_ms, None]);
+    }
+
+    #[tokio::test]
+    async fn file_edit_tool_calls_emit_file_edited_after_the_call() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        write_mock_script(
+            &env_dir,
+            &[
+                MOCK_SESSION_STARTED,
+                r#"{"type":"item.completed","item":{"id":"c1","type":"tool_call","name":"Edit","call_id":"call_1","arguments":"{\"file_path\":\"README.md\",\"old_string\":\"a\",\"new_string\":\"b\\nc\"}"}}"#,
+                r#"{"type":"item.completed","item":{"id":"c2","type":"tool_call","name":"Read","call_id":"call_2","arguments":"{\"file_path\":\"README.md\"}"}}"#,
+            ],
+        )
+        .await;
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let request = AgentCreateTaskRequest {
+            use_worktree: Some(false),
+            agent: Some(AgentKind::Mock),
+            ..worktree_request("Edits")
+        };
+        let AgentResponse::CreatedTask { id, .. } = create_task(state.clone(), request, out_tx)
+            .await
+            .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+        wait_until_stopped(&state, id, 1).await;
+
+        let logged: Vec<String> = read_all_output_events(&task_log_path(&state, id).await)
+            .await
+            .expect("events")
+            .into_iter()
+            .filter_map(|event| match event {
+                AgentEvent::ItemCompleted { item } => item.name,
+                AgentEvent::FileEdited {
+                    path,
+                    added,
+                    removed,
+                } => Some(format!("{} +{} -{}", path, added, removed)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(logged, vec!["Edit", "README.md +2 -1", "Read"]);
+    }
+
+    #[tokio::test]
+    async fn diff_stream_waits_for_the_outbound_writer() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        fs::write(env_dir.join("big.txt"), "line\n".repeat(1000))
+            .await
+            .unwrap();
+        let slots = state.diff_chunk_slots().await;
+        let out_tx = state.outbound_sender().await;
+        let stream = tokio::spawn({
+            let slots = slots.clone();
+            async move {
+                stream_git_diff(&env_dir, None, uuid::Uuid::new_v4(), &out_tx, &slots, 64).await
+            }
+        });
+
+        // With nothing sending, the stream stops once every slot holds a queued chunk.
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while slots.available_permits() > 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("slots used up");
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(!stream.is_finished());
+
+        let (sink, _sent) = futures::channel::mpsc::unbounded();
+        let writer = tokio::spawn(write_outbound(state.outbound_receiver().await, sink));
+        tokio::time::timeout(std::time::Duration::from_secs(5), stream)
+            .await
+            .expect("stream finished")
+            .expect("join")
+            .expect("stream diff");
+        writer.abort();
+    }
+
+    #[tokio::test]
+    async fn streamed_diff_matches_one_shot_diff_in_capped_chunks() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let repo = temp.path();
+        git(repo, &["init"]).await;
+        fs::write(repo.join("staged.txt"), "before\n")
+            .await
+            .unwrap();
+        fs::write(repo.join("tracked.txt"), "one\n").await.unwrap();
+        git(repo, &["add", "."]).await;
+        git(repo, &["commit", "-m", "init"]).await;
+        fs::write(repo.join("staged.txt"), "naïve café ünïcödé ".repeat(20))
+            .await
+            .unwrap();
+        git(repo, &["add", "staged.txt"]).await;
+        fs::write(repo.join("tracked.txt"), "one\ntwo\n")
+            .await
+            .unwrap();
+        fs::write(repo.join("new.txt"), "brand new\n")
+            .await
+            .unwrap();
+
+        let (out_tx, mut out_rx) = tokio::sync::mpsc::unbounded_channel();
+        let stream_id = uuid::Uuid::new_v4();
+        let slots = tokio::sync::Semaphore::new(tokio::sync::Semaphore::MAX_PERMITS);
+        stream_git_diff(repo, None, stream_id, &out_tx, &slots, 64)
+            .await
+            .expect("stream diff");
+        drop(out_tx);
+
+        let (mut staged, mut unstaged) = (String::new(), String::new());
+        while let Some(envelope) = out_rx.recv().await {
+            let AgentEnvelope::DiffChunk {
+                stream_id: id,
+                section,
+                data,
+            } = envelope
+            else {
+                panic!("unexpected envelope");
+            };
+            assert_eq!(id, stream_id);
+            assert!(!data.is_empty() && data.len() <= 64);
+            match section {
+                DiffSection::Staged => staged.push_str(&data),
+                DiffSection::Unstaged => unstaged.push_str(&data),
+            }
+        }
+
+        let expected = load_git_diff(repo, None).await.expect("diff");
+        assert_eq!(staged, expected.staged);
+        assert_eq!(unstaged, expected.unstaged);
+        assert!(unstaged.contains("brand new"));
+    }
+
+    #[tokio::test]
+    async fn prompt_attachments_are_written_to_workspace_and_validated() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        write_mock_script(&env_dir, &PARKED_MOCK_SCRIPT).await;
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+        let png = Attachment {
+            filename: "screenshot.png".to_string(),
+            mime: "image/png".to_string(),
+            // "\x89PNG\r\n"
+            base64: "iVBORw0K".to_string(),
+        };
+
+        let request = AgentCreateTaskRequest {
+            use_worktree: Some(false),
+            agent: Some(AgentKind::Mock),
+            attachments: vec![png.clone()],
+            ..worktree_request("Attach")
+        };
+        let AgentResponse::CreatedTask { id, .. } =
+            create_task(state.clone(), request, out_tx.clone())
+                .await
+                .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+        wait_until_running(&state, id).await;
+        let root = env_dir.join(".slopcoder-attachments");
+        let mut runs = fs::read_dir(&root).await.expect("attachments dir");
+        let mut written = None;
+        while let Some(entry) = runs.next_entry().await.unwrap() {
+            if entry.path().is_dir() {
+                written = Some(entry.path().join("screenshot.png"));
+            }
+        }
+        let written = written.expect("run directory");
+        assert_eq!(fs::read(&written).await.unwrap(), b"\x89PNG\r\n");
+        let status = tokio::process::Command::new("git")
+            .args(["status", "--porcelain", "--untracked-files=all"])
+            .current_dir(&env_dir)
+            .output()
+            .await
+            .expect("git status");
+        assert!(!String::from_utf8_lossy(&status.stdout).contains(".slopcoder-attachments"));
+
+        // The run's directory goes away once the run is over.
+        respond_approval(state.clone(), id, "call_1".to_string(), true)
+            .await
+            .expect("approve");
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while written.exists() {
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("attachments removed");
+        assert!(!written.parent().unwrap().exists());
+
+        for (agent, attachment, status) in [
+            (AgentKind::Cursor, png.clone(), 400),
+            (
+                AgentKind::Mock,
+                Attachment {
+                    mime: "application/x-sh".to_string(),
+                    ..png.clone()
+                },
+                400,
+            ),
+            (
+                AgentKind::Mock,
+                Attachment {
+                    filename: "../escape.png".to_string(),
+                    ..png.clone()
+                },
+                400,
+            ),
+        ] {
+            let request = AgentCreateTaskRequest {
+                use_worktree: Some(false),
+                agent: Some(agent),
+                attachments: vec![attachment],
+                ..worktree_request("Rejected")
+            };
+            let err = create_task(state.clone(), request, out_tx.clone())
+                .await
+                .expect_err("invalid attachment");
+            assert_eq!(err.status, status);
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_file_diff_is_truncated_with_marker() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let repo = temp.path();
+        git(repo, &["init"]).await;
+        git(repo, &["commit", "--allow-empty", "-m", "init"]).await;
+        let lockfile = "generated-line-0123456789\n".repeat(4000);
+        fs::write(repo.join("Cargo.lock"), &lockfile).await.unwrap();
+        fs::write(repo.join("small.txt"), "small change\n")
+            .await
+            .unwrap();
+
+        let diff = load_git_diff(repo, None).await.expect("diff");
+        let (unstaged, truncated) = truncate_file_diffs(&diff.unstaged, 4096);
+
+        // Untracked files are listed in path order, so the lockfile comes first.
+        let lock_bytes = diff.unstaged.find("\ndiff --git a/small.txt").unwrap() + 1;
+        let marker = unstaged.find("... [diff truncated, ").expect("marker");
+        assert!(truncated);
+        assert!(marker <= 4096);
+        assert!(unstaged[marker..].starts_with(&format!(
+            "... [diff truncated, {} more bytes]\n",
+            lock_bytes - marker
+        )));
+        assert!(unstaged.contains("+small change\n"));
+
+        let (untouched, truncated) = truncate_file_diffs(&diff.unstaged, usize::MAX);
+        assert!(!truncated);
+        assert_eq!(untouched, diff.unstaged);
+    }
+
+    #[tokio::test]
+    async fn batch_request_round_trips_each_result_in_order() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, _env_dir) = state_with_committed_repo(&temp).await;
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let batch = AgentRequest::Batch {
+            requests: vec![
+                AgentRequest::ListEnvironments,
+                AgentRequest::GetTaskDiff {
+                    task_id: slopcoder_core::task::TaskId::new(),
+                    max_diff_bytes: None,
+                },
+            ],
+        };
+        let wire = serde_json::to_string(&batch).expect("serialize request");
+        let request: AgentRequest = serde_json::from_str(&wire).expect("parse request");
+        let response = handle_request(state, request, out_tx)
+            .await
+            .expect("batch response");
+        let wire = serde_json::to_string(&response).expect("serialize response");
+        let AgentResponse::Batch { results } = serde_json::from_str(&wire).expect("parse response")
+        else {
+            panic!("unexpected response");
+        };
+
+        assert_eq!(results.len(), 2);
+        match &results[0] {
+            Ok(AgentResponse::Environments { environments }) => {
+                assert!(environments.iter().any(|env| env.name == "env"));
+            }
+            other => panic!("unexpected first result: {:?}", other),
+        }
+        let err = results[1].as_ref().expect_err("missing task");
+        assert_eq!(err.status, 404);
+    }
+
+    #[tokio::test]
+    async fn approval_response_resumes_paused_run() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        write_mock_script(
+            &env_dir,
+            &[
+                MOCK_SESSION_STARTED,
+                r#"{"type":"approval.requested","call_id":"call_1","tool":"shell","arguments":"{\"command\":\"rm -rf build\"}"}"#,
+                r#"{"type":"item.completed","item":{"id":"m1","type":"agent_message","text":"done"}}"#,
+            ],
+        )
+        .await;
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let request = AgentCreateTaskRequest {
+            use_worktree: Some(false),
+            agent: Some(AgentKind::Mock),
+            ..worktree_request("Approve")
+        };
+        let AgentResponse::CreatedTask { id, .. } = create_task(state.clone(), request, out_tx)
+            .await
+            .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+        let output_path = task_log_path(&state, id).await;
+
+        let mut requested = false;
+        for _ in 0..100 {
+            let events = read_all_output_events(&output_path)
+                .await
+                .unwrap_or_default();
+            requested = events
+                .iter()
+                .any(|event| matches!(event, AgentEvent::ApprovalRequested { .. }));
+            if requested {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        assert!(requested, "approval request logged");
+        assert!(state.get_task(id).await.expect("task").is_running());
+
+        let response = respond_approval(state.clone(), id, "call_1".to_string(), true)
+            .await
+            .expect("respond");
+        assert!(matches!(
+            response,
+            AgentResponse::ApprovalResult { status } if status == "delivered"
+        ));
+
+        let task = wait_until_stopped(&state, id, 1).await;
+        assert_eq!(task.status, slopcoder_core::task::TaskStatus::Completed);
+        let items: Vec<String> = read_all_output_events(&output_path)
+            .await
+            .expect("events")
+            .into_iter()
+            .filter_map(|event| match event {
+                AgentEvent::ItemCompleted { item } => Some(item.item_type),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(items, vec!["approval_response", "agent_message"]);
+
+        let err = respond_approval(state, id, "call_1".to_string(), true)
+            .await
+            .expect_err("task no longer running");
+        assert_eq!(err.status, 409);
+    }
+
+    #[tokio::test]
+    async fn heartbeats_are_broadcast_while_the_run_is_quiet() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        state
+            .set_run_heartbeat(Some(std::time::Duration::from_secs(1)))
+            .await;
+        write_mock_script(&env_dir, &PARKED_MOCK_SCRIPT).await;
+        let (out_tx, mut out_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let request = AgentCreateTaskRequest {
+            use_worktree: Some(false),
+            agent: Some(AgentKind::Mock),
+            ..worktree_request("Quiet")
+        };
+        let AgentResponse::CreatedTask { id, .. } = create_task(state.clone(), request, out_tx)
+            .await
+            .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+
+        // The mock now waits on the approval, so nothing but heartbeats should arrive.
+        let mut heartbeats = Vec::new();
+        while heartbeats.len() < 2 {
+            let envelope = tokio::time::timeout(std::time::Duration::from_secs(5), out_rx.recv())
+                .await
+                .expect("heartbeat broadcast")
+                .expect("channel open");
+            if let AgentEnvelope::TaskEvent {
+                event: AgentEvent::Heartbeat { elapsed_secs },
+                ..
+            } = envelope
+            {
+                heartbeats.push(elapsed_secs);
+            }
+        }
+        assert!(heartbeats[0] >= 1);
+        assert!(heartbeats[1] > heartbeats[0]);
+
+        respond_approval(state.clone(), id, "call_1".to_string(), true)
+            .await
+            .expect("respond");
+        wait_until_stopped(&state, id, 1).await;
+        let logged = read_all_output_events(&task_log_path(&state, id).await)
+            .await
+            .expect("events");
+        assert!(!logged
+            .iter()
+            .any(|event| matches!(event, AgentEvent::Heartbeat { .. })));
+    }
+
+    #[tokio::test]
+    async fn dependent_task_starts_only_after_its_dependency_completes() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        write_mock_script(&env_dir, &PARKED_MOCK_SCRIPT).await;
+        let (out_tx, mut out_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let in_place = |name: &str, depends_on: Option<TaskId>| AgentCreateTaskRequest {
+            use_worktree: Some(false),
+            agent: Some(AgentKind::Mock),
+            depends_on,
+            ..worktree_request(name)
+        };
+        let AgentResponse::CreatedTask { id: first, .. } =
+            create_task(state.clone(), in_place("First", None), out_tx.clone())
+                .await
+                .expect("create first")
+        else {
+            panic!("unexpected response");
+        };
+        let AgentResponse::CreatedTask { id: second, .. } = create_task(
+            state.clone(),
+            in_place("Second", Some(first)),
+            out_tx.clone(),
+        )
+        .await
+        .expect("create second") else {
+            panic!("unexpected response");
+        };
+        let err = create_task(
+            state.clone(),
+            in_place("Orphan", Some(TaskId::new())),
+            out_tx,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status, 404);
+
+        // The first task now waits on its approval; the second must not have started.
+        loop {
+            let envelope = tokio::time::timeout(std::time::Duration::from_secs(5), out_rx.recv())
+                .await
+                .expect("approval request")
+                .expect("channel open");
+            if let AgentEnvelope::TaskEvent {
+                task_id,
+                event: AgentEvent::ApprovalRequested { .. },
+            } = envelope
+            {
+                assert_eq!(task_id, first);
+                break;
+            }
+        }
+        let waiting = state.get_task(second).await.expect("second task");
+        assert_eq!(waiting.status, TaskStatus::Pending);
+        assert!(waiting.history.is_empty());
+        assert_eq!(waiting.queued_prompts, vec!["do things"]);
+
+        respond_approval(state.clone(), first, "call_1".to_string(), true)
+            .await
+            .expect("respond");
+        wait_until_running(&state, second).await;
+        assert_eq!(
+            state.get_task(first).await.expect("first task").status,
+            TaskStatus::Completed
+        );
+        assert!(state.queued_prompts(second).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn interrupt_all_interrupts_every_running_task() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        write_mock_script(&env_dir, &PARKED_MOCK_SCRIPT).await;
+        // Only one in-place run is allowed per environment, so these run in worktrees,
+        // which get the script from the commit.
+        git(&env_dir, &["add", "mock-agent.jsonl"]).await;
+        git(&env_dir, &["commit", "-m", "mock script"]).await;
+        let (out_tx, mut out_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let mut ids = Vec::new();
+        for name in ["First", "Second", "Third"] {
+            let request = AgentCreateTaskRequest {
+                agent: Some(AgentKind::Mock),
+                ..worktree_request(name)
+            };
+            let AgentResponse::CreatedTask { id, .. } =
+                create_task(state.clone(), request, out_tx.clone())
+                    .await
+                    .expect("create")
+            else {
+                panic!("unexpected response");
+            };
+            ids.push(id);
+        }
+
+        // Every run is parked on its approval before the interrupt goes out.
+        let mut waiting = Vec::new();
+        while waiting.len() < ids.len() {
+            let envelope = tokio::time::timeout(std::time::Duration::from_secs(5), out_rx.recv())
+                .await
+                .expect("approval request")
+                .expect("channel open");
+            if let AgentEnvelope::TaskEvent {
+                task_id,
+                event: AgentEvent::ApprovalRequested { .. },
+            } = envelope
+            {
+                waiting.push(task_id);
+            }
+        }
+
+        let response = handle_request(state.clone(), AgentRequest::InterruptAll, out_tx)
+            .await
+            .expect("interrupt all");
+        assert!(matches!(
+            response,
+            AgentResponse::InterruptedAll { interrupted: 3 }
+        ));
+        for id in ids {
+            let task = wait_until_stopped(&state, id, 1).await;
+            assert_eq!(task.status, TaskStatus::Interrupted);
+        }
+    }
+
+    #[tokio::test]
+    async fn coordinator_shutdown_interrupts_runs_left_after_the_grace_period() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        assert_eq!(
+            wind_down_runs(state.clone(), std::time::Duration::from_secs(5)).await,
+            0
+        );
+
+        let id = parked_mock_task(&state, &env_dir).await;
+        wait_until_running(&state, id).await;
+        assert_eq!(
+            wind_down_runs(state.clone(), std::time::Duration::from_millis(300)).await,
+            1
+        );
+        let task = wait_until_stopped(&state, id, 1).await;
+        assert_eq!(task.status, TaskStatus::Interrupted);
+
+        let config = EnvironmentConfig {
+            environments_root: temp.path().join("environments"),
+            worktrees_directory: temp.path().join("worktrees"),
+            environments: vec![Environment {
+                name: "env".to_string(),
+                directory: env_dir,
+            }],
+        };
+        let reloaded = AppState::new(config, None, 10, 100, "model".to_string())
+            .await
+            .expect("reload state");
+        assert_eq!(
+            reloaded.get_task(id).await.expect("task").status,
+            TaskStatus::Interrupted
+        );
+    }
+
+    #[tokio::test]
+    async fn second_in_place_run_in_an_environment_is_refused() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        let idle = in_place_task(&state, &env_dir).await;
+        let running = parked_mock_task(&state, &env_dir).await;
+        wait_until_running(&state, running).await;
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let request = AgentCreateTaskRequest {
+            use_worktree: Some(false),
+            agent: Some(AgentKind::Mock),
+            ..worktree_request("Second")
+        };
+        let err = create_task(state.clone(), request, out_tx.clone())
+            .await
+            .expect_err("busy environment");
+        assert_eq!(err.status, 409);
+        assert!(err.error.contains("running in-place task"), "{}", err.error);
+        let err = send_prompt(
+            state.clone(),
+            idle,
+            "go".to_string(),
+            Vec::new(),
+            false,
+            out_tx.clone(),
+        )
+        .await
+        .expect_err("busy environment");
+        assert_eq!(err.status, 409);
+        assert!(matches!(
+            state.start_task_run(idle, "go".to_string()).await,
+            Err(StateError::EnvironmentBusy(_))
+        ));
+
+        // Worktree tasks don't share the checkout and start as usual.
+        let request = AgentCreateTaskRequest {
+            agent: Some(AgentKind::Mock),
+            ..worktree_request("Isolated")
+        };
+        create_task(state.clone(), request, out_tx.clone())
+            .await
+            .expect("worktree task");
+
+        interrupt_task(state.clone(), running)
+            .await
+            .expect("interrupt");
+        wait_until_stopped(&state, running, 1).await;
+        state
+            .start_task_run(idle, "go".to_string())
+            .await
+            .expect("environment free again");
+    }
+
+    /// Creates an in-place mock task whose run parks on an approval request.
+    async fn parked_mock_task(state: &AppState, env_dir: &Path) -> TaskId {
+        write_mock_script(env_dir, &PARKED_MOCK_SCRIPT).await;
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+        let request = AgentCreateTaskRequest {
+            use_worktree: Some(false),
+            agent: Some(AgentKind::Mock),
+            ..worktree_request("Parked")
+        };
+        let AgentResponse::CreatedTask { id, .. } = create_task(state.clone(), request, out_tx)
+            .await
+            .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+        id
+    }
+
+    /// Waits until the task has `runs` prompt runs and none is in progress. Runs are spawned,
+    /// so right after `create_task` or `send_prompt` the new run may not have started yet.
+    async fn wait_until_stopped(state: &AppState, id: TaskId, runs: usize) -> Task {
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                let task = state.get_task(id).await.expect("task");
+                if task.history.len() >= runs && !task.is_running() {
+                    return task;
                 }
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
             }
-            _ = &mut interrupt_rx => {
-                interrupted = true;
-                if let Err(e) = agent.kill().await {
-                    tracing::warn!("Failed to kill agent for task {}: {}", task_id, e);
+        })
+        .await
+        .expect("run stopped")
+    }
+
+    #[tokio::test]
+    async fn run_past_its_timeout_is_recorded_as_timed_out() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        state
+            .set_run_timeout(Some(std::time::Duration::from_millis(300)))
+            .await;
+        let id = parked_mock_task(&state, &env_dir).await;
+
+        let task = wait_until_stopped(&state, id, 1).await;
+        assert_eq!(task.status, TaskStatus::Failed);
+        let run = task.history.last().expect("run");
+        assert_eq!(run.outcome, Some(TaskOutcome::TimedOut));
+        assert_eq!(run.success, Some(false));
+
+        let events = read_all_output_events(&task_log_path(&state, id).await)
+            .await
+            .expect("output log");
+        assert!(events.iter().any(|event| matches!(
+            event,
+            AgentEvent::Error { message } if message.contains("timed out")
+        )));
+    }
+
+    #[tokio::test]
+    async fn transient_spawn_failure_is_retried() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        write_mock_script(
+            &env_dir,
+            &[
+                MOCK_SESSION_STARTED,
+                r#"{"type":"item.completed","item":{"id":"msg_1","type":"agent_message","text":"done"}}"#,
+            ],
+        )
+        .await;
+        state
+            .set_spawn_retry(2, std::time::Duration::from_millis(10))
+            .await;
+        // The config clone shares the counter with the host's config.
+        let spawn_failures = state.get_agent_config().await.mock.spawn_failures;
+        spawn_failures.store(1, std::sync::atomic::Ordering::SeqCst);
+
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+        let request = AgentCreateTaskRequest {
+            use_worktree: Some(false),
+            agent: Some(AgentKind::Mock),
+            ..worktree_request("Flaky")
+        };
+        let AgentResponse::CreatedTask { id, .. } = create_task(state.clone(), request, out_tx)
+            .await
+            .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+
+        let task = wait_until_stopped(&state, id, 1).await;
+        assert_eq!(task.status, TaskStatus::Completed);
+        assert_eq!(spawn_failures.load(std::sync::atomic::Ordering::SeqCst), 0);
+        let events = read_all_output_events(&task_log_path(&state, id).await)
+            .await
+            .expect("output log");
+        let retries: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                AgentEvent::BackgroundEvent { event, extra }
+                    if event.as_deref() == Some("spawn_retry") =>
+                {
+                    Some(extra["attempt"].as_u64())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(retries, vec![Some(1)]);
+        assert!(events.iter().any(|event| matches!(
+            event,
+            AgentEvent::ItemCompleted { item } if item.text.as_deref() == Some("done")
+        )));
+    }
+
+    #[tokio::test]
+    async fn interrupted_run_is_recorded_as_interrupted() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        let id = parked_mock_task(&state, &env_dir).await;
+        wait_until_running(&state, id).await;
+
+        interrupt_task(state.clone(), id).await.expect("interrupt");
+
+        let task = wait_until_stopped(&state, id, 1).await;
+        assert_eq!(task.status, TaskStatus::Interrupted);
+        assert_eq!(
+            task.history.last().expect("run").outcome,
+            Some(TaskOutcome::Interrupted)
+        );
+    }
+
+    #[tokio::test]
+    async fn queued_prompts_can_be_cancelled_by_index() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        write_mock_script(&env_dir, &PARKED_MOCK_SCRIPT).await;
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let request = AgentCreateTaskRequest {
+            use_worktree: Some(false),
+            agent: Some(AgentKind::Mock),
+            ..worktree_request("Queue")
+        };
+        let AgentResponse::CreatedTask { id, .. } =
+            create_task(state.clone(), request, out_tx.clone())
+                .await
+                .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+        wait_until_running(&state, id).await;
+
+        for (n, prompt) in ["first", "second", "third"].into_iter().enumerate() {
+            let response = send_prompt(
+                state.clone(),
+                id,
+                prompt.to_string(),
+                Vec::new(),
+                true,
+                out_tx.clone(),
+            )
+            .await
+            .expect("queue prompt");
+            assert!(matches!(
+                response,
+                AgentResponse::QueuedPrompts { queued } if queued == n + 1
+            ));
+        }
+
+        let response = handle_request(
+            state.clone(),
+            AgentRequest::CancelQueuedPrompt {
+                task_id: id,
+                index: 1,
+            },
+            out_tx.clone(),
+        )
+        .await
+        .expect("cancel");
+        assert!(matches!(
+            response,
+            AgentResponse::QueuedPrompts { queued: 2 }
+        ));
+        assert_eq!(state.queued_prompts(id).await, vec!["first", "third"]);
+
+        let err = handle_request(
+            state.clone(),
+            AgentRequest::CancelQueuedPrompt {
+                task_id: id,
+                index: 5,
+            },
+            out_tx.clone(),
+        )
+        .await
+        .expect_err("index out of range");
+        assert_eq!(err.status, 404);
+
+        handle_request(
+            state.clone(),
+            AgentRequest::CancelAllQueued { task_id: id },
+            out_tx,
+        )
+        .await
+        .expect("cancel all");
+        assert!(state.queued_prompts(id).await.is_empty());
+    }
+
+    #[test]
+    fn find_task_stash_matches_only_own_entries() {
+        let task_id = slopcoder_core::task::TaskId::new();
+        let other = slopcoder_core::task::TaskId::new();
+        let list = format!(
+            "stash@{{0}}\x1fOn task/a: slopcoder task {}\nstash@{{1}}\x1fWIP on main: 1234567 init\nstash@{{2}}\x1fOn task/b: slopcoder task {}\n",
+            other, task_id
+        );
+        assert_eq!(
+            find_task_stash(&list, task_id).as_deref(),
+            Some("stash@{2}")
+        );
+        assert_eq!(find_task_stash("", task_id), None);
+    }
+
+    #[tokio::test]
+    async fn stash_and_unstash_round_trip_task_changes() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        // `git stash` records a commit, so it needs an identity.
+        git(&env_dir, &["config", "user.name", "Test"]).await;
+        git(&env_dir, &["config", "user.email", "test@example.com"]).await;
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+        let request = AgentCreateTaskRequest {
+            agent: Some(AgentKind::Mock),
+            ..worktree_request("Stash me")
+        };
+        let AgentResponse::CreatedTask { id, worktree_path } =
+            create_task(state.clone(), request, out_tx)
+                .await
+                .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+        // The mock has no script in the new worktree, so the run fails straight away.
+        wait_until_stopped(&state, id, 1).await;
+        let worktree = PathBuf::from(worktree_path);
+        fs::write(worktree.join("notes.txt"), "draft")
+            .await
+            .unwrap();
+
+        let stashed = stash_task_changes(state.clone(), id).await.expect("stash");
+        assert!(
+            matches!(stashed, AgentResponse::StashResult { ref status, .. } if status == "stashed")
+        );
+        assert!(!worktree.join("notes.txt").exists());
+        let nothing = stash_task_changes(state.clone(), id)
+            .await
+            .expect("clean stash");
+        assert!(
+            matches!(nothing, AgentResponse::StashResult { ref status, .. } if status == "nothing_to_stash")
+        );
+
+        unstash_task_changes(state.clone(), id)
+            .await
+            .expect("unstash");
+        assert_eq!(
+            fs::read_to_string(worktree.join("notes.txt"))
+                .await
+                .unwrap(),
+            "draft"
+        );
+        let err = unstash_task_changes(state, id)
+            .await
+            .expect_err("no stash left");
+        assert_eq!(err.status, 404);
+    }
+
+    #[tokio::test]
+    async fn move_task_worktree_relocates_and_persists() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+        let request = AgentCreateTaskRequest {
+            agent: Some(AgentKind::Mock),
+            ..worktree_request("Move me")
+        };
+        let AgentResponse::CreatedTask { id, worktree_path } =
+            create_task(state.clone(), request, out_tx)
+                .await
+                .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+        wait_until_stopped(&state, id, 1).await;
+        fs::write(PathBuf::from(&worktree_path).join("notes.txt"), "draft")
+            .await
+            .unwrap();
+
+        let taken = temp.path().join("taken");
+        fs::create_dir_all(&taken).await.unwrap();
+        let err = move_task_worktree(state.clone(), id, taken)
+            .await
+            .expect_err("destination exists");
+        assert_eq!(err.status, 409);
+
+        let new_path = temp.path().join("other-disk").join("moved");
+        let response = move_task_worktree(state.clone(), id, new_path.clone())
+            .await
+            .expect("move");
+        assert!(matches!(
+            response,
+            AgentResponse::WorktreeMoved { ref worktree_path } if *worktree_path == new_path.to_string_lossy()
+        ));
+        assert!(!Path::new(&worktree_path).exists());
+        assert_eq!(
+            fs::read_to_string(new_path.join("notes.txt"))
+                .await
+                .unwrap(),
+            "draft"
+        );
+
+        let config = EnvironmentConfig {
+            environments_root: temp.path().join("environments"),
+            worktrees_directory: temp.path().join("worktrees"),
+            environments: vec![Environment {
+                name: "env".to_string(),
+                directory: env_dir,
+            }],
+        };
+        let reloaded = AppState::new(config, None, 10, 100, "model".to_string())
+            .await
+            .expect("reload state");
+        assert_eq!(
+            reloaded.get_task(id).await.expect("task").worktree_path,
+            new_path
+        );
+    }
+
+    #[tokio::test]
+    async fn moving_a_worktree_closes_its_terminals_first() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, _env_dir) = state_with_committed_repo(&temp).await;
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+        let request = AgentCreateTaskRequest {
+            agent: Some(AgentKind::Mock),
+            ..worktree_request("Move me")
+        };
+        let AgentResponse::CreatedTask { id, .. } = create_task(state.clone(), request, out_tx)
+            .await
+            .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+        wait_until_stopped(&state, id, 1).await;
+
+        let (out_tx, mut out_rx) = tokio::sync::mpsc::unbounded_channel();
+        let manager = TerminalManager::new(out_tx.clone());
+        let terminal_id = uuid::Uuid::new_v4();
+        manager
+            .open(state.clone(), terminal_id, id, terminal_size())
+            .await;
+        assert!(manager.sessions.lock().await.contains_key(&terminal_id));
+
+        let new_path = temp.path().join("moved");
+        dispatch_request(
+            state.clone(),
+            &manager,
+            RequestId::new(),
+            AgentRequest::MoveTaskWorktree {
+                task_id: id,
+                new_path,
+            },
+            out_tx,
+        )
+        .await;
+        // Closed before the move was even started.
+        assert!(manager.sessions.lock().await.is_empty());
+
+        let (mut closed, mut moved) = (false, false);
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while !(closed && moved) {
+                match out_rx.recv().await.expect("channel open") {
+                    AgentEnvelope::TerminalClosed {
+                        terminal_id: closed_id,
+                    } => {
+                        assert_eq!(closed_id, terminal_id);
+                        closed = true;
+                    }
+                    AgentEnvelope::Response { response, .. } => {
+                        assert!(matches!(response, AgentResponse::WorktreeMoved { .. }));
+                        moved = true;
+                    }
+                    AgentEnvelope::Error { error, .. } => panic!("{}", error),
+                    _ => {}
                 }
-                break;
             }
+        })
+        .await
+        .expect("terminal closed and worktree moved");
+    }
+
+    #[tokio::test]
+    async fn crashed_run_resumes_its_session_after_restart() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        let id = in_place_task(&state, &env_dir).await;
+        let session_id = uuid::Uuid::new_v4();
+        // The agent had reported its session when slopagent died mid-run.
+        state
+            .start_task_run(id, "first".to_string())
+            .await
+            .expect("start");
+        state
+            .set_task_session_id(id, session_id)
+            .await
+            .expect("session");
+        write_mock_script(
+            &env_dir,
+            &[
+                r#"{"type":"item.completed","item":{"id":"m1","type":"agent_message","text":"resumed"}}"#,
+            ],
+        )
+        .await;
+
+        let config = EnvironmentConfig {
+            environments_root: temp.path().join("environments"),
+            worktrees_directory: temp.path().join("worktrees"),
+            environments: vec![Environment {
+                name: "env".to_string(),
+                directory: env_dir.clone(),
+            }],
+        };
+        let reloaded = AppState::new(config, None, 10, 100, "model".to_string())
+            .await
+            .expect("reload state");
+        let task = reloaded.get_task(id).await.expect("task");
+        assert_eq!(task.status, TaskStatus::Failed);
+        assert!(task.last_run_crashed());
+        assert_eq!(task.session_id, Some(session_id));
+
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+        send_prompt(
+            reloaded.clone(),
+            id,
+            "second".to_string(),
+            Vec::new(),
+            false,
+            out_tx,
+        )
+        .await
+        .expect("prompt");
+        let task = wait_until_stopped(&reloaded, id, 2).await;
+        assert_eq!(task.status, TaskStatus::Completed);
+        assert_eq!(task.session_id, Some(session_id));
+        assert_eq!(task.history.len(), 2);
+        assert_eq!(task.history[0].outcome, Some(TaskOutcome::Crashed));
+        let events = read_all_output_events(&task_log_path(&reloaded, id).await)
+            .await
+            .expect("output log");
+        assert!(events.iter().any(|event| matches!(
+            event,
+            AgentEvent::BackgroundEvent { event, .. }
+                if event.as_deref() == Some("resumed_after_crash")
+        )));
+    }
+
+    async fn rev_parse(dir: &Path, rev: &str) -> String {
+        let output = tokio::process::Command::new("git")
+            .args(["rev-parse", "--verify", "--quiet", rev])
+            .current_dir(dir)
+            .output()
+            .await
+            .expect("git rev-parse");
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    #[tokio::test]
+    async fn cherry_pick_applies_until_conflict_and_restores_branch() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        git(&env_dir, &["config", "user.name", "Test"]).await;
+        git(&env_dir, &["config", "user.email", "test@example.com"]).await;
+        fs::write(env_dir.join("shared.txt"), "base\n")
+            .await
+            .unwrap();
+        git(&env_dir, &["add", "shared.txt"]).await;
+        git(&env_dir, &["commit", "-m", "shared"]).await;
+        let original = head_branch(&env_dir).await;
+
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+        let request = AgentCreateTaskRequest {
+            agent: Some(AgentKind::Mock),
+            ..worktree_request("Pick me")
+        };
+        let AgentResponse::CreatedTask { id, worktree_path } =
+            create_task(state.clone(), request, out_tx)
+                .await
+                .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+        wait_until_stopped(&state, id, 1).await;
+        let worktree = PathBuf::from(worktree_path);
+        fs::write(worktree.join("feature.txt"), "feature\n")
+            .await
+            .unwrap();
+        git(&worktree, &["add", "feature.txt"]).await;
+        git(&worktree, &["commit", "-m", "feature"]).await;
+        let feature = rev_parse(&worktree, "HEAD").await;
+        fs::write(worktree.join("shared.txt"), "task\n")
+            .await
+            .unwrap();
+        git(&worktree, &["commit", "-am", "task edit"]).await;
+        let task_edit = rev_parse(&worktree, "HEAD").await;
+
+        git(&env_dir, &["checkout", "-b", "develop"]).await;
+        fs::write(env_dir.join("shared.txt"), "develop\n")
+            .await
+            .unwrap();
+        git(&env_dir, &["commit", "-am", "develop edit"]).await;
+        git(&env_dir, &["checkout", &original]).await;
+
+        let response = cherry_pick_task(
+            state.clone(),
+            id,
+            "develop",
+            vec![feature.clone(), task_edit.clone()],
+        )
+        .await
+        .expect("cherry-pick");
+        let AgentResponse::CherryPickResult {
+            status,
+            applied,
+            conflict,
+            ..
+        } = response
+        else {
+            panic!("unexpected response");
+        };
+        assert_eq!(status, "conflict");
+        assert_eq!(applied, vec![feature]);
+        assert_eq!(conflict, Some(task_edit));
+        assert_eq!(head_branch(&env_dir).await, original);
+        assert!(!env_dir.join(".git/CHERRY_PICK_HEAD").exists());
+        assert!(!rev_parse(&env_dir, "develop:feature.txt").await.is_empty());
+
+        let err = cherry_pick_task(state, id, "develop", vec!["--abort".to_string()])
+            .await
+            .expect_err("invalid sha");
+        assert_eq!(err.status, 400);
+    }
+
+    /// A finished worktree task with one commit adding `file` on its branch.
+    async fn task_with_commit(state: &AppState, name: &str, file: &str) -> TaskId {
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+        let request = AgentCreateTaskRequest {
+            agent: Some(AgentKind::Mock),
+            ..worktree_request(name)
+        };
+        let AgentResponse::CreatedTask { id, worktree_path } =
+            create_task(state.clone(), request, out_tx)
+                .await
+                .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+        wait_until_stopped(state, id, 1).await;
+        let worktree = PathBuf::from(worktree_path);
+        fs::write(worktree.join(file), "feature\n").await.unwrap();
+        git(&worktree, &["add", file]).await;
+        git(&worktree, &["commit", "-m", file]).await;
+        id
+    }
+
+    /// A task whose one commit on its branch has been merged into the environment's branch,
+    /// with `--no-ff` or as a fast-forward.
+    async fn merged_task(state: &AppState, env_dir: &Path, fast_forward: bool) -> TaskId {
+        git(env_dir, &["config", "user.name", "Test"]).await;
+        git(env_dir, &["config", "user.email", "test@example.com"]).await;
+        let id = task_with_commit(state, "Revert me", "feature.txt").await;
+
+        let merge_branch = state.get_task(id).await.unwrap().merge_branch.unwrap();
+        let mode = if fast_forward { "--ff-only" } else { "--no-ff" };
+        git(env_dir, &["merge", mode, "--no-edit", &merge_branch]).await;
+        assert!(env_dir.join("feature.txt").exists());
+        id
+    }
+
+    #[tokio::test]
+    async fn revert_merge_reverts_the_merge_commit() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        let id = merged_task(&state, &env_dir, false).await;
+        let merge_commit = rev_parse(&env_dir, "HEAD").await;
+
+        fs::write(env_dir.join("feature.txt"), "dirty\n")
+            .await
+            .unwrap();
+        let err = revert_merge(state.clone(), id)
+            .await
+            .expect_err("dirty environment");
+        assert_eq!(err.status, 409);
+        git(&env_dir, &["checkout", "--", "feature.txt"]).await;
+
+        let AgentResponse::RevertResult { sha, .. } =
+            revert_merge(state, id).await.expect("revert")
+        else {
+            panic!("unexpected response");
+        };
+        assert_eq!(sha, rev_parse(&env_dir, "HEAD").await);
+        assert_eq!(rev_parse(&env_dir, "HEAD^").await, merge_commit);
+        assert!(!env_dir.join("feature.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn revert_merge_reverts_a_fast_forwarded_range() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        let id = merged_task(&state, &env_dir, true).await;
+        let feature = rev_parse(&env_dir, "HEAD").await;
+        assert!(rev_parse(&env_dir, "HEAD^2").await.is_empty());
+
+        let AgentResponse::RevertResult { sha, .. } =
+            revert_merge(state, id).await.expect("revert")
+        else {
+            panic!("unexpected response");
+        };
+        assert_eq!(sha, rev_parse(&env_dir, "HEAD").await);
+        assert_eq!(rev_parse(&env_dir, "HEAD^").await, feature);
+        assert!(!env_dir.join("feature.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn concurrent_merges_are_serialized_per_environment() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        git(&env_dir, &["config", "user.name", "Test"]).await;
+        git(&env_dir, &["config", "user.email", "test@example.com"]).await;
+        let first = task_with_commit(&state, "First", "first.txt").await;
+        let second = task_with_commit(&state, "Second", "second.txt").await;
+        let before = rev_parse(&env_dir, "HEAD").await;
+
+        // While something else holds the environment, neither merge may touch the checkout.
+        let guard = state.lock_environment("env").await;
+        let merges = [first, second].map(|id| {
+            let state = state.clone();
+            tokio::spawn(async move { merge_task(state, id, None, false).await })
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(merges.iter().all(|merge| !merge.is_finished()));
+        assert_eq!(rev_parse(&env_dir, "HEAD").await, before);
+        drop(guard);
+
+        for merge in merges {
+            let response = merge.await.expect("join").expect("merge");
+            assert!(matches!(response, AgentResponse::MergeResult { .. }));
         }
+        assert!(env_dir.join("first.txt").exists());
+        assert!(env_dir.join("second.txt").exists());
+        assert!(!env_dir.join(".git/MERGE_HEAD").exists());
+        assert!(!env_dir.join(".git/index.lock").exists());
+    }
+
+    #[tokio::test]
+    async fn merge_targets_base_branch_when_environment_is_elsewhere() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        git(&env_dir, &["config", "user.name", "Test"]).await;
+        git(&env_dir, &["config", "user.email", "test@example.com"]).await;
+        let base = head_branch(&env_dir).await;
+        let id = task_with_commit(&state, "Feature", "feature.txt").await;
+
+        git(&env_dir, &["checkout", "-b", "other"]).await;
+        fs::write(env_dir.join("notes.txt"), "notes\n")
+            .await
+            .unwrap();
+        git(&env_dir, &["add", "notes.txt"]).await;
+        git(&env_dir, &["commit", "-m", "notes"]).await;
+        fs::write(env_dir.join("notes.txt"), "dirty\n")
+            .await
+            .unwrap();
+
+        let err = merge_task(state.clone(), id, None, false)
+            .await
+            .expect_err("dirty environment on another branch");
+        assert_eq!(err.status, 409);
+        assert!(err.error.contains("'other'"), "{}", err.error);
+        assert!(rev_parse(&env_dir, &format!("{}:feature.txt", base))
+            .await
+            .is_empty());
+
+        git(&env_dir, &["checkout", "--", "notes.txt"]).await;
+        merge_task(state, id, None, false).await.expect("merge");
+        assert_eq!(head_branch(&env_dir).await, "other");
+        assert!(!env_dir.join("feature.txt").exists());
+        assert!(!rev_parse(&env_dir, &format!("{}:feature.txt", base))
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn task_bundle_collects_every_part() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, _env_dir) = state_with_committed_repo(&temp).await;
+        let id = task_with_commit(&state, "Bundle", "bundle.txt").await;
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let AgentResponse::TaskBundle { bundle } = handle_request(
+            state.clone(),
+            AgentRequest::ExportTaskBundle { task_id: id },
+            out_tx,
+        )
+        .await
+        .expect("bundle") else {
+            panic!("unexpected response");
+        };
+
+        let json = serde_json::to_value(&bundle).expect("serialize");
+        let mut keys: Vec<_> = json.as_object().expect("object").keys().cloned().collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "agent_config",
+                "commits",
+                "diff",
+                "errors",
+                "events",
+                "task"
+            ]
+        );
+        assert_eq!(json["task"]["id"], id.to_string());
+        assert_eq!(bundle.commits.len(), 1);
+        assert_eq!(bundle.commits[0].subject, "bundle.txt");
+        assert!(bundle.diff.is_some());
+        assert!(bundle.errors.is_empty(), "{:?}", bundle.errors);
+        assert_eq!(json["agent_config"]["agent"], "mock");
+    }
+
+    #[tokio::test]
+    async fn merge_readiness_lists_dirty_worktree_files() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, _env_dir) = state_with_committed_repo(&temp).await;
+        let id = task_with_commit(&state, "Dirty", "feature.txt").await;
+        let worktree = state.get_task(id).await.unwrap().worktree_path;
+        git(&worktree, &["mv", "feature.txt", "renamed.txt"]).await;
+        fs::create_dir_all(worktree.join("my notes")).await.unwrap();
+        fs::write(worktree.join("my notes/todo list.txt"), "todo\n")
+            .await
+            .unwrap();
+
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+        let response = handle_request(
+            state,
+            AgentRequest::GetMergeReadiness { task_id: id },
+            out_tx,
+        )
+        .await
+        .expect("readiness");
+        let AgentResponse::MergeReadiness {
+            can_merge,
+            reason,
+            mut dirty_files,
+            ..
+        } = response
+        else {
+            panic!("unexpected response");
+        };
+        assert!(!can_merge);
+        assert!(reason.unwrap().contains("uncommitted"));
+        dirty_files.sort();
+        assert_eq!(dirty_files, vec!["my notes/todo list.txt", "renamed.txt"]);
+    }
+
+    async fn head_branch(dir: &Path) -> String {
+        let output = tokio::process::Command::new("git")
+            .args(["rev-parse", "--abbrev-ref", "HEAD"])
+            .current_dir(dir)
+            .output()
+            .await
+            .expect("git rev-parse");
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    #[tokio::test]
+    async fn delete_with_keep_branch_removes_only_the_worktree() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        let id = task_with_commit(&state, "Keep", "kept.txt").await;
+        let task = state.get_task(id).await.unwrap();
+        let branch = task.merge_branch.clone().unwrap();
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let response = handle_request(
+            state.clone(),
+            AgentRequest::DeleteTask {
+                task_id: id,
+                force: true,
+                keep_branch: true,
+            },
+            out_tx,
+        )
+        .await
+        .expect("delete");
+        let AgentResponse::DeleteResult { message, .. } = response else {
+            panic!("unexpected response: {:?}", response);
+        };
+        assert!(
+            message.starts_with(&format!("Deleted worktree (kept branch '{}')", branch)),
+            "{}",
+            message
+        );
+        assert!(state.get_task(id).await.is_none());
+        assert!(!task.worktree_path.exists());
+        assert!(!rev_parse(&env_dir, &format!("refs/heads/{}", branch))
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn delete_completed_tasks_skips_running_ones() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        let finished = task_with_commit(&state, "Finished", "done.txt").await;
+        let finished_worktree = state.get_task(finished).await.unwrap().worktree_path;
+
+        // Worktrees check out the mock script, so this task pauses on its approval.
+        write_mock_script(&env_dir, &PARKED_MOCK_SCRIPT).await;
+        git(&env_dir, &["add", "mock-agent.jsonl"]).await;
+        git(&env_dir, &["commit", "-m", "mock script"]).await;
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+        let request = AgentCreateTaskRequest {
+            agent: Some(AgentKind::Mock),
+            ..worktree_request("Running")
+        };
+        let AgentResponse::CreatedTask { id: running, .. } =
+            create_task(state.clone(), request, out_tx)
+                .await
+                .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+        wait_until_running(&state, running).await;
+
+        let AgentResponse::BulkDeleteResult { deleted, skipped } =
+            delete_completed_tasks(state.clone(), "env", true)
+                .await
+                .expect("bulk delete")
+        else {
+            panic!("unexpected response");
+        };
+        assert_eq!(deleted, vec![finished]);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].task_id, running);
+        assert!(state.get_task(finished).await.is_none());
+        assert!(!finished_worktree.exists());
+        assert!(state.get_task(running).await.is_some());
+
+        let err = delete_completed_tasks(state.clone(), "missing", false)
+            .await
+            .expect_err("unknown environment");
+        assert_eq!(err.status, 404);
+
+        respond_approval(state.clone(), running, "call_1".to_string(), true)
+            .await
+            .expect("respond");
+        wait_until_stopped(&state, running, 1).await;
     }
 
-    if interrupted {
-        if let Err(e) = state.interrupt_task_run(task_id).await {
-            tracing::warn!("Failed to persist interrupt for {}: {}", task_id, e);
+    #[tokio::test]
+    async fn prune_worktrees_reports_stale_and_orphaned_worktrees() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        let kept = task_with_commit(&state, "Kept", "kept.txt").await;
+        let kept_worktree = state.get_task(kept).await.unwrap().worktree_path;
+        let worktrees = state.get_worktrees_directory().await;
+        let stale = worktrees.join("stale");
+        let orphan = worktrees.join("orphan");
+        for path in [&stale, &orphan] {
+            let name = path.file_name().unwrap().to_str().unwrap();
+            git(
+                &env_dir,
+                &["worktree", "add", "-b", name, path.to_str().unwrap()],
+            )
+            .await;
         }
-    } else {
-        let result = agent.wait().await;
-        let success = match &result {
-            Ok(r) => {
-                if let Err(e) = state.set_task_session_id(task_id, r.session_id).await {
-                    tracing::warn!("Failed to save session ID: {}", e);
-                }
-                r.success
-            }
-            Err(_) => false,
-        };
+        fs::remove_dir_all(&stale).await.expect("remove stale");
 
-        if let Err(e) = state.complete_task_run(task_id, success).await {
-            tracing::warn!("Failed to persist completion for {}: {}", task_id, e);
-        }
+        let AgentResponse::WorktreesPruned {
+            pruned,
+            orphaned,
+            removed,
+        } = prune_worktrees(state.clone(), "env", false)
+            .await
+            .expect("prune")
+        else {
+            panic!("unexpected response");
+        };
+        assert_eq!(pruned, vec!["stale".to_string()]);
+        assert_eq!(orphaned, vec![orphan.clone()]);
+        assert!(removed.is_empty());
+        assert!(orphan.exists());
+
+        let AgentResponse::WorktreesPruned {
+            pruned, removed, ..
+        } = prune_worktrees(state.clone(), "env", true)
+            .await
+            .expect("force prune")
+        else {
+            panic!("unexpected response");
+        };
+        assert!(pruned.is_empty());
+        assert_eq!(removed, vec![orphan.clone()]);
+        assert!(!orphan.exists());
+        assert!(kept_worktree.exists());
     }
-}
 
-fn task_output_path(env_dir: &Path, task_id: TaskId) -> PathBuf {
-    env_dir.join(format!("task-{}.jsonl", task_id))
-}
+    #[tokio::test]
+    async fn dry_run_create_task_plans_without_creating() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, _env_dir) = state_with_committed_repo(&temp).await;
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let request = AgentCreateTaskRequest {
+            dry_run: true,
+            ..worktree_request("Preview")
+        };
+        let response = create_task(state.clone(), request, out_tx)
+            .await
+            .expect("dry run");
+        let AgentResponse::CreatedTask { id, worktree_path } = response else {
+            panic!("unexpected response");
+        };
 
-struct OutputEventsPage {
-    events: Vec<AgentEvent>,
-    total_events: usize,
-    has_more_before: bool,
-}
+        assert!(
+            worktree_path.starts_with(&temp.path().join("worktrees").to_string_lossy().to_string())
+        );
+        assert!(!Path::new(&worktree_path).exists());
+        assert!(state.get_task(id).await.is_none());
+        assert!(state.list_tasks().await.is_empty());
+    }
 
-async fn read_output_events_page(
-    path: &Path,
-    before: usize,
-    limit: usize,
-) -> Result<OutputEventsPage, std::io::Error> {
-    if !path.exists() {
-        return Ok(OutputEventsPage {
-            events: Vec::new(),
-            total_events: 0,
-            has_more_before: false,
-        });
+    #[tokio::test]
+    async fn create_task_starts_new_branch_from_requested_base() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        git(&env_dir, &["branch", "develop"]).await;
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let request = AgentCreateTaskRequest {
+            base_branch: Some("develop".to_string()),
+            ..worktree_request("New work")
+        };
+        let AgentResponse::CreatedTask { id, worktree_path } =
+            create_task(state.clone(), request, out_tx.clone())
+                .await
+                .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+        let task = state.get_task(id).await.expect("task");
+        assert_eq!(task.base_branch.as_deref(), Some("develop"));
+        let merge_branch = task.merge_branch.expect("merge branch");
+        assert!(
+            merge_branch.starts_with("task/new-work-"),
+            "{}",
+            merge_branch
+        );
+        assert_eq!(head_branch(Path::new(&worktree_path)).await, merge_branch);
+
+        let request = AgentCreateTaskRequest {
+            base_branch: Some("missing".to_string()),
+            ..worktree_request("New work")
+        };
+        let err = create_task(state, request, out_tx)
+            .await
+            .expect_err("missing base");
+        assert_eq!(err.status, 404);
     }
 
-    let limit = limit.max(1);
-    let window_size = before.saturating_add(limit);
-    let file = File::open(path).await?;
-    let mut lines = BufReader::new(file).lines();
-    let mut window = std::collections::VecDeque::with_capacity(window_size);
-    let mut total_events = 0usize;
+    #[tokio::test]
+    async fn create_task_checks_out_existing_branch_once() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        git(&env_dir, &["branch", "feature/login"]).await;
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let request = AgentCreateTaskRequest {
+            existing_branch: Some("feature/login".to_string()),
+            ..worktree_request("Continue login")
+        };
+        let AgentResponse::CreatedTask { id, worktree_path } =
+            create_task(state.clone(), request.clone(), out_tx.clone())
+                .await
+                .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+        let task = state.get_task(id).await.expect("task");
+        assert_eq!(task.merge_branch.as_deref(), Some("feature/login"));
+        assert_eq!(
+            head_branch(Path::new(&worktree_path)).await,
+            "feature/login"
+        );
 
-    while let Some(line) = lines.next_line().await? {
-        let trimmed = line.trim();
-        if trimmed.is_empty() {
-            continue;
-        }
-        if let Ok(event) = serde_json::from_str::<AgentEvent>(trimmed) {
-            total_events += 1;
-            if window.len() == window_size {
-                window.pop_front();
-            }
-            window.push_back(event.normalize());
-        }
+        // Drop the directory so only git's record of the checkout blocks a second worktree.
+        fs::remove_dir_all(&worktree_path).await.ok();
+        let err = create_task(state, request, out_tx)
+            .await
+            .expect_err("already checked out");
+        assert_eq!(err.status, 409);
     }
 
-    let available_before = total_events.saturating_sub(before);
-    let take_count = available_before.min(limit);
-    let drop_count = window
-        .len()
-        .saturating_sub(before.saturating_add(take_count));
-    let events = window
-        .into_iter()
-        .skip(drop_count)
-        .take(take_count)
-        .collect::<Vec<_>>();
+    #[tokio::test]
+    async fn create_task_cleans_up_when_subdir_is_missing_from_base() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        // Untracked, so it passes the checkout pre-check but never reaches the worktree.
+        fs::create_dir_all(env_dir.join("scratch"))
+            .await
+            .expect("create scratch");
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
 
-    Ok(OutputEventsPage {
-        events,
-        total_events,
-        has_more_before: total_events > before.saturating_add(take_count),
-    })
-}
+        let request = AgentCreateTaskRequest {
+            subdir: Some("scratch".to_string()),
+            ..worktree_request("Scratch work")
+        };
+        let err = create_task(state.clone(), request, out_tx)
+            .await
+            .expect_err("missing subdir");
+        assert_eq!(err.status, 404);
+        assert!(state.list_tasks().await.is_empty());
+        assert_no_task_worktrees(&env_dir).await;
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::read_output_events_page;
-    use slopcoder_core::AgentEvent;
-    use tempfile::NamedTempFile;
-    use tokio::fs;
+    #[tokio::test]
+    async fn create_task_cleans_up_when_attachments_cannot_be_written() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        // A tracked file where the attachments directory should go makes the write fail.
+        fs::write(env_dir.join(ATTACHMENTS_DIR), "in the way")
+            .await
+            .expect("write blocker");
+        git(&env_dir, &["add", "."]).await;
+        git(&env_dir, &["commit", "-m", "block attachments"]).await;
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let request = AgentCreateTaskRequest {
+            attachments: vec![Attachment {
+                filename: "shot.png".to_string(),
+                mime: "image/png".to_string(),
+                base64: "aGVsbG8=".to_string(),
+            }],
+            ..worktree_request("Look at this")
+        };
+        let err = create_task(state.clone(), request, out_tx)
+            .await
+            .expect_err("attachment write");
+        assert_eq!(err.status, 500);
+        assert!(state.list_tasks().await.is_empty());
+        assert_no_task_worktrees(&env_dir).await;
+    }
 
-    async fn write_events(lines: &[&str]) -> NamedTempFile {
-        let file = NamedTempFile::new().expect("temp file");
-        let body = format!("{}\n", lines.join("\n"));
-        fs::write(file.path(), body).await.expect("write events");
-        file
+    /// Assert that no task branch or worktree is left in `env_dir` after a failed create.
+    async fn assert_no_task_worktrees(env_dir: &Path) {
+        let branches = tokio::process::Command::new("git")
+            .args(["branch", "--list", "task/*"])
+            .current_dir(env_dir)
+            .output()
+            .await
+            .expect("git branch");
+        assert!(String::from_utf8_lossy(&branches.stdout).trim().is_empty());
+        let worktrees = tokio::process::Command::new("git")
+            .args(["worktree", "list", "--porcelain"])
+            .current_dir(env_dir)
+            .output()
+            .await
+            .expect("git worktree list");
+        let listed = String::from_utf8_lossy(&worktrees.stdout);
+        assert_eq!(listed.matches("worktree ").count(), 1, "{listed}");
     }
 
     #[tokio::test]
-    async fn read_output_events_page_returns_latest_window() {
-        let file = write_events(&[
-            r#"{"type":"prompt.sent","prompt":"one"}"#,
-            r#"{"type":"prompt.sent","prompt":"two"}"#,
-            r#"{"type":"prompt.sent","prompt":"three"}"#,
-            r#"{"type":"prompt.sent","prompt":"four"}"#,
-        ])
-        .await;
+    async fn deleting_a_task_keeps_the_branch_it_reused() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        // Unmerged work on the user's branch, which a forced `git branch -D` would lose.
+        git(&env_dir, &["checkout", "-b", "feature/login"]).await;
+        git(&env_dir, &["commit", "--allow-empty", "-m", "wip"]).await;
+        git(&env_dir, &["checkout", "-"]).await;
+        let (out_tx, _out_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let request = AgentCreateTaskRequest {
+            existing_branch: Some("feature/login".to_string()),
+            ..worktree_request("Continue login")
+        };
+        let AgentResponse::CreatedTask { id, .. } = create_task(state.clone(), request, out_tx)
+            .await
+            .expect("create")
+        else {
+            panic!("unexpected response");
+        };
+        assert!(state.get_task(id).await.expect("task").reused_branch);
+        wait_until_stopped(&state, id, 1).await;
 
-        let page = read_output_events_page(file.path(), 0, 2)
+        delete_completed_tasks(state.clone(), "env", true)
             .await
-            .expect("page");
+            .expect("delete completed");
+        assert!(state.get_task(id).await.is_none());
+        let branch = tokio::process::Command::new("git")
+            .args(["rev-parse", "--verify", "--quiet", "feature/login"])
+            .current_dir(&env_dir)
+            .status()
+            .await
+            .expect("git rev-parse");
+        assert!(branch.success(), "reused branch was deleted");
+    }
 
-        assert_eq!(page.total_events, 4);
-        assert!(page.has_more_before);
-        let prompts = page
-            .events
-            .iter()
-            .filter_map(|event| match event {
-                AgentEvent::PromptSent { prompt } => Some(prompt.as_str()),
-                _ => None,
-            })
-            .collect::<Vec<_>>();
-        assert_eq!(prompts, vec!["three", "four"]);
+    #[tokio::test]
+    async fn oversized_terminal_recording_is_refused() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let (state, env_dir) = state_with_committed_repo(&temp).await;
+        let task_id = in_place_task(&state, &env_dir).await;
+        let env_state_dir = state.get_environment_directory("env").await.unwrap();
+        let task_dir = recording::task_recordings_dir(&env_state_dir, task_id);
+        fs::create_dir_all(&task_dir).await.unwrap();
+        let cast = tokio::fs::File::create(task_dir.join("term.cast"))
+            .await
+            .unwrap();
+        cast.set_len(MAX_RECORDING_BYTES + 1).await.unwrap();
+
+        let err = get_terminal_recording(state, task_id)
+            .await
+            .expect_err("oversized recording");
+        assert_eq!(err.status, 413);
     }
 
     #[tokio::test]
-    async fn read_output_events_page_returns_older_window() {
-        let file = write_events(&[
-            r#"{"type":"prompt.sent","prompt":"one"}"#,
-            r#"{"type":"prompt.sent","prompt":"two"}"#,
-            r#"{"type":"prompt.sent","prompt":"three"}"#,
-            r#"{"type":"prompt.sent","prompt":"four"}"#,
-            r#"{"type":"prompt.sent","prompt":"five"}"#,
-        ])
-        .await;
+    async fn archive_then_restore_round_trips_task() {
+        let temp = tempfile::TempDir::new().expect("temp dir");
+        let env_dir = temp.path().join("env");
+        fs::create_dir_all(&env_dir).await.expect("env dir");
+        let status = tokio::process::Command::new("git")
+            .arg("init")
+            .current_dir(&env_dir)
+            .status()
+            .await
+            .expect("git init");
+        assert!(status.success());
+
+        let config = EnvironmentConfig {
+            environments_root: temp.path().join("environments"),
+            worktrees_directory: temp.path().join("worktrees"),
+            environments: vec![Environment {
+                name: "env".to_string(),
+                directory: env_dir.clone(),
+            }],
+        };
+        let state = AppState::new(config, None, 10, 100, "model".to_string())
+            .await
+            .expect("state");
 
-        let page = read_output_events_page(file.path(), 2, 2)
+        let mut task = Task::new(
+            AgentKind::Claude,
+            "env".to_string(),
+            "topic".to_string(),
+            TaskWorkspaceKind::Environment,
+            None,
+            None,
+            false,
+            env_dir,
+        );
+        task.session_id = Some(uuid::Uuid::new_v4());
+        let task_id = task.id;
+        state.insert_task(task.clone()).await.expect("insert");
+
+        let env_state_dir = state.get_environment_directory("env").await.unwrap();
+        fs::create_dir_all(&env_state_dir).await.unwrap();
+        let transcript = "{\"type\":\"prompt.sent\",\"prompt\":\"hi\"}\n";
+        fs::write(task_output_path(&env_state_dir, task_id), transcript)
             .await
-            .expect("page");
+            .unwrap();
 
-        assert_eq!(page.total_events, 5);
-        assert!(page.has_more_before);
-        let prompts = page
-            .events
-            .iter()
-            .filter_map(|event| match event {
-                AgentEvent::PromptSent { prompt } => Some(prompt.as_str()),
-                _ => None,
-            })
-            .collect::<Vec<_>>();
-        assert_eq!(prompts, vec!["two", "three"]);
+        let archived = archive_task_output(&state, &task)
+            .await
+            .expect("archive")
+            .expect("transcript archived");
+        state.remove_task(task_id).await.expect("remove");
+        match list_archives(state.clone(), "env").await.expect("list") {
+            AgentResponse::Archives { archives } => {
+                assert_eq!(archives.len(), 1);
+                assert_eq!(archives[0].task_id, task_id);
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+
+        let restored = match restore_archived_task(state.clone(), "env", task_id)
+            .await
+            .expect("restore")
+        {
+            AgentResponse::RestoredTask { task } => task,
+            other => panic!("unexpected response: {:?}", other),
+        };
+        assert_eq!(restored.agent, AgentKind::Claude);
+        assert_eq!(restored.session_id, task.session_id);
+        assert!(!restored.history_only);
+        assert!(state.get_task(task_id).await.is_some());
+        assert!(!archived.exists());
+        assert_eq!(
+            fs::read_to_string(task_output_path(&env_state_dir, task_id))
+                .await
+                .unwrap(),
+            transcript
+        );
     }
 }
 
@@ -1580,6 +8465,206 @@ async fn load_git_diff(
     Ok(DiffResult { staged, unstaged })
 }
 
+/// Cap each file's section of a `git diff` at `max_file_bytes`, ending cut sections at a
+/// line boundary with a `... [diff truncated, N more bytes]` marker. Also reports whether
+/// anything was cut.
+fn truncate_file_diffs(diff: &str, max_file_bytes: usize) -> (String, bool) {
+    let mut starts: Vec<usize> = diff
+        .match_indices("\ndiff --git ")
+        .map(|(index, _)| index + 1)
+        .collect();
+    starts.insert(0, 0);
+    starts.push(diff.len());
+
+    let mut out = String::new();
+    let mut truncated = false;
+    for bounds in starts.windows(2) {
+        let file = &diff[bounds[0]..bounds[1]];
+        if file.len() <= max_file_bytes {
+            out.push_str(file);
+            continue;
+        }
+        let mut cut = max_file_bytes;
+        while !file.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        if let Some(newline) = file[..cut].rfind('\n') {
+            cut = newline + 1;
+        }
+        out.push_str(&file[..cut]);
+        if !out.is_empty() && !out.ends_with('\n') {
+            out.push('\n');
+        }
+        out.push_str(&format!(
+            "... [diff truncated, {} more bytes]\n",
+            file.len() - cut
+        ));
+        truncated = true;
+    }
+    (out, truncated)
+}
+
+/// Buffers diff text and sends it as `DiffChunk` envelopes of at most `chunk_bytes`.
+struct DiffChunker<'a> {
+    stream_id: Uuid,
+    section: DiffSection,
+    out_tx: &'a mpsc::UnboundedSender<AgentEnvelope>,
+    /// One slot per chunk in the outbound queue, so git output is read no faster than it is sent.
+    slots: &'a Semaphore,
+    chunk_bytes: usize,
+    buf: String,
+}
+
+impl<'a> DiffChunker<'a> {
+    fn new(
+        stream_id: Uuid,
+        section: DiffSection,
+        out_tx: &'a mpsc::UnboundedSender<AgentEnvelope>,
+        slots: &'a Semaphore,
+        chunk_bytes: usize,
+    ) -> Self {
+        Self {
+            stream_id,
+            section,
+            out_tx,
+            slots,
+            chunk_bytes: chunk_bytes.max(1),
+            buf: String::new(),
+        }
+    }
+
+    async fn push(&mut self, text: &str) -> Result<(), std::io::Error> {
+        self.buf.push_str(text);
+        while self.buf.len() >= self.chunk_bytes {
+            let mut end = self.chunk_bytes;
+            while !self.buf.is_char_boundary(end) {
+                end -= 1;
+            }
+            if end == 0 {
+                // A single character wider than the cap still has to go out whole.
+                end = self.buf.chars().next().map_or(0, char::len_utf8);
+            }
+            let rest = self.buf.split_off(end);
+            let chunk = std::mem::replace(&mut self.buf, rest);
+            self.send(chunk).await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), std::io::Error> {
+        if !self.buf.is_empty() {
+            let data = std::mem::take(&mut self.buf);
+            self.send(data).await?;
+        }
+        Ok(())
+    }
+
+    async fn send(&self, data: String) -> Result<(), std::io::Error> {
+        // The outbound writer returns the slot once the chunk is on the wire.
+        let permit = timeout(DIFF_SLOT_WAIT, self.slots.acquire())
+            .await
+            .map_err(|_| std::io::Error::other("coordinator stopped taking diff chunks"))?
+            .map_err(std::io::Error::other)?;
+        permit.forget();
+        let _ = self.out_tx.send(AgentEnvelope::DiffChunk {
+            stream_id: self.stream_id,
+            section: self.section,
+            data,
+        });
+        Ok(())
+    }
+}
+
+/// Streaming counterpart of [`load_git_diff`]: runs the same git commands and forwards
+/// their output line by line. Memory stays bounded by one line plus the chunks `slots` lets
+/// wait in the outbound queue.
+async fn stream_git_diff(
+    worktree_path: &Path,
+    base_branch: Option<&str>,
+    stream_id: Uuid,
+    out_tx: &mpsc::UnboundedSender<AgentEnvelope>,
+    slots: &Semaphore,
+    chunk_bytes: usize,
+) -> Result<(), std::io::Error> {
+    let mut staged = DiffChunker::new(stream_id, DiffSection::Staged, out_tx, slots, chunk_bytes);
+    let mut staged_cmd = Command::new("git");
+    staged_cmd.args(["diff", "--cached"]);
+    if let Some(base_branch) = base_branch {
+        staged_cmd.arg(base_branch);
+    }
+    stream_command_output(staged_cmd, worktree_path, &mut staged, false).await?;
+    staged.flush().await?;
+
+    let mut unstaged =
+        DiffChunker::new(stream_id, DiffSection::Unstaged, out_tx, slots, chunk_bytes);
+    let mut unstaged_cmd = Command::new("git");
+    unstaged_cmd.arg("diff");
+    stream_command_output(unstaged_cmd, worktree_path, &mut unstaged, false).await?;
+
+    let untracked = Command::new("git")
+        .args(["ls-files", "--others", "--exclude-standard"])
+        .current_dir(worktree_path)
+        .output()
+        .await?;
+    if !untracked.status.success() {
+        let stderr = String::from_utf8_lossy(&untracked.stderr);
+        if !stderr.trim().is_empty() {
+            return Err(std::io::Error::other(stderr.to_string()));
+        }
+    }
+    for path in String::from_utf8_lossy(&untracked.stdout).lines() {
+        if path.trim().is_empty() {
+            continue;
+        }
+        let mut untracked_cmd = Command::new("git");
+        untracked_cmd.args(["diff", "--no-index", "--", "/dev/null", path]);
+        stream_command_output(untracked_cmd, worktree_path, &mut unstaged, true).await?;
+    }
+    unstaged.flush().await?;
+    Ok(())
+}
+
+/// Feed a command's stdout into `chunker` line by line. With `diff_exit_ok`, exit code 1
+/// (differences found, as `git diff --no-index` reports) is not a failure.
+async fn stream_command_output(
+    mut cmd: Command,
+    dir: &Path,
+    chunker: &mut DiffChunker<'_>,
+    diff_exit_ok: bool,
+) -> Result<(), std::io::Error> {
+    let mut child = cmd
+        .current_dir(dir)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let mut stderr = child.stderr.take();
+    let stderr_task = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        if let Some(stderr) = stderr.as_mut() {
+            let _ = stderr.read_to_end(&mut buf).await;
+        }
+        buf
+    });
+    if let Some(stdout) = child.stdout.take() {
+        let mut reader = BufReader::new(stdout);
+        let mut line = Vec::new();
+        while reader.read_until(b'\n', &mut line).await? > 0 {
+            chunker.push(&String::from_utf8_lossy(&line)).await?;
+            line.clear();
+        }
+    }
+    let status = child.wait().await?;
+    let stderr = stderr_task.await.unwrap_or_default();
+    if !status.success() && (!diff_exit_ok || status.code() != Some(1)) {
+        let stderr = String::from_utf8_lossy(&stderr);
+        if !stderr.trim().is_empty() {
+            return Err(std::io::Error::other(stderr.to_string()));
+        }
+    }
+    Ok(())
+}
+
 async fn has_unstaged_changes(worktree_path: &Path) -> bool {
     let unstaged_output = Command::new("git")
         .args(["diff", "--quiet"])
@@ -1618,31 +8703,6 @@ async fn has_unstaged_changes(worktree_path: &Path) -> bool {
     false
 }
 
-fn sanitize_for_path(value: &str) -> String {
-    let mut out = String::new();
-    for ch in value.chars() {
-        let lower = ch.to_ascii_lowercase();
-        if lower.is_ascii_alphanumeric() || lower == '-' || lower == '_' {
-            out.push(lower);
-        } else {
-            out.push('-');
-        }
-    }
-
-    let compact = out
-        .split('-')
-        .filter(|part| !part.is_empty())
-        .collect::<Vec<_>>()
-        .join("-");
-
-    if compact.is_empty() {
-        "env".to_string()
-    } else {
-        compact
-    }
-}
-
-#[allow(dead_code)]
 fn map_state_error(err: StateError) -> RpcError {
     match err {
         StateError::TaskNotFound(_) => RpcError::new(StatusCode::NOT_FOUND, "Task not found"),
@@ -1651,6 +8711,7 @@ fn map_state_error(err: StateError) -> RpcError {
             RpcError::new(StatusCode::BAD_REQUEST, "Task name is required")
         }
         StateError::TaskNotReady => RpcError::new(StatusCode::CONFLICT, "Task not ready"),
+        e @ StateError::EnvironmentBusy(_) => RpcError::new(StatusCode::CONFLICT, e.to_string()),
         StateError::PersistenceError(e) => {
             RpcError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
         }
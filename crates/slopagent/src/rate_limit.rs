@@ -0,0 +1,243 @@
+//! Token bucket that spaces out agent starts so bursts of tasks don't trip upstream API quotas.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use tokio::time::{Duration, Instant};
+
+/// Refills at `rate_per_sec` up to `burst` tokens; every agent start takes one.
+pub struct TokenBucket {
+    rate_per_sec: f64,
+    burst: f64,
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    tokens: f64,
+    refilled_at: Instant,
+    /// Starts waiting for a token: highest priority first, then in arrival order.
+    waiters: BinaryHeap<Waiter>,
+    /// Arrival counter breaking priority ties.
+    next_seq: u64,
+    /// Whether a dispatch task is handing tokens to `waiters`.
+    dispatching: bool,
+}
+
+struct Waiter {
+    priority: i32,
+    seq: u64,
+    ready: oneshot::Sender<()>,
+}
+
+impl Ord for Waiter {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+impl BucketState {
+    fn refill(&mut self, rate_per_sec: f64, burst: f64) {
+        let now = Instant::now();
+        let refill = now.duration_since(self.refilled_at).as_secs_f64() * rate_per_sec;
+        self.tokens = (self.tokens + refill).min(burst);
+        self.refilled_at = now;
+    }
+}
+
+/// A place in the start queue, from [`TokenBucket::reserve`].
+pub struct StartTicket {
+    wait: Option<(Duration, oneshot::Receiver<()>)>,
+}
+
+impl StartTicket {
+    /// `None` when the start may go ahead now, otherwise a rough estimate of the wait.
+    pub fn queued_wait(&self) -> Option<Duration> {
+        self.wait.as_ref().map(|(estimate, _)| *estimate)
+    }
+
+    /// Wait for this ticket's token. Dropping the future first gives up the place in the
+    /// queue without spending a token.
+    pub async fn ready(self) {
+        if let Some((_, ready)) = self.wait {
+            let _ = ready.await;
+        }
+    }
+}
+
+impl TokenBucket {
+    /// A full bucket. `burst` is clamped to at least one token.
+    pub fn new(rate_per_sec: f64, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        Self {
+            rate_per_sec,
+            burst,
+            state: Mutex::new(BucketState {
+                tokens: burst,
+                refilled_at: Instant::now(),
+                waiters: BinaryHeap::new(),
+                next_seq: 0,
+                dispatching: false,
+            }),
+        }
+    }
+
+    /// Take a token if one is free and nobody is queued, or join the queue at `priority`.
+    ///
+    /// Queued starts are handed tokens one at a time as they refill, highest priority first
+    /// and in arrival order within a priority, so concurrent callers start one refill
+    /// interval apart rather than all retrying when a token appears.
+    pub fn reserve(self: &Arc<Self>, priority: i32) -> StartTicket {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.refill(self.rate_per_sec, self.burst);
+        if state.waiters.is_empty() && state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+            return StartTicket { wait: None };
+        }
+        let ahead = state
+            .waiters
+            .iter()
+            .filter(|waiter| waiter.priority >= priority)
+            .count();
+        let owed = ahead as f64 + 1.0 - state.tokens;
+        let estimate = Duration::from_secs_f64(owed.max(0.0) / self.rate_per_sec);
+        let (tx, rx) = oneshot::channel();
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.waiters.push(Waiter {
+            priority,
+            seq,
+            ready: tx,
+        });
+        if !state.dispatching {
+            state.dispatching = true;
+            tokio::spawn(self.clone().dispatch());
+        }
+        StartTicket {
+            wait: Some((estimate, rx)),
+        }
+    }
+
+    /// Hand refilled tokens to queued starts until the queue is empty. A waiter that gave
+    /// up (its run was interrupted) is skipped and its token goes to the next one.
+    async fn dispatch(self: Arc<Self>) {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+                state.refill(self.rate_per_sec, self.burst);
+                while state.tokens >= 1.0 {
+                    let Some(waiter) = state.waiters.pop() else {
+                        break;
+                    };
+                    if waiter.ready.send(()).is_ok() {
+                        state.tokens -= 1.0;
+                    }
+                }
+                if state.waiters.is_empty() {
+                    state.dispatching = false;
+                    return;
+                }
+                Duration::from_secs_f64((1.0 - state.tokens) / self.rate_per_sec)
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TokenBucket;
+    use std::sync::Arc;
+    use tokio::time::{Duration, Instant};
+
+    #[tokio::test(start_paused = true)]
+    async fn simultaneous_starts_are_throttled_to_the_rate() {
+        // 20 starts/s with a burst of 2: of 6 simultaneous starts, 2 go at once and the
+        // other 4 follow 50ms apart.
+        let bucket = Arc::new(TokenBucket::new(20.0, 2));
+        let began = Instant::now();
+        let starts = (0..6).map(|_| {
+            let ticket = bucket.reserve(0);
+            tokio::spawn(async move {
+                ticket.ready().await;
+                began.elapsed()
+            })
+        });
+        let mut elapsed = Vec::new();
+        for start in starts.collect::<Vec<_>>() {
+            elapsed.push(start.await.expect("start task"));
+        }
+
+        assert_eq!(elapsed[..2], [Duration::ZERO, Duration::ZERO]);
+        for (index, started) in elapsed.iter().enumerate().skip(2) {
+            let expected = Duration::from_millis(50 * (index as u64 - 1));
+            assert!(
+                started.abs_diff(expected) < Duration::from_millis(2),
+                "start {} at {:?}",
+                index,
+                started
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn abandoned_wait_does_not_spend_a_token() {
+        let bucket = Arc::new(TokenBucket::new(10.0, 1));
+        assert!(bucket.reserve(0).queued_wait().is_none());
+        let began = Instant::now();
+
+        let abandoned = bucket.reserve(0);
+        assert!(abandoned.queued_wait().is_some());
+        drop(abandoned);
+        let next = bucket.reserve(0);
+        assert!(next.queued_wait().is_some());
+        next.ready().await;
+
+        // The abandoned waiter's token went to the next start one refill later.
+        let elapsed = began.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(100) && elapsed < Duration::from_millis(102),
+            "{:?}",
+            elapsed
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn higher_priority_starts_jump_the_queue() {
+        let bucket = Arc::new(TokenBucket::new(10.0, 1));
+        assert!(bucket.reserve(0).queued_wait().is_none());
+
+        let (order_tx, mut order_rx) = tokio::sync::mpsc::unbounded_channel();
+        for (name, priority) in [("first", 0), ("second", 0), ("urgent", 5)] {
+            let ticket = bucket.reserve(priority);
+            let order_tx = order_tx.clone();
+            tokio::spawn(async move {
+                ticket.ready().await;
+                let _ = order_tx.send(name);
+            });
+        }
+        drop(order_tx);
+
+        let mut order = Vec::new();
+        while let Some(name) = order_rx.recv().await {
+            order.push(name);
+        }
+        assert_eq!(order, vec!["urgent", "first", "second"]);
+    }
+}
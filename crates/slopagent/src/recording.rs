@@ -0,0 +1,100 @@
+//! Asciinema (v2 `.cast`) recording of remote terminal sessions.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Directory holding recordings for one task: `<env_state_dir>/terminals/task-<id>/`.
+pub fn task_recordings_dir(env_state_dir: &Path, task_id: impl std::fmt::Display) -> PathBuf {
+    env_state_dir
+        .join("terminals")
+        .join(format!("task-{}", task_id))
+}
+
+/// Recording file for a single terminal session inside [`task_recordings_dir`].
+pub fn recording_path(task_dir: &Path, terminal_id: Uuid) -> PathBuf {
+    task_dir.join(format!("{}.cast", terminal_id))
+}
+
+/// Streams terminal output into an asciinema v2 file as it arrives.
+pub struct CastWriter {
+    out: BufWriter<File>,
+    started: Instant,
+}
+
+impl CastWriter {
+    /// Create the file and write the header line.
+    pub fn create(path: &Path, cols: u16, rows: u16) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let header = serde_json::json!({
+            "version": 2,
+            "width": cols,
+            "height": rows,
+            "timestamp": timestamp,
+        });
+        writeln!(out, "{}", header)?;
+        out.flush()?;
+        Ok(Self {
+            out,
+            started: Instant::now(),
+        })
+    }
+
+    /// Append an output (`"o"`) event. Non-UTF-8 bytes are replaced.
+    pub fn write_output(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write_event("o", &String::from_utf8_lossy(data))
+    }
+
+    /// Append a resize (`"r"`) event.
+    pub fn write_resize(&mut self, cols: u16, rows: u16) -> io::Result<()> {
+        self.write_event("r", &format!("{}x{}", cols, rows))
+    }
+
+    fn write_event(&mut self, kind: &str, data: &str) -> io::Result<()> {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let event = serde_json::json!([elapsed, kind, data]);
+        writeln!(self.out, "{}", event)?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{recording_path, task_recordings_dir, CastWriter};
+    use uuid::Uuid;
+
+    #[test]
+    fn cast_writer_emits_header_and_events() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let task_dir = task_recordings_dir(dir.path(), "abc");
+        std::fs::create_dir_all(&task_dir).expect("create dir");
+        let terminal_id = Uuid::new_v4();
+        let path = recording_path(&task_dir, terminal_id);
+
+        let mut writer = CastWriter::create(&path, 120, 30).expect("create cast");
+        writer.write_output(b"$ ls\r\n").expect("write output");
+        writer.write_resize(80, 24).expect("write resize");
+        drop(writer);
+
+        let body = std::fs::read_to_string(&path).expect("read cast");
+        let lines: Vec<serde_json::Value> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("json line"))
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["version"], 2);
+        assert_eq!(lines[0]["width"], 120);
+        assert_eq!(lines[0]["height"], 30);
+        assert_eq!(lines[1][1], "o");
+        assert_eq!(lines[1][2], "$ ls\r\n");
+        assert_eq!(lines[2][1], "r");
+        assert_eq!(lines[2][2], "80x24");
+        assert!(path.ends_with(format!("terminals/task-abc/{}.cast", terminal_id)));
+    }
+}
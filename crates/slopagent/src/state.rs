@@ -1,19 +1,38 @@
+use crate::disk_usage::DiskUsageCache;
+use crate::rate_limit::TokenBucket;
+use crate::task_logs::TaskLogs;
 use slopcoder_core::{
+    agent_rpc::{AgentEnvelope, CLONE_TIMEOUT_SECS},
     anyagent::AnyAgentConfig,
-    environment::{Environment, EnvironmentConfig, EnvironmentError},
+    branch_picker::normalize_task_name,
+    environment::{Environment, EnvironmentConfig, EnvironmentError, EnvironmentSettings},
     persistence::{PendingEnvironmentSave, PersistentTaskStore},
-    task::{Task, TaskId},
+    task::{Task, TaskId, TaskOutcome, TaskStatus, TaskWorkspaceKind},
     PersistenceError,
 };
-use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 use thiserror::Error;
 use tokio::process::Command;
-use tokio::sync::RwLock;
+use tokio::sync::{mpsc, Mutex, RwLock, Semaphore};
 use uuid::Uuid;
 
+/// Default cap on one file's section of a `GetTaskDiff` response (`--max-diff-bytes`).
+pub const DEFAULT_MAX_DIFF_BYTES: usize = 1024 * 1024;
+
+/// Default quiet time in a run before a heartbeat event is sent (`--run-heartbeat-secs`).
+pub const DEFAULT_RUN_HEARTBEAT_SECS: u64 = 15;
+
+/// Default retries after a transient agent start failure (`--spawn-retries`).
+pub const DEFAULT_SPAWN_RETRIES: u32 = 2;
+
+/// Default wait before the first start retry, doubled for each later one (`--spawn-retry-backoff-ms`).
+pub const DEFAULT_SPAWN_RETRY_BACKOFF_MS: u64 = 1000;
+/// Most diff chunks, across all diff streams, waiting in the outbound queue at once.
+pub const DIFF_CHUNKS_IN_FLIGHT: usize = 16;
+
 #[derive(Debug, Error)]
 pub enum StateError {
     #[error("Task not found: {0}")]
@@ -25,6 +44,9 @@ pub enum StateError {
     #[error("Task cannot accept prompts in current state")]
     TaskNotReady,
 
+    #[error("Environment '{0}' already has a running in-place task")]
+    EnvironmentBusy(String),
+
     #[error("Task name is required")]
     InvalidTaskName,
 
@@ -66,6 +88,39 @@ pub enum CreateEnvironmentError {
 
     #[error("Failed to initialize git repository: {0}")]
     GitInit(String),
+
+    #[error("Unsupported clone URL: {0}")]
+    InvalidCloneUrl(String),
+
+    #[error("Failed to clone repository: {0}")]
+    CloneFailed(String),
+}
+
+/// Receiving end of the outbound queue. The envelope being sent stays in `unsent` until the
+/// send succeeds, so a connection that drops mid-send leaves it for the next one.
+pub struct OutboundQueue {
+    rx: mpsc::UnboundedReceiver<AgentEnvelope>,
+    unsent: Option<AgentEnvelope>,
+    /// Diff chunk slots; a diff stream takes one per chunk and the writer returns it once sent.
+    diff_slots: Arc<Semaphore>,
+}
+
+impl OutboundQueue {
+    /// The next envelope to send: one a previous connection failed to deliver, else the next
+    /// queued one. `None` once every sender is gone.
+    pub async fn peek(&mut self) -> Option<&AgentEnvelope> {
+        if self.unsent.is_none() {
+            self.unsent = self.rx.recv().await;
+        }
+        self.unsent.as_ref()
+    }
+
+    /// Drop the envelope returned by [`OutboundQueue::peek`] once it has been delivered.
+    pub fn mark_sent(&mut self) {
+        if let Some(AgentEnvelope::DiffChunk { .. }) = self.unsent.take() {
+            self.diff_slots.add_permits(1);
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -83,10 +138,49 @@ struct AppStateInner {
     env_cache_refresh_in_flight: bool,
     env_cache_refresh_interval: Duration,
     state_root: PathBuf,
+    /// Parsed `.slopcoder.yaml` per environment directory, keyed with the file's mtime.
+    env_settings: std::collections::HashMap<PathBuf, (Option<SystemTime>, EnvironmentSettings)>,
     tasks: PersistentTaskStore,
     interrupt_channels: std::collections::HashMap<TaskId, tokio::sync::oneshot::Sender<()>>,
+    /// Approval answers (`call_id`, approve) for running tasks whose agent supports them.
+    approval_channels:
+        std::collections::HashMap<TaskId, tokio::sync::mpsc::UnboundedSender<(String, bool)>>,
+    /// Last `merge_check_command` outcome per task, with the command and worktree HEAD it ran on.
+    merge_checks: std::collections::HashMap<TaskId, (String, String, Result<(), String>)>,
+    /// Whether queued prompts persisted before startup still need to be resumed.
+    resume_queues_pending: bool,
     agent_config: AnyAgentConfig,
     branch_model: String,
+    record_terminals: bool,
+    /// Time without terminal input before the PTY is closed; `None` keeps terminals open.
+    terminal_idle_timeout: Option<Duration>,
+    debug_raw: bool,
+    compress_output: bool,
+    max_diff_bytes: usize,
+    /// Size past which a running task's output log is rotated; `None` never rotates.
+    max_output_bytes: Option<u64>,
+    /// Quiet time in a run before a heartbeat event; `None` disables heartbeats.
+    run_heartbeat: Option<Duration>,
+    /// Longest a single run may take before it is killed as timed out; `None` never times out.
+    run_timeout: Option<Duration>,
+    /// Retries after a transient agent start failure, and the wait before the first.
+    spawn_retries: u32,
+    spawn_retry_backoff: Duration,
+    /// `--start-rate-per-minute` limiter shared by every run; `None` starts agents immediately.
+    start_limiter: Option<Arc<TokenBucket>>,
+    /// Buffers filled by the `tracing` layer installed in `main`.
+    task_logs: TaskLogs,
+    /// `--label` pairs advertised in the hello.
+    host_labels: std::collections::HashMap<String, String>,
+    disk_usage: DiskUsageCache,
+    /// Envelopes for the coordinator. They outlive any one connection, so events from runs
+    /// started before a reconnect are delivered over the next one.
+    outbound_tx: mpsc::UnboundedSender<AgentEnvelope>,
+    outbound_rx: Arc<Mutex<OutboundQueue>>,
+    /// Caps the diff chunks sitting in the outbound queue; see [`OutboundQueue`].
+    diff_slots: Arc<Semaphore>,
+    /// Per-environment locks serializing operations that mutate the repo's working tree.
+    env_locks: std::collections::HashMap<String, Arc<Mutex<()>>>,
 }
 
 impl AppState {
@@ -137,11 +231,13 @@ impl AppState {
         .await;
         let merged_environments = merge_environments(&config.environments, discovered);
         for env in &merged_environments {
-            let env_state_dir = state_root.join(sanitize_for_path(&env.name));
+            let env_state_dir = adopt_legacy_environment_dirs(&state_root, &env.name).await;
             tokio::fs::create_dir_all(&env_state_dir).await?;
             tasks.register_environment(env.name.clone(), env_state_dir);
         }
         tasks.load_all().await?;
+        let (outbound_tx, outbound_rx) = mpsc::unbounded_channel();
+        let diff_slots = Arc::new(Semaphore::new(DIFF_CHUNKS_IN_FLIGHT));
 
         Ok(Self {
             inner: Arc::new(RwLock::new(AppStateInner {
@@ -154,10 +250,36 @@ impl AppState {
                 env_cache_refresh_in_flight: false,
                 env_cache_refresh_interval: Duration::from_secs(5),
                 state_root,
+                env_settings: std::collections::HashMap::new(),
                 tasks,
                 interrupt_channels: std::collections::HashMap::new(),
+                approval_channels: std::collections::HashMap::new(),
+                merge_checks: std::collections::HashMap::new(),
+                resume_queues_pending: true,
                 agent_config: AnyAgentConfig::default(),
                 branch_model,
+                record_terminals: false,
+                terminal_idle_timeout: None,
+                debug_raw: false,
+                compress_output: false,
+                max_diff_bytes: DEFAULT_MAX_DIFF_BYTES,
+                max_output_bytes: None,
+                run_heartbeat: Some(Duration::from_secs(DEFAULT_RUN_HEARTBEAT_SECS)),
+                run_timeout: None,
+                spawn_retries: DEFAULT_SPAWN_RETRIES,
+                spawn_retry_backoff: Duration::from_millis(DEFAULT_SPAWN_RETRY_BACKOFF_MS),
+                start_limiter: None,
+                task_logs: TaskLogs::default(),
+                host_labels: std::collections::HashMap::new(),
+                disk_usage: DiskUsageCache::default(),
+                outbound_tx,
+                outbound_rx: Arc::new(Mutex::new(OutboundQueue {
+                    rx: outbound_rx,
+                    unsent: None,
+                    diff_slots: diff_slots.clone(),
+                })),
+                diff_slots,
+                env_locks: std::collections::HashMap::new(),
             })),
         })
     }
@@ -203,6 +325,43 @@ impl AppState {
         cached
     }
 
+    /// Rescan for environments immediately, returning the added and removed names.
+    pub async fn reload_environments(&self) -> (Vec<String>, Vec<String>) {
+        let (environments_root, repo_root, discovery_max_depth, discovery_max_repos) = {
+            let inner = self.inner.read().await;
+            (
+                inner.config.environments_root.clone(),
+                inner.repo_root.clone(),
+                inner.discovery_max_depth,
+                inner.discovery_max_repos,
+            )
+        };
+        let discovered = discover_environments(
+            &environments_root,
+            repo_root.as_deref(),
+            discovery_max_depth,
+            discovery_max_repos,
+        )
+        .await;
+
+        let mut inner = self.inner.write().await;
+        let merged = merge_environments(&inner.config.environments, discovered);
+        let diff = diff_environment_names(&inner.cached_environments, &merged);
+        inner.cached_environments = merged;
+        inner.env_cache_last_refresh = Instant::now();
+        diff
+    }
+
+    /// Hold the environment's lock for the guard's lifetime; merges, commits and other
+    /// operations that check out or write to the environment's repo take it first.
+    pub async fn lock_environment(&self, name: &str) -> tokio::sync::OwnedMutexGuard<()> {
+        let lock = {
+            let mut inner = self.inner.write().await;
+            inner.env_locks.entry(name.to_string()).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+
     pub async fn find_environment(&self, name: &str) -> Option<Environment> {
         self.list_environments()
             .await
@@ -210,9 +369,12 @@ impl AppState {
             .find(|env| env.name == name)
     }
 
+    /// Create `raw_name` under the environments root: an empty repo with one initial commit,
+    /// or a clone of `clone_url` when given.
     pub async fn create_environment(
         &self,
         raw_name: &str,
+        clone_url: Option<&str>,
     ) -> Result<Environment, CreateEnvironmentError> {
         let name = raw_name.trim();
         if name.is_empty() {
@@ -221,6 +383,12 @@ impl AppState {
         if name.contains('/') || name.contains('\\') || name == "." || name == ".." {
             return Err(CreateEnvironmentError::InvalidName);
         }
+        let clone_url = clone_url.map(str::trim).filter(|url| !url.is_empty());
+        if let Some(url) = clone_url {
+            if !is_supported_clone_url(url) {
+                return Err(CreateEnvironmentError::InvalidCloneUrl(url.to_string()));
+            }
+        }
 
         let root = self.inner.read().await.config.environments_root.clone();
         tokio::fs::create_dir_all(&root)
@@ -235,43 +403,13 @@ impl AppState {
             .await
             .map_err(CreateEnvironmentError::CreateDirectory)?;
 
-        let init_output = Command::new("git")
-            .args(["init", "--initial-branch=main"])
-            .current_dir(&directory)
-            .output()
-            .await
-            .map_err(|e| CreateEnvironmentError::GitInit(e.to_string()))?;
-        if !init_output.status.success() {
-            let fallback_output = Command::new("git")
-                .args(["init"])
-                .current_dir(&directory)
-                .output()
-                .await
-                .map_err(|e| CreateEnvironmentError::GitInit(e.to_string()))?;
-            if !fallback_output.status.success() {
-                let stderr = String::from_utf8_lossy(&fallback_output.stderr).to_string();
-                return Err(CreateEnvironmentError::GitInit(stderr));
+        if let Some(url) = clone_url {
+            if let Err(e) = clone_repository(url, &directory).await {
+                let _ = tokio::fs::remove_dir_all(&directory).await;
+                return Err(e);
             }
-        }
-
-        let commit_output = Command::new("git")
-            .args([
-                "-c",
-                "user.name=slopcoder",
-                "-c",
-                "user.email=slopcoder@local",
-                "commit",
-                "--allow-empty",
-                "-m",
-                "Initialize repository",
-            ])
-            .current_dir(&directory)
-            .output()
-            .await
-            .map_err(|e| CreateEnvironmentError::GitInit(e.to_string()))?;
-        if !commit_output.status.success() {
-            let stderr = String::from_utf8_lossy(&commit_output.stderr).to_string();
-            return Err(CreateEnvironmentError::GitInit(stderr));
+        } else {
+            init_repository(&directory).await?;
         }
 
         let env = Environment {
@@ -286,15 +424,21 @@ impl AppState {
     }
 
     pub async fn ensure_environment_registered(&self, env_name: &str) -> Result<(), StateError> {
+        let state_root = {
+            let inner = self.inner.read().await;
+            if inner.tasks.has_environment(env_name) {
+                return Ok(());
+            }
+            inner.state_root.clone()
+        };
+
+        let env_state_dir = adopt_legacy_environment_dirs(&state_root, env_name).await;
         let mut inner = self.inner.write().await;
-        if inner.tasks.has_environment(env_name) {
-            return Ok(());
+        if !inner.tasks.has_environment(env_name) {
+            inner
+                .tasks
+                .register_environment(env_name.to_string(), env_state_dir);
         }
-
-        let env_state_dir = inner.state_root.join(sanitize_for_path(env_name));
-        inner
-            .tasks
-            .register_environment(env_name.to_string(), env_state_dir);
         Ok(())
     }
 
@@ -310,14 +454,161 @@ impl AppState {
         self.inner.read().await.config.worktrees_directory.clone()
     }
 
+    /// Settings from `env`'s `.slopcoder.yaml`, re-read whenever the file changes.
+    /// An unreadable or invalid file is logged and treated as empty.
+    pub async fn environment_settings(&self, env: &Environment) -> EnvironmentSettings {
+        let path = EnvironmentSettings::path(&env.directory);
+        let modified = tokio::fs::metadata(&path)
+            .await
+            .and_then(|meta| meta.modified())
+            .ok();
+        if let Some((cached_modified, settings)) =
+            self.inner.read().await.env_settings.get(&env.directory)
+        {
+            if *cached_modified == modified {
+                return settings.clone();
+            }
+        }
+
+        let settings = match EnvironmentSettings::load(&env.directory).await {
+            Ok(settings) => settings,
+            Err(e) => {
+                tracing::warn!(path = %path.display(), error = %e, "Ignoring environment settings");
+                EnvironmentSettings::default()
+            }
+        };
+        self.inner
+            .write()
+            .await
+            .env_settings
+            .insert(env.directory.clone(), (modified, settings.clone()));
+        settings
+    }
+
     pub async fn get_agent_config(&self) -> AnyAgentConfig {
         self.inner.read().await.agent_config.clone()
     }
 
+    pub async fn set_claude_approvals(&self, enabled: bool) {
+        self.inner.write().await.agent_config.claude.approvals = enabled;
+    }
+
     pub async fn get_branch_model(&self) -> String {
         self.inner.read().await.branch_model.clone()
     }
 
+    pub async fn set_record_terminals(&self, enabled: bool) {
+        self.inner.write().await.record_terminals = enabled;
+    }
+
+    pub async fn record_terminals(&self) -> bool {
+        self.inner.read().await.record_terminals
+    }
+
+    pub async fn set_terminal_idle_timeout(&self, limit: Option<Duration>) {
+        self.inner.write().await.terminal_idle_timeout = limit;
+    }
+
+    pub async fn terminal_idle_timeout(&self) -> Option<Duration> {
+        self.inner.read().await.terminal_idle_timeout
+    }
+
+    pub async fn set_debug_raw(&self, enabled: bool) {
+        self.inner.write().await.debug_raw = enabled;
+    }
+
+    pub async fn debug_raw(&self) -> bool {
+        self.inner.read().await.debug_raw
+    }
+
+    pub async fn set_compress_output(&self, enabled: bool) {
+        self.inner.write().await.compress_output = enabled;
+    }
+
+    pub async fn compress_output(&self) -> bool {
+        self.inner.read().await.compress_output
+    }
+
+    pub async fn set_max_diff_bytes(&self, max_diff_bytes: usize) {
+        self.inner.write().await.max_diff_bytes = max_diff_bytes;
+    }
+
+    pub async fn max_diff_bytes(&self) -> usize {
+        self.inner.read().await.max_diff_bytes
+    }
+
+    pub async fn set_max_output_bytes(&self, max_output_bytes: Option<u64>) {
+        self.inner.write().await.max_output_bytes = max_output_bytes;
+    }
+
+    pub async fn max_output_bytes(&self) -> Option<u64> {
+        self.inner.read().await.max_output_bytes
+    }
+
+    pub async fn set_run_timeout(&self, limit: Option<Duration>) {
+        self.inner.write().await.run_timeout = limit;
+    }
+
+    pub async fn run_timeout(&self) -> Option<Duration> {
+        self.inner.read().await.run_timeout
+    }
+
+    pub async fn set_spawn_retry(&self, retries: u32, backoff: Duration) {
+        let mut inner = self.inner.write().await;
+        inner.spawn_retries = retries;
+        inner.spawn_retry_backoff = backoff;
+    }
+
+    /// Retries allowed after a transient start failure and the initial backoff.
+    pub async fn spawn_retry(&self) -> (u32, Duration) {
+        let inner = self.inner.read().await;
+        (inner.spawn_retries, inner.spawn_retry_backoff)
+    }
+
+    pub async fn set_run_heartbeat(&self, interval: Option<Duration>) {
+        self.inner.write().await.run_heartbeat = interval;
+    }
+
+    pub async fn run_heartbeat(&self) -> Option<Duration> {
+        self.inner.read().await.run_heartbeat
+    }
+
+    pub async fn set_start_limiter(&self, limiter: Option<TokenBucket>) {
+        self.inner.write().await.start_limiter = limiter.map(Arc::new);
+    }
+
+    pub async fn start_limiter(&self) -> Option<Arc<TokenBucket>> {
+        self.inner.read().await.start_limiter.clone()
+    }
+
+    pub async fn set_task_logs(&self, logs: TaskLogs) {
+        self.inner.write().await.task_logs = logs;
+    }
+
+    pub async fn task_logs(&self) -> TaskLogs {
+        self.inner.read().await.task_logs.clone()
+    }
+
+    pub async fn set_host_labels(&self, labels: std::collections::HashMap<String, String>) {
+        self.inner.write().await.host_labels = labels;
+    }
+
+    pub async fn host_labels(&self) -> std::collections::HashMap<String, String> {
+        self.inner.read().await.host_labels.clone()
+    }
+
+    pub async fn cached_disk_usage(&self, path: &Path, exclude_git: bool) -> Option<u64> {
+        self.inner.read().await.disk_usage.get(path, exclude_git)
+    }
+
+    pub async fn store_disk_usage(&self, path: PathBuf, exclude_git: bool, bytes: u64) {
+        self.inner
+            .write()
+            .await
+            .disk_usage
+            .insert(path, exclude_git, bytes);
+    }
+
     pub async fn list_tasks(&self) -> Vec<Task> {
         let stale_cleanup = {
             let mut inner = self.inner.write().await;
@@ -344,6 +635,32 @@ impl AppState {
             .collect()
     }
 
+    pub async fn outbound_sender(&self) -> mpsc::UnboundedSender<AgentEnvelope> {
+        self.inner.read().await.outbound_tx.clone()
+    }
+
+    /// Slots for diff chunks waiting in the outbound queue; a diff stream takes one per chunk.
+    pub async fn diff_chunk_slots(&self) -> Arc<Semaphore> {
+        self.inner.read().await.diff_slots.clone()
+    }
+
+    /// The outbound queue, held by the connection currently writing to the coordinator.
+    pub async fn outbound_receiver(&self) -> Arc<Mutex<OutboundQueue>> {
+        self.inner.read().await.outbound_rx.clone()
+    }
+
+    pub async fn running_task_ids(&self) -> Vec<TaskId> {
+        self.inner
+            .read()
+            .await
+            .tasks
+            .list()
+            .into_iter()
+            .filter(|task| task.is_running())
+            .map(|task| task.id)
+            .collect()
+    }
+
     pub async fn get_task(&self, id: TaskId) -> Option<Task> {
         self.inner.read().await.tasks.get(id).cloned()
     }
@@ -366,6 +683,8 @@ impl AppState {
         let (removed, snapshot) = {
             let mut inner = self.inner.write().await;
             inner.interrupt_channels.remove(&id);
+            inner.approval_channels.remove(&id);
+            inner.merge_checks.remove(&id);
             inner.tasks.remove_and_snapshot(id)?
         };
         if let Some(snapshot) = snapshot {
@@ -397,15 +716,62 @@ impl AppState {
     }
 
     pub async fn rename_task(&self, id: TaskId, raw_name: &str) -> Result<Task, StateError> {
-        let name = raw_name.trim();
-        if name.is_empty() {
+        // Same normalization/length cap as names chosen at task creation.
+        let Some(name) = normalize_task_name(raw_name) else {
             return Err(StateError::InvalidTaskName);
+        };
+
+        let (updated, snapshot) = {
+            let mut inner = self.inner.write().await;
+            if let Some(task) = inner.tasks.get_mut(id) {
+                task.rename(name);
+                let updated = task.clone();
+                let snapshot = inner.tasks.save_task_snapshot(id)?;
+                (updated, snapshot)
+            } else {
+                return Err(StateError::TaskNotFound(id));
+            }
+        };
+        if let Some(snapshot) = snapshot {
+            Self::persist_snapshot(snapshot).await?;
+        }
+        Ok(updated)
+    }
+
+    /// Point the task at its relocated worktree; a working subdirectory moves with it.
+    pub async fn set_task_worktree_path(
+        &self,
+        id: TaskId,
+        new_path: PathBuf,
+    ) -> Result<Task, StateError> {
+        let (updated, snapshot) = {
+            let mut inner = self.inner.write().await;
+            if let Some(task) = inner.tasks.get_mut(id) {
+                if let Some(working_dir) = task.working_dir.take() {
+                    task.working_dir = Some(match working_dir.strip_prefix(&task.worktree_path) {
+                        Ok(relative) => new_path.join(relative),
+                        Err(_) => working_dir,
+                    });
+                }
+                task.worktree_path = new_path;
+                let updated = task.clone();
+                let snapshot = inner.tasks.save_task_snapshot(id)?;
+                (updated, snapshot)
+            } else {
+                return Err(StateError::TaskNotFound(id));
+            }
+        };
+        if let Some(snapshot) = snapshot {
+            Self::persist_snapshot(snapshot).await?;
         }
+        Ok(updated)
+    }
 
+    pub async fn set_task_tags(&self, id: TaskId, tags: Vec<String>) -> Result<Task, StateError> {
         let (updated, snapshot) = {
             let mut inner = self.inner.write().await;
             if let Some(task) = inner.tasks.get_mut(id) {
-                task.rename(name.to_string());
+                task.set_tags(tags);
                 let updated = task.clone();
                 let snapshot = inner.tasks.save_task_snapshot(id)?;
                 (updated, snapshot)
@@ -419,13 +785,47 @@ impl AppState {
         Ok(updated)
     }
 
+    /// A running in-place task in `environment`, if any.
+    pub async fn running_in_place_task(&self, environment: &str) -> Option<TaskId> {
+        let inner = self.inner.read().await;
+        Self::running_in_place_task_locked(&inner, environment)
+    }
+
+    fn running_in_place_task_locked(inner: &AppStateInner, environment: &str) -> Option<TaskId> {
+        inner
+            .tasks
+            .list_by_environment(environment)
+            .into_iter()
+            .find(|task| task.workspace_kind == TaskWorkspaceKind::Environment && task.is_running())
+            .map(|task| task.id)
+    }
+
+    /// Mark the task as running with a new prompt run. In-place tasks share the
+    /// environment's checkout, so one is refused while another in-place task in the same
+    /// environment is running; the environment lock is held for the check so the run
+    /// doesn't start in the middle of a merge or commit.
     pub async fn start_task_run(&self, id: TaskId, prompt: String) -> Result<(), StateError> {
+        let in_place_environment = match self.get_task(id).await {
+            Some(task) if task.workspace_kind == TaskWorkspaceKind::Environment => {
+                Some(task.environment)
+            }
+            _ => None,
+        };
+        let _env_guard = match in_place_environment.as_deref() {
+            Some(environment) => Some(self.lock_environment(environment).await),
+            None => None,
+        };
         let snapshot = {
             let mut inner = self.inner.write().await;
 
             if !inner.tasks.validate_task_worktree(id) {
                 return Err(StateError::WorktreeMissing(id));
             }
+            if let Some(environment) = in_place_environment {
+                if Self::running_in_place_task_locked(&inner, &environment).is_some() {
+                    return Err(StateError::EnvironmentBusy(environment));
+                }
+            }
 
             if let Some(task) = inner.tasks.get_mut(id) {
                 if task.can_run() {
@@ -444,16 +844,25 @@ impl AppState {
         Ok(())
     }
 
-    pub async fn complete_task_run(&self, id: TaskId, success: bool) -> Result<(), StateError> {
+    /// Finish the current run, recording the agent's exit code and killing signal if known.
+    pub async fn complete_task_run(
+        &self,
+        id: TaskId,
+        outcome: TaskOutcome,
+        exit_code: Option<i32>,
+        signal: Option<i32>,
+    ) -> Result<(), StateError> {
         let snapshot = {
             let mut inner = self.inner.write().await;
             let snapshot = if let Some(task) = inner.tasks.get_mut(id) {
-                task.complete_run(success);
+                task.record_run_exit(exit_code, signal);
+                task.finish_run(outcome);
                 inner.tasks.save_task_snapshot(id)?
             } else {
                 None
             };
             inner.interrupt_channels.remove(&id);
+            inner.approval_channels.remove(&id);
             snapshot
         };
         if let Some(snapshot) = snapshot {
@@ -475,6 +884,7 @@ impl AppState {
                 return Err(StateError::TaskNotFound(id));
             };
             inner.interrupt_channels.remove(&id);
+            inner.approval_channels.remove(&id);
             snapshot
         };
         if let Some(snapshot) = snapshot {
@@ -500,6 +910,212 @@ impl AppState {
         }
     }
 
+    /// Fire every registered interrupt channel, returning how many runs were signalled.
+    pub async fn send_interrupt_all(&self) -> usize {
+        let channels: Vec<_> = self
+            .inner
+            .write()
+            .await
+            .interrupt_channels
+            .drain()
+            .collect();
+        channels
+            .into_iter()
+            .filter_map(|(_, tx)| tx.send(()).ok())
+            .count()
+    }
+
+    pub async fn register_approval_channel(
+        &self,
+        id: TaskId,
+    ) -> tokio::sync::mpsc::UnboundedReceiver<(String, bool)> {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        self.inner.write().await.approval_channels.insert(id, tx);
+        rx
+    }
+
+    /// Forward an approval answer to the task's run loop. Returns false when the
+    /// task has no run accepting approvals.
+    pub async fn send_approval(&self, id: TaskId, call_id: String, approve: bool) -> bool {
+        match self.inner.read().await.approval_channels.get(&id) {
+            Some(tx) => tx.send((call_id, approve)).is_ok(),
+            None => false,
+        }
+    }
+
+    /// The recorded outcome of `command` for the task, if it last ran on commit `head`.
+    pub async fn cached_merge_check(
+        &self,
+        id: TaskId,
+        command: &str,
+        head: &str,
+    ) -> Option<Result<(), String>> {
+        match self.inner.read().await.merge_checks.get(&id) {
+            Some((cached_command, cached_head, result))
+                if cached_command == command && cached_head == head =>
+            {
+                Some(result.clone())
+            }
+            _ => None,
+        }
+    }
+
+    pub async fn record_merge_check(
+        &self,
+        id: TaskId,
+        command: String,
+        head: String,
+        result: Result<(), String>,
+    ) {
+        self.inner
+            .write()
+            .await
+            .merge_checks
+            .insert(id, (command, head, result));
+    }
+
+    /// Queue `prompt` behind the task's current run and return the queue length. Fails with
+    /// `TaskNotReady` when the task is not running, so the caller can start it directly.
+    pub async fn enqueue_prompt(&self, id: TaskId, prompt: String) -> Result<usize, StateError> {
+        let (queued, snapshot) = {
+            let mut inner = self.inner.write().await;
+            let Some(task) = inner.tasks.get_mut(id) else {
+                return Err(StateError::TaskNotFound(id));
+            };
+            if !task.is_running() {
+                return Err(StateError::TaskNotReady);
+            }
+            task.queued_prompts.push(prompt);
+            let queued = task.queued_prompts.len();
+            (queued, inner.tasks.save_task_snapshot(id)?)
+        };
+        if let Some(snapshot) = snapshot {
+            Self::persist_snapshot(snapshot).await?;
+        }
+        Ok(queued)
+    }
+
+    /// Take the next queued prompt if the task is free to run it.
+    pub async fn pop_queued_prompt(&self, id: TaskId) -> Result<Option<String>, StateError> {
+        let (prompt, snapshot) = {
+            let mut inner = self.inner.write().await;
+            let Some(task) = inner.tasks.get_mut(id) else {
+                return Err(StateError::TaskNotFound(id));
+            };
+            if !task.can_run() || task.queued_prompts.is_empty() {
+                return Ok(None);
+            }
+            let prompt = task.queued_prompts.remove(0);
+            (prompt, inner.tasks.save_task_snapshot(id)?)
+        };
+        if let Some(snapshot) = snapshot {
+            Self::persist_snapshot(snapshot).await?;
+        }
+        Ok(Some(prompt))
+    }
+
+    #[cfg(test)]
+    pub async fn queued_prompts(&self, id: TaskId) -> Vec<String> {
+        self.inner
+            .read()
+            .await
+            .tasks
+            .get(id)
+            .map(|task| task.queued_prompts.clone())
+            .unwrap_or_default()
+    }
+
+    /// Tasks with prompts still waiting in their queue.
+    pub async fn tasks_with_queued_prompts(&self) -> Vec<TaskId> {
+        self.inner
+            .read()
+            .await
+            .tasks
+            .list()
+            .into_iter()
+            .filter(|task| !task.queued_prompts.is_empty())
+            .map(|task| task.id)
+            .collect()
+    }
+
+    /// `(task, dependency)` for every task still held back by `depends_on`.
+    pub async fn tasks_awaiting_dependency(&self) -> Vec<(TaskId, TaskId)> {
+        self.inner
+            .read()
+            .await
+            .tasks
+            .list()
+            .into_iter()
+            .filter(|task| task.status == TaskStatus::Pending && task.history.is_empty())
+            .filter_map(|task| Some((task.id, task.depends_on?)))
+            .collect()
+    }
+
+    /// Mark a task that never started as `Failed`. Returns `false` if it has already run.
+    pub async fn fail_waiting_task(&self, id: TaskId) -> Result<bool, StateError> {
+        let snapshot = {
+            let mut inner = self.inner.write().await;
+            let Some(task) = inner.tasks.get_mut(id) else {
+                return Err(StateError::TaskNotFound(id));
+            };
+            if task.status != TaskStatus::Pending || !task.history.is_empty() {
+                return Ok(false);
+            }
+            task.status = TaskStatus::Failed;
+            inner.tasks.save_task_snapshot(id)?
+        };
+        if let Some(snapshot) = snapshot {
+            Self::persist_snapshot(snapshot).await?;
+        }
+        Ok(true)
+    }
+
+    /// Drop the queued prompt at `index` and return how many remain, or `None` when
+    /// `index` is out of range.
+    pub async fn cancel_queued_prompt(
+        &self,
+        id: TaskId,
+        index: usize,
+    ) -> Result<Option<usize>, StateError> {
+        let (remaining, snapshot) = {
+            let mut inner = self.inner.write().await;
+            let Some(task) = inner.tasks.get_mut(id) else {
+                return Err(StateError::TaskNotFound(id));
+            };
+            if index >= task.queued_prompts.len() {
+                return Ok(None);
+            }
+            task.queued_prompts.remove(index);
+            let remaining = task.queued_prompts.len();
+            (remaining, inner.tasks.save_task_snapshot(id)?)
+        };
+        if let Some(snapshot) = snapshot {
+            Self::persist_snapshot(snapshot).await?;
+        }
+        Ok(Some(remaining))
+    }
+
+    pub async fn clear_prompt_queue(&self, id: TaskId) -> Result<(), StateError> {
+        let snapshot = {
+            let mut inner = self.inner.write().await;
+            let Some(task) = inner.tasks.get_mut(id) else {
+                return Err(StateError::TaskNotFound(id));
+            };
+            task.queued_prompts.clear();
+            inner.tasks.save_task_snapshot(id)?
+        };
+        if let Some(snapshot) = snapshot {
+            Self::persist_snapshot(snapshot).await?;
+        }
+        Ok(())
+    }
+
+    /// True exactly once, for the first coordinator connection after startup, so queues
+    /// left over from before a restart are resumed a single time.
+    pub async fn take_queue_resume(&self) -> bool {
+        std::mem::take(&mut self.inner.write().await.resume_queues_pending)
+    }
+
     async fn finish_environment_refresh(&self, discovered: Vec<Environment>) {
         let mut inner = self.inner.write().await;
         inner.cached_environments = merge_environments(&inner.config.environments, discovered);
@@ -524,6 +1140,17 @@ impl AppState {
     }
 }
 
+fn diff_environment_names(
+    before: &[Environment],
+    after: &[Environment],
+) -> (Vec<String>, Vec<String>) {
+    let before: BTreeSet<&str> = before.iter().map(|env| env.name.as_str()).collect();
+    let after: BTreeSet<&str> = after.iter().map(|env| env.name.as_str()).collect();
+    let added = after.difference(&before).map(|s| s.to_string()).collect();
+    let removed = before.difference(&after).map(|s| s.to_string()).collect();
+    (added, removed)
+}
+
 fn merge_environments(
     configured: &[Environment],
     discovered: Vec<Environment>,
@@ -650,6 +1277,161 @@ async fn is_checked_out_repo(path: &Path) -> bool {
     String::from_utf8_lossy(&output.stdout).trim() == "true"
 }
 
+/// Directory name for an environment's task state and archives: the sanitized name (for
+/// readability) plus a hash of the original, since distinct names like `/a/b` and `/a-b`
+/// sanitize identically.
+pub(crate) fn environment_dir_name(env_name: &str) -> String {
+    // FNV-1a, which unlike `DefaultHasher` is guaranteed stable across Rust releases.
+    let hash = env_name.bytes().fold(0x811c_9dc5u32, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    });
+    format!("{}-{:08x}", sanitize_for_path(env_name), hash)
+}
+
+/// `git init` (on `main` when supported) plus an empty initial commit.
+async fn init_repository(directory: &Path) -> Result<(), CreateEnvironmentError> {
+    let init_output = Command::new("git")
+        .args(["init", "--initial-branch=main"])
+        .current_dir(directory)
+        .output()
+        .await
+        .map_err(|e| CreateEnvironmentError::GitInit(e.to_string()))?;
+    if !init_output.status.success() {
+        let fallback_output = Command::new("git")
+            .args(["init"])
+            .current_dir(directory)
+            .output()
+            .await
+            .map_err(|e| CreateEnvironmentError::GitInit(e.to_string()))?;
+        if !fallback_output.status.success() {
+            let stderr = String::from_utf8_lossy(&fallback_output.stderr).to_string();
+            return Err(CreateEnvironmentError::GitInit(stderr));
+        }
+    }
+
+    let commit_output = Command::new("git")
+        .args([
+            "-c",
+            "user.name=slopcoder",
+            "-c",
+            "user.email=slopcoder@local",
+            "commit",
+            "--allow-empty",
+            "-m",
+            "Initialize repository",
+        ])
+        .current_dir(directory)
+        .output()
+        .await
+        .map_err(|e| CreateEnvironmentError::GitInit(e.to_string()))?;
+    if !commit_output.status.success() {
+        let stderr = String::from_utf8_lossy(&commit_output.stderr).to_string();
+        return Err(CreateEnvironmentError::GitInit(stderr));
+    }
+    Ok(())
+}
+
+/// Whether `url` is a remote `git clone` may be pointed at: `https`, `http`, `ssh`, `git`, `file`,
+/// or scp-like `user@host:path`. Rejects option-like values and transports such as `ext::`.
+fn is_supported_clone_url(url: &str) -> bool {
+    if url.starts_with('-') || url.chars().any(char::is_whitespace) {
+        return false;
+    }
+    if let Some((scheme, rest)) = url.split_once("://") {
+        return matches!(scheme, "https" | "http" | "ssh" | "git" | "file") && !rest.is_empty();
+    }
+    match url.split_once(':') {
+        Some((user_host, path)) => {
+            user_host.contains('@')
+                && !user_host.contains('/')
+                && !path.is_empty()
+                && !path.starts_with(':')
+        }
+        None => false,
+    }
+}
+
+/// `git clone` `url` into the empty `directory`, never prompting for credentials and giving
+/// up after [`CLONE_TIMEOUT_SECS`].
+async fn clone_repository(url: &str, directory: &Path) -> Result<(), CreateEnvironmentError> {
+    // Keep any configured SSH command, but never let it ask for a password or passphrase.
+    let ssh_command = match std::env::var("GIT_SSH_COMMAND") {
+        Ok(command) if !command.trim().is_empty() => format!("{} -o BatchMode=yes", command),
+        _ => "ssh -o BatchMode=yes".to_string(),
+    };
+    let clone = Command::new("git")
+        .arg("clone")
+        .arg("--")
+        .arg(url)
+        .arg(directory)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("GIT_SSH_COMMAND", ssh_command)
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = match tokio::time::timeout(Duration::from_secs(CLONE_TIMEOUT_SECS), clone).await {
+        Ok(output) => output.map_err(|e| CreateEnvironmentError::CloneFailed(e.to_string()))?,
+        Err(_) => {
+            return Err(CreateEnvironmentError::CloneFailed(format!(
+                "git clone of {} timed out after {}s",
+                url, CLONE_TIMEOUT_SECS
+            )))
+        }
+    };
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    let auth_failed = [
+        "Authentication failed",
+        "could not read Username",
+        "Permission denied (publickey",
+        "terminal prompts disabled",
+    ]
+    .iter()
+    .any(|needle| stderr.contains(needle));
+    if auth_failed {
+        return Err(CreateEnvironmentError::CloneFailed(format!(
+            "authentication to {} failed; configure git credentials or an SSH key on this host ({})",
+            url, stderr
+        )));
+    }
+    Err(CreateEnvironmentError::CloneFailed(stderr))
+}
+
+/// Return `env_name`'s state directory under `state_root`, first renaming its state and
+/// archive directories from the old hash-less layout if only those exist.
+async fn adopt_legacy_environment_dirs(state_root: &Path, env_name: &str) -> PathBuf {
+    let dir_name = environment_dir_name(env_name);
+    let legacy_name = sanitize_for_path(env_name);
+    let archive_root = state_root.join("archive");
+    for parent in [state_root, archive_root.as_path()] {
+        let legacy = parent.join(&legacy_name);
+        let current = parent.join(&dir_name);
+        // The legacy state dir of an environment sanitized to `archive` is the archive root.
+        if legacy == archive_root
+            || !legacy.is_dir()
+            || tokio::fs::try_exists(&current).await.unwrap_or(true)
+        {
+            continue;
+        }
+        match tokio::fs::rename(&legacy, &current).await {
+            Ok(()) => tracing::info!(
+                environment = %env_name,
+                from = %legacy.display(),
+                to = %current.display(),
+                "Moved environment state to its hashed directory"
+            ),
+            Err(e) => tracing::warn!(
+                environment = %env_name,
+                error = %e,
+                "Failed to move legacy environment state directory"
+            ),
+        }
+    }
+    state_root.join(dir_name)
+}
+
 fn sanitize_for_path(value: &str) -> String {
     let mut out = String::new();
     for ch in value.chars() {
@@ -727,6 +1509,18 @@ mod tests {
         assert!(!names.contains(&root.join(".hidden-repo").to_string_lossy().to_string()));
     }
 
+    #[test]
+    fn test_diff_environment_names_reports_added_and_removed() {
+        let env = |name: &str| Environment {
+            name: name.to_string(),
+            directory: PathBuf::from(format!("/repos/{}", name)),
+        };
+        let (added, removed) =
+            diff_environment_names(&[env("a"), env("b")], &[env("b"), env("c"), env("d")]);
+        assert_eq!(added, vec!["c".to_string(), "d".to_string()]);
+        assert_eq!(removed, vec!["a".to_string()]);
+    }
+
     #[test]
     fn test_merge_environments_prefers_discovered_and_sorts() {
         let configured = vec![
@@ -792,5 +1586,78 @@ mod tests {
 
         let err = state.rename_task(task_id, "   ").await.unwrap_err();
         assert!(matches!(err, StateError::InvalidTaskName));
+
+        let renamed = state
+            .rename_task(
+                task_id,
+                "  Implement websocket auth and coordinator routing ",
+            )
+            .await
+            .unwrap();
+        assert_eq!(renamed.name, "Implement websocket");
+        assert_eq!(state.get_task(task_id).await.unwrap().name, renamed.name);
+    }
+
+    #[tokio::test]
+    async fn test_colliding_environment_names_get_distinct_state_dirs() {
+        let temp = TempDir::new().unwrap();
+        let config = EnvironmentConfig {
+            environments_root: temp.path().join("environments"),
+            worktrees_directory: temp.path().join("worktrees"),
+            environments: Vec::new(),
+        };
+        let state = AppState::new(config, None, 10, 100, "model".to_string())
+            .await
+            .unwrap();
+        assert_eq!(sanitize_for_path("/a/b"), sanitize_for_path("/a-b"));
+
+        state.ensure_environment_registered("/a/b").await.unwrap();
+        state.ensure_environment_registered("/a-b").await.unwrap();
+        let first = state.get_environment_directory("/a/b").await.unwrap();
+        let second = state.get_environment_directory("/a-b").await.unwrap();
+        assert_ne!(first, second);
+        assert_eq!(
+            first.file_name().unwrap().to_str().unwrap(),
+            environment_dir_name("/a/b")
+        );
+        assert!(environment_dir_name("/a/b").starts_with("a-b-"));
+    }
+
+    #[tokio::test]
+    async fn test_environment_settings_reload_when_file_appears() {
+        let temp = TempDir::new().unwrap();
+        let env_dir = temp.path().join("env");
+        init_repo(&env_dir).await;
+        let env = Environment {
+            name: "env".to_string(),
+            directory: env_dir.clone(),
+        };
+
+        let config = EnvironmentConfig {
+            environments_root: temp.path().join("environments"),
+            worktrees_directory: temp.path().join("worktrees"),
+            environments: vec![env.clone()],
+        };
+        let state = AppState::new(config, None, 10, 100, "model".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            state.environment_settings(&env).await,
+            EnvironmentSettings::default()
+        );
+
+        tokio::fs::write(
+            env_dir.join(".slopcoder.yaml"),
+            "default_agent: claude\ndefault_use_worktree: true\n",
+        )
+        .await
+        .unwrap();
+        let settings = state.environment_settings(&env).await;
+        assert_eq!(
+            settings.default_agent,
+            Some(slopcoder_core::anyagent::AgentKind::Claude)
+        );
+        assert_eq!(settings.default_use_worktree, Some(true));
     }
 }
@@ -0,0 +1,185 @@
+//! Per-task ring buffers of slopagent's own `tracing` output, served by `GetTaskLogs`.
+
+use chrono::Utc;
+use slopcoder_core::agent_rpc::TaskLogLine;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Write as _};
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Lines kept per task; the oldest are dropped first.
+pub const TASK_LOG_LINES: usize = 500;
+
+/// Tasks with buffered lines; the one logged to least recently is dropped first.
+const TASK_LOG_TASKS: usize = 256;
+
+/// Shared handle to the buffers filled by [`TaskLogLayer`].
+#[derive(Clone, Default)]
+pub struct TaskLogs {
+    inner: Arc<Mutex<Buffers>>,
+}
+
+#[derive(Default)]
+struct Buffers {
+    lines: HashMap<String, VecDeque<TaskLogLine>>,
+    /// Task ids by latest line, least recent first.
+    recency: VecDeque<String>,
+}
+
+impl TaskLogs {
+    /// A layer recording into these buffers.
+    pub fn layer(&self) -> TaskLogLayer {
+        TaskLogLayer { logs: self.clone() }
+    }
+
+    /// The newest `limit` lines logged for `task_id`, oldest first.
+    pub fn recent(&self, task_id: &str, limit: usize) -> Vec<TaskLogLine> {
+        let buffers = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(lines) = buffers.lines.get(task_id) else {
+            return Vec::new();
+        };
+        lines
+            .iter()
+            .skip(lines.len().saturating_sub(limit))
+            .cloned()
+            .collect()
+    }
+
+    fn push(&self, task_id: String, line: TaskLogLine) {
+        let mut buffers = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        buffers.recency.retain(|id| *id != task_id);
+        buffers.recency.push_back(task_id.clone());
+        if buffers.recency.len() > TASK_LOG_TASKS {
+            if let Some(evicted) = buffers.recency.pop_front() {
+                buffers.lines.remove(&evicted);
+            }
+        }
+        let lines = buffers.lines.entry(task_id).or_default();
+        if lines.len() == TASK_LOG_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+}
+
+/// Records events that carry a `task_id` field or happen inside a span that does.
+pub struct TaskLogLayer {
+    logs: TaskLogs,
+}
+
+/// Span extension holding the `task_id` the span was created with.
+struct TaskSpan(String);
+
+impl<S> Layer<S> for TaskLogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut visitor = LineVisitor::default();
+        attrs.record(&mut visitor);
+        if let (Some(task_id), Some(span)) = (visitor.task_id, ctx.span(id)) {
+            span.extensions_mut().insert(TaskSpan(task_id));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut visitor = LineVisitor::default();
+        event.record(&mut visitor);
+        let task_id = visitor.task_id.or_else(|| {
+            ctx.event_scope(event)?.find_map(|span| {
+                let extensions = span.extensions();
+                extensions.get::<TaskSpan>().map(|task| task.0.clone())
+            })
+        });
+        let Some(task_id) = task_id else {
+            return;
+        };
+        let metadata = event.metadata();
+        self.logs.push(
+            task_id,
+            TaskLogLine {
+                timestamp: Utc::now(),
+                level: metadata.level().to_string(),
+                target: metadata.target().to_string(),
+                message: visitor.message + &visitor.fields,
+            },
+        );
+    }
+}
+
+/// Collects an event's message, its `task_id` and the remaining fields as ` key=value`.
+#[derive(Default)]
+struct LineVisitor {
+    task_id: Option<String>,
+    message: String,
+    fields: String,
+}
+
+impl LineVisitor {
+    fn record(&mut self, field: &Field, value: String) {
+        match field.name() {
+            "message" => self.message = value,
+            "task_id" => self.task_id = Some(value),
+            name => {
+                let _ = write!(self.fields, " {}={}", name, value);
+            }
+        }
+    }
+}
+
+impl Visit for LineVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record(field, format!("{:?}", value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TaskLogs, TASK_LOG_LINES};
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn messages_in_a_task_span_are_kept_per_task() {
+        let logs = TaskLogs::default();
+        let subscriber = tracing_subscriber::registry().with(logs.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("before any task");
+            let span = tracing::info_span!("run_agent", task_id = "task-a");
+            let _entered = span.enter();
+            tracing::warn!(exit_code = 3, "Agent exited");
+            tracing::info!(task_id = "task-b", "Logged for another task");
+        });
+
+        let lines = logs.recent("task-a", 10);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].level, "WARN");
+        assert_eq!(lines[0].message, "Agent exited exit_code=3");
+        let other = logs.recent("task-b", 10);
+        assert_eq!(other.len(), 1);
+        assert_eq!(other[0].message, "Logged for another task");
+    }
+
+    #[test]
+    fn buffers_keep_only_the_newest_lines() {
+        let logs = TaskLogs::default();
+        let subscriber = tracing_subscriber::registry().with(logs.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            for n in 0..TASK_LOG_LINES + 5 {
+                tracing::info!(task_id = "task-a", "line {}", n);
+            }
+        });
+
+        let lines = logs.recent("task-a", usize::MAX);
+        assert_eq!(lines.len(), TASK_LOG_LINES);
+        assert_eq!(lines[0].message, "line 5");
+        assert_eq!(logs.recent("task-a", 2)[1].message, "line 504");
+    }
+}
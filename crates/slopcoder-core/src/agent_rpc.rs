@@ -2,11 +2,16 @@
 
 use crate::{
     anyagent::AgentKind,
-    environment::Environment,
-    task::{Task, TaskId},
+    environment::{Environment, WorktreeInfo},
+    events::UsageStats,
+    export::ExportFormat,
+    task::{Task, TaskId, TaskOutcome},
     AgentEvent,
 };
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,6 +22,52 @@ pub struct TaskOutputPageRequest {
     pub limit: usize,
 }
 
+/// Version string an agent CLI reported for `--version`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentVersion {
+    pub agent: AgentKind,
+    pub version: String,
+}
+
+/// Correlates an `AgentEnvelope::Request` with its `Response` or `Error`.
+///
+/// Serialized as the bare UUID string, as plain string ids were before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RequestId(pub Uuid);
+
+impl RequestId {
+    /// Create a new random request ID.
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Version of the coordinator<->agent protocol. Bump on incompatible envelope changes.
+///
+/// 2: `AgentRequest::Batch`, which the coordinator sends without checking for support.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Longest an environment's `merge_check_command` may run on the agent. The coordinator's
+/// merge and readiness requests wait longer than this.
+pub const MERGE_CHECK_TIMEOUT_SECS: u64 = 10 * 60;
+
+/// Longest a `git clone` for a new environment may run on the agent. The coordinator's
+/// create-environment requests wait longer than this.
+pub const CLONE_TIMEOUT_SECS: u64 = 10 * 60;
+
 /// Message envelope exchanged over the coordinator<->agent websocket.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -26,27 +77,52 @@ pub enum AgentEnvelope {
         hostname: String,
         #[serde(default)]
         display_name: Option<String>,
+        /// Agent CLIs found on the host; `None` when the agent did not probe.
+        #[serde(default)]
+        installed_agents: Option<Vec<AgentKind>>,
+        /// Versions of the installed agent CLIs, as probed at startup.
+        #[serde(default)]
+        agent_versions: Vec<AgentVersion>,
+        /// [`PROTOCOL_VERSION`] of the agent; `0` for agents predating versioning.
+        #[serde(default)]
+        protocol_version: u32,
+        /// Operator-assigned host attributes (`--label key=value`) used to route tasks.
+        #[serde(default)]
+        labels: HashMap<String, String>,
+        /// Tasks running on the host when it (re)connects, so the coordinator can route to
+        /// them without a fan-out.
+        #[serde(default)]
+        running_tasks: Vec<TaskId>,
     },
     /// Request sent by the coordinator.
     Request {
-        request_id: String,
+        request_id: RequestId,
         request: AgentRequest,
     },
     /// Response sent by the agent.
     Response {
-        request_id: String,
+        request_id: RequestId,
         response: AgentResponse,
     },
     /// Error response sent by the agent.
     Error {
-        request_id: String,
+        request_id: RequestId,
         status: u16,
         error: String,
     },
     /// Event emitted by a running task.
     TaskEvent { task_id: TaskId, event: AgentEvent },
     /// Open a remote terminal session for a task workspace.
-    TerminalOpen { terminal_id: Uuid, task_id: TaskId },
+    ///
+    /// `rows`/`cols` set the initial PTY size (80x24 when absent).
+    TerminalOpen {
+        terminal_id: Uuid,
+        task_id: TaskId,
+        #[serde(default)]
+        rows: Option<u16>,
+        #[serde(default)]
+        cols: Option<u16>,
+    },
     /// Send terminal stdin bytes to an existing remote terminal session.
     TerminalInput { terminal_id: Uuid, data: Vec<u8> },
     /// Resize an existing remote terminal session.
@@ -63,6 +139,24 @@ pub enum AgentEnvelope {
     TerminalClosed { terminal_id: Uuid },
     /// Remote terminal setup/runtime error.
     TerminalError { terminal_id: Uuid, error: String },
+    /// Part of the diff requested by `AgentRequest::StreamTaskDiff`.
+    DiffChunk {
+        stream_id: Uuid,
+        section: DiffSection,
+        data: String,
+    },
+    /// Last message of a diff stream; `error` is set when git failed part way.
+    DiffEnd {
+        stream_id: Uuid,
+        #[serde(default)]
+        error: Option<String>,
+    },
+    /// Heartbeat probe sent by the coordinator.
+    Ping { nonce: u64 },
+    /// Heartbeat reply echoing the probe's nonce.
+    Pong { nonce: u64 },
+    /// The coordinator is stopping; the agent should drain and reconnect later.
+    Shutdown,
 }
 
 /// Request payloads from coordinator -> agent.
@@ -70,12 +164,25 @@ pub enum AgentEnvelope {
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum AgentRequest {
     ListEnvironments,
+    /// Rediscover environments now instead of waiting for the cache to expire.
+    ReloadEnvironments,
     CreateEnvironment {
         name: String,
+        /// Clone this remote into the new environment instead of starting an empty repo.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        clone_url: Option<String>,
     },
     ListBranches {
         environment: String,
     },
+    /// Directory, current branch, branch count and running task count of one environment.
+    GetEnvironmentInfo {
+        environment: String,
+    },
+    /// Every git worktree of the environment's repository, whether or not a task owns it.
+    ListWorktrees {
+        environment: String,
+    },
     ListTasks,
     GetTask {
         task_id: TaskId,
@@ -90,29 +197,185 @@ pub enum AgentRequest {
     SendPrompt {
         task_id: TaskId,
         prompt: String,
+        #[serde(default)]
+        attachments: Vec<Attachment>,
+        /// Queue the prompt behind the current run instead of failing while the task is busy.
+        #[serde(default)]
+        queue: bool,
+    },
+    /// Drop the prompt at `index` (0 = next to run) from the task's queue.
+    CancelQueuedPrompt {
+        task_id: TaskId,
+        index: usize,
+    },
+    CancelAllQueued {
+        task_id: TaskId,
     },
     GetTaskOutput {
         task_id: TaskId,
         pagination: TaskOutputPageRequest,
     },
+    /// The task's last agent message, latest token usage and last run outcome.
+    GetTaskSummary {
+        task_id: TaskId,
+    },
+    /// Task record, events, diff, commits and agent settings in one support bundle.
+    ExportTaskBundle {
+        task_id: TaskId,
+    },
+    /// Recent slopagent log lines emitted while handling the task (not agent output).
+    GetTaskLogs {
+        task_id: TaskId,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    /// Render the full conversation as a document.
+    ExportTask {
+        task_id: TaskId,
+        #[serde(default)]
+        format: ExportFormat,
+    },
     GetTaskDiff {
         task_id: TaskId,
+        /// Per-file cap overriding the host's `--max-diff-bytes`.
+        #[serde(default)]
+        max_diff_bytes: Option<usize>,
+    },
+    /// Send the task diff as `DiffChunk` envelopes tagged with `stream_id`, then `DiffEnd`.
+    /// Answered with `Ack` once the stream has started.
+    StreamTaskDiff {
+        task_id: TaskId,
+        stream_id: Uuid,
     },
     InterruptTask {
         task_id: TaskId,
     },
+    /// Interrupt every running task on the host; answered by `InterruptedAll`.
+    InterruptAll,
+    /// Answer an `approval.requested` event of the task's current run.
+    RespondApproval {
+        task_id: TaskId,
+        call_id: String,
+        approve: bool,
+    },
     MergeTask {
         task_id: TaskId,
+        /// Branch to merge into; defaults to the task's `base_branch`.
+        #[serde(default)]
+        target: Option<String>,
+        /// Skip the environment's `merge_check_command`.
+        #[serde(default)]
+        skip_checks: bool,
     },
     GetMergeReadiness {
         task_id: TaskId,
     },
+    /// Cherry-pick `commits` (in order) from the task branch onto `target` in the environment repo.
+    CherryPickTask {
+        task_id: TaskId,
+        target: String,
+        commits: Vec<String>,
+    },
+    /// Revert the task's merge into its `base_branch` in the environment repo.
+    RevertMerge {
+        task_id: TaskId,
+    },
     ArchiveTask {
         task_id: TaskId,
     },
     DeleteTask {
         task_id: TaskId,
         force: bool,
+        /// Remove the worktree but leave the task's `merge_branch` in place.
+        #[serde(default)]
+        keep_branch: bool,
+    },
+    /// Delete every worktree task in `environment` that is not running, like `DeleteTask`.
+    DeleteCompletedTasks {
+        environment: String,
+        #[serde(default)]
+        force: bool,
+    },
+    /// `git worktree prune` the environment and report worktrees no task references;
+    /// `force` also removes those orphans. Answered by `WorktreesPruned`.
+    PruneWorktrees {
+        environment: String,
+        #[serde(default)]
+        force: bool,
+    },
+    ResetTaskWorktree {
+        task_id: TaskId,
+        #[serde(default)]
+        keep_untracked: bool,
+    },
+    /// `git worktree move` the task's worktree to `new_path` (absolute, must not exist).
+    MoveTaskWorktree {
+        task_id: TaskId,
+        new_path: PathBuf,
+    },
+    ApplyPatch {
+        task_id: TaskId,
+        diff: String,
+    },
+    CommitTask {
+        task_id: TaskId,
+        message: String,
+        #[serde(default)]
+        add_all: bool,
+    },
+    /// `git stash push --include-untracked` in the task workspace.
+    StashTaskChanges {
+        task_id: TaskId,
+    },
+    /// Pop the stash entry created by `StashTaskChanges` for this task.
+    UnstashTaskChanges {
+        task_id: TaskId,
+    },
+    GetTaskCommits {
+        task_id: TaskId,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    ReadWorktreeFile {
+        task_id: TaskId,
+        path: String,
+    },
+    SetTaskTags {
+        task_id: TaskId,
+        tags: Vec<String>,
+    },
+    GetTerminalRecording {
+        task_id: TaskId,
+    },
+    /// Bytes used by the task's workspace directory.
+    GetTaskDiskUsage {
+        task_id: TaskId,
+        #[serde(default)]
+        exclude_git: bool,
+    },
+    /// Bytes used by every isolated worktree task in an environment.
+    GetEnvironmentDiskUsage {
+        environment: String,
+        #[serde(default)]
+        exclude_git: bool,
+    },
+    /// Archived conversations for an environment, newest first.
+    ListArchives {
+        environment: String,
+    },
+    GetArchivedOutput {
+        environment: String,
+        task_id: TaskId,
+    },
+    /// Move an archived conversation back and re-create its task.
+    RestoreArchivedTask {
+        environment: String,
+        task_id: TaskId,
+    },
+    /// Several requests in one round-trip; answered by `AgentResponse::Batch` in the same order.
+    /// Batches cannot be nested.
+    Batch {
+        requests: Vec<AgentRequest>,
     },
 }
 
@@ -126,9 +389,20 @@ pub enum AgentResponse {
     Environment {
         environment: Environment,
     },
+    /// Environment names that appeared or disappeared during a reload.
+    EnvironmentsReloaded {
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
     Branches {
         branches: Vec<String>,
     },
+    EnvironmentInfo {
+        info: EnvironmentInfo,
+    },
+    Worktrees {
+        worktrees: Vec<WorktreeInfo>,
+    },
     Tasks {
         tasks: Vec<Task>,
     },
@@ -147,9 +421,29 @@ pub enum AgentResponse {
         total_events: usize,
         has_more_before: bool,
     },
+    TaskExport {
+        format: ExportFormat,
+        content: String,
+    },
+    TaskSummary {
+        /// Text of the most recent `agent_message` item, if the agent has replied.
+        last_message: Option<String>,
+        /// Usage reported by the most recent completed turn.
+        usage: Option<UsageStats>,
+        outcome: Option<TaskOutcome>,
+    },
+    TaskLogs {
+        lines: Vec<TaskLogLine>,
+    },
+    TaskBundle {
+        bundle: Box<TaskBundle>,
+    },
     TaskDiff {
         staged: String,
         unstaged: String,
+        /// Whether any file's diff was cut at the size cap.
+        #[serde(default)]
+        truncated: bool,
     },
     MergeResult {
         status: String,
@@ -158,6 +452,11 @@ pub enum AgentResponse {
     MergeReadiness {
         can_merge: bool,
         reason: Option<String>,
+        #[serde(default)]
+        conflicts: Vec<String>,
+        /// `git status` paths of the worktree or environment when uncommitted changes block the merge.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        dirty_files: Vec<String>,
     },
     ArchiveResult {
         status: String,
@@ -167,20 +466,256 @@ pub enum AgentResponse {
         status: String,
         message: String,
     },
+    ResetResult {
+        files_reset: usize,
+    },
+    BulkDeleteResult {
+        deleted: Vec<TaskId>,
+        skipped: Vec<SkippedTask>,
+    },
+    WorktreesPruned {
+        /// Worktree entries `git worktree prune` dropped, by their admin name.
+        pruned: Vec<String>,
+        /// Linked worktrees under the worktrees directory that no task references.
+        orphaned: Vec<PathBuf>,
+        /// The orphans that were removed (`force` only; locked ones are kept).
+        removed: Vec<PathBuf>,
+    },
+    WorktreeMoved {
+        worktree_path: String,
+    },
+    /// `status` is `committed` (with `sha`) or `nothing_to_commit`.
+    CommitResult {
+        status: String,
+        sha: Option<String>,
+    },
+    /// `status` is `picked`, or `conflict` when `conflict` could not be applied (and was aborted).
+    CherryPickResult {
+        status: String,
+        applied: Vec<String>,
+        conflict: Option<String>,
+        message: String,
+    },
+    /// `sha` is the commit that undid the merge (the last one when a range was reverted).
+    RevertResult {
+        sha: String,
+        message: String,
+    },
+    /// Number of running tasks that were sent an interrupt.
+    InterruptedAll {
+        interrupted: usize,
+    },
+    /// `status` is `delivered`, or `unsupported` when the task's agent takes no approvals.
+    ApprovalResult {
+        status: String,
+    },
+    /// Prompts waiting behind the task's current run after a queue change.
+    QueuedPrompts {
+        queued: usize,
+    },
+    /// `status` is `stashed`, `nothing_to_stash`, or `unstashed`.
+    StashResult {
+        status: String,
+        message: String,
+    },
+    TaskCommits {
+        commits: Vec<CommitInfo>,
+    },
+    /// `content` is `None` when the file is binary or larger than the read cap.
+    WorktreeFile {
+        path: String,
+        size: u64,
+        binary: bool,
+        too_large: bool,
+        content: Option<String>,
+    },
+    /// Contents of the most recent asciinema recording for a task.
+    TerminalRecording {
+        path: String,
+        content: String,
+    },
+    DiskUsage {
+        task_id: TaskId,
+        bytes: u64,
+    },
+    EnvironmentDiskUsage {
+        total_bytes: u64,
+        tasks: Vec<TaskDiskUsage>,
+    },
+    Archives {
+        archives: Vec<ArchivedTask>,
+    },
+    ArchivedOutput {
+        task_id: TaskId,
+        events: Vec<AgentEvent>,
+    },
+    RestoredTask {
+        task: Task,
+    },
+    /// One result per request of an `AgentRequest::Batch`, in request order.
+    Batch {
+        results: Vec<Result<AgentResponse, BatchError>>,
+    },
     Ack,
 }
 
+/// Which half of a task diff a `DiffChunk` belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffSection {
+    Staged,
+    Unstaged,
+}
+
+/// Failure of one request inside a batch, carrying what `AgentEnvelope::Error` would.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchError {
+    pub status: u16,
+    pub error: String,
+}
+
+/// Everything a host knows about one task, for attaching to bug reports.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskBundle {
+    pub task: Task,
+    pub events: Vec<AgentEvent>,
+    /// Uncommitted and committed changes against the base branch; `None` if it could not be read.
+    pub diff: Option<BundleDiff>,
+    pub commits: Vec<CommitInfo>,
+    /// Launch settings for the task's agent, with `env` values redacted.
+    pub agent_config: serde_json::Value,
+    /// Parts of the bundle that could not be collected, e.g. the diff of a removed worktree.
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleDiff {
+    pub staged: String,
+    pub unstaged: String,
+    pub truncated: bool,
+}
+
+/// Details of one environment, answered by `GetEnvironmentInfo`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvironmentInfo {
+    pub name: String,
+    pub directory: PathBuf,
+    pub current_branch: String,
+    pub branch_count: usize,
+    /// Tasks in the environment that are currently running.
+    pub active_tasks: usize,
+}
+
+/// One of slopagent's own diagnostic log lines, attributed to a task.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskLogLine {
+    pub timestamp: DateTime<Utc>,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+}
+
+/// Size of one task workspace.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskDiskUsage {
+    pub task_id: TaskId,
+    pub bytes: u64,
+}
+
+/// A task left in place by a bulk delete, with why.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedTask {
+    pub task_id: TaskId,
+    pub reason: String,
+}
+
+/// A conversation moved to the archive by archive/delete.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchivedTask {
+    pub task_id: TaskId,
+    /// Modification time of the archived transcript.
+    pub archived_at: DateTime<Utc>,
+}
+
+/// A commit on a task branch, as reported by `git log`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitInfo {
+    pub sha: String,
+    pub author: String,
+    pub timestamp: DateTime<Utc>,
+    pub subject: String,
+}
+
 /// Task creation payload from coordinator -> agent.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentCreateTaskRequest {
     pub environment: String,
     #[serde(default)]
     pub name: Option<String>,
+    /// `None` defers to the environment's `.slopcoder.yaml`, then to in-place.
     #[serde(default)]
-    pub use_worktree: bool,
+    pub use_worktree: Option<bool>,
     #[serde(default)]
     pub web_search: bool,
     pub prompt: String,
     #[serde(default)]
     pub agent: Option<AgentKind>,
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Directory inside the workspace the agent runs from (e.g. `packages/api`).
+    #[serde(default)]
+    pub subdir: Option<String>,
+    /// Branch new worktrees start from; defaults to the environment's current branch.
+    #[serde(default)]
+    pub base_branch: Option<String>,
+    /// Check out this existing branch into the worktree instead of creating `task/<slug>-<id>`.
+    #[serde(default)]
+    pub existing_branch: Option<String>,
+    /// Run every check and report the planned workspace without creating it or starting the agent.
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Rate-limit priority for the new task (see `Task::priority`).
+    #[serde(default)]
+    pub priority: i32,
+    /// Files for the first prompt; rejected for agents without attachment support.
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    /// Hold the task in `Pending` until this task on the same host completes successfully.
+    #[serde(default)]
+    pub depends_on: Option<TaskId>,
+}
+
+/// A file sent along with a prompt.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub filename: String,
+    pub mime: String,
+    /// File contents, base64-encoded (standard alphabet, padded).
+    pub base64: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_id_is_a_plain_uuid_on_the_wire() {
+        let request_id = RequestId::new();
+        let envelope = AgentEnvelope::Request {
+            request_id,
+            request: AgentRequest::ListEnvironments,
+        };
+        let json = serde_json::to_value(&envelope).expect("serialize");
+        assert_eq!(json["request_id"], request_id.0.to_string());
+
+        let malformed = serde_json::json!({
+            "type": "error",
+            "request_id": "not-a-uuid",
+            "status": 500,
+            "error": "boom",
+        });
+        assert!(serde_json::from_value::<AgentEnvelope>(malformed).is_err());
+    }
 }
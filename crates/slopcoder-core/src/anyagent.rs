@@ -5,10 +5,14 @@ use crate::codex_agent::CodexAgent;
 use crate::cursor_agent::CursorAgent;
 use crate::events::AgentEvent;
 use crate::gemini_agent::GeminiAgent;
+use crate::jsonl::{RawOutputLog, DEFAULT_MAX_LINE_BYTES};
+#[cfg(feature = "test-mock")]
+use crate::mock_agent::MockAgent;
 use crate::opencode_agent::OpencodeAgent;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 use uuid::Uuid;
 
@@ -26,6 +30,26 @@ pub enum AgentError {
 
     #[error("No session ID received from agent")]
     NoSessionId,
+
+    #[error("Agent does not support tool approvals")]
+    ApprovalsUnsupported,
+}
+
+impl AgentError {
+    /// Whether a failed spawn or resume might succeed if tried again.
+    ///
+    /// A missing or non-executable CLI binary never will, and neither will a bad
+    /// script or configuration.
+    pub fn is_transient_spawn_failure(&self) -> bool {
+        match self {
+            AgentError::ProcessError(_) => true,
+            AgentError::SpawnError(e) => !matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied
+            ),
+            _ => false,
+        }
+    }
 }
 
 /// Result of running the agent.
@@ -37,6 +61,8 @@ pub struct AgentResult {
     pub success: bool,
     /// Exit code if available.
     pub exit_code: Option<i32>,
+    /// Signal that killed the process (e.g. 9 for an OOM kill), on Unix.
+    pub signal: Option<i32>,
 }
 
 /// Which agent implementation to use.
@@ -48,6 +74,9 @@ pub enum AgentKind {
     Cursor,
     Opencode,
     Gemini,
+    /// Scripted replay agent used by tests; never advertised or probed.
+    #[cfg(feature = "test-mock")]
+    Mock,
 }
 
 impl Default for AgentKind {
@@ -56,6 +85,48 @@ impl Default for AgentKind {
     }
 }
 
+impl AgentKind {
+    /// Every supported agent kind.
+    pub const ALL: [AgentKind; 5] = [
+        AgentKind::Codex,
+        AgentKind::Claude,
+        AgentKind::Cursor,
+        AgentKind::Opencode,
+        AgentKind::Gemini,
+    ];
+
+    /// Lowercase identifier, matching the serde representation.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AgentKind::Codex => "codex",
+            AgentKind::Claude => "claude",
+            AgentKind::Cursor => "cursor",
+            AgentKind::Opencode => "opencode",
+            AgentKind::Gemini => "gemini",
+            #[cfg(feature = "test-mock")]
+            AgentKind::Mock => "mock",
+        }
+    }
+
+    /// Whether prompts for this agent may carry file attachments (see
+    /// [`AnyAgentConfig::attach_files`]).
+    pub fn supports_attachments(self) -> bool {
+        match self {
+            AgentKind::Codex | AgentKind::Claude | AgentKind::Gemini => true,
+            AgentKind::Cursor | AgentKind::Opencode => false,
+            #[cfg(feature = "test-mock")]
+            AgentKind::Mock => true,
+        }
+    }
+}
+
+/// A prompt attachment already written to disk for the agent to read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttachedFile {
+    pub path: PathBuf,
+    pub mime: String,
+}
+
 /// Configuration for running the Codex agent.
 #[derive(Debug, Clone)]
 pub struct CodexAgentConfig {
@@ -65,6 +136,12 @@ pub struct CodexAgentConfig {
     pub model: Option<String>,
     /// Additional flags to pass to codex.
     pub extra_args: Vec<String>,
+    /// Longest stdout line parsed before it is replaced by a truncated item.
+    pub max_line_bytes: usize,
+    /// When set, every raw stdout line is also appended to this file.
+    pub raw_output: Option<RawOutputLog>,
+    /// Extra environment variables for the spawned CLI.
+    pub env: HashMap<String, String>,
 }
 
 impl Default for CodexAgentConfig {
@@ -73,6 +150,9 @@ impl Default for CodexAgentConfig {
             codex_path: "codex".to_string(),
             model: None,
             extra_args: Vec::new(),
+            max_line_bytes: DEFAULT_MAX_LINE_BYTES,
+            raw_output: None,
+            env: HashMap::new(),
         }
     }
 }
@@ -86,6 +166,14 @@ pub struct ClaudeAgentConfig {
     pub model: Option<String>,
     /// Additional flags to pass to claude.
     pub extra_args: Vec<String>,
+    /// Longest stdout line parsed before it is replaced by a truncated item.
+    pub max_line_bytes: usize,
+    /// When set, every raw stdout line is also appended to this file.
+    pub raw_output: Option<RawOutputLog>,
+    /// Extra environment variables for the spawned CLI.
+    pub env: HashMap<String, String>,
+    /// Ask for approval over stdin before each tool call instead of skipping permission checks.
+    pub approvals: bool,
 }
 
 impl Default for ClaudeAgentConfig {
@@ -94,6 +182,10 @@ impl Default for ClaudeAgentConfig {
             claude_path: "claude".to_string(),
             model: None,
             extra_args: Vec::new(),
+            max_line_bytes: DEFAULT_MAX_LINE_BYTES,
+            raw_output: None,
+            env: HashMap::new(),
+            approvals: false,
         }
     }
 }
@@ -107,6 +199,12 @@ pub struct CursorAgentConfig {
     pub model: Option<String>,
     /// Additional flags to pass to cursor-agent.
     pub extra_args: Vec<String>,
+    /// Longest stdout line parsed before it is replaced by a truncated item.
+    pub max_line_bytes: usize,
+    /// When set, every raw stdout line is also appended to this file.
+    pub raw_output: Option<RawOutputLog>,
+    /// Extra environment variables for the spawned CLI.
+    pub env: HashMap<String, String>,
 }
 
 impl Default for CursorAgentConfig {
@@ -115,6 +213,9 @@ impl Default for CursorAgentConfig {
             cursor_path: "cursor-agent".to_string(),
             model: None,
             extra_args: Vec::new(),
+            max_line_bytes: DEFAULT_MAX_LINE_BYTES,
+            raw_output: None,
+            env: HashMap::new(),
         }
     }
 }
@@ -128,6 +229,12 @@ pub struct OpencodeAgentConfig {
     pub model: String,
     /// Additional flags to pass to opencode.
     pub extra_args: Vec<String>,
+    /// Longest stdout line parsed before it is replaced by a truncated item.
+    pub max_line_bytes: usize,
+    /// When set, every raw stdout line is also appended to this file.
+    pub raw_output: Option<RawOutputLog>,
+    /// Extra environment variables for the spawned CLI.
+    pub env: HashMap<String, String>,
 }
 
 impl Default for OpencodeAgentConfig {
@@ -136,6 +243,9 @@ impl Default for OpencodeAgentConfig {
             opencode_path: "opencode".to_string(),
             model: "litellm-guha-anderson/boa".to_string(),
             extra_args: Vec::new(),
+            max_line_bytes: DEFAULT_MAX_LINE_BYTES,
+            raw_output: None,
+            env: HashMap::new(),
         }
     }
 }
@@ -149,6 +259,12 @@ pub struct GeminiAgentConfig {
     pub model: Option<String>,
     /// Additional flags to pass to gemini.
     pub extra_args: Vec<String>,
+    /// Longest stdout line parsed before it is replaced by a truncated item.
+    pub max_line_bytes: usize,
+    /// When set, every raw stdout line is also appended to this file.
+    pub raw_output: Option<RawOutputLog>,
+    /// Extra environment variables for the spawned CLI.
+    pub env: HashMap<String, String>,
 }
 
 impl Default for GeminiAgentConfig {
@@ -157,6 +273,36 @@ impl Default for GeminiAgentConfig {
             gemini_path: "gemini".to_string(),
             model: None,
             extra_args: Vec::new(),
+            max_line_bytes: DEFAULT_MAX_LINE_BYTES,
+            raw_output: None,
+            env: HashMap::new(),
+        }
+    }
+}
+
+/// Configuration for the scripted mock agent.
+#[cfg(feature = "test-mock")]
+#[derive(Debug, Clone)]
+pub struct MockAgentConfig {
+    /// JSONL file of `AgentEvent`s to replay, relative to the working directory unless absolute.
+    pub script_path: PathBuf,
+    /// Reported `AgentResult::success` once the script is exhausted.
+    pub success: bool,
+    /// Reported exit code.
+    pub exit_code: Option<i32>,
+    /// Spawns and resumes left to fail with a `ProcessError` before the script is replayed.
+    /// Shared between clones, so a test can arm a running host's config.
+    pub spawn_failures: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+#[cfg(feature = "test-mock")]
+impl Default for MockAgentConfig {
+    fn default() -> Self {
+        Self {
+            script_path: "mock-agent.jsonl".into(),
+            success: true,
+            exit_code: Some(0),
+            spawn_failures: Default::default(),
         }
     }
 }
@@ -169,6 +315,8 @@ pub struct AnyAgentConfig {
     pub cursor: CursorAgentConfig,
     pub opencode: OpencodeAgentConfig,
     pub gemini: GeminiAgentConfig,
+    #[cfg(feature = "test-mock")]
+    pub mock: MockAgentConfig,
 }
 
 impl Default for AnyAgentConfig {
@@ -179,10 +327,233 @@ impl Default for AnyAgentConfig {
             cursor: CursorAgentConfig::default(),
             opencode: OpencodeAgentConfig::default(),
             gemini: GeminiAgentConfig::default(),
+            #[cfg(feature = "test-mock")]
+            mock: MockAgentConfig::default(),
         }
     }
 }
 
+impl AnyAgentConfig {
+    /// Binary used to launch the given agent kind.
+    pub fn binary_path(&self, kind: AgentKind) -> &str {
+        match kind {
+            AgentKind::Codex => &self.codex.codex_path,
+            AgentKind::Claude => &self.claude.claude_path,
+            AgentKind::Cursor => &self.cursor.cursor_path,
+            AgentKind::Opencode => &self.opencode.opencode_path,
+            AgentKind::Gemini => &self.gemini.gemini_path,
+            // The mock runs in-process; report its script so callers have something to show.
+            #[cfg(feature = "test-mock")]
+            AgentKind::Mock => self.mock.script_path.to_str().unwrap_or("mock"),
+        }
+    }
+
+    /// Whether runs of `kind` pause on `ApprovalRequested` and take a response.
+    ///
+    /// Claude does when started with [`ClaudeAgentConfig::approvals`]; the other CLIs take no
+    /// approval answers in their non-interactive JSON modes.
+    pub fn supports_approvals(&self, kind: AgentKind) -> bool {
+        match kind {
+            AgentKind::Claude => self.claude.approvals,
+            #[cfg(feature = "test-mock")]
+            AgentKind::Mock => true,
+            _ => false,
+        }
+    }
+
+    /// Override the model used by `kind`.
+    pub fn set_model(&mut self, kind: AgentKind, model: String) {
+        match kind {
+            AgentKind::Codex => self.codex.model = Some(model),
+            AgentKind::Claude => self.claude.model = Some(model),
+            AgentKind::Cursor => self.cursor.model = Some(model),
+            AgentKind::Opencode => self.opencode.model = model,
+            AgentKind::Gemini => self.gemini.model = Some(model),
+            #[cfg(feature = "test-mock")]
+            AgentKind::Mock => {}
+        }
+    }
+
+    /// Add environment variables for every agent, replacing existing values for the same names.
+    pub fn extend_env(&mut self, env: &HashMap<String, String>) {
+        for agent_env in [
+            &mut self.codex.env,
+            &mut self.claude.env,
+            &mut self.cursor.env,
+            &mut self.opencode.env,
+            &mut self.gemini.env,
+        ] {
+            agent_env.extend(env.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+    }
+
+    /// Hand `files` to the next run of `kind` and return the prompt to send with them.
+    ///
+    /// Codex takes images through `--image`; every other file is listed at the end of the
+    /// prompt, as `@path` references for Gemini and as plain paths for Claude to read.
+    pub fn attach_files(
+        &mut self,
+        kind: AgentKind,
+        prompt: &str,
+        files: &[AttachedFile],
+    ) -> String {
+        let mut listed = Vec::new();
+        for file in files {
+            if kind == AgentKind::Codex && file.mime.starts_with("image/") {
+                self.codex.extra_args.push("--image".to_string());
+                self.codex
+                    .extra_args
+                    .push(file.path.to_string_lossy().into_owned());
+            } else {
+                listed.push(&file.path);
+            }
+        }
+        if listed.is_empty() {
+            return prompt.to_string();
+        }
+
+        let mut prompt = format!("{}\n\nAttached files:", prompt.trim_end());
+        for path in listed {
+            let path = path.to_string_lossy();
+            if kind == AgentKind::Gemini {
+                prompt.push_str(&format!("\n@{}", path));
+            } else {
+                prompt.push_str(&format!("\n- {}", path));
+            }
+        }
+        prompt
+    }
+
+    /// Give the next run of `kind` standing `instructions` and return the prompt to send
+    /// with them.
+    ///
+    /// Claude takes them through `--append-system-prompt`; the other CLIs have no
+    /// system-prompt flag, so the instructions are prepended to the prompt between markers.
+    pub fn add_instructions(
+        &mut self,
+        kind: AgentKind,
+        prompt: &str,
+        instructions: &str,
+    ) -> String {
+        let instructions = instructions.trim();
+        if instructions.is_empty() {
+            return prompt.to_string();
+        }
+        if kind == AgentKind::Claude {
+            self.claude
+                .extra_args
+                .push("--append-system-prompt".to_string());
+            self.claude.extra_args.push(instructions.to_string());
+            return prompt.to_string();
+        }
+        format!(
+            "<environment-instructions>\n{}\n</environment-instructions>\n\n{}",
+            instructions, prompt
+        )
+    }
+
+    /// Launch settings for `kind` as JSON for diagnostics, with every `env` value
+    /// replaced by `<redacted>`.
+    pub fn redacted_summary(&self, kind: AgentKind) -> serde_json::Value {
+        let (model, extra_args, env): (Option<&str>, &[String], Option<&HashMap<String, String>>) =
+            match kind {
+                AgentKind::Codex => (
+                    self.codex.model.as_deref(),
+                    &self.codex.extra_args,
+                    Some(&self.codex.env),
+                ),
+                AgentKind::Claude => (
+                    self.claude.model.as_deref(),
+                    &self.claude.extra_args,
+                    Some(&self.claude.env),
+                ),
+                AgentKind::Cursor => (
+                    self.cursor.model.as_deref(),
+                    &self.cursor.extra_args,
+                    Some(&self.cursor.env),
+                ),
+                AgentKind::Opencode => (
+                    Some(self.opencode.model.as_str()),
+                    &self.opencode.extra_args,
+                    Some(&self.opencode.env),
+                ),
+                AgentKind::Gemini => (
+                    self.gemini.model.as_deref(),
+                    &self.gemini.extra_args,
+                    Some(&self.gemini.env),
+                ),
+                #[cfg(feature = "test-mock")]
+                AgentKind::Mock => (None, &[], None),
+            };
+        let env: serde_json::Map<String, serde_json::Value> = env
+            .into_iter()
+            .flatten()
+            .map(|(name, _)| (name.clone(), "<redacted>".into()))
+            .collect();
+        serde_json::json!({
+            "agent": kind.as_str(),
+            "binary_path": self.binary_path(kind),
+            "model": model,
+            "extra_args": extra_args,
+            "env": env,
+        })
+    }
+
+    /// Tee raw stdout of whichever agent runs next into `log`.
+    pub fn set_raw_output(&mut self, log: Option<RawOutputLog>) {
+        self.codex.raw_output = log.clone();
+        self.claude.raw_output = log.clone();
+        self.cursor.raw_output = log.clone();
+        self.opencode.raw_output = log.clone();
+        self.gemini.raw_output = log;
+    }
+}
+
+/// Describe agent environment variables for logs without exposing their values.
+pub fn redact_env(env: &HashMap<String, String>) -> String {
+    let mut names: Vec<&str> = env.keys().map(String::as_str).collect();
+    names.sort_unstable();
+    names
+        .iter()
+        .map(|name| format!("{}=<redacted>", name))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Error for a failed CLI launch, naming the agent when its binary is missing.
+pub(crate) fn spawn_error(
+    kind: AgentKind,
+    binary: &str,
+    working_dir: &Path,
+    err: std::io::Error,
+) -> AgentError {
+    // A missing working directory also surfaces as NotFound; only blame the CLI when the
+    // directory is there.
+    if err.kind() == std::io::ErrorKind::NotFound && working_dir.is_dir() {
+        AgentError::ProcessError(format!(
+            "{} CLI not found ({}); is it installed and on PATH?",
+            kind.as_str(),
+            binary
+        ))
+    } else {
+        AgentError::SpawnError(err)
+    }
+}
+
+/// Signal that terminated a process, if it was killed by one.
+pub(crate) fn exit_signal(status: &std::process::ExitStatus) -> Option<i32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        status.signal()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = status;
+        None
+    }
+}
+
 /// A running agent process with streaming output.
 #[async_trait]
 pub trait AnyAgent: Send {
@@ -196,6 +567,11 @@ pub trait AnyAgent: Send {
     fn try_wait(&mut self) -> Result<Option<std::process::ExitStatus>, AgentError>;
     /// Get the session ID if available.
     fn session_id(&self) -> Option<Uuid>;
+    /// Answer an `ApprovalRequested` event; only agents with
+    /// [`AgentKind::supports_approvals`] override this.
+    async fn respond_approval(&mut self, _call_id: &str, _approve: bool) -> Result<(), AgentError> {
+        Err(AgentError::ApprovalsUnsupported)
+    }
 }
 
 /// Spawn a new agent for a fresh task.
@@ -228,10 +604,19 @@ pub async fn spawn_anyagent(
             let agent = GeminiAgent::spawn(&config.gemini, working_dir, prompt, web_search).await?;
             Ok(Box::new(agent))
         }
+        #[cfg(feature = "test-mock")]
+        AgentKind::Mock => {
+            let agent = MockAgent::spawn(&config.mock, working_dir, prompt, web_search).await?;
+            Ok(Box::new(agent))
+        }
     }
 }
 
 /// Spawn an agent to resume an existing session.
+///
+/// The session may end mid-turn when slopagent died during the previous run. Each CLI
+/// resumes from the last message it persisted, so the interrupted turn is abandoned rather
+/// than continued; callers should not expect its pending tool calls to complete.
 pub async fn resume_anyagent(
     kind: AgentKind,
     config: &AnyAgentConfig,
@@ -276,6 +661,13 @@ pub async fn resume_anyagent(
                     .await?;
             Ok(Box::new(agent))
         }
+        #[cfg(feature = "test-mock")]
+        AgentKind::Mock => {
+            let agent =
+                MockAgent::resume(&config.mock, working_dir, session_id, prompt, web_search)
+                    .await?;
+            Ok(Box::new(agent))
+        }
     }
 }
 
@@ -288,6 +680,169 @@ mod tests {
         assert_eq!(AgentKind::default(), AgentKind::Codex);
     }
 
+    #[tokio::test]
+    async fn test_missing_binary_reports_not_installed() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let mut config = AnyAgentConfig::default();
+        config.codex.codex_path = dir
+            .path()
+            .join("no-such-codex")
+            .to_string_lossy()
+            .into_owned();
+
+        let err = match spawn_anyagent(AgentKind::Codex, &config, dir.path(), "hi", false).await {
+            Ok(_) => panic!("spawning a nonexistent binary should fail"),
+            Err(err) => err,
+        };
+        match err {
+            AgentError::ProcessError(message) => {
+                assert!(message.starts_with("codex CLI not found"), "{}", message);
+                assert!(message.contains("no-such-codex"), "{}", message);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_spawned_agent_sees_injected_env() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // A stand-in CLI that reports the variable back as an agent message.
+        let dir = tempfile::tempdir().expect("temp dir");
+        let script = dir.path().join("fake-codex");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\nprintf '{\"type\":\"item.completed\",\"item\":{\"id\":\"1\",\"type\":\"agent_message\",\"text\":\"%s\"}}\\n' \"$SLOPCODER_TEST_PROXY\"\n",
+        )
+        .expect("write script");
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut config = AnyAgentConfig::default();
+        config.codex.codex_path = script.to_string_lossy().into_owned();
+        let env = HashMap::from([(
+            "SLOPCODER_TEST_PROXY".to_string(),
+            "http://proxy.internal:3128".to_string(),
+        )]);
+        config.extend_env(&env);
+
+        let mut agent = spawn_anyagent(AgentKind::Codex, &config, dir.path(), "hi", false)
+            .await
+            .expect("spawn fake codex");
+        match agent.next_event().await {
+            Some(Ok(AgentEvent::ItemCompleted { item })) => {
+                assert_eq!(item.text.as_deref(), Some("http://proxy.internal:3128"));
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+        assert_eq!(redact_env(&env), "SLOPCODER_TEST_PROXY=<redacted>");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_wait_reports_exit_code_and_signal() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().expect("temp dir");
+        let mut config = AnyAgentConfig::default();
+        for (name, exit, exit_code, signal) in [
+            ("fails", "exit 3", Some(3), None),
+            ("killed", "kill -9 $$", None, Some(9)),
+        ] {
+            let script = dir.path().join(name);
+            std::fs::write(
+                &script,
+                format!(
+                    "#!/bin/sh\necho '{{\"type\":\"thread.started\",\"thread_id\":\"019b8211-cfdc-7b42-aba2-f10cf3236c70\"}}'\n{}\n",
+                    exit
+                ),
+            )
+            .expect("write script");
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+            config.codex.codex_path = script.to_string_lossy().into_owned();
+
+            let agent = spawn_anyagent(AgentKind::Codex, &config, dir.path(), "hi", false)
+                .await
+                .expect("spawn fake codex");
+            let result = agent.wait().await.expect("result");
+            assert!(!result.success, "{}", name);
+            assert_eq!(result.exit_code, exit_code, "{}", name);
+            assert_eq!(result.signal, signal, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_attach_files_uses_image_flag_or_prompt_references() {
+        let files = [
+            AttachedFile {
+                path: "/work/.slopcoder-attachments/1/shot.png".into(),
+                mime: "image/png".to_string(),
+            },
+            AttachedFile {
+                path: "/work/.slopcoder-attachments/1/notes.txt".into(),
+                mime: "text/plain".to_string(),
+            },
+        ];
+
+        let mut config = AnyAgentConfig::default();
+        let prompt = config.attach_files(AgentKind::Codex, "Fix the layout", &files);
+        assert_eq!(
+            config.codex.extra_args,
+            ["--image", "/work/.slopcoder-attachments/1/shot.png"]
+        );
+        assert_eq!(
+            prompt,
+            "Fix the layout\n\nAttached files:\n- /work/.slopcoder-attachments/1/notes.txt"
+        );
+
+        let mut config = AnyAgentConfig::default();
+        let prompt = config.attach_files(AgentKind::Gemini, "Fix the layout", &files[..1]);
+        assert!(config.gemini.extra_args.is_empty());
+        assert!(prompt.ends_with("\n@/work/.slopcoder-attachments/1/shot.png"));
+    }
+
+    #[test]
+    fn test_add_instructions_uses_system_prompt_flag_or_prompt_prefix() {
+        let instructions = "Never edit vendor/.\n";
+
+        let mut config = AnyAgentConfig::default();
+        let prompt = config.add_instructions(AgentKind::Claude, "Fix the build", instructions);
+        assert_eq!(prompt, "Fix the build");
+        assert_eq!(
+            config.claude.extra_args,
+            ["--append-system-prompt", "Never edit vendor/."]
+        );
+
+        let mut config = AnyAgentConfig::default();
+        let prompt = config.add_instructions(AgentKind::Codex, "Fix the build", instructions);
+        assert!(config.codex.extra_args.is_empty());
+        assert_eq!(
+            prompt,
+            "<environment-instructions>\nNever edit vendor/.\n</environment-instructions>\n\nFix the build"
+        );
+        assert_eq!(
+            config.add_instructions(AgentKind::Gemini, "Fix the build", "  "),
+            "Fix the build"
+        );
+    }
+
+    #[test]
+    fn test_redacted_summary_hides_env_values() {
+        let mut config = AnyAgentConfig::default();
+        config.set_model(AgentKind::Claude, "sonnet".to_string());
+        config.extend_env(&HashMap::from([(
+            "API_TOKEN".to_string(),
+            "hunter2-secret-value".to_string(),
+        )]));
+
+        let summary = config.redacted_summary(AgentKind::Claude);
+        assert_eq!(summary["agent"], "claude");
+        assert_eq!(summary["binary_path"], "claude");
+        assert_eq!(summary["model"], "sonnet");
+        assert_eq!(summary["env"]["API_TOKEN"], "<redacted>");
+        assert!(!summary.to_string().contains("hunter2"));
+    }
+
     #[test]
     fn test_codex_config_default() {
         let config = CodexAgentConfig::default();
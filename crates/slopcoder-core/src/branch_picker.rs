@@ -3,7 +3,7 @@
 use dspy_rs::{configure, example, ChatAdapter, Predict, Predictor, Signature, LM};
 use thiserror::Error;
 
-const TOPIC_MAX_CHARS_EXCLUSIVE: usize = 25;
+const TOPIC_MAX_CHARS_EXCLUSIVE: usize = 21;
 const TOPIC_MAX_CHARS: usize = TOPIC_MAX_CHARS_EXCLUSIVE - 1;
 
 #[derive(Debug, Error)]
@@ -21,7 +21,7 @@ struct TopicNameSignature {
     /// Task prompt to summarize.
     #[input]
     prompt: String,
-    /// A short topic name for this task that stays under 25 characters.
+    /// A short topic name for this task of at most 20 characters.
     #[output]
     topic: String,
 }
@@ -188,10 +188,10 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_normalize_task_name_word_limited_under_25_chars() {
+    fn test_normalize_task_name_word_limited_to_20_chars() {
         let long_name = "Implement websocket auth and coordinator routing";
         let normalized = normalize_task_name(long_name).unwrap();
-        assert_eq!(normalized, "Implement websocket auth");
+        assert_eq!(normalized, "Implement websocket");
         assert!(normalized.chars().count() < TOPIC_MAX_CHARS_EXCLUSIVE);
 
         let short_name = "Fix login";
@@ -211,7 +211,7 @@ mod tests {
     #[test]
     fn test_normalize_task_name_long_single_word() {
         let normalized = normalize_task_name("supercalifragilisticexpialidocious").unwrap();
-        assert_eq!(normalized, "supercalifragilisticexpi");
+        assert_eq!(normalized, "supercalifragilistic");
         assert!(normalized.chars().count() < TOPIC_MAX_CHARS_EXCLUSIVE);
     }
 
@@ -3,21 +3,29 @@
 //! This module provides an async interface for spawning and managing
 //! Claude CLI processes, including streaming JSONL output.
 
-use crate::anyagent::{AgentError, AgentResult, AnyAgent, ClaudeAgentConfig};
+use crate::anyagent::{
+    exit_signal, spawn_error, AgentError, AgentKind, AgentResult, AnyAgent, ClaudeAgentConfig,
+};
 use crate::events::AgentEvent;
+use crate::jsonl;
 use async_trait::async_trait;
+use std::collections::HashMap;
 use std::path::Path;
 use std::process::Stdio;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::{Child, Command};
+use tokio::io::AsyncWriteExt;
+use tokio::process::{Child, ChildStdin, Command};
 use tokio::sync::mpsc;
 use uuid::Uuid;
 
 /// A running Claude agent process with streaming output.
 pub struct ClaudeAgent {
     child: Child,
+    /// Open only in approval mode, until the turn's result arrives.
+    stdin: Option<ChildStdin>,
     event_rx: mpsc::Receiver<Result<AgentEvent, AgentError>>,
     session_id: Option<Uuid>,
+    /// Tool input of each unanswered permission request, echoed back when it is approved.
+    pending_approvals: HashMap<String, serde_json::Value>,
 }
 
 impl ClaudeAgent {
@@ -34,7 +42,6 @@ impl ClaudeAgent {
             .arg("--verbose")
             .arg("--output-format")
             .arg("stream-json")
-            .arg("--dangerously-skip-permissions")
             .current_dir(working_dir);
 
         if let Some(model) = &config.model {
@@ -45,22 +52,7 @@ impl ClaudeAgent {
             cmd.arg(arg);
         }
 
-        cmd.arg(prompt);
-
-        cmd.stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .stdin(Stdio::null());
-
-        let mut child = cmd.spawn()?;
-
-        let stdout = child.stdout.take().expect("stdout was piped");
-        let event_rx = Self::spawn_reader(stdout);
-
-        Ok(Self {
-            child,
-            event_rx,
-            session_id: None,
-        })
+        Self::start(cmd, config, working_dir, prompt, None).await
     }
 
     /// Spawn an agent to resume an existing session.
@@ -77,7 +69,6 @@ impl ClaudeAgent {
             .arg("--verbose")
             .arg("--output-format")
             .arg("stream-json")
-            .arg("--dangerously-skip-permissions")
             .arg("--resume")
             .arg(session_id.to_string())
             .current_dir(working_dir);
@@ -90,58 +81,85 @@ impl ClaudeAgent {
             cmd.arg(arg);
         }
 
-        cmd.arg(prompt);
+        Self::start(cmd, config, working_dir, prompt, Some(session_id)).await
+    }
 
-        cmd.stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .stdin(Stdio::null());
+    /// Hand the prompt to `cmd` and launch it.
+    ///
+    /// With [`ClaudeAgentConfig::approvals`] the prompt goes over stdin as a stream-json user
+    /// message and stdin stays open for answers to tool permission requests; otherwise it is
+    /// the last argument and permission checks are skipped.
+    async fn start(
+        mut cmd: Command,
+        config: &ClaudeAgentConfig,
+        working_dir: &Path,
+        prompt: &str,
+        session_id: Option<Uuid>,
+    ) -> Result<Self, AgentError> {
+        if config.approvals {
+            cmd.arg("--input-format")
+                .arg("stream-json")
+                .arg("--permission-prompt-tool")
+                .arg("stdio")
+                .stdin(Stdio::piped());
+        } else {
+            cmd.arg("--dangerously-skip-permissions")
+                .arg(prompt)
+                .stdin(Stdio::null());
+        }
+
+        cmd.envs(&config.env);
+        cmd.stdout(Stdio::piped()).stderr(Stdio::inherit());
 
-        let mut child = cmd.spawn()?;
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| spawn_error(AgentKind::Claude, &config.claude_path, working_dir, e))?;
 
         let stdout = child.stdout.take().expect("stdout was piped");
-        let event_rx = Self::spawn_reader(stdout);
+        let event_rx = Self::spawn_reader(stdout, config);
 
-        Ok(Self {
+        let mut agent = Self {
+            stdin: child.stdin.take(),
             child,
             event_rx,
-            session_id: Some(session_id),
-        })
+            session_id,
+            pending_approvals: HashMap::new(),
+        };
+        if config.approvals {
+            let message = serde_json::json!({
+                "type": "user",
+                "message": { "role": "user", "content": prompt },
+            });
+            agent.write_line(&message).await?;
+        }
+        Ok(agent)
+    }
+
+    /// Send one stream-json line to the CLI's stdin.
+    async fn write_line(&mut self, message: &serde_json::Value) -> Result<(), AgentError> {
+        let Some(stdin) = self.stdin.as_mut() else {
+            return Err(AgentError::ProcessError(
+                "Claude stdin is closed".to_string(),
+            ));
+        };
+        let mut line = message.to_string();
+        line.push('\n');
+        stdin.write_all(line.as_bytes()).await?;
+        stdin.flush().await?;
+        Ok(())
     }
 
     /// Spawn a background task to read lines from stdout and parse events.
     fn spawn_reader(
         stdout: tokio::process::ChildStdout,
+        config: &ClaudeAgentConfig,
     ) -> mpsc::Receiver<Result<AgentEvent, AgentError>> {
-        let (tx, rx) = mpsc::channel(100);
-
-        tokio::spawn(async move {
-            let reader = BufReader::new(stdout);
-            let mut lines = reader.lines();
-
-            while let Ok(Some(line)) = lines.next_line().await {
-                if line.trim().is_empty() {
-                    continue;
-                }
-
-                let events = AgentEvent::parse_claude(&line).map_err(AgentError::from);
-                match events {
-                    Ok(events) => {
-                        for event in events {
-                            if tx.send(Ok(event)).await.is_err() {
-                                return;
-                            }
-                        }
-                    }
-                    Err(err) => {
-                        if tx.send(Err(err)).await.is_err() {
-                            return;
-                        }
-                    }
-                }
-            }
-        });
-
-        rx
+        jsonl::spawn_event_reader(
+            stdout,
+            config.max_line_bytes,
+            config.raw_output.clone(),
+            AgentEvent::parse_claude,
+        )
     }
 }
 
@@ -154,6 +172,20 @@ impl AnyAgent for ClaudeAgent {
             if let Some(id) = event.session_id() {
                 self.session_id = Some(id);
             }
+            match event {
+                AgentEvent::ApprovalRequested {
+                    call_id, arguments, ..
+                } => {
+                    let input = arguments
+                        .as_deref()
+                        .and_then(|arguments| serde_json::from_str(arguments).ok())
+                        .unwrap_or_else(|| serde_json::json!({}));
+                    self.pending_approvals.insert(call_id.clone(), input);
+                }
+                // Closing stdin ends a stream-json session once its one turn is done.
+                AgentEvent::TurnCompleted { .. } => self.stdin = None,
+                _ => {}
+            }
         }
 
         Some(result)
@@ -173,6 +205,7 @@ impl AnyAgent for ClaudeAgent {
             session_id,
             success: status.success(),
             exit_code: status.code(),
+            signal: exit_signal(&status),
         })
     }
 
@@ -187,4 +220,28 @@ impl AnyAgent for ClaudeAgent {
     fn session_id(&self) -> Option<Uuid> {
         self.session_id
     }
+
+    /// Answer a `can_use_tool` control request with a `control_response` on stdin.
+    async fn respond_approval(&mut self, call_id: &str, approve: bool) -> Result<(), AgentError> {
+        let Some(input) = self.pending_approvals.remove(call_id) else {
+            return Err(AgentError::ProcessError(format!(
+                "No pending approval for call {}",
+                call_id
+            )));
+        };
+        let decision = if approve {
+            serde_json::json!({ "behavior": "allow", "updatedInput": input })
+        } else {
+            serde_json::json!({ "behavior": "deny", "message": "Denied by the user" })
+        };
+        let message = serde_json::json!({
+            "type": "control_response",
+            "response": {
+                "subtype": "success",
+                "request_id": call_id,
+                "response": decision,
+            },
+        });
+        self.write_line(&message).await
+    }
 }
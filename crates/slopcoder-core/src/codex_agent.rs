@@ -3,12 +3,14 @@
 //! This module provides an async interface for spawning and managing
 //! Codex CLI processes, including streaming JSONL output.
 
-use crate::anyagent::{AgentError, AgentResult, AnyAgent, CodexAgentConfig};
+use crate::anyagent::{
+    exit_signal, spawn_error, AgentError, AgentKind, AgentResult, AnyAgent, CodexAgentConfig,
+};
 use crate::events::AgentEvent;
+use crate::jsonl;
 use async_trait::async_trait;
 use std::path::Path;
 use std::process::Stdio;
-use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::{Child, Command};
 use tokio::sync::mpsc;
 use uuid::Uuid;
@@ -49,14 +51,17 @@ impl CodexAgent {
 
         cmd.arg(prompt);
 
+        cmd.envs(&config.env);
         cmd.stdout(Stdio::piped())
             .stderr(Stdio::inherit())
             .stdin(Stdio::null());
 
-        let mut child = cmd.spawn()?;
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| spawn_error(AgentKind::Codex, &config.codex_path, working_dir, e))?;
 
         let stdout = child.stdout.take().expect("stdout was piped");
-        let event_rx = Self::spawn_reader(stdout);
+        let event_rx = Self::spawn_reader(stdout, config);
 
         Ok(Self {
             child,
@@ -94,14 +99,17 @@ impl CodexAgent {
 
         cmd.arg("resume").arg(session_id.to_string()).arg(prompt);
 
+        cmd.envs(&config.env);
         cmd.stdout(Stdio::piped())
             .stderr(Stdio::inherit())
             .stdin(Stdio::null());
 
-        let mut child = cmd.spawn()?;
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| spawn_error(AgentKind::Codex, &config.codex_path, working_dir, e))?;
 
         let stdout = child.stdout.take().expect("stdout was piped");
-        let event_rx = Self::spawn_reader(stdout);
+        let event_rx = Self::spawn_reader(stdout, config);
 
         Ok(Self {
             child,
@@ -113,26 +121,14 @@ impl CodexAgent {
     /// Spawn a background task to read lines from stdout and parse events.
     fn spawn_reader(
         stdout: tokio::process::ChildStdout,
+        config: &CodexAgentConfig,
     ) -> mpsc::Receiver<Result<AgentEvent, AgentError>> {
-        let (tx, rx) = mpsc::channel(100);
-
-        tokio::spawn(async move {
-            let reader = BufReader::new(stdout);
-            let mut lines = reader.lines();
-
-            while let Ok(Some(line)) = lines.next_line().await {
-                if line.trim().is_empty() {
-                    continue;
-                }
-
-                let event = AgentEvent::parse_codex(&line).map_err(AgentError::from);
-                if tx.send(event).await.is_err() {
-                    break;
-                }
-            }
-        });
-
-        rx
+        jsonl::spawn_event_reader(
+            stdout,
+            config.max_line_bytes,
+            config.raw_output.clone(),
+            |line| AgentEvent::parse_codex(line).map(|event| vec![event]),
+        )
     }
 
     /// Get the session ID if we've received it.
@@ -151,6 +147,7 @@ mod tests {
             codex_path: "/usr/bin/codex".to_string(),
             model: Some("gpt-4".to_string()),
             extra_args: vec!["--verbose".to_string()],
+            ..Default::default()
         };
         assert_eq!(config.model, Some("gpt-4".to_string()));
     }
@@ -184,6 +181,7 @@ impl AnyAgent for CodexAgent {
             session_id,
             success: status.success(),
             exit_code: status.code(),
+            signal: exit_signal(&status),
         })
     }
 
@@ -3,12 +3,14 @@
 //! This module provides an async interface for spawning and managing
 //! Cursor Agent CLI processes, including streaming JSONL output.
 
-use crate::anyagent::{AgentError, AgentResult, AnyAgent, CursorAgentConfig};
+use crate::anyagent::{
+    exit_signal, spawn_error, AgentError, AgentKind, AgentResult, AnyAgent, CursorAgentConfig,
+};
 use crate::events::AgentEvent;
+use crate::jsonl;
 use async_trait::async_trait;
 use std::path::Path;
 use std::process::Stdio;
-use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::{Child, Command};
 use tokio::sync::mpsc;
 use uuid::Uuid;
@@ -46,14 +48,17 @@ impl CursorAgent {
 
         cmd.arg(prompt);
 
+        cmd.envs(&config.env);
         cmd.stdout(Stdio::piped())
             .stderr(Stdio::inherit())
             .stdin(Stdio::null());
 
-        let mut child = cmd.spawn()?;
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| spawn_error(AgentKind::Cursor, &config.cursor_path, working_dir, e))?;
 
         let stdout = child.stdout.take().expect("stdout was piped");
-        let event_rx = Self::spawn_reader(stdout);
+        let event_rx = Self::spawn_reader(stdout, config);
 
         Ok(Self {
             child,
@@ -90,14 +95,17 @@ impl CursorAgent {
 
         cmd.arg(prompt);
 
+        cmd.envs(&config.env);
         cmd.stdout(Stdio::piped())
             .stderr(Stdio::inherit())
             .stdin(Stdio::null());
 
-        let mut child = cmd.spawn()?;
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| spawn_error(AgentKind::Cursor, &config.cursor_path, working_dir, e))?;
 
         let stdout = child.stdout.take().expect("stdout was piped");
-        let event_rx = Self::spawn_reader(stdout);
+        let event_rx = Self::spawn_reader(stdout, config);
 
         Ok(Self {
             child,
@@ -109,38 +117,14 @@ impl CursorAgent {
     /// Spawn a background task to read lines from stdout and parse events.
     fn spawn_reader(
         stdout: tokio::process::ChildStdout,
+        config: &CursorAgentConfig,
     ) -> mpsc::Receiver<Result<AgentEvent, AgentError>> {
-        let (tx, rx) = mpsc::channel(100);
-
-        tokio::spawn(async move {
-            let reader = BufReader::new(stdout);
-            let mut lines = reader.lines();
-
-            while let Ok(Some(line)) = lines.next_line().await {
-                if line.trim().is_empty() {
-                    continue;
-                }
-
-                // Parse cursor-agent format
-                let events = AgentEvent::parse_cursor(&line).map_err(AgentError::from);
-                match events {
-                    Ok(events) => {
-                        for event in events {
-                            if tx.send(Ok(event)).await.is_err() {
-                                return;
-                            }
-                        }
-                    }
-                    Err(err) => {
-                        if tx.send(Err(err)).await.is_err() {
-                            return;
-                        }
-                    }
-                }
-            }
-        });
-
-        rx
+        jsonl::spawn_event_reader(
+            stdout,
+            config.max_line_bytes,
+            config.raw_output.clone(),
+            AgentEvent::parse_cursor,
+        )
     }
 }
 
@@ -172,6 +156,7 @@ impl AnyAgent for CursorAgent {
             session_id,
             success: status.success(),
             exit_code: status.code(),
+            signal: exit_signal(&status),
         })
     }
 
@@ -198,6 +183,7 @@ mod tests {
             cursor_path: "/usr/bin/cursor-agent".to_string(),
             model: Some("gpt-5".to_string()),
             extra_args: vec!["--force".to_string()],
+            ..Default::default()
         };
         assert_eq!(config.model, Some("gpt-5".to_string()));
     }
@@ -3,7 +3,9 @@
 //! Each environment maps to a checked-out Git repository directory.
 //! Optional isolated task worktrees are created in a shared worktrees directory.
 
+use crate::anyagent::AgentKind;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use thiserror::Error;
 use tokio::process::Command;
@@ -38,14 +40,109 @@ pub enum EnvironmentError {
     #[error("Failed to create worktree: {0}")]
     WorktreeCreateError(String),
 
+    #[error("Failed to list worktrees: {0}")]
+    WorktreeListError(String),
+
     #[error("Branch already exists: {0}")]
     BranchExists(String),
 
+    #[error("Branch not found: {0}")]
+    BranchNotFound(String),
+
+    #[error("Branch '{branch}' is already checked out at {path}")]
+    BranchCheckedOut { branch: String, path: PathBuf },
+
     #[error("Worktree already exists at {0}")]
     WorktreeExists(PathBuf),
 
     #[error("Worktrees directory does not exist or is not a directory: {0}")]
     WorktreesDirInvalid(PathBuf),
+
+    #[error("Invalid .slopcoder.yaml: {0}")]
+    SettingsParseError(String),
+}
+
+/// Per-repository settings file, read from the environment directory.
+pub const ENVIRONMENT_SETTINGS_FILE: &str = ".slopcoder.yaml";
+
+/// Task defaults from an environment's `.slopcoder.yaml`.
+///
+/// Each field only applies when the create-task request leaves it unset, and
+/// falls back to the global default when the file doesn't set it either.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnvironmentSettings {
+    pub default_agent: Option<AgentKind>,
+    pub default_model: Option<String>,
+    pub default_use_worktree: Option<bool>,
+    /// Extra environment variables passed to agents started in this environment.
+    pub env: HashMap<String, String>,
+    /// Shell command run in the task worktree before merging; a non-zero exit blocks the merge.
+    pub merge_check_command: Option<String>,
+    /// Shell command run in the task worktree after each successful agent run (e.g. a formatter).
+    pub post_run_command: Option<String>,
+    /// Regexes scrubbed from agent output before it is logged or streamed, on top of
+    /// the `env` values.
+    pub redact_patterns: Vec<String>,
+    /// Shell started for task terminals; defaults to `$SHELL`, then `/bin/bash`.
+    pub terminal_shell: Option<String>,
+    /// Typed into each new task terminal before the user gets it (e.g. activating a venv).
+    pub terminal_init_command: Option<String>,
+    /// Standing guidance (coding standards, directories not to touch) given to every agent
+    /// run in this environment.
+    pub instructions: Option<String>,
+}
+
+impl EnvironmentSettings {
+    /// Location of the settings file for an environment directory.
+    pub fn path(directory: &Path) -> PathBuf {
+        directory.join(ENVIRONMENT_SETTINGS_FILE)
+    }
+
+    /// Load settings from `directory`; a missing file yields the defaults.
+    pub async fn load(directory: &Path) -> Result<Self, EnvironmentError> {
+        match tokio::fs::read_to_string(Self::path(directory)).await {
+            Ok(contents) => Self::parse(&contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(EnvironmentError::ConfigReadError(e)),
+        }
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, EnvironmentError> {
+        if contents.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        serde_yaml::from_str(contents)
+            .map_err(|e| EnvironmentError::SettingsParseError(e.to_string()))
+    }
+
+    pub fn resolve_agent(&self, requested: Option<AgentKind>) -> AgentKind {
+        requested.or(self.default_agent).unwrap_or_default()
+    }
+
+    /// The requested model, else `default_model` when `agent` is the environment's default
+    /// agent; the default model names a model of that agent and means nothing to the others.
+    pub fn resolve_model(&self, agent: AgentKind, requested: Option<String>) -> Option<String> {
+        requested
+            .filter(|model| !model.trim().is_empty())
+            .or_else(|| {
+                self.default_model
+                    .clone()
+                    .filter(|_| agent == self.resolve_agent(None))
+            })
+    }
+
+    pub fn resolve_use_worktree(&self, requested: Option<bool>) -> bool {
+        requested.or(self.default_use_worktree).unwrap_or(false)
+    }
+
+    pub fn resolve_terminal_shell(&self) -> String {
+        self.terminal_shell
+            .clone()
+            .filter(|shell| !shell.trim().is_empty())
+            .or_else(|| std::env::var("SHELL").ok())
+            .unwrap_or_else(|| "/bin/bash".to_string())
+    }
 }
 
 /// In-memory environment configuration.
@@ -240,11 +337,9 @@ impl Environment {
         worktrees_directory: &Path,
         branch: &str,
     ) -> Result<PathBuf, EnvironmentError> {
-        let worktree_path = self.worktree_path(worktrees_directory, branch);
-
-        if worktree_path.exists() {
-            return Err(EnvironmentError::WorktreeExists(worktree_path));
-        }
+        let worktree_path = self
+            .check_branch_checkout_available(worktrees_directory, branch)
+            .await?;
 
         if let Some(parent) = worktree_path.parent() {
             tokio::fs::create_dir_all(parent)
@@ -267,11 +362,76 @@ impl Environment {
         Ok(worktree_path)
     }
 
-    /// Create a new worktree for a new branch based on a base branch.
-    pub async fn create_worktree_from_base(
+    /// Return the worktree path an existing `branch` would be checked out to, failing if the
+    /// branch is missing, already checked out, or the path is taken.
+    pub async fn check_branch_checkout_available(
+        &self,
+        worktrees_directory: &Path,
+        branch: &str,
+    ) -> Result<PathBuf, EnvironmentError> {
+        let worktree_path = self.worktree_path(worktrees_directory, branch);
+
+        if worktree_path.exists() {
+            return Err(EnvironmentError::WorktreeExists(worktree_path));
+        }
+
+        if !self.branch_exists(branch).await? {
+            return Err(EnvironmentError::BranchNotFound(branch.to_string()));
+        }
+
+        if let Some(path) = self.branch_worktree(branch).await? {
+            return Err(EnvironmentError::BranchCheckedOut {
+                branch: branch.to_string(),
+                path,
+            });
+        }
+
+        Ok(worktree_path)
+    }
+
+    /// Where `branch` is currently checked out (the main checkout counts), if anywhere.
+    pub async fn branch_worktree(&self, branch: &str) -> Result<Option<PathBuf>, EnvironmentError> {
+        let output = Command::new("git")
+            .args(["worktree", "list", "--porcelain"])
+            .current_dir(&self.directory)
+            .output()
+            .await
+            .map_err(|e| EnvironmentError::BranchCheckError(e.to_string()))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(EnvironmentError::BranchCheckError(stderr.to_string()));
+        }
+
+        Ok(find_branch_worktree(
+            &String::from_utf8_lossy(&output.stdout),
+            branch,
+        ))
+    }
+
+    /// Every worktree git knows for this repository, the main checkout first.
+    pub async fn list_worktrees(&self) -> Result<Vec<WorktreeInfo>, EnvironmentError> {
+        let output = Command::new("git")
+            .args(["worktree", "list", "--porcelain"])
+            .current_dir(&self.directory)
+            .output()
+            .await
+            .map_err(|e| EnvironmentError::WorktreeListError(e.to_string()))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(EnvironmentError::WorktreeListError(stderr.to_string()));
+        }
+
+        Ok(parse_worktree_list(&String::from_utf8_lossy(
+            &output.stdout,
+        )))
+    }
+
+    /// Return the worktree path `feature_branch` would get, failing if the path or branch is taken.
+    pub async fn check_worktree_available(
         &self,
         worktrees_directory: &Path,
-        base_branch: &str,
         feature_branch: &str,
     ) -> Result<PathBuf, EnvironmentError> {
         let worktree_path = self.worktree_path(worktrees_directory, feature_branch);
@@ -284,6 +444,20 @@ impl Environment {
             return Err(EnvironmentError::BranchExists(feature_branch.to_string()));
         }
 
+        Ok(worktree_path)
+    }
+
+    /// Create a new worktree for a new branch based on a base branch.
+    pub async fn create_worktree_from_base(
+        &self,
+        worktrees_directory: &Path,
+        base_branch: &str,
+        feature_branch: &str,
+    ) -> Result<PathBuf, EnvironmentError> {
+        let worktree_path = self
+            .check_worktree_available(worktrees_directory, feature_branch)
+            .await?;
+
         if let Some(parent) = worktree_path.parent() {
             tokio::fs::create_dir_all(parent)
                 .await
@@ -313,6 +487,64 @@ impl Environment {
     }
 }
 
+/// One entry of `git worktree list --porcelain`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorktreeInfo {
+    pub path: PathBuf,
+    /// Checked-out branch without `refs/heads/`; `None` when detached or bare.
+    #[serde(default)]
+    pub branch: Option<String>,
+    #[serde(default)]
+    pub is_bare: bool,
+    #[serde(default)]
+    pub locked: bool,
+}
+
+/// Parse `git worktree list --porcelain` output into its entries.
+pub fn parse_worktree_list(porcelain: &str) -> Vec<WorktreeInfo> {
+    let mut worktrees = Vec::new();
+    let mut current: Option<WorktreeInfo> = None;
+    for line in porcelain.lines() {
+        if let Some(path) = line.strip_prefix("worktree ") {
+            worktrees.extend(current.take());
+            current = Some(WorktreeInfo {
+                path: PathBuf::from(path),
+                branch: None,
+                is_bare: false,
+                locked: false,
+            });
+            continue;
+        }
+        let Some(worktree) = current.as_mut() else {
+            continue;
+        };
+        if let Some(branch) = line.strip_prefix("branch ") {
+            let branch = branch.strip_prefix("refs/heads/").unwrap_or(branch);
+            worktree.branch = Some(branch.to_string());
+        } else if line == "bare" {
+            worktree.is_bare = true;
+        } else if line == "locked" || line.starts_with("locked ") {
+            worktree.locked = true;
+        }
+    }
+    worktrees.extend(current);
+    worktrees
+}
+
+/// Find the worktree with `branch` checked out in `git worktree list --porcelain` output.
+fn find_branch_worktree(porcelain: &str, branch: &str) -> Option<PathBuf> {
+    let target = format!("refs/heads/{}", branch);
+    let mut current: Option<&str> = None;
+    for line in porcelain.lines() {
+        if let Some(path) = line.strip_prefix("worktree ") {
+            current = Some(path);
+        } else if line.strip_prefix("branch ") == Some(target.as_str()) {
+            return current.map(PathBuf::from);
+        }
+    }
+    None
+}
+
 fn sanitize_for_path(value: &str) -> String {
     let mut out = String::new();
     for ch in value.chars() {
@@ -385,6 +617,118 @@ mod tests {
         assert!(config.find("nonexistent").is_none());
     }
 
+    #[test]
+    fn test_environment_settings_precedence() {
+        let settings = EnvironmentSettings::parse(
+            "default_agent: claude\ndefault_model: sonnet\ndefault_use_worktree: true\nenv:\n  HTTPS_PROXY: http://proxy:3128\n",
+        )
+        .unwrap();
+        assert_eq!(
+            settings.env.get("HTTPS_PROXY").map(String::as_str),
+            Some("http://proxy:3128")
+        );
+
+        // Request values win over the environment file.
+        assert_eq!(
+            settings.resolve_agent(Some(AgentKind::Codex)),
+            AgentKind::Codex
+        );
+        assert_eq!(
+            settings
+                .resolve_model(AgentKind::Codex, Some("o3".to_string()))
+                .as_deref(),
+            Some("o3")
+        );
+        assert!(!settings.resolve_use_worktree(Some(false)));
+
+        // The environment file fills in what the request leaves out.
+        assert_eq!(settings.resolve_agent(None), AgentKind::Claude);
+        assert_eq!(
+            settings.resolve_model(AgentKind::Claude, None).as_deref(),
+            Some("sonnet")
+        );
+        assert!(settings.resolve_use_worktree(None));
+
+        // The default model belongs to the default agent; other agents keep their own.
+        assert_eq!(settings.resolve_model(AgentKind::Codex, None), None);
+
+        // Without either, the global defaults apply.
+        let empty = EnvironmentSettings::parse("").unwrap();
+        assert_eq!(empty.resolve_agent(None), AgentKind::default());
+        assert_eq!(empty.resolve_model(AgentKind::default(), None), None);
+        assert!(!empty.resolve_use_worktree(None));
+    }
+
+    #[test]
+    fn test_environment_settings_rejects_unknown_agent() {
+        let err = EnvironmentSettings::parse("default_agent: nope\n").unwrap_err();
+        assert!(matches!(err, EnvironmentError::SettingsParseError(_)));
+    }
+
+    #[test]
+    fn test_find_branch_worktree() {
+        let porcelain = "worktree /repo
+HEAD 1111111111111111111111111111111111111111
+branch refs/heads/main
+
+worktree /worktrees/feature
+HEAD 2222222222222222222222222222222222222222
+branch refs/heads/feature/login
+
+worktree /worktrees/detached
+HEAD 3333333333333333333333333333333333333333
+detached
+";
+        assert_eq!(
+            find_branch_worktree(porcelain, "feature/login"),
+            Some(PathBuf::from("/worktrees/feature"))
+        );
+        assert_eq!(
+            find_branch_worktree(porcelain, "main"),
+            Some(PathBuf::from("/repo"))
+        );
+        assert_eq!(find_branch_worktree(porcelain, "feature"), None);
+    }
+
+    #[test]
+    fn test_parse_worktree_list() {
+        let porcelain = "worktree /repo.git
+bare
+
+worktree /repo
+HEAD 1111111111111111111111111111111111111111
+branch refs/heads/main
+
+worktree /worktrees/feature
+HEAD 2222222222222222222222222222222222222222
+branch refs/heads/feature/login
+locked reason: on a usb drive
+
+worktree /worktrees/detached
+HEAD 3333333333333333333333333333333333333333
+detached
+locked
+prunable gitdir file points to non-existent location
+";
+        let worktree =
+            |path: &str, branch: Option<&str>, is_bare: bool, locked: bool| WorktreeInfo {
+                path: PathBuf::from(path),
+                branch: branch.map(str::to_string),
+                is_bare,
+                locked,
+            };
+        assert_eq!(
+            parse_worktree_list(porcelain),
+            vec![
+                worktree("/repo.git", None, true, false),
+                worktree("/repo", Some("main"), false, false),
+                worktree("/worktrees/feature", Some("feature/login"), false, true),
+                worktree("/worktrees/detached", None, false, true),
+            ]
+        );
+        assert!(parse_worktree_list("").is_empty());
+    }
+
     #[test]
     fn test_worktree_path() {
         let env = Environment {
@@ -52,10 +52,38 @@ pub enum AgentEvent {
         extra: serde_json::Value,
     },
 
-    /// Prompt sent to the agent.
+    /// Prompt sent to the agent. slopagent emits exactly one per run and it is the
+    /// canonical record of the prompt; parsers drop the CLIs' own echoes of it.
     #[serde(rename = "prompt.sent")]
     PromptSent { prompt: String },
 
+    /// The agent could not be started or failed outside its own event stream.
+    #[serde(rename = "agent.error")]
+    Error { message: String },
+
+    /// The agent paused before a tool call and waits for `RespondApproval`.
+    #[serde(rename = "approval.requested")]
+    ApprovalRequested {
+        call_id: String,
+        tool: String,
+        #[serde(default)]
+        arguments: Option<String>,
+    },
+
+    /// The run has been quiet for `elapsed_secs`; sent by slopagent so the UI can show the
+    /// agent is still working. Broadcast only, never written to the task log.
+    #[serde(rename = "agent.heartbeat")]
+    Heartbeat { elapsed_secs: u64 },
+
+    /// Lines a file-editing tool call changed in `path`; emitted by slopagent alongside
+    /// the `tool_call` item it was derived from.
+    #[serde(rename = "file.edited")]
+    FileEdited {
+        path: String,
+        added: usize,
+        removed: usize,
+    },
+
     /// Unknown event type - we capture these to avoid breaking on new event types.
     #[serde(other)]
     Unknown,
@@ -64,7 +92,14 @@ pub enum AgentEvent {
 impl AgentEvent {
     /// Parse a JSONL line into an AgentEvent (Codex format).
     pub fn parse_codex(line: &str) -> Result<Self, serde_json::Error> {
-        serde_json::from_str(line).map(Self::normalize)
+        serde_json::from_str(line)
+            .map(|event| match event {
+                AgentEvent::ItemCompleted { item } => AgentEvent::ItemCompleted {
+                    item: item.lift_codex_tool_output(),
+                },
+                other => other,
+            })
+            .map(Self::normalize)
     }
 
     /// Parse a JSONL line into AgentEvents (Claude format).
@@ -187,6 +222,11 @@ pub struct CompletedItem {
     #[serde(default)]
     pub output: Option<String>,
 
+    /// Milliseconds since the `tool_call` with the same `call_id` (for tool_output type).
+    /// Filled in by slopagent while it observes the run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+
     /// Whether any part of this item was truncated during normalization.
     /// This remains internal normalization metadata and is not sent to clients.
     #[serde(default, skip_serializing)]
@@ -242,6 +282,55 @@ impl CompletedItem {
         self.item_type == "tool_output"
     }
 
+    /// Codex reports command and patch results under item-specific keys
+    /// (`aggregated_output`, `changes`); move them into `output`, with the item id as
+    /// `call_id`, so they read like any other tool output.
+    fn lift_codex_tool_output(mut self) -> Self {
+        let output = match self.item_type.as_str() {
+            "command_execution" => self
+                .extra
+                .as_object_mut()
+                .and_then(|extra| extra.remove("aggregated_output"))
+                .and_then(|value| {
+                    // Same limits the command's output had while it sat in `extra`.
+                    value.as_str().map(|output| {
+                        truncate_lines_and_chars(
+                            output,
+                            MAX_COMMAND_OUTPUT_LINES,
+                            MAX_COMMAND_OUTPUT_CHARS,
+                            &mut self.truncated,
+                        )
+                    })
+                }),
+            "file_change" => self
+                .extra
+                .get("changes")
+                .and_then(|changes| changes.as_array())
+                .map(|changes| {
+                    changes
+                        .iter()
+                        .map(|change| {
+                            let field = |key| change.get(key).and_then(|v| v.as_str());
+                            format!(
+                                "{} {}",
+                                field("kind").unwrap_or("change"),
+                                field("path").unwrap_or("?")
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                }),
+            _ => return self,
+        };
+        if self.output.is_none() {
+            self.output = output;
+        }
+        if self.call_id.is_none() {
+            self.call_id = Some(self.id.clone());
+        }
+        self
+    }
+
     pub fn normalize(mut self) -> Self {
         let mut truncated = false;
         self.text = truncate_optional_chars(self.text.take(), MAX_ITEM_TEXT_CHARS, &mut truncated);
@@ -400,7 +489,7 @@ fn truncate_json_value(
 }
 
 /// Token usage statistics.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct UsageStats {
     #[serde(default)]
     pub input_tokens: Option<u64>,
@@ -416,7 +505,6 @@ enum ClaudeStreamEvent {
     #[serde(rename = "system")]
     System {
         #[serde(default)]
-        #[allow(dead_code)]
         subtype: Option<String>,
         #[serde(default)]
         session_id: Option<Uuid>,
@@ -445,19 +533,44 @@ enum ClaudeStreamEvent {
         #[serde(default)]
         usage: Option<ClaudeUsage>,
     },
+    /// Sent on stdout under `--permission-prompt-tool stdio`; answered on stdin.
+    #[serde(rename = "control_request")]
+    ControlRequest {
+        request_id: String,
+        request: ClaudeControlRequest,
+    },
     #[serde(other)]
     Unknown,
 }
 
+#[derive(Debug, Deserialize)]
+struct ClaudeControlRequest {
+    subtype: String,
+    #[serde(default)]
+    tool_name: Option<String>,
+    #[serde(default)]
+    input: Option<serde_json::Value>,
+}
+
 impl ClaudeStreamEvent {
     fn into_agent_events(self) -> Vec<AgentEvent> {
         match self {
-            ClaudeStreamEvent::System { session_id, .. } => {
+            ClaudeStreamEvent::System {
+                subtype,
+                session_id,
+            } => {
+                let mut events = Vec::new();
                 if let Some(session_id) = session_id {
-                    vec![AgentEvent::SessionStarted { session_id }]
-                } else {
-                    vec![AgentEvent::Unknown]
+                    events.push(AgentEvent::SessionStarted { session_id });
+                }
+                // Claude emits `system`/`init` once per `claude -p` invocation, i.e. per turn.
+                if subtype.as_deref() == Some("init") {
+                    events.push(AgentEvent::TurnStarted {});
                 }
+                if events.is_empty() {
+                    events.push(AgentEvent::Unknown);
+                }
+                events
             }
             ClaudeStreamEvent::Assistant { message, .. } => message.into_events(),
             ClaudeStreamEvent::User {
@@ -472,7 +585,17 @@ impl ClaudeStreamEvent {
                     output_tokens: u.output_tokens,
                 }),
             }],
-            ClaudeStreamEvent::Unknown => vec![AgentEvent::Unknown],
+            ClaudeStreamEvent::ControlRequest {
+                request_id,
+                request,
+            } if request.subtype == "can_use_tool" => vec![AgentEvent::ApprovalRequested {
+                call_id: request_id,
+                tool: request.tool_name.unwrap_or_default(),
+                arguments: request.input.map(|input| input.to_string()),
+            }],
+            ClaudeStreamEvent::ControlRequest { .. } | ClaudeStreamEvent::Unknown => {
+                vec![AgentEvent::Unknown]
+            }
         }
     }
 }
@@ -489,6 +612,9 @@ struct ClaudeContent {
     kind: String,
     #[serde(default)]
     text: Option<String>,
+    /// Extended-thinking text; absent for `redacted_thinking` blocks.
+    #[serde(default)]
+    thinking: Option<String>,
     #[serde(default)]
     id: Option<String>,
     #[serde(default)]
@@ -513,6 +639,24 @@ impl ClaudeMessage {
                         text.push_str(&t);
                     }
                 }
+                "thinking" => {
+                    if let Some(thinking) = block.thinking.filter(|t| !t.is_empty()) {
+                        events.push(AgentEvent::ItemCompleted {
+                            item: CompletedItem {
+                                id: uuid::Uuid::new_v4().to_string(),
+                                item_type: "reasoning".to_string(),
+                                text: Some(thinking),
+                                name: None,
+                                arguments: None,
+                                call_id: None,
+                                output: None,
+                                duration_ms: None,
+                                truncated: false,
+                                extra: serde_json::Value::Null,
+                            },
+                        });
+                    }
+                }
                 "tool_use" => {
                     let call_id = block.id.clone();
                     let arguments = block
@@ -528,6 +672,7 @@ impl ClaudeMessage {
                             arguments,
                             call_id,
                             output: None,
+                            duration_ms: None,
                             truncated: false,
                             extra: serde_json::Value::Null,
                         },
@@ -547,6 +692,7 @@ impl ClaudeMessage {
                     arguments: None,
                     call_id: None,
                     output: None,
+                    duration_ms: None,
                     truncated: false,
                     extra: serde_json::Value::Null,
                 },
@@ -584,6 +730,7 @@ impl ClaudeUserMessage {
                         arguments: None,
                         call_id: block.tool_use_id,
                         output: block.content.or(block.text),
+                        duration_ms: None,
                         truncated: false,
                         extra: tool_use_result.clone().unwrap_or(serde_json::Value::Null),
                     },
@@ -591,10 +738,8 @@ impl ClaudeUserMessage {
             }
         }
 
-        if events.is_empty() {
-            events.push(AgentEvent::Unknown);
-        }
-
+        // Any other user content is the prompt echoed back (`--replay-user-messages`);
+        // slopagent's `PromptSent` already records it.
         events
     }
 }
@@ -684,10 +829,8 @@ impl CursorStreamEvent {
                 }
             }
             CursorStreamEvent::Assistant { message, .. } => message.into_events(),
-            CursorStreamEvent::User { .. } => {
-                // User messages are typically prompts, we can ignore or mark as prompt.sent
-                vec![AgentEvent::Unknown]
-            }
+            // Cursor echoes the prompt back; slopagent's `PromptSent` already records it.
+            CursorStreamEvent::User { .. } => Vec::new(),
             CursorStreamEvent::Result { is_error, .. } => {
                 vec![AgentEvent::TurnCompleted { usage: None }]
             }
@@ -702,6 +845,7 @@ impl CursorStreamEvent {
                             arguments: None,
                             call_id: None,
                             output: None,
+                            duration_ms: None,
                             truncated: false,
                             extra: serde_json::Value::Null,
                         },
@@ -770,6 +914,7 @@ impl CursorMessage {
                             arguments,
                             call_id,
                             output: None,
+                            duration_ms: None,
                             truncated: false,
                             extra: serde_json::Value::Null,
                         },
@@ -789,6 +934,7 @@ impl CursorMessage {
                     arguments: None,
                     call_id: None,
                     output: None,
+                    duration_ms: None,
                     truncated: false,
                     extra: serde_json::Value::Null,
                 },
@@ -933,6 +1079,7 @@ impl OpencodeStreamEvent {
                                 arguments: None,
                                 call_id: None,
                                 output: None,
+                                duration_ms: None,
                                 truncated: false,
                                 extra: serde_json::Value::Null,
                             },
@@ -959,6 +1106,7 @@ impl OpencodeStreamEvent {
                             arguments,
                             call_id: part.call_id,
                             output,
+                            duration_ms: None,
                             truncated: false,
                             extra: serde_json::Value::Null,
                         },
@@ -1043,9 +1191,10 @@ struct GeminiStats {
 impl GeminiStreamEvent {
     fn into_agent_events(self) -> Vec<AgentEvent> {
         match self {
-            GeminiStreamEvent::Init { session_id, .. } => {
-                vec![AgentEvent::SessionStarted { session_id }]
-            }
+            GeminiStreamEvent::Init { session_id, .. } => vec![
+                AgentEvent::SessionStarted { session_id },
+                AgentEvent::TurnStarted {},
+            ],
             GeminiStreamEvent::Message { role, content, .. } => {
                 if role == "assistant" {
                     vec![AgentEvent::ItemCompleted {
@@ -1057,13 +1206,14 @@ impl GeminiStreamEvent {
                             arguments: None,
                             call_id: None,
                             output: None,
+                            duration_ms: None,
                             truncated: false,
                             extra: serde_json::Value::Null,
                         },
                     }]
                 } else {
-                    // User messages are typically prompts or local echos
-                    vec![AgentEvent::Unknown]
+                    // Gemini echoes the prompt back; slopagent's `PromptSent` already records it.
+                    Vec::new()
                 }
             }
             GeminiStreamEvent::ToolUse {
@@ -1082,6 +1232,7 @@ impl GeminiStreamEvent {
                         arguments,
                         call_id: Some(tool_id),
                         output: None,
+                        duration_ms: None,
                         truncated: false,
                         extra: serde_json::Value::Null,
                     },
@@ -1098,6 +1249,7 @@ impl GeminiStreamEvent {
                     arguments: None,
                     call_id: Some(tool_id),
                     output,
+                    duration_ms: None,
                     truncated: false,
                     extra: serde_json::Value::Null,
                 },
@@ -1193,17 +1345,45 @@ mod tests {
             AgentEvent::ItemCompleted { item } => {
                 assert_eq!(item.item_type, "command_execution");
                 assert!(item.truncated);
-                let output = item
-                    .extra
-                    .get("aggregated_output")
-                    .and_then(|value| value.as_str())
-                    .expect("command output string");
+                let output = item.output.as_deref().expect("command output string");
                 assert_eq!(output, "1\n2\n3\n4\n5\n... [truncated]");
             }
             _ => panic!("Expected ItemCompleted event"),
         }
     }
 
+    #[test]
+    fn test_parse_codex_command_execution_fills_output_and_call_id() {
+        let json = r#"{"type":"item.completed","item":{"id":"item_3","type":"command_execution","command":"bash -lc 'cargo fmt --check'","aggregated_output":"Diff in src/main.rs at line 4:\n","exit_code":1,"status":"failed"}}"#;
+        let AgentEvent::ItemCompleted { item } = AgentEvent::parse_codex(json).unwrap() else {
+            panic!("Expected ItemCompleted event");
+        };
+        assert_eq!(item.call_id.as_deref(), Some("item_3"));
+        assert_eq!(
+            item.output.as_deref(),
+            Some("Diff in src/main.rs at line 4:")
+        );
+        assert!(item.extra.get("aggregated_output").is_none());
+        assert_eq!(item.extra["command"], "bash -lc 'cargo fmt --check'");
+        assert_eq!(item.extra["exit_code"], 1);
+        assert!(!item.truncated);
+    }
+
+    #[test]
+    fn test_parse_codex_file_change_fills_output_and_call_id() {
+        let json = r#"{"type":"item.completed","item":{"id":"item_5","type":"file_change","changes":[{"path":"/home/user/repo/src/lib.rs","kind":"update"},{"path":"/home/user/repo/README.md","kind":"add"}],"status":"completed"}}"#;
+        let AgentEvent::ItemCompleted { item } = AgentEvent::parse_codex(json).unwrap() else {
+            panic!("Expected ItemCompleted event");
+        };
+        assert_eq!(item.item_type, "file_change");
+        assert_eq!(item.call_id.as_deref(), Some("item_5"));
+        assert_eq!(
+            item.output.as_deref(),
+            Some("update /home/user/repo/src/lib.rs\nadd /home/user/repo/README.md")
+        );
+        assert_eq!(item.extra["changes"][1]["kind"], "add");
+    }
+
     #[test]
     fn test_parse_tool_call_truncates_large_arguments() {
         let long_text = "x".repeat(MAX_ITEM_ARGUMENTS_CHARS + 100);
@@ -1257,9 +1437,38 @@ mod tests {
     #[test]
     fn test_parse_claude_system() {
         let events = AgentEvent::parse_claude(CLAUDE_SYSTEM_JSON).unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], AgentEvent::SessionStarted { .. }));
+        assert!(matches!(events[1], AgentEvent::TurnStarted {}));
+    }
+
+    #[test]
+    fn test_parse_claude_non_init_system_has_no_turn_start() {
+        let json = r#"{"type":"system","subtype":"compact_boundary","session_id":"6c0b0f60-d9b0-4ee7-9f12-6de09fbfc6d5"}"#;
+        let events = AgentEvent::parse_claude(json).unwrap();
+        assert_eq!(events.len(), 1);
         assert!(matches!(events[0], AgentEvent::SessionStarted { .. }));
     }
 
+    #[test]
+    fn test_parse_claude_tool_permission_request() {
+        let json = r#"{"type":"control_request","request_id":"req_1","request":{"subtype":"can_use_tool","tool_name":"Bash","input":{"command":"ls"}}}"#;
+        let events = AgentEvent::parse_claude(json).unwrap();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            AgentEvent::ApprovalRequested {
+                call_id,
+                tool,
+                arguments,
+            } => {
+                assert_eq!(call_id, "req_1");
+                assert_eq!(tool, "Bash");
+                assert_eq!(arguments.as_deref(), Some(r#"{"command":"ls"}"#));
+            }
+            other => panic!("expected ApprovalRequested, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_parse_claude_assistant_message() {
         let events = AgentEvent::parse_claude(CLAUDE_ASSISTANT_TEXT_JSON).unwrap();
@@ -1273,6 +1482,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_claude_thinking_block() {
+        let json = r#"{"type":"assistant","message":{"id":"msg_2","content":[{"type":"thinking","thinking":"Check the tests first.","signature":"sig"},{"type":"redacted_thinking","data":"opaque"},{"type":"text","text":"Done"}]}}"#;
+        let events = AgentEvent::parse_claude(json).unwrap();
+        assert_eq!(events.len(), 2);
+        match &events[0] {
+            AgentEvent::ItemCompleted { item } => {
+                assert_eq!(item.item_type, "reasoning");
+                assert_eq!(item.text.as_deref(), Some("Check the tests first."));
+            }
+            _ => panic!("Expected reasoning item"),
+        }
+        match &events[1] {
+            AgentEvent::ItemCompleted { item } => {
+                assert_eq!(item.item_type, "agent_message");
+                assert_eq!(item.text.as_deref(), Some("Done"));
+            }
+            _ => panic!("Expected agent message"),
+        }
+    }
+
+    #[test]
+    fn test_parse_claude_redacted_thinking_only() {
+        let json = r#"{"type":"assistant","message":{"id":"msg_3","content":[{"type":"redacted_thinking","data":"opaque"}]}}"#;
+        let events = AgentEvent::parse_claude(json).unwrap();
+        assert!(matches!(events.as_slice(), [AgentEvent::Unknown]));
+    }
+
     #[test]
     fn test_parse_claude_tool_call() {
         let events = AgentEvent::parse_claude(CLAUDE_ASSISTANT_TOOL_JSON).unwrap();
@@ -1401,6 +1638,34 @@ mod tests {
         assert_eq!(uuid, expected_uuid);
     }
 
+    #[test]
+    fn test_cursor_prompt_echo_is_dropped() {
+        let stream = [
+            r#"{"type":"system","subtype":"init","session_id":"6c0b0f60-d9b0-4ee7-9f12-6de09fbfc6d5"}"#,
+            r#"{"type":"user","message":{"role":"user","content":[{"type":"text","text":"fix the tests"}]},"session_id":"6c0b0f60-d9b0-4ee7-9f12-6de09fbfc6d5"}"#,
+            r#"{"type":"assistant","message":{"role":"assistant","content":[{"type":"text","text":"Fixed."}]},"session_id":"6c0b0f60-d9b0-4ee7-9f12-6de09fbfc6d5"}"#,
+        ];
+        let events: Vec<AgentEvent> = stream
+            .iter()
+            .flat_map(|line| AgentEvent::parse_cursor(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 2, "{:?}", events);
+        assert!(matches!(events[0], AgentEvent::SessionStarted { .. }));
+        match &events[1] {
+            AgentEvent::ItemCompleted { item } => {
+                assert_eq!(item.text.as_deref(), Some("Fixed."));
+            }
+            other => panic!("Expected agent message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_claude_prompt_echo_is_dropped() {
+        let json =
+            r#"{"type":"user","message":{"content":[{"type":"text","text":"fix the tests"}]}}"#;
+        assert!(AgentEvent::parse_claude(json).unwrap().is_empty());
+    }
+
     // Gemini event parsing tests
     const GEMINI_INIT_JSON: &str = r#"{"type":"init","timestamp":"2026-01-07T02:18:58.980Z","session_id":"219b0367-780a-4ea0-8ebb-875d740e8fe2","model":"auto-gemini-3"}"#;
     const GEMINI_MESSAGE_JSON: &str = r#"{"type":"message","timestamp":"2026-01-07T02:19:02.137Z","role":"assistant","content":"I will execute the command.","delta":true}"#;
@@ -1411,7 +1676,7 @@ mod tests {
     #[test]
     fn test_parse_gemini_init() {
         let events = AgentEvent::parse_gemini(GEMINI_INIT_JSON).unwrap();
-        assert_eq!(events.len(), 1);
+        assert_eq!(events.len(), 2);
         match &events[0] {
             AgentEvent::SessionStarted { session_id } => {
                 assert_eq!(
@@ -1421,6 +1686,7 @@ mod tests {
             }
             _ => panic!("Expected SessionStarted event"),
         }
+        assert!(matches!(events[1], AgentEvent::TurnStarted {}));
     }
 
     #[test]
@@ -1436,6 +1702,12 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_gemini_prompt_echo_is_dropped() {
+        let json = r#"{"type":"message","timestamp":"2026-01-07T02:19:00.101Z","role":"user","content":"fix the tests"}"#;
+        assert!(AgentEvent::parse_gemini(json).unwrap().is_empty());
+    }
+
     #[test]
     fn test_parse_gemini_tool_use() {
         let events = AgentEvent::parse_gemini(GEMINI_TOOL_USE_JSON).unwrap();
@@ -0,0 +1,361 @@
+//! Rendering task transcripts into shareable documents.
+
+use crate::events::{AgentEvent, CompletedItem, UsageStats};
+use crate::task::{Task, TaskStatus};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// Tool outputs with more lines than this are folded into a `<details>` block.
+const COLLAPSE_OUTPUT_LINES: usize = 20;
+
+/// Output format for a task export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Markdown,
+}
+
+impl ExportFormat {
+    /// HTTP content type of the rendered document.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Markdown => "text/markdown; charset=utf-8",
+        }
+    }
+}
+
+/// Render a task and its recorded events in the requested format.
+pub fn render_task(format: ExportFormat, task: &Task, events: &[AgentEvent]) -> String {
+    match format {
+        ExportFormat::Markdown => render_markdown(task, events),
+    }
+}
+
+/// Render a task conversation as a Markdown document.
+///
+/// Each prompt starts a section; agent messages, tool calls and tool outputs
+/// follow in order, with per-turn usage lines and a closing summary.
+pub fn render_markdown(task: &Task, events: &[AgentEvent]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# {}\n", task.name);
+    let _ = writeln!(out, "- **Agent:** {}", task.agent.as_str());
+    let _ = writeln!(out, "- **Environment:** {}", task.environment);
+    if let Some(branch) = &task.merge_branch {
+        match &task.base_branch {
+            Some(base) => {
+                let _ = writeln!(out, "- **Branch:** `{}` (from `{}`)", branch, base);
+            }
+            None => {
+                let _ = writeln!(out, "- **Branch:** `{}`", branch);
+            }
+        }
+    }
+    let _ = writeln!(out, "- **Created:** {}", task.created_at.to_rfc3339());
+    let _ = writeln!(out, "- **Status:** {}", status_label(task));
+
+    let mut prompts = 0usize;
+    let mut turns = 0usize;
+    let mut totals = UsageStats::default();
+    for event in events {
+        match event {
+            AgentEvent::PromptSent { prompt } => {
+                prompts += 1;
+                let _ = writeln!(out, "\n## Prompt {}\n", prompts);
+                out.push_str(&blockquote(prompt));
+            }
+            AgentEvent::ItemCompleted { item } => render_item(&mut out, item),
+            AgentEvent::TurnCompleted { usage } => {
+                turns += 1;
+                match usage {
+                    Some(usage) => {
+                        add_usage(&mut totals, usage);
+                        let _ = writeln!(
+                            out,
+                            "\n_Turn {} completed: {}._",
+                            turns,
+                            usage_summary(usage)
+                        );
+                    }
+                    None => {
+                        let _ = writeln!(out, "\n_Turn {} completed._", turns);
+                    }
+                }
+            }
+            AgentEvent::Error { message } => {
+                let _ = writeln!(out, "\n**Error:** {}", message);
+            }
+            AgentEvent::ApprovalRequested {
+                tool, arguments, ..
+            } => {
+                let _ = writeln!(out, "\n**Approval requested:** `{}`", tool);
+                if let Some(arguments) = arguments.as_deref() {
+                    out.push_str(&fenced(&pretty_arguments(arguments), "json"));
+                }
+            }
+            AgentEvent::FileEdited {
+                path,
+                added,
+                removed,
+            } => {
+                let _ = writeln!(out, "\n_Edited `{}` (+{} -{})._", path, added, removed);
+            }
+            AgentEvent::SessionStarted { .. }
+            | AgentEvent::TurnStarted {}
+            | AgentEvent::BackgroundEvent { .. }
+            | AgentEvent::Heartbeat { .. }
+            | AgentEvent::Unknown => {}
+        }
+    }
+
+    let _ = writeln!(out, "\n---\n\n## Summary\n");
+    let _ = writeln!(out, "- **Prompts:** {}", prompts);
+    let _ = writeln!(out, "- **Completed turns:** {}", turns);
+    let _ = writeln!(out, "- **Tokens:** {}", usage_summary(&totals));
+    out
+}
+
+fn render_item(out: &mut String, item: &CompletedItem) {
+    match item.item_type.as_str() {
+        "agent_message" => {
+            if let Some(text) = item.text.as_deref() {
+                let _ = writeln!(out, "\n{}", text.trim_end());
+            }
+        }
+        "reasoning" => {
+            if let Some(text) = item.text.as_deref() {
+                let _ = writeln!(out);
+                out.push_str(&blockquote(&format!("_Reasoning:_ {}", text.trim_end())));
+            }
+        }
+        "tool_call" | "function_call" => {
+            let name = item.name.as_deref().unwrap_or("tool");
+            let _ = writeln!(out, "\n**Tool call:** `{}`", name);
+            if let Some(arguments) = item.arguments.as_deref() {
+                out.push_str(&fenced(&pretty_arguments(arguments), "json"));
+            }
+        }
+        "command_execution" => {
+            let command = item
+                .extra
+                .get("command")
+                .and_then(|value| value.as_str())
+                .unwrap_or("");
+            let _ = writeln!(out, "\n**Command:** `{}`", command.replace('`', "'"));
+            let output = item.output.as_deref().or_else(|| {
+                item.extra
+                    .get("aggregated_output")
+                    .and_then(|value| value.as_str())
+            });
+            if let Some(output) = output {
+                out.push_str(&collapsible_output(output));
+            }
+        }
+        "tool_output" | "function_call_output" => {
+            if let Some(output) = item.output.as_deref().or(item.text.as_deref()) {
+                let _ = writeln!(out, "\n**Tool output:**");
+                out.push_str(&collapsible_output(output));
+            }
+        }
+        other => {
+            if let Some(text) = item.text.as_deref() {
+                let _ = writeln!(out, "\n**{}:** {}", other, text.trim_end());
+            }
+        }
+    }
+}
+
+fn status_label(task: &Task) -> &'static str {
+    match task.status {
+        TaskStatus::Pending => "pending",
+        TaskStatus::Running => "running",
+        TaskStatus::Completed => "completed",
+        TaskStatus::Failed => "failed",
+        TaskStatus::Interrupted => "interrupted",
+    }
+}
+
+fn add_usage(totals: &mut UsageStats, usage: &UsageStats) {
+    fn add(total: &mut Option<u64>, value: Option<u64>) {
+        if let Some(value) = value {
+            *total = Some(total.unwrap_or(0) + value);
+        }
+    }
+    add(&mut totals.input_tokens, usage.input_tokens);
+    add(&mut totals.cached_input_tokens, usage.cached_input_tokens);
+    add(&mut totals.output_tokens, usage.output_tokens);
+}
+
+fn usage_summary(usage: &UsageStats) -> String {
+    let mut summary = format!(
+        "{} input / {} output tokens",
+        usage.input_tokens.unwrap_or(0),
+        usage.output_tokens.unwrap_or(0)
+    );
+    if let Some(cached) = usage.cached_input_tokens.filter(|cached| *cached > 0) {
+        let _ = write!(summary, " ({} cached)", cached);
+    }
+    summary
+}
+
+fn blockquote(text: &str) -> String {
+    let mut out = String::new();
+    for line in text.trim_end().lines() {
+        if line.is_empty() {
+            out.push_str(">\n");
+        } else {
+            let _ = writeln!(out, "> {}", line);
+        }
+    }
+    out
+}
+
+/// Pretty-print JSON arguments, leaving anything unparseable as-is.
+fn pretty_arguments(arguments: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(arguments)
+        .ok()
+        .and_then(|value| serde_json::to_string_pretty(&value).ok())
+        .unwrap_or_else(|| arguments.to_string())
+}
+
+/// Fence `text` with enough backticks that its own backtick runs can't close the block.
+fn fenced(text: &str, lang: &str) -> String {
+    let longest_run = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    format!(
+        "\n{}{}\n{}\n{}\n",
+        fence,
+        lang,
+        text.trim_end_matches('\n'),
+        fence
+    )
+}
+
+fn collapsible_output(output: &str) -> String {
+    let line_count = output.lines().count();
+    if line_count <= COLLAPSE_OUTPUT_LINES {
+        return fenced(output, "");
+    }
+    format!(
+        "\n<details>\n<summary>Output ({} lines)</summary>\n{}\n</details>\n",
+        line_count,
+        fenced(output, "")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::anyagent::AgentKind;
+    use crate::task::TaskWorkspaceKind;
+
+    fn item(json: serde_json::Value) -> AgentEvent {
+        AgentEvent::ItemCompleted {
+            item: serde_json::from_value(json).expect("item"),
+        }
+    }
+
+    fn fixture() -> (Task, Vec<AgentEvent>) {
+        let mut task = Task::new(
+            AgentKind::Codex,
+            "demo".to_string(),
+            "Fix the parser".to_string(),
+            TaskWorkspaceKind::Worktree,
+            Some("main".to_string()),
+            Some("task/fix-parser".to_string()),
+            false,
+            "/tmp/demo".into(),
+        );
+        task.status = TaskStatus::Completed;
+
+        let long_output = (1..=30)
+            .map(|n| format!("line {}", n))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let events = vec![
+            AgentEvent::PromptSent {
+                prompt: "Fix the parser\n\nIt panics on `{}`.".to_string(),
+            },
+            AgentEvent::TurnStarted {},
+            item(serde_json::json!({
+                "id": "r1", "type": "reasoning", "text": "Look at parse()."
+            })),
+            item(serde_json::json!({
+                "id": "c1", "type": "tool_call", "name": "read_file",
+                "arguments": "{\"path\":\"src/parser.rs\"}"
+            })),
+            item(serde_json::json!({
+                "id": "o1", "type": "tool_output", "output": "fn parse() {}"
+            })),
+            item(serde_json::json!({
+                "id": "x1", "type": "command_execution", "command": "cargo test",
+                "aggregated_output": long_output
+            })),
+            item(serde_json::json!({
+                "id": "m1", "type": "agent_message", "text": "Fixed the panic."
+            })),
+            AgentEvent::TurnCompleted {
+                usage: Some(UsageStats {
+                    input_tokens: Some(100),
+                    cached_input_tokens: Some(40),
+                    output_tokens: Some(20),
+                }),
+            },
+            AgentEvent::PromptSent {
+                prompt: "Add a test".to_string(),
+            },
+            AgentEvent::Error {
+                message: "codex CLI not found".to_string(),
+            },
+            AgentEvent::TurnCompleted {
+                usage: Some(UsageStats {
+                    input_tokens: Some(50),
+                    cached_input_tokens: None,
+                    output_tokens: Some(5),
+                }),
+            },
+        ];
+        (task, events)
+    }
+
+    #[test]
+    fn test_render_markdown_structure() {
+        let (task, events) = fixture();
+        let markdown = render_markdown(&task, &events);
+
+        assert!(markdown.starts_with("# Fix the parser\n"));
+        assert!(markdown.contains("- **Agent:** codex\n"));
+        assert!(markdown.contains("- **Branch:** `task/fix-parser` (from `main`)\n"));
+        assert!(markdown.contains("## Prompt 1\n\n> Fix the parser\n>\n> It panics on `{}`.\n"));
+        assert!(markdown.contains("## Prompt 2\n\n> Add a test\n"));
+        assert!(markdown.contains("> _Reasoning:_ Look at parse().\n"));
+        assert!(markdown.contains(
+            "**Tool call:** `read_file`\n\n```json\n{\n  \"path\": \"src/parser.rs\"\n}\n```\n"
+        ));
+        assert!(markdown.contains("**Tool output:**\n\n```\nfn parse() {}\n```\n"));
+        assert!(markdown.contains("\nFixed the panic.\n"));
+        assert!(markdown.contains("**Error:** codex CLI not found"));
+        assert!(markdown.contains("_Turn 1 completed: 100 input / 20 output tokens (40 cached)._"));
+        assert!(markdown.contains("- **Completed turns:** 2\n"));
+        assert!(markdown.contains("- **Tokens:** 150 input / 25 output tokens (40 cached)\n"));
+    }
+
+    #[test]
+    fn test_long_command_output_is_collapsed() {
+        let (task, events) = fixture();
+        let markdown = render_markdown(&task, &events);
+
+        assert!(markdown.contains("**Command:** `cargo test`"));
+        assert!(markdown.contains("<details>\n<summary>Output (30 lines)</summary>\n"));
+        assert!(markdown.contains("line 30\n```\n\n</details>"));
+        // Short outputs stay inline.
+        assert_eq!(markdown.matches("<details>").count(), 1);
+    }
+
+    #[test]
+    fn test_fence_outgrows_backticks_in_output() {
+        let fence = fenced("```rust\nfn main() {}\n```", "");
+        assert!(fence.starts_with("\n````\n"));
+        assert!(fence.ends_with("\n````\n"));
+    }
+}
@@ -0,0 +1,185 @@
+//! Summaries of agent file edits, derived from their `tool_call` arguments.
+
+use crate::events::{AgentEvent, CompletedItem};
+
+/// `file.edited` events for a tool call that edits files; empty for any other item.
+///
+/// Recognizes single-file edit tools (`Edit`, `MultiEdit`, `Write` and their Gemini and
+/// OpenCode spellings) and `apply_patch`. Arguments that were clipped during
+/// normalization no longer parse and yield nothing.
+pub fn file_edits(item: &CompletedItem) -> Vec<AgentEvent> {
+    if !item.is_tool_call() {
+        return Vec::new();
+    }
+    let (Some(name), Some(arguments)) = (item.name.as_deref(), item.arguments.as_deref()) else {
+        return Vec::new();
+    };
+    let edits = match name.to_ascii_lowercase().as_str() {
+        "apply_patch" => patch_edits(&patch_text(arguments)),
+        "edit" | "multiedit" | "replace" | "write" | "write_file" => {
+            serde_json::from_str::<serde_json::Value>(arguments)
+                .ok()
+                .and_then(|arguments| tool_edit(&arguments))
+                .into_iter()
+                .collect()
+        }
+        _ => Vec::new(),
+    };
+    edits
+        .into_iter()
+        .map(|(path, added, removed)| AgentEvent::FileEdited {
+            path,
+            added,
+            removed,
+        })
+        .collect()
+}
+
+/// Path and line counts for an `Edit`/`MultiEdit`/`Write`-style argument object.
+fn tool_edit(arguments: &serde_json::Value) -> Option<(String, usize, usize)> {
+    let field = |value: &serde_json::Value, keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| value.get(*key).and_then(|v| v.as_str()))
+            .map(str::to_string)
+    };
+    let path = field(arguments, &["file_path", "filePath", "path"])?;
+
+    if let Some(content) = field(arguments, &["content"]) {
+        return Some((path, content.lines().count(), 0));
+    }
+    let replacements = match arguments.get("edits").and_then(|edits| edits.as_array()) {
+        Some(edits) => edits.iter().collect(),
+        None => vec![arguments],
+    };
+    let (mut added, mut removed) = (0, 0);
+    for replacement in replacements {
+        let old = field(replacement, &["old_string", "oldString"]).unwrap_or_default();
+        let new = field(replacement, &["new_string", "newString"])?;
+        let (a, r) = changed_lines(&old, &new);
+        added += a;
+        removed += r;
+    }
+    Some((path, added, removed))
+}
+
+/// Lines added and removed when `old` is replaced by `new`, ignoring the unchanged
+/// lines both start and end with.
+fn changed_lines(old: &str, new: &str) -> (usize, usize) {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    (new.len() - prefix - suffix, old.len() - prefix - suffix)
+}
+
+/// The patch body of `apply_patch` arguments: raw text, `{"input": ...}`, or an
+/// exec-style `{"command": ["apply_patch", ...]}`.
+fn patch_text(arguments: &str) -> String {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(arguments) else {
+        return arguments.to_string();
+    };
+    value
+        .get("input")
+        .or_else(|| value.get("patch"))
+        .and_then(|patch| patch.as_str())
+        .or_else(|| {
+            value
+                .get("command")
+                .and_then(|command| command.as_array())
+                .and_then(|command| command.last())
+                .and_then(|patch| patch.as_str())
+        })
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Per-file line counts from a `*** Begin Patch` envelope.
+fn patch_edits(patch: &str) -> Vec<(String, usize, usize)> {
+    let mut edits: Vec<(String, usize, usize)> = Vec::new();
+    for line in patch.lines() {
+        let header = ["*** Update File: ", "*** Add File: ", "*** Delete File: "]
+            .iter()
+            .find_map(|prefix| line.strip_prefix(prefix));
+        if let Some(path) = header {
+            edits.push((path.trim().to_string(), 0, 0));
+            continue;
+        }
+        let Some((path, added, removed)) = edits.last_mut() else {
+            continue;
+        };
+        if let Some(destination) = line.strip_prefix("*** Move to: ") {
+            *path = destination.trim().to_string();
+        } else if line.starts_with("***") {
+            continue;
+        } else if line.starts_with('+') {
+            *added += 1;
+        } else if line.starts_with('-') {
+            *removed += 1;
+        }
+    }
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edited(events: &[AgentEvent]) -> Vec<(String, usize, usize)> {
+        events
+            .iter()
+            .map(|event| match event {
+                AgentEvent::FileEdited {
+                    path,
+                    added,
+                    removed,
+                } => (path.clone(), *added, *removed),
+                other => panic!("unexpected event: {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_claude_edit_and_write_tool_calls() {
+        let line = r#"{"type":"assistant","message":{"id":"msg_1","content":[{"type":"tool_use","id":"toolu_1","name":"Edit","input":{"file_path":"/repo/src/lib.rs","old_string":"fn a() {\n    1\n}","new_string":"fn a() {\n    2\n    3\n}"}},{"type":"tool_use","id":"toolu_2","name":"Write","input":{"file_path":"/repo/NOTES.md","content":"one\ntwo\n"}}]}}"#;
+        let events = AgentEvent::parse_claude(line).unwrap();
+        let edits: Vec<_> = events
+            .iter()
+            .filter_map(AgentEvent::item)
+            .flat_map(file_edits)
+            .collect();
+        assert_eq!(
+            edited(&edits),
+            vec![
+                ("/repo/src/lib.rs".to_string(), 2, 1),
+                ("/repo/NOTES.md".to_string(), 2, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_codex_apply_patch_tool_call() {
+        let line = r#"{"type":"item.completed","item":{"id":"item_7","type":"tool_call","name":"apply_patch","call_id":"call_9","arguments":"{\"input\":\"*** Begin Patch\n*** Update File: src/main.rs\n@@ fn main() {\n-    println!(\\\"hi\\\");\n+    println!(\\\"hello\\\");\n+    run();\n*** Add File: src/run.rs\n+pub fn run() {}\n*** Update File: old.rs\n*** Move to: new.rs\n@@\n-// old\n*** End Patch\"}"}}"#;
+        let event = AgentEvent::parse_codex(line).unwrap();
+        let edits = file_edits(event.item().expect("item"));
+        assert_eq!(
+            edited(&edits),
+            vec![
+                ("src/main.rs".to_string(), 2, 1),
+                ("src/run.rs".to_string(), 1, 0),
+                ("new.rs".to_string(), 0, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_other_tool_calls_are_not_edits() {
+        let line = r#"{"type":"assistant","message":{"id":"msg_2","content":[{"type":"tool_use","id":"toolu_3","name":"Read","input":{"file_path":"/repo/src/lib.rs"}}]}}"#;
+        let events = AgentEvent::parse_claude(line).unwrap();
+        assert!(file_edits(events[0].item().expect("item")).is_empty());
+    }
+}
@@ -3,12 +3,14 @@
 //! This module provides an async interface for spawning and managing
 //! Gemini CLI processes, including streaming JSONL output.
 
-use crate::anyagent::{AgentError, AgentResult, AnyAgent, GeminiAgentConfig};
+use crate::anyagent::{
+    exit_signal, spawn_error, AgentError, AgentKind, AgentResult, AnyAgent, GeminiAgentConfig,
+};
 use crate::events::AgentEvent;
+use crate::jsonl;
 use async_trait::async_trait;
 use std::path::Path;
 use std::process::Stdio;
-use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::{Child, Command};
 use tokio::sync::mpsc;
 use uuid::Uuid;
@@ -48,14 +50,17 @@ impl GeminiAgent {
         // Positional prompt
         cmd.arg(prompt);
 
+        cmd.envs(&config.env);
         cmd.stdout(Stdio::piped())
             .stderr(Stdio::inherit())
             .stdin(Stdio::null());
 
-        let mut child = cmd.spawn()?;
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| spawn_error(AgentKind::Gemini, &config.gemini_path, working_dir, e))?;
 
         let stdout = child.stdout.take().expect("stdout was piped");
-        let event_rx = Self::spawn_reader(stdout);
+        let event_rx = Self::spawn_reader(stdout, config);
 
         Ok(Self {
             child,
@@ -92,14 +97,17 @@ impl GeminiAgent {
 
         cmd.arg(prompt);
 
+        cmd.envs(&config.env);
         cmd.stdout(Stdio::piped())
             .stderr(Stdio::inherit())
             .stdin(Stdio::null());
 
-        let mut child = cmd.spawn()?;
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| spawn_error(AgentKind::Gemini, &config.gemini_path, working_dir, e))?;
 
         let stdout = child.stdout.take().expect("stdout was piped");
-        let event_rx = Self::spawn_reader(stdout);
+        let event_rx = Self::spawn_reader(stdout, config);
 
         Ok(Self {
             child,
@@ -111,38 +119,14 @@ impl GeminiAgent {
     /// Spawn a background task to read lines from stdout and parse events.
     fn spawn_reader(
         stdout: tokio::process::ChildStdout,
+        config: &GeminiAgentConfig,
     ) -> mpsc::Receiver<Result<AgentEvent, AgentError>> {
-        let (tx, rx) = mpsc::channel(100);
-
-        tokio::spawn(async move {
-            let reader = BufReader::new(stdout);
-            let mut lines = reader.lines();
-
-            while let Ok(Some(line)) = lines.next_line().await {
-                if line.trim().is_empty() {
-                    continue;
-                }
-
-                // Parse Gemini events
-                let events = AgentEvent::parse_gemini(&line).map_err(AgentError::from);
-                match events {
-                    Ok(events) => {
-                        for event in events {
-                            if tx.send(Ok(event)).await.is_err() {
-                                return;
-                            }
-                        }
-                    }
-                    Err(err) => {
-                        if tx.send(Err(err)).await.is_err() {
-                            return;
-                        }
-                    }
-                }
-            }
-        });
-
-        rx
+        jsonl::spawn_event_reader(
+            stdout,
+            config.max_line_bytes,
+            config.raw_output.clone(),
+            AgentEvent::parse_gemini,
+        )
     }
 }
 
@@ -174,6 +158,7 @@ impl AnyAgent for GeminiAgent {
             session_id,
             success: status.success(),
             exit_code: status.code(),
+            signal: exit_signal(&status),
         })
     }
 
@@ -0,0 +1,292 @@
+//! Length-capped JSONL reading for agent CLI stdout.
+
+use crate::anyagent::AgentError;
+use crate::events::{AgentEvent, CompletedItem};
+use crate::redact::SecretRedactor;
+use std::path::PathBuf;
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+
+/// Default cap on a single stdout line before it is replaced by a truncated item.
+pub const DEFAULT_MAX_LINE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Bytes of an oversized line kept for the truncated item's preview.
+const OVERSIZED_PREVIEW_BYTES: usize = 2 * 1024;
+
+/// One line read by [`read_capped_line`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum CappedLine {
+    /// A complete line (without the trailing newline).
+    Complete(String),
+    /// A line longer than the cap; only a preview of its start is kept.
+    Oversized { preview: String, total_bytes: usize },
+}
+
+/// Read the next line, never buffering more than `max_line_bytes`.
+///
+/// A final line without a trailing newline is still returned at EOF.
+/// Invalid UTF-8 is replaced rather than ending the stream.
+pub async fn read_capped_line<R>(
+    reader: &mut R,
+    max_line_bytes: usize,
+) -> std::io::Result<Option<CappedLine>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut buf = Vec::new();
+    let mut total_bytes = 0usize;
+    let mut read_any = false;
+    let mut oversized = false;
+
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            if !read_any {
+                return Ok(None);
+            }
+            break;
+        }
+        read_any = true;
+
+        let (chunk, found_newline) = match available.iter().position(|b| *b == b'\n') {
+            Some(index) => (&available[..index], true),
+            None => (available, false),
+        };
+        total_bytes += chunk.len();
+        if !oversized {
+            buf.extend_from_slice(chunk);
+            if buf.len() > max_line_bytes {
+                oversized = true;
+                buf.truncate(OVERSIZED_PREVIEW_BYTES.min(max_line_bytes));
+            }
+        }
+
+        let consumed = chunk.len() + usize::from(found_newline);
+        reader.consume(consumed);
+        if found_newline {
+            break;
+        }
+    }
+
+    if buf.last() == Some(&b'\r') && !oversized {
+        buf.pop();
+    }
+    let text = String::from_utf8_lossy(&buf).into_owned();
+    if oversized {
+        Ok(Some(CappedLine::Oversized {
+            preview: text,
+            total_bytes,
+        }))
+    } else {
+        Ok(Some(CappedLine::Complete(text)))
+    }
+}
+
+/// Item reported in place of a line that exceeded the cap.
+pub fn oversized_line_event(preview: String, total_bytes: usize) -> AgentEvent {
+    AgentEvent::ItemCompleted {
+        item: CompletedItem {
+            id: uuid::Uuid::new_v4().to_string(),
+            item_type: "tool_output".to_string(),
+            text: None,
+            name: None,
+            arguments: None,
+            call_id: None,
+            output: Some(format!(
+                "{}\n[output line truncated: {} bytes]",
+                preview, total_bytes
+            )),
+            duration_ms: None,
+            truncated: true,
+            extra: serde_json::json!({ "line_bytes": total_bytes }),
+        },
+    }
+    .normalize()
+}
+
+/// Where to tee unparsed stdout, and the secrets to scrub from it first.
+#[derive(Debug, Clone)]
+pub struct RawOutputLog {
+    pub path: PathBuf,
+    pub redactor: SecretRedactor,
+}
+
+/// Spawn a task that parses capped JSONL lines from `stdout` into agent events.
+///
+/// With `raw_output`, each line is also appended to its file, redacted but otherwise
+/// verbatim (oversized lines only up to their preview), so parser gaps can be inspected later.
+pub fn spawn_event_reader<R>(
+    stdout: R,
+    max_line_bytes: usize,
+    raw_output: Option<RawOutputLog>,
+    parse: fn(&str) -> Result<Vec<AgentEvent>, serde_json::Error>,
+) -> mpsc::Receiver<Result<AgentEvent, AgentError>>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(100);
+
+    tokio::spawn(async move {
+        let mut reader = BufReader::new(stdout);
+        let mut raw_file = match raw_output {
+            Some(log) => match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&log.path)
+                .await
+            {
+                Ok(file) => Some((file, log.redactor)),
+                Err(e) => {
+                    tracing::warn!(path = %log.path.display(), error = %e, "Failed to open raw output log");
+                    None
+                }
+            },
+            None => None,
+        };
+
+        while let Ok(Some(line)) = read_capped_line(&mut reader, max_line_bytes).await {
+            if let Some((file, redactor)) = raw_file.as_mut() {
+                let raw = match &line {
+                    CappedLine::Complete(line) => line,
+                    CappedLine::Oversized { preview, .. } => preview,
+                };
+                let raw = redactor.redact(raw);
+                if file.write_all(raw.as_bytes()).await.is_err()
+                    || file.write_all(b"\n").await.is_err()
+                {
+                    raw_file = None;
+                }
+            }
+            let events = match line {
+                CappedLine::Complete(line) => {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    parse(&line).map_err(AgentError::from)
+                }
+                CappedLine::Oversized {
+                    preview,
+                    total_bytes,
+                } => {
+                    tracing::warn!(total_bytes, "Agent output line exceeded cap; truncating");
+                    Ok(vec![oversized_line_event(preview, total_bytes)])
+                }
+            };
+            match events {
+                Ok(events) => {
+                    for event in events {
+                        if tx.send(Ok(event)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(err) => {
+                    if tx.send(Err(err)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    });
+
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_capped_line_flushes_final_line_without_newline() {
+        let mut reader = BufReader::new(&b"first\r\n\nlast"[..]);
+        assert_eq!(
+            read_capped_line(&mut reader, 1024).await.unwrap(),
+            Some(CappedLine::Complete("first".to_string()))
+        );
+        assert_eq!(
+            read_capped_line(&mut reader, 1024).await.unwrap(),
+            Some(CappedLine::Complete(String::new()))
+        );
+        assert_eq!(
+            read_capped_line(&mut reader, 1024).await.unwrap(),
+            Some(CappedLine::Complete("last".to_string()))
+        );
+        assert_eq!(read_capped_line(&mut reader, 1024).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_multi_megabyte_line_becomes_truncated_item() {
+        let huge_output = "x".repeat(3 * 1024 * 1024);
+        let huge_line = format!(
+            r#"{{"type":"user","message":{{"content":[{{"type":"tool_result","tool_use_id":"toolu_1","content":"{}"}}]}}}}"#,
+            huge_output
+        );
+        let stdout = format!(
+            "{}\n{}\n{}",
+            r#"{"type":"system","subtype":"init","session_id":"6c0b0f60-d9b0-4ee7-9f12-6de09fbfc6d5"}"#,
+            huge_line,
+            r#"{"type":"result","usage":{"input_tokens":1,"output_tokens":2}}"#
+        );
+
+        let mut rx = spawn_event_reader(
+            std::io::Cursor::new(stdout.into_bytes()),
+            1024 * 1024,
+            None,
+            AgentEvent::parse_claude,
+        );
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            events.push(event.expect("event parses"));
+        }
+
+        assert!(matches!(events[0], AgentEvent::SessionStarted { .. }));
+        let truncated = events
+            .iter()
+            .find_map(|event| match event {
+                AgentEvent::ItemCompleted { item } if item.truncated => Some(item),
+                _ => None,
+            })
+            .expect("truncated item");
+        assert_eq!(truncated.item_type, "tool_output");
+        let output = truncated.output.as_deref().unwrap();
+        assert!(output.ends_with(&format!(
+            "[output line truncated: {} bytes]",
+            huge_line.len()
+        )));
+        assert_eq!(
+            truncated.extra["line_bytes"].as_u64(),
+            Some(huge_line.len() as u64)
+        );
+        // The line after the oversized one (without a trailing newline) still arrives.
+        assert!(matches!(
+            events.last(),
+            Some(AgentEvent::TurnCompleted { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_raw_output_keeps_unparsed_lines_redacted() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let raw_path = dir.path().join("task.raw.jsonl");
+        let stdout = "{\"type\":\"turn.started\"}\n{\"type\":\"brand_new_event\",\"x\":\"hunter2hunter2\"}\n";
+
+        let mut rx = spawn_event_reader(
+            std::io::Cursor::new(stdout.as_bytes().to_vec()),
+            1024,
+            Some(RawOutputLog {
+                path: raw_path.clone(),
+                redactor: SecretRedactor::new(&[], ["hunter2hunter2".to_string()]),
+            }),
+            |line| AgentEvent::parse_codex(line).map(|event| vec![event]),
+        );
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            events.push(event.expect("event parses"));
+        }
+
+        assert!(matches!(events[1], AgentEvent::Unknown));
+        let raw = tokio::fs::read_to_string(&raw_path).await.expect("raw log");
+        assert_eq!(raw, stdout.replace("hunter2hunter2", "***"));
+    }
+}
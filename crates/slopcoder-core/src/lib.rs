@@ -6,9 +6,15 @@ pub mod codex_agent;
 pub mod cursor_agent;
 pub mod environment;
 pub mod events;
+pub mod export;
+pub mod file_edit;
 pub mod gemini_agent;
+pub mod jsonl;
+#[cfg(feature = "test-mock")]
+pub mod mock_agent;
 pub mod opencode_agent;
 pub mod persistence;
+pub mod redact;
 pub mod task;
 
 pub use agent_rpc::{AgentCreateTaskRequest, AgentEnvelope, AgentRequest, AgentResponse};
@@ -16,7 +22,7 @@ pub use anyagent::{
     resume_anyagent, spawn_anyagent, AgentError, AgentKind, AgentResult, AnyAgent, AnyAgentConfig,
     ClaudeAgentConfig, CodexAgentConfig, CursorAgentConfig, GeminiAgentConfig, OpencodeAgentConfig,
 };
-pub use environment::{Environment, EnvironmentConfig};
+pub use environment::{Environment, EnvironmentConfig, EnvironmentSettings};
 pub use events::AgentEvent;
 pub use persistence::{PersistenceError, PersistentTaskStore};
-pub use task::{Task, TaskId, TaskStatus};
+pub use task::{Task, TaskId, TaskOutcome, TaskStatus};
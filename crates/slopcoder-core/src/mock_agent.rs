@@ -0,0 +1,282 @@
+//! Scripted agent for deterministic tests (feature `test-mock`).
+//!
+//! Instead of launching a CLI, the mock replays `AgentEvent`s from a JSONL
+//! script (one event per line, in slopcoder's own event format) and then
+//! reports the configured result. Replay pauses after an `approval.requested`
+//! event until [`AnyAgent::respond_approval`] answers it, the way an agent
+//! waiting on its user would.
+
+use crate::anyagent::{AgentError, AgentResult, AnyAgent, MockAgentConfig};
+use crate::events::AgentEvent;
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use uuid::Uuid;
+
+/// A replaying agent that never spawns a process.
+pub struct MockAgent {
+    events: VecDeque<AgentEvent>,
+    session_id: Option<Uuid>,
+    success: bool,
+    exit_code: Option<i32>,
+    killed: bool,
+    /// `call_id` of the approval the replay is paused on.
+    awaiting_approval: Option<String>,
+}
+
+impl MockAgent {
+    /// Start replaying the configured script for a fresh task.
+    pub async fn spawn(
+        config: &MockAgentConfig,
+        working_dir: &Path,
+        _prompt: &str,
+        _web_search: bool,
+    ) -> Result<Self, AgentError> {
+        Self::take_spawn_failure(config)?;
+        let events = Self::load_script(config, working_dir).await?;
+        Ok(Self::new(config, events, None))
+    }
+
+    /// Replay the configured script as a continuation of `session_id`.
+    pub async fn resume(
+        config: &MockAgentConfig,
+        working_dir: &Path,
+        session_id: Uuid,
+        _prompt: &str,
+        _web_search: bool,
+    ) -> Result<Self, AgentError> {
+        Self::take_spawn_failure(config)?;
+        let events = Self::load_script(config, working_dir).await?;
+        Ok(Self::new(config, events, Some(session_id)))
+    }
+
+    fn new(config: &MockAgentConfig, events: VecDeque<AgentEvent>, session: Option<Uuid>) -> Self {
+        Self {
+            events,
+            session_id: session,
+            success: config.success,
+            exit_code: config.exit_code,
+            killed: false,
+            awaiting_approval: None,
+        }
+    }
+
+    /// Fail this start if the config still has `spawn_failures` armed.
+    fn take_spawn_failure(config: &MockAgentConfig) -> Result<(), AgentError> {
+        let armed = config
+            .spawn_failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                left.checked_sub(1)
+            })
+            .is_ok();
+        if armed {
+            return Err(AgentError::ProcessError(
+                "Mock agent failed to start".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Read the script; relative paths are resolved against the working directory.
+    async fn load_script(
+        config: &MockAgentConfig,
+        working_dir: &Path,
+    ) -> Result<VecDeque<AgentEvent>, AgentError> {
+        let path = working_dir.join(&config.script_path);
+        let contents = tokio::fs::read_to_string(&path).await?;
+        contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| AgentEvent::parse_codex(line).map_err(AgentError::from))
+            .collect()
+    }
+
+    fn exit_status(&self) -> std::process::ExitStatus {
+        let code = self.exit_code.unwrap_or(if self.success { 0 } else { 1 });
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            std::process::ExitStatus::from_raw((code & 0xff) << 8)
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::process::ExitStatusExt;
+            std::process::ExitStatus::from_raw(code as u32)
+        }
+    }
+}
+
+#[async_trait]
+impl AnyAgent for MockAgent {
+    async fn next_event(&mut self) -> Option<Result<AgentEvent, AgentError>> {
+        if self.killed {
+            return None;
+        }
+        if self.awaiting_approval.is_some() {
+            return std::future::pending().await;
+        }
+        let event = self.events.pop_front()?;
+        if let Some(id) = event.session_id() {
+            self.session_id = Some(id);
+        }
+        if let AgentEvent::ApprovalRequested { call_id, .. } = &event {
+            self.awaiting_approval = Some(call_id.clone());
+        }
+        Some(Ok(event))
+    }
+
+    async fn wait(mut self: Box<Self>) -> Result<AgentResult, AgentError> {
+        while self.next_event().await.is_some() {}
+
+        let session_id = self.session_id.ok_or(AgentError::NoSessionId)?;
+        Ok(AgentResult {
+            session_id,
+            success: self.success && !self.killed,
+            exit_code: self.exit_code,
+            signal: None,
+        })
+    }
+
+    async fn kill(&mut self) -> Result<(), AgentError> {
+        self.killed = true;
+        self.events.clear();
+        Ok(())
+    }
+
+    fn try_wait(&mut self) -> Result<Option<std::process::ExitStatus>, AgentError> {
+        if self.events.is_empty() && self.awaiting_approval.is_none() {
+            Ok(Some(self.exit_status()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn session_id(&self) -> Option<Uuid> {
+        self.session_id
+    }
+
+    /// Resume replay, reporting the answer as an `approval_response` item.
+    async fn respond_approval(&mut self, call_id: &str, approve: bool) -> Result<(), AgentError> {
+        if self.awaiting_approval.as_deref() != Some(call_id) {
+            return Err(AgentError::ProcessError(format!(
+                "No pending approval for call {}",
+                call_id
+            )));
+        }
+        self.awaiting_approval = None;
+        let answer = serde_json::json!({
+            "type": "item.completed",
+            "item": {
+                "id": format!("approval-{}", call_id),
+                "type": "approval_response",
+                "call_id": call_id,
+                "text": if approve { "approved" } else { "denied" },
+            },
+        });
+        self.events
+            .push_front(AgentEvent::parse_codex(&answer.to_string())?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = r#"{"type":"session.started","session_id":"0b6f6d7e-4f8a-4c52-9c1e-2f0f5a1d3b21"}
+{"type":"turn.started"}
+{"type":"item.completed","item":{"id":"msg_1","type":"agent_message","text":"done"}}
+"#;
+
+    async fn write_script(dir: &Path) -> MockAgentConfig {
+        tokio::fs::write(dir.join("script.jsonl"), SCRIPT)
+            .await
+            .expect("write script");
+        MockAgentConfig {
+            script_path: "script.jsonl".into(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_kill_stops_replay_and_reports_failure() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let config = write_script(dir.path()).await;
+        let mut agent = MockAgent::spawn(&config, dir.path(), "prompt", false)
+            .await
+            .expect("spawn mock");
+
+        assert!(matches!(
+            agent.next_event().await,
+            Some(Ok(AgentEvent::SessionStarted { .. }))
+        ));
+        assert!(agent.try_wait().unwrap().is_none());
+        agent.kill().await.unwrap();
+        assert!(agent.next_event().await.is_none());
+
+        let result = Box::new(agent).wait().await.expect("result");
+        assert!(!result.success);
+    }
+
+    #[tokio::test]
+    async fn test_resume_keeps_session_without_session_event() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        tokio::fs::write(
+            dir.path().join("script.jsonl"),
+            "{\"type\":\"turn.started\"}\n",
+        )
+        .await
+        .unwrap();
+        let config = MockAgentConfig {
+            script_path: "script.jsonl".into(),
+            ..Default::default()
+        };
+        let session = Uuid::new_v4();
+        let agent = MockAgent::resume(&config, dir.path(), session, "again", false)
+            .await
+            .expect("resume mock");
+
+        let result = Box::new(agent).wait().await.expect("result");
+        assert_eq!(result.session_id, session);
+        assert!(result.success);
+    }
+
+    #[tokio::test]
+    async fn test_replay_pauses_until_approval_answered() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        tokio::fs::write(
+            dir.path().join("script.jsonl"),
+            "{\"type\":\"approval.requested\",\"call_id\":\"call_1\",\"tool\":\"shell\"}\n",
+        )
+        .await
+        .unwrap();
+        let config = MockAgentConfig {
+            script_path: "script.jsonl".into(),
+            ..Default::default()
+        };
+        let mut agent = MockAgent::spawn(&config, dir.path(), "prompt", false)
+            .await
+            .expect("spawn mock");
+
+        assert!(matches!(
+            agent.next_event().await,
+            Some(Ok(AgentEvent::ApprovalRequested { .. }))
+        ));
+        assert!(agent.try_wait().unwrap().is_none());
+        let paused =
+            tokio::time::timeout(std::time::Duration::from_millis(50), agent.next_event()).await;
+        assert!(paused.is_err());
+        assert!(agent.respond_approval("call_2", true).await.is_err());
+
+        agent.respond_approval("call_1", false).await.unwrap();
+        match agent.next_event().await {
+            Some(Ok(AgentEvent::ItemCompleted { item })) => {
+                assert_eq!(item.item_type, "approval_response");
+                assert_eq!(item.text.as_deref(), Some("denied"));
+            }
+            other => panic!("unexpected event: {:?}", other.map(|r| r.is_ok())),
+        }
+        assert!(agent.try_wait().unwrap().is_some());
+    }
+}
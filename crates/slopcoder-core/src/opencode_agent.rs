@@ -3,13 +3,15 @@
 //! This module provides an async interface for spawning and managing
 //! OpenCode CLI processes, including streaming JSONL output.
 
-use crate::anyagent::{AgentError, AgentResult, AnyAgent, OpencodeAgentConfig};
+use crate::anyagent::{
+    exit_signal, spawn_error, AgentError, AgentKind, AgentResult, AnyAgent, OpencodeAgentConfig,
+};
 use crate::events::AgentEvent;
+use crate::jsonl;
 use async_trait::async_trait;
 use std::collections::HashMap;
 use std::path::Path;
 use std::process::Stdio;
-use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::{Child, Command};
 use tokio::sync::mpsc;
 use uuid::Uuid;
@@ -51,14 +53,17 @@ impl OpencodeAgent {
 
         cmd.arg(prompt);
 
+        cmd.envs(&config.env);
         cmd.stdout(Stdio::piped())
             .stderr(Stdio::inherit())
             .stdin(Stdio::null());
 
-        let mut child = cmd.spawn()?;
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| spawn_error(AgentKind::Opencode, &config.opencode_path, working_dir, e))?;
 
         let stdout = child.stdout.take().expect("stdout was piped");
-        let event_rx = Self::spawn_reader(stdout);
+        let event_rx = Self::spawn_reader(stdout, config);
 
         Ok(Self {
             child,
@@ -97,14 +102,17 @@ impl OpencodeAgent {
 
         cmd.arg(prompt);
 
+        cmd.envs(&config.env);
         cmd.stdout(Stdio::piped())
             .stderr(Stdio::inherit())
             .stdin(Stdio::null());
 
-        let mut child = cmd.spawn()?;
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| spawn_error(AgentKind::Opencode, &config.opencode_path, working_dir, e))?;
 
         let stdout = child.stdout.take().expect("stdout was piped");
-        let event_rx = Self::spawn_reader(stdout);
+        let event_rx = Self::spawn_reader(stdout, config);
 
         Ok(Self {
             child,
@@ -118,38 +126,14 @@ impl OpencodeAgent {
     /// Spawn a background task to read lines from stdout and parse events.
     fn spawn_reader(
         stdout: tokio::process::ChildStdout,
+        config: &OpencodeAgentConfig,
     ) -> mpsc::Receiver<Result<AgentEvent, AgentError>> {
-        let (tx, rx) = mpsc::channel(100);
-
-        tokio::spawn(async move {
-            let reader = BufReader::new(stdout);
-            let mut lines = reader.lines();
-
-            while let Ok(Some(line)) = lines.next_line().await {
-                if line.trim().is_empty() {
-                    continue;
-                }
-
-                // Parse opencode format
-                let events = AgentEvent::parse_opencode(&line).map_err(AgentError::from);
-                match events {
-                    Ok(events) => {
-                        for event in events {
-                            if tx.send(Ok(event)).await.is_err() {
-                                return;
-                            }
-                        }
-                    }
-                    Err(err) => {
-                        if tx.send(Err(err)).await.is_err() {
-                            return;
-                        }
-                    }
-                }
-            }
-        });
-
-        rx
+        jsonl::spawn_event_reader(
+            stdout,
+            config.max_line_bytes,
+            config.raw_output.clone(),
+            AgentEvent::parse_opencode,
+        )
     }
 
     /// Generate a deterministic UUID from an opencode session string.
@@ -243,6 +227,7 @@ impl AnyAgent for OpencodeAgent {
             session_id,
             success: status.success(),
             exit_code: status.code(),
+            signal: exit_signal(&status),
         })
     }
 
@@ -269,6 +254,7 @@ mod tests {
             opencode_path: "/usr/bin/opencode".to_string(),
             model: "test-model".to_string(),
             extra_args: vec!["--force".to_string()],
+            ..Default::default()
         };
         assert_eq!(config.model, "test-model");
     }
@@ -3,7 +3,7 @@
 //! Each environment has a `tasks.yaml` file in its directory that
 //! stores all tasks for that environment.
 
-use crate::task::{Task, TaskId, TaskStatus};
+use crate::task::{Task, TaskId, TaskOutcome, TaskStatus};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
@@ -79,7 +79,7 @@ impl TasksFile {
         let mut removed = Vec::new();
 
         self.tasks.retain(|task| {
-            if task.worktree_path.exists() {
+            if task.history_only || task.worktree_path.exists() {
                 return true;
             }
 
@@ -98,8 +98,7 @@ impl TasksFile {
                 task.status = TaskStatus::Failed;
                 if let Some(run) = task.history.last_mut() {
                     if run.success.is_none() {
-                        run.success = Some(false);
-                        run.finished_at = Some(chrono::Utc::now());
+                        run.finish(TaskOutcome::Crashed);
                     }
                 }
             }
@@ -311,7 +310,7 @@ impl PersistentTaskStore {
         let stale_tasks: Vec<(TaskId, String)> = self
             .tasks
             .values()
-            .filter(|t| !t.worktree_path.exists())
+            .filter(|t| !t.history_only && !t.worktree_path.exists())
             .map(|t| (t.id, t.environment.clone()))
             .collect();
 
@@ -345,7 +344,7 @@ impl PersistentTaskStore {
         let stale_tasks: Vec<(TaskId, String)> = self
             .tasks
             .values()
-            .filter(|t| !t.worktree_path.exists())
+            .filter(|t| !t.history_only && !t.worktree_path.exists())
             .map(|t| (t.id, t.environment.clone()))
             .collect();
 
@@ -400,12 +399,20 @@ mod tests {
             workspace_kind: crate::task::TaskWorkspaceKind::Worktree,
             base_branch: base_branch.map(|b| b.to_string()),
             merge_branch: Some(merge_branch.to_string()),
+            reused_branch: false,
             web_search: false,
+            model: None,
+            tags: Vec::new(),
+            priority: 0,
             worktree_path: worktree,
+            working_dir: None,
             status: TaskStatus::Completed,
             session_id: None,
             created_at: Utc::now(),
             history: vec![PromptRun::new("test prompt".to_string())],
+            queued_prompts: Vec::new(),
+            depends_on: None,
+            history_only: false,
         }
     }
 
@@ -459,6 +466,24 @@ mod tests {
         assert_eq!(file.tasks.len(), 1);
     }
 
+    #[tokio::test]
+    async fn test_validate_worktrees_keeps_history_only_tasks() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut task = create_test_task(
+            "env",
+            Some("main"),
+            "feature/a",
+            temp_dir.path().join("gone"),
+        );
+        task.history_only = true;
+
+        let mut file = TasksFile { tasks: vec![task] };
+
+        assert!(file.validate_worktrees().is_empty());
+        assert_eq!(file.tasks.len(), 1);
+        assert!(!file.tasks[0].can_run());
+    }
+
     #[tokio::test]
     async fn test_recover_crashed_tasks() {
         let temp_dir = TempDir::new().unwrap();
@@ -475,6 +500,34 @@ mod tests {
 
         assert_eq!(file.tasks[0].status, TaskStatus::Failed);
         assert_eq!(file.tasks[0].history[0].success, Some(false));
+        assert_eq!(file.tasks[0].history[0].outcome, Some(TaskOutcome::Crashed));
+        assert!(file.tasks[0].last_run_crashed());
+    }
+
+    #[tokio::test]
+    async fn test_queued_prompts_survive_reload() {
+        let temp_dir = TempDir::new().unwrap();
+        let worktree = temp_dir.path().join("worktree");
+        tokio::fs::create_dir(&worktree).await.unwrap();
+        let env_dir = temp_dir.path().join("env");
+        tokio::fs::create_dir(&env_dir).await.unwrap();
+
+        let mut task = create_test_task("env", Some("main"), "feature/a", worktree);
+        task.status = TaskStatus::Running;
+        task.history[0].success = None;
+        task.queued_prompts = vec!["add tests".to_string(), "update docs".to_string()];
+        let task_id = task.id;
+        let file = TasksFile { tasks: vec![task] };
+        file.save(&TasksFile::path_for_env(&env_dir)).await.unwrap();
+
+        let mut store = PersistentTaskStore::new();
+        store.register_environment("env".to_string(), env_dir);
+        store.load_all().await.unwrap();
+
+        // The crashed run is failed, but what was queued behind it is kept.
+        let task = store.get(task_id).unwrap();
+        assert_eq!(task.status, TaskStatus::Failed);
+        assert_eq!(task.queued_prompts, vec!["add tests", "update docs"]);
     }
 
     #[tokio::test]
@@ -0,0 +1,176 @@
+//! Scrubbing secrets from agent events before they are logged or streamed.
+
+use crate::events::{AgentEvent, CompletedItem};
+use regex::Regex;
+
+/// Replacement for every redacted match.
+pub const REDACTED: &str = "***";
+
+/// Injected values shorter than this are not treated as secrets, so flags like
+/// `DEBUG=1` don't blank out every `1` in the transcript.
+const MIN_SECRET_VALUE_CHARS: usize = 8;
+
+/// Redacts configured regex matches and known secret values from completed items.
+#[derive(Debug, Clone, Default)]
+pub struct SecretRedactor {
+    patterns: Vec<Regex>,
+    values: Vec<String>,
+}
+
+impl SecretRedactor {
+    /// Build a redactor from regex `patterns` and literal `values` (typically the
+    /// environment variables injected into the agent). Invalid patterns are logged
+    /// and skipped.
+    pub fn new<I>(patterns: &[String], values: I) -> Self
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let patterns = patterns
+            .iter()
+            .filter_map(|pattern| match Regex::new(pattern) {
+                Ok(regex) => Some(regex),
+                Err(e) => {
+                    tracing::warn!(pattern = %pattern, error = %e, "Ignoring invalid redact pattern");
+                    None
+                }
+            })
+            .collect();
+        let mut values: Vec<String> = values
+            .into_iter()
+            .filter(|value| value.chars().count() >= MIN_SECRET_VALUE_CHARS)
+            .collect();
+        // Longest first, so a value containing another is replaced whole.
+        values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+        values.dedup();
+        Self { patterns, values }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty() && self.values.is_empty()
+    }
+
+    /// Replace every secret in `text` with [`REDACTED`].
+    pub fn redact(&self, text: &str) -> String {
+        let mut out = text.to_string();
+        for value in &self.values {
+            if out.contains(value.as_str()) {
+                out = out.replace(value.as_str(), REDACTED);
+            }
+        }
+        for pattern in &self.patterns {
+            if pattern.is_match(&out) {
+                out = pattern.replace_all(&out, REDACTED).into_owned();
+            }
+        }
+        out
+    }
+
+    /// Scrub every free-text field of an event: a completed item's text, output, arguments
+    /// and string `extra` fields (such as a command's `aggregated_output`), error messages,
+    /// approval arguments and background event payloads. Prompts are kept as typed.
+    pub fn redact_event(&self, event: AgentEvent) -> AgentEvent {
+        if self.is_empty() {
+            return event;
+        }
+        match event {
+            AgentEvent::ItemCompleted { item } => AgentEvent::ItemCompleted {
+                item: self.redact_item(item),
+            },
+            AgentEvent::Error { message } => AgentEvent::Error {
+                message: self.redact(&message),
+            },
+            AgentEvent::ApprovalRequested {
+                call_id,
+                tool,
+                arguments,
+            } => AgentEvent::ApprovalRequested {
+                call_id,
+                tool,
+                arguments: arguments.map(|arguments| self.redact(&arguments)),
+            },
+            AgentEvent::BackgroundEvent { event, mut extra } => {
+                self.redact_json(&mut extra);
+                AgentEvent::BackgroundEvent {
+                    event: event.map(|event| self.redact(&event)),
+                    extra,
+                }
+            }
+            other => other,
+        }
+    }
+
+    fn redact_item(&self, mut item: CompletedItem) -> CompletedItem {
+        for field in [&mut item.text, &mut item.output, &mut item.arguments] {
+            if let Some(text) = field.as_mut() {
+                *text = self.redact(text);
+            }
+        }
+        self.redact_json(&mut item.extra);
+        item
+    }
+
+    fn redact_json(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::String(text) => *text = self.redact(text),
+            serde_json::Value::Array(values) => {
+                values.iter_mut().for_each(|value| self.redact_json(value))
+            }
+            serde_json::Value::Object(map) => {
+                map.values_mut().for_each(|value| self.redact_json(value))
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redacts_patterns_and_injected_values() {
+        let redactor = SecretRedactor::new(
+            &[r"sk-[A-Za-z0-9]{16,}".to_string(), "(".to_string()],
+            ["hunter2hunter2".to_string(), "1".to_string()],
+        );
+        assert_eq!(
+            redactor.redact("key=sk-abcdefghijklmnop1234 pass=hunter2hunter2 n=1"),
+            "key=*** pass=*** n=1"
+        );
+
+        let event = AgentEvent::ItemCompleted {
+            item: serde_json::from_value(serde_json::json!({
+                "id": "x1",
+                "type": "command_execution",
+                "command": "env",
+                "aggregated_output": "TOKEN=hunter2hunter2\n",
+            }))
+            .expect("item"),
+        };
+        let AgentEvent::ItemCompleted { item } = redactor.redact_event(event) else {
+            panic!("item expected");
+        };
+        assert_eq!(item.extra["aggregated_output"], "TOKEN=***\n");
+        assert_eq!(item.extra["command"], "env");
+
+        let event = AgentEvent::ApprovalRequested {
+            call_id: "call_1".to_string(),
+            tool: "shell".to_string(),
+            arguments: Some(r#"{"command":"curl -H 'Bearer hunter2hunter2'"}"#.to_string()),
+        };
+        let AgentEvent::ApprovalRequested { arguments, .. } = redactor.redact_event(event) else {
+            panic!("approval expected");
+        };
+        assert_eq!(
+            arguments.as_deref(),
+            Some(r#"{"command":"curl -H 'Bearer ***'"}"#)
+        );
+        let event = AgentEvent::Error {
+            message: "auth failed for hunter2hunter2".to_string(),
+        };
+        let AgentEvent::Error { message } = redactor.redact_event(event) else {
+            panic!("error expected");
+        };
+        assert_eq!(message, "auth failed for ***");
+    }
+}
@@ -6,7 +6,7 @@
 use crate::anyagent::AgentKind;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
 /// Unique identifier for a task.
@@ -60,6 +60,38 @@ pub enum TaskStatus {
     Interrupted,
 }
 
+/// How a prompt run ended; finer-grained than the [`TaskStatus`] it maps to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskOutcome {
+    /// The agent exited successfully.
+    Completed,
+    /// The agent exited unsuccessfully.
+    Failed,
+    /// The user interrupted the run.
+    Interrupted,
+    /// The run outlived the host's run timeout and was killed.
+    TimedOut,
+    /// The agent could not be started or waited on.
+    ToolError,
+    /// slopagent stopped while the run was in progress; the agent's turn was abandoned.
+    Crashed,
+}
+
+impl TaskOutcome {
+    /// Task status after a run with this outcome.
+    pub fn status(self) -> TaskStatus {
+        match self {
+            TaskOutcome::Completed => TaskStatus::Completed,
+            TaskOutcome::Interrupted => TaskStatus::Interrupted,
+            TaskOutcome::Failed
+            | TaskOutcome::TimedOut
+            | TaskOutcome::ToolError
+            | TaskOutcome::Crashed => TaskStatus::Failed,
+        }
+    }
+}
+
 /// A single prompt and its result in the task history.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PromptRun {
@@ -72,6 +104,15 @@ pub struct PromptRun {
     pub finished_at: Option<DateTime<Utc>>,
     /// Whether this run succeeded.
     pub success: Option<bool>,
+    /// Agent process exit code, when the run ended with one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// Signal that killed the agent process, when it was killed by one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    /// How the run ended; absent while running and for runs recorded before outcomes existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<TaskOutcome>,
 }
 
 impl PromptRun {
@@ -82,13 +123,17 @@ impl PromptRun {
             started_at: Utc::now(),
             finished_at: None,
             success: None,
+            exit_code: None,
+            signal: None,
+            outcome: None,
         }
     }
 
-    /// Mark this run as finished.
-    pub fn finish(&mut self, success: bool) {
+    /// Mark this run as finished with `outcome`; only `Completed` counts as success.
+    pub fn finish(&mut self, outcome: TaskOutcome) {
         self.finished_at = Some(Utc::now());
-        self.success = Some(success);
+        self.success = Some(outcome == TaskOutcome::Completed);
+        self.outcome = Some(outcome);
     }
 }
 
@@ -113,11 +158,27 @@ pub struct Task {
     /// Branch used for merge when task runs in an isolated worktree.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub merge_branch: Option<String>,
+    /// The merge branch existed before the task checked it out, so deleting the task keeps it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reused_branch: bool,
     /// Whether web search is enabled for this task.
     #[serde(default)]
     pub web_search: bool,
+    /// Model override passed to the agent; `None` keeps the agent's own default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// User-assigned labels for grouping tasks.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Rate-limit priority: higher values get start-rate tokens first and sort first in
+    /// `sort=priority` listings. It does not reorder queued prompts.
+    #[serde(default)]
+    pub priority: i32,
     /// Path to the task workspace directory.
     pub worktree_path: PathBuf,
+    /// Directory inside the workspace where the agent runs, when not the workspace root.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<PathBuf>,
     /// Current status of the task.
     pub status: TaskStatus,
     /// Session ID (set after first run).
@@ -126,6 +187,16 @@ pub struct Task {
     pub created_at: DateTime<Utc>,
     /// History of prompt runs.
     pub history: Vec<PromptRun>,
+    /// Prompts waiting to run after the current one, next first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub queued_prompts: Vec<String>,
+    /// Task that must complete successfully before this one starts; until then this task
+    /// stays `Pending` with its first prompt queued.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depends_on: Option<TaskId>,
+    /// Restored from the archive after its workspace was removed; viewable but not runnable.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub history_only: bool,
 }
 
 impl Task {
@@ -148,24 +219,38 @@ impl Task {
             workspace_kind,
             base_branch,
             merge_branch,
+            reused_branch: false,
             web_search,
+            model: None,
+            tags: Vec::new(),
+            priority: 0,
             worktree_path,
+            working_dir: None,
             status: TaskStatus::Pending,
             session_id: None,
             created_at: Utc::now(),
             history: Vec::new(),
+            queued_prompts: Vec::new(),
+            depends_on: None,
+            history_only: false,
         }
     }
 
+    /// Directory the agent process is started in.
+    pub fn agent_working_dir(&self) -> &Path {
+        self.working_dir.as_deref().unwrap_or(&self.worktree_path)
+    }
+
     /// Check if this task can accept new prompts.
     pub fn can_run(&self) -> bool {
-        matches!(
-            self.status,
-            TaskStatus::Pending
-                | TaskStatus::Completed
-                | TaskStatus::Failed
-                | TaskStatus::Interrupted
-        )
+        !self.history_only
+            && matches!(
+                self.status,
+                TaskStatus::Pending
+                    | TaskStatus::Completed
+                    | TaskStatus::Failed
+                    | TaskStatus::Interrupted
+            )
     }
 
     /// Check if the agent is currently running.
@@ -173,30 +258,47 @@ impl Task {
         self.status == TaskStatus::Running
     }
 
+    /// Whether the last run was cut short by a slopagent restart.
+    pub fn last_run_crashed(&self) -> bool {
+        self.history
+            .last()
+            .is_some_and(|run| run.outcome == Some(TaskOutcome::Crashed))
+    }
+
     /// Start a new prompt run.
     pub fn start_run(&mut self, prompt: String) {
         self.status = TaskStatus::Running;
         self.history.push(PromptRun::new(prompt));
     }
 
-    /// Mark the current run as completed.
-    pub fn complete_run(&mut self, success: bool) {
+    /// Record how the current run's agent process exited.
+    pub fn record_run_exit(&mut self, exit_code: Option<i32>, signal: Option<i32>) {
         if let Some(run) = self.history.last_mut() {
-            run.finish(success);
+            run.exit_code = exit_code;
+            run.signal = signal;
         }
-        self.status = if success {
-            TaskStatus::Completed
+    }
+
+    /// Finish the current run with `outcome` and set the matching status.
+    pub fn finish_run(&mut self, outcome: TaskOutcome) {
+        if let Some(run) = self.history.last_mut() {
+            run.finish(outcome);
+        }
+        self.status = outcome.status();
+    }
+
+    /// Mark the current run as completed.
+    pub fn complete_run(&mut self, success: bool) {
+        self.finish_run(if success {
+            TaskOutcome::Completed
         } else {
-            TaskStatus::Failed
-        };
+            TaskOutcome::Failed
+        });
     }
 
     /// Mark the current run as interrupted.
     pub fn interrupt_run(&mut self) {
-        if let Some(run) = self.history.last_mut() {
-            run.finish(false);
-        }
-        self.status = TaskStatus::Interrupted;
+        self.finish_run(TaskOutcome::Interrupted);
     }
 
     /// Rename the task to a new human-friendly topic.
@@ -204,6 +306,18 @@ impl Task {
         self.name = name;
     }
 
+    /// Replace the task's tags, trimming whitespace and dropping blanks and duplicates.
+    pub fn set_tags(&mut self, tags: Vec<String>) {
+        let mut normalized: Vec<String> = Vec::new();
+        for tag in tags {
+            let tag = tag.trim();
+            if !tag.is_empty() && !normalized.iter().any(|existing| existing == tag) {
+                normalized.push(tag.to_string());
+            }
+        }
+        self.tags = normalized;
+    }
+
     /// Get the last prompt that was run.
     pub fn last_prompt(&self) -> Option<&str> {
         self.history.last().map(|r| r.prompt.as_str())
@@ -330,6 +444,58 @@ mod tests {
         assert_eq!(task.name, "new topic");
     }
 
+    #[test]
+    fn test_task_tags_round_trip() {
+        let mut task = Task::new(
+            AgentKind::Codex,
+            "env".to_string(),
+            "topic".to_string(),
+            TaskWorkspaceKind::Environment,
+            None,
+            None,
+            false,
+            PathBuf::from("/tmp"),
+        );
+
+        let untagged = serde_yaml::to_string(&task).unwrap();
+        assert!(!untagged.contains("tags"));
+        let restored: Task = serde_yaml::from_str(&untagged).unwrap();
+        assert!(restored.tags.is_empty());
+
+        task.set_tags(vec![
+            " backend ".to_string(),
+            "".to_string(),
+            "urgent".to_string(),
+            "backend".to_string(),
+        ]);
+        assert_eq!(task.tags, vec!["backend", "urgent"]);
+
+        let restored: Task = serde_yaml::from_str(&serde_yaml::to_string(&task).unwrap()).unwrap();
+        assert_eq!(restored.tags, task.tags);
+    }
+
+    #[test]
+    fn test_task_priority_defaults_and_round_trips() {
+        let mut task = Task::new(
+            AgentKind::Codex,
+            "env".to_string(),
+            "topic".to_string(),
+            TaskWorkspaceKind::Environment,
+            None,
+            None,
+            false,
+            PathBuf::from("/tmp"),
+        );
+        let mut legacy = serde_yaml::to_value(&task).unwrap();
+        legacy.as_mapping_mut().unwrap().remove("priority");
+        let restored: Task = serde_yaml::from_value(legacy).unwrap();
+        assert_eq!(restored.priority, 0);
+
+        task.priority = 5;
+        let restored: Task = serde_yaml::from_str(&serde_yaml::to_string(&task).unwrap()).unwrap();
+        assert_eq!(restored.priority, 5);
+    }
+
     #[test]
     fn test_task_store() {
         let mut store = TaskStore::new();
@@ -391,6 +557,44 @@ mod tests {
         assert!(!task.is_running());
         assert_eq!(task.history.len(), 1);
         assert_eq!(task.history[0].success, Some(false));
+        assert_eq!(task.history[0].outcome, Some(TaskOutcome::Interrupted));
+    }
+
+    #[test]
+    fn test_run_outcome_maps_to_status_and_round_trips() {
+        let mut task = Task::new(
+            AgentKind::Codex,
+            "env".to_string(),
+            "topic".to_string(),
+            TaskWorkspaceKind::Environment,
+            None,
+            None,
+            false,
+            PathBuf::from("/tmp"),
+        );
+
+        task.start_run("slow".to_string());
+        task.finish_run(TaskOutcome::TimedOut);
+        assert_eq!(task.status, TaskStatus::Failed);
+        task.start_run("broken".to_string());
+        task.finish_run(TaskOutcome::ToolError);
+        assert_eq!(task.status, TaskStatus::Failed);
+        task.start_run("fine".to_string());
+        task.complete_run(true);
+        assert_eq!(task.status, TaskStatus::Completed);
+
+        let json = serde_json::to_value(&task.history).unwrap();
+        assert_eq!(json[0]["outcome"], "timed_out");
+        assert_eq!(json[1]["outcome"], "tool_error");
+        assert_eq!(json[2]["outcome"], "completed");
+        assert_eq!(json[0]["success"], false);
+
+        // Runs recorded before outcomes existed have none.
+        let legacy: PromptRun = serde_json::from_str(
+            r#"{"started_at":"2024-01-01T00:00:00Z","finished_at":null,"success":true}"#,
+        )
+        .unwrap();
+        assert_eq!(legacy.outcome, None);
     }
 
     #[test]
@@ -452,4 +656,30 @@ mod tests {
         assert_eq!(task.history[1].success, Some(false));
         assert_eq!(task.history[2].success, Some(true));
     }
+
+    #[test]
+    fn test_run_exit_status_is_recorded_and_persisted() {
+        let mut task = Task::new(
+            AgentKind::Codex,
+            "env".to_string(),
+            "topic".to_string(),
+            TaskWorkspaceKind::Environment,
+            None,
+            None,
+            false,
+            PathBuf::from("/tmp"),
+        );
+
+        task.start_run("prompt".to_string());
+        task.record_run_exit(Some(3), None);
+        task.complete_run(false);
+        assert_eq!(task.status, TaskStatus::Failed);
+
+        let yaml = serde_yaml::to_string(&task.history[0]).unwrap();
+        assert!(!yaml.contains("signal"));
+        let run: PromptRun = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(run.exit_code, Some(3));
+        assert_eq!(run.signal, None);
+        assert_eq!(run.success, Some(false));
+    }
 }
@@ -5,6 +5,7 @@
 //! - `test-claude`: Enable Claude agent tests
 //! - `test-cursor`: Enable Cursor agent tests
 //! - `test-opencode`: Enable OpenCode agent tests
+//! - `test-mock`: Enable scripted mock agent tests (no CLI required)
 //!
 //! Run with: `cargo test --features test-opencode` (or other features)
 
@@ -717,3 +718,74 @@ async fn test_opencode_agent_resume_after_interrupt() {
 async fn test_opencode_agent_double_interrupt() {
     run_agent_double_interrupt(AgentKind::Opencode).await;
 }
+
+#[cfg(feature = "test-mock")]
+#[tokio::test]
+async fn test_mock_agent_spawn_events_complete() {
+    use slopcoder_core::AgentEvent;
+
+    let (_temp_dir, env) = setup_test_env().await;
+    let worktrees = worktrees_dir(_temp_dir.path());
+    let worktree_path = env
+        .create_worktree_from_base(&worktrees, "main", "task-mock")
+        .await
+        .expect("Should create worktree");
+
+    let mut config = AnyAgentConfig::default();
+    config.mock.script_path =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/mock_agent.jsonl");
+
+    let mut task = Task::new(
+        AgentKind::Mock,
+        env.name.clone(),
+        "topic".to_string(),
+        TaskWorkspaceKind::Worktree,
+        Some("main".to_string()),
+        Some("task/mock".to_string()),
+        false,
+        worktree_path.clone(),
+    );
+    task.start_run("Create hello.txt".to_string());
+
+    let mut agent = spawn_anyagent(
+        AgentKind::Mock,
+        &config,
+        &worktree_path,
+        "Create hello.txt",
+        false,
+    )
+    .await
+    .expect("Should spawn mock agent");
+
+    let mut events = Vec::new();
+    while let Some(result) = agent.next_event().await {
+        events.push(result.expect("Scripted event should parse"));
+    }
+    assert_eq!(events.len(), 7);
+    assert!(matches!(events[0], AgentEvent::SessionStarted { .. }));
+    assert!(matches!(events[1], AgentEvent::TurnStarted {}));
+    assert!(matches!(
+        events.last(),
+        Some(AgentEvent::TurnCompleted { usage: Some(_) })
+    ));
+
+    let result = agent.wait().await.expect("Mock agent should complete");
+    assert!(result.success);
+    assert_eq!(result.exit_code, Some(0));
+    task.session_id = Some(result.session_id);
+    task.complete_run(result.success);
+    assert_eq!(task.status, TaskStatus::Completed);
+
+    let resumed = resume_anyagent(
+        AgentKind::Mock,
+        &config,
+        &worktree_path,
+        result.session_id,
+        "Again",
+        false,
+    )
+    .await
+    .expect("Should resume mock agent");
+    let resumed = resumed.wait().await.expect("Resumed mock should complete");
+    assert_eq!(resumed.session_id, result.session_id);
+}
@@ -7,9 +7,14 @@ use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 use uuid::Uuid;
 use warp::Filter;
 
-use state::AppState;
+use state::{
+    AppState, HeartbeatConfig, DEFAULT_AGENT_REQUEST_TIMEOUT_SECS, DEFAULT_LIST_HOST_BACKOFF_SECS,
+};
 
 const DEFAULT_LIST_REQUEST_TIMEOUT_SECS: u64 = 15;
+const DEFAULT_EVENT_BUFFER: usize = 200;
+/// How long shutdown waits for in-flight agent requests and HTTP connections.
+const SHUTDOWN_GRACE: std::time::Duration = std::time::Duration::from_secs(10);
 
 struct ServerCli {
     addr_arg: Option<String>,
@@ -19,7 +24,13 @@ struct ServerCli {
     no_password: bool,
     explicit_ui_password: Option<String>,
     explicit_agent_password: Option<String>,
+    readonly_password: Option<String>,
     list_request_timeout_secs: u64,
+    list_backoff_secs: u64,
+    agent_request_timeout_secs: u64,
+    event_buffer: usize,
+    heartbeat: HeartbeatConfig,
+    json_logs: bool,
 }
 
 fn parse_cli_args<I>(args: I) -> ServerCli
@@ -35,7 +46,13 @@ where
         no_password: false,
         explicit_ui_password: None,
         explicit_agent_password: None,
+        readonly_password: None,
         list_request_timeout_secs: DEFAULT_LIST_REQUEST_TIMEOUT_SECS,
+        list_backoff_secs: DEFAULT_LIST_HOST_BACKOFF_SECS,
+        agent_request_timeout_secs: DEFAULT_AGENT_REQUEST_TIMEOUT_SECS,
+        event_buffer: DEFAULT_EVENT_BUFFER,
+        heartbeat: HeartbeatConfig::default(),
+        json_logs: std::env::var("SLOPCODER_LOG_FORMAT").is_ok_and(|value| value == "json"),
     };
 
     while let Some(arg) = args.next() {
@@ -61,17 +78,59 @@ where
             "--no-password" => {
                 cli.no_password = true;
             }
-            "--list-request-timeout-secs" => {
+            "--readonly-password" => {
+                cli.readonly_password = args.next().filter(|value| !value.is_empty());
+            }
+            "--list-request-timeout-secs" | "--list-timeout" => {
                 cli.list_request_timeout_secs = args
                     .next()
                     .and_then(|value| value.parse().ok())
                     .filter(|value| *value > 0)
                     .unwrap_or(DEFAULT_LIST_REQUEST_TIMEOUT_SECS);
             }
+            "--list-backoff-secs" | "--list-backoff" => {
+                cli.list_backoff_secs = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(DEFAULT_LIST_HOST_BACKOFF_SECS);
+            }
+            "--agent-request-timeout-secs" => {
+                cli.agent_request_timeout_secs = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .filter(|value| *value > 0)
+                    .unwrap_or(DEFAULT_AGENT_REQUEST_TIMEOUT_SECS);
+            }
+            "--event-buffer" => {
+                cli.event_buffer = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .filter(|value| *value > 0)
+                    .unwrap_or(DEFAULT_EVENT_BUFFER);
+            }
+            "--heartbeat-interval-secs" => {
+                if let Some(secs) = args.next().and_then(|value| value.parse().ok()) {
+                    cli.heartbeat.interval = std::time::Duration::from_secs(secs);
+                }
+            }
+            "--heartbeat-max-missed" => {
+                if let Some(max_missed) = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .filter(|value| *value > 0)
+                {
+                    cli.heartbeat.max_missed = max_missed;
+                }
+            }
+            "--log-format" => {
+                if let Some(format) = args.next() {
+                    cli.json_logs = format == "json";
+                }
+            }
             "-h" | "--help" => {
                 println!(
-                    "Usage: slopcoder-server [--addr HOST:PORT] [--static-dir PATH] [--password VALUE|--password-prompt|--no-password] [--agent-password VALUE|--agent-password-prompt] [--list-request-timeout-secs SECONDS]\n\
-Defaults: addr=127.0.0.1:8080, static-dir=frontend/dist, UI auth disabled, agent auth enabled with generated startup password, list-request-timeout-secs=15"
+                    "Usage: slopcoder-server [--addr HOST:PORT] [--static-dir PATH] [--password VALUE|--password-prompt|--no-password] [--agent-password VALUE|--agent-password-prompt] [--readonly-password VALUE] [--list-request-timeout-secs SECONDS] [--list-backoff-secs SECONDS] [--agent-request-timeout-secs SECONDS] [--event-buffer N] [--heartbeat-interval-secs SECONDS] [--heartbeat-max-missed N] [--log-format text|json]\n\
+Defaults: addr=127.0.0.1:8080, static-dir=frontend/dist, UI auth disabled, no read-only password, agent auth enabled with generated startup password, list-request-timeout-secs=15, list-backoff-secs=30 (0 disables), agent-request-timeout-secs=120, event-buffer=200, heartbeat-interval-secs=15 (0 disables), heartbeat-max-missed=3, log-format=text (or SLOPCODER_LOG_FORMAT)"
                 );
                 std::process::exit(0);
             }
@@ -84,14 +143,22 @@ Defaults: addr=127.0.0.1:8080, static-dir=frontend/dist, UI auth disabled, agent
 
 #[tokio::main]
 async fn main() {
-    // Initialize logging
-    tracing_subscriber::registry()
-        .with(fmt::layer())
-        .with(EnvFilter::from_default_env().add_directive("slopcoder=info".parse().unwrap()))
-        .init();
-
     let cli = parse_cli_args(std::env::args().skip(1));
 
+    // Initialize logging
+    let filter = EnvFilter::from_default_env().add_directive("slopcoder=info".parse().unwrap());
+    if cli.json_logs {
+        tracing_subscriber::registry()
+            .with(fmt::layer().json())
+            .with(filter)
+            .init();
+    } else {
+        tracing_subscriber::registry()
+            .with(fmt::layer())
+            .with(filter)
+            .init();
+    }
+
     let ui_auth_password = if cli.no_password {
         tracing::warn!("UI authentication disabled (--no-password).");
         None
@@ -140,10 +207,24 @@ async fn main() {
         ui_auth_password,
         agent_auth_password,
         cli.list_request_timeout_secs,
+        cli.event_buffer,
     );
+    if cli.readonly_password.is_some() && state.get_ui_auth_password().await.is_none() {
+        tracing::warn!("--readonly-password has no effect while UI authentication is disabled.");
+    }
+    state
+        .set_readonly_auth_password(cli.readonly_password)
+        .await;
+    state.set_list_backoff_secs(cli.list_backoff_secs).await;
+    state.set_heartbeat_config(cli.heartbeat).await;
+    state
+        .set_agent_request_timeout(std::time::Duration::from_secs(
+            cli.agent_request_timeout_secs,
+        ))
+        .await;
 
     // Build API routes
-    let api_routes = routes::routes(state);
+    let api_routes = routes::routes(state.clone());
 
     // Add CORS for development
     let cors = warp::cors()
@@ -181,12 +262,54 @@ async fn main() {
 
     tracing::info!("Starting server at http://{}", addr);
 
-    warp::serve(routes).run(addr).await;
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let (_, server) = warp::serve(routes).bind_with_graceful_shutdown(addr, async move {
+        let _ = stop_rx.await;
+    });
+    let server = tokio::spawn(server);
+
+    shutdown_signal().await;
+    tracing::info!("Shutdown requested; no longer accepting connections");
+    let _ = stop_tx.send(());
+    state.shutdown_agents(SHUTDOWN_GRACE).await;
+    if tokio::time::timeout(SHUTDOWN_GRACE, server).await.is_err() {
+        tracing::warn!("Timed out waiting for open connections to close");
+    }
+}
+
+/// Resolve on Ctrl-C, or SIGTERM on Unix.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to install SIGTERM handler: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{parse_cli_args, DEFAULT_LIST_REQUEST_TIMEOUT_SECS};
+    use super::{
+        parse_cli_args, DEFAULT_AGENT_REQUEST_TIMEOUT_SECS, DEFAULT_EVENT_BUFFER,
+        DEFAULT_LIST_HOST_BACKOFF_SECS, DEFAULT_LIST_REQUEST_TIMEOUT_SECS,
+    };
 
     #[test]
     fn parse_cli_uses_default_list_request_timeout() {
@@ -205,4 +328,61 @@ mod tests {
         ]);
         assert_eq!(cli.list_request_timeout_secs, 22);
     }
+
+    #[test]
+    fn parse_cli_accepts_list_timeout_and_backoff_overrides() {
+        let cli = parse_cli_args(Vec::<String>::new());
+        assert_eq!(cli.list_backoff_secs, DEFAULT_LIST_HOST_BACKOFF_SECS);
+        let cli = parse_cli_args(
+            ["--list-timeout", "9", "--list-backoff", "0"]
+                .into_iter()
+                .map(String::from),
+        );
+        assert_eq!(cli.list_request_timeout_secs, 9);
+        assert_eq!(cli.list_backoff_secs, 0);
+    }
+
+    #[test]
+    fn parse_cli_accepts_agent_request_timeout_override() {
+        assert_eq!(
+            parse_cli_args(Vec::<String>::new()).agent_request_timeout_secs,
+            DEFAULT_AGENT_REQUEST_TIMEOUT_SECS
+        );
+        let cli = parse_cli_args(
+            ["--agent-request-timeout-secs", "600"]
+                .into_iter()
+                .map(String::from),
+        );
+        assert_eq!(cli.agent_request_timeout_secs, 600);
+    }
+
+    #[test]
+    fn parse_cli_accepts_event_buffer_override() {
+        assert_eq!(
+            parse_cli_args(Vec::<String>::new()).event_buffer,
+            DEFAULT_EVENT_BUFFER
+        );
+        let cli = parse_cli_args(vec!["--event-buffer".to_string(), "1024".to_string()]);
+        assert_eq!(cli.event_buffer, 1024);
+    }
+
+    #[test]
+    fn parse_cli_accepts_json_log_format() {
+        let cli = parse_cli_args(vec!["--log-format".to_string(), "json".to_string()]);
+        assert!(cli.json_logs);
+        let cli = parse_cli_args(vec!["--log-format".to_string(), "text".to_string()]);
+        assert!(!cli.json_logs);
+    }
+
+    #[test]
+    fn parse_cli_accepts_heartbeat_overrides() {
+        let cli = parse_cli_args(vec![
+            "--heartbeat-interval-secs".to_string(),
+            "0".to_string(),
+            "--heartbeat-max-missed".to_string(),
+            "5".to_string(),
+        ]);
+        assert!(cli.heartbeat.interval.is_zero());
+        assert_eq!(cli.heartbeat.max_missed, 5);
+    }
 }
@@ -1,23 +1,35 @@
 //! HTTP routes for the Slopcoder coordinator API.
 
-use crate::state::{AppState, ConnectedAgent, RemoteError, StateError, TerminalEvent};
+use crate::state::{
+    AgentIdentity, AppState, ConnectedAgent, DiffStreamEvent, HostStatus, PendingResponse,
+    RemoteError, SequencedEvent, StateError, TerminalEvent,
+};
 use futures::future::join_all;
 use futures::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use slopcoder_core::{
     agent_rpc::{
-        AgentCreateTaskRequest, AgentEnvelope, AgentRequest, AgentResponse, TaskOutputPageRequest,
+        AgentCreateTaskRequest, AgentEnvelope, AgentRequest, AgentResponse, ArchivedTask,
+        Attachment, BatchError, CommitInfo, RequestId, SkippedTask, TaskDiskUsage, TaskLogLine,
+        TaskOutputPageRequest, CLONE_TIMEOUT_SECS, MERGE_CHECK_TIMEOUT_SECS, PROTOCOL_VERSION,
     },
-    task::{Task, TaskId},
+    environment::{Environment, WorktreeInfo},
+    events::UsageStats,
+    export::ExportFormat,
+    task::{Task, TaskId, TaskOutcome},
     AgentEvent,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::convert::Infallible;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::{mpsc, oneshot, Mutex};
 use uuid::Uuid;
+use warp::filters::BoxedFilter;
 use warp::http::{Method, StatusCode};
+use warp::path::FullPath;
 use warp::reject::InvalidQuery;
 use warp::ws::{Message, WebSocket};
 use warp::{Filter, Reply};
@@ -26,16 +38,28 @@ use warp::{Filter, Reply};
 struct AuthError;
 impl warp::reject::Reject for AuthError {}
 
+/// A read-only credential was used for a request that changes state.
+#[derive(Debug)]
+struct ReadOnlyError;
+impl warp::reject::Reject for ReadOnlyError {}
+
 /// Create all API routes.
+///
+/// Each route group is boxed; nesting every group's filter type in one tree overflows
+/// rustc's query depth limit.
 pub fn routes(
     state: AppState,
 ) -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
     let hosts = warp::path("hosts").and(hosts_routes(state.clone()));
     let environments = warp::path("environments").and(environments_routes(state.clone()));
     let tasks = warp::path("tasks").and(tasks_routes(state.clone()));
+    let reload = warp::path!("reload")
+        .and(warp::post())
+        .and(with_state(state.clone()))
+        .and_then(reload_environments);
 
     let api_scoped = auth_filter_api(state.clone())
-        .and(hosts.or(environments).or(tasks))
+        .and(hosts.or(environments).or(tasks).or(reload))
         .recover(handle_rejection);
     let api_routes = warp::path("api").and(api_scoped);
 
@@ -54,13 +78,30 @@ pub fn routes(
 // Hosts
 // ============================================================================
 
-fn hosts_routes(
-    state: AppState,
-) -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
-    warp::path::end()
+fn hosts_routes(state: AppState) -> BoxedFilter<(impl Reply,)> {
+    let list = warp::path::end()
         .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(list_hosts);
+
+    let clear_backoff = warp::path!(String / "clear-backoff")
+        .and(warp::post())
+        .and(with_state(state.clone()))
+        .and_then(clear_host_backoff);
+
+    let interrupt_all = warp::path!(String / "interrupt-all")
+        .and(warp::post())
+        .and(with_state(state.clone()))
+        .and_then(interrupt_all_on_host);
+
+    let stream = warp::path!("stream")
+        .and(warp::ws())
         .and(with_state(state))
-        .and_then(list_hosts)
+        .map(|ws: warp::ws::Ws, state: AppState| {
+            ws.on_upgrade(move |socket| handle_host_events_websocket(socket, state))
+        });
+
+    list.or(stream).or(clear_backoff).or(interrupt_all).boxed()
 }
 
 #[derive(Serialize)]
@@ -68,6 +109,16 @@ struct HostResponse {
     host: String,
     hostname: String,
     connected_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    installed_agents: Option<Vec<slopcoder_core::anyagent::AgentKind>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    agent_versions: Vec<slopcoder_core::agent_rpc::AgentVersion>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    labels: HashMap<String, String>,
+    /// `unreachable` while the host is under list-poll backoff.
+    status: HostStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backoff_until: Option<String>,
 }
 
 async fn list_hosts(state: AppState) -> Result<impl Reply, Infallible> {
@@ -78,18 +129,77 @@ async fn list_hosts(state: AppState) -> Result<impl Reply, Infallible> {
             host: h.host,
             hostname: h.hostname,
             connected_at: h.connected_at.to_rfc3339(),
+            installed_agents: h.installed_agents,
+            agent_versions: h.agent_versions,
+            labels: h.labels,
+            status: h.status,
+            backoff_until: h.backoff_until.map(|until| until.to_rfc3339()),
         })
         .collect();
     Ok(warp::reply::json(&response))
 }
 
+async fn clear_host_backoff(host: String, state: AppState) -> Result<impl Reply, Infallible> {
+    match state.clear_host_list_backoff(&host).await {
+        Ok(()) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "status": "cleared" })),
+            StatusCode::OK,
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+/// Interrupt every running task on a host, replying with how many were signalled.
+async fn interrupt_all_on_host(host: String, state: AppState) -> Result<impl Reply, Infallible> {
+    let agent = match pick_agent(state, Some(&host)).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    match agent.request(AgentRequest::InterruptAll).await {
+        Ok(AgentResponse::InterruptedAll { interrupted }) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "interrupted": interrupted })),
+            StatusCode::OK,
+        )),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+/// Push host connect/disconnect events until the client goes away.
+async fn handle_host_events_websocket(ws: WebSocket, state: AppState) {
+    let mut events = state.subscribe_host_events().await;
+    let (mut tx, _rx) = ws.split();
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::warn!(dropped = skipped, "Host event websocket lagged");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let json = match serde_json::to_string(&event) {
+            Ok(json) => json,
+            Err(e) => {
+                tracing::warn!("Failed to serialize host event: {}", e);
+                continue;
+            }
+        };
+        if tx.send(Message::text(json)).await.is_err() {
+            break;
+        }
+    }
+}
+
 // ============================================================================
 // Environment routes
 // ============================================================================
 
-fn environments_routes(
-    state: AppState,
-) -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
+fn environments_routes(state: AppState) -> BoxedFilter<(impl Reply,)> {
     let list = warp::path::end()
         .and(warp::get())
         .and(with_state(state.clone()))
@@ -101,13 +211,78 @@ fn environments_routes(
         .and(with_state(state.clone()))
         .and_then(create_environment);
 
+    let detail = warp::path!(String)
+        .and(warp::get())
+        .and(warp::query::<HostQuery>())
+        .and(with_state(state.clone()))
+        .and_then(get_environment);
+
     let branches = warp::path!(String / "branches")
         .and(warp::get())
         .and(warp::query::<HostQuery>())
-        .and(with_state(state))
+        .and(with_state(state.clone()))
         .and_then(list_branches);
 
-    list.or(create).or(branches)
+    let worktrees = warp::path!(String / "worktrees")
+        .and(warp::get())
+        .and(warp::query::<HostQuery>())
+        .and(with_state(state.clone()))
+        .and_then(list_worktrees);
+
+    let prune_worktrees = warp::path!(String / "prune-worktrees")
+        .and(warp::post())
+        .and(warp::query::<PruneWorktreesQuery>())
+        .and(with_state(state.clone()))
+        .and_then(prune_environment_worktrees);
+
+    let archives = warp::path!(String / "archives")
+        .and(warp::get())
+        .and(warp::query::<HostQuery>())
+        .and(with_state(state.clone()))
+        .and_then(list_archives);
+
+    let disk_usage = warp::path!(String / "disk-usage")
+        .and(warp::get())
+        .and(warp::query::<EnvironmentDiskUsageQuery>())
+        .and(with_state(state.clone()))
+        .and_then(get_environment_disk_usage);
+
+    let archived_output = warp::path!(String / "archives" / String)
+        .and(warp::get())
+        .and(warp::query::<HostQuery>())
+        .and(with_state(state.clone()))
+        .and_then(get_archived_output);
+
+    let restore = warp::path!(String / "archives" / String / "restore")
+        .and(warp::post())
+        .and(warp::query::<HostQuery>())
+        .and(with_state(state.clone()))
+        .and_then(restore_archived_task);
+
+    let tasks = warp::path!(String / "tasks")
+        .and(warp::get())
+        .and(warp::query::<HostQuery>())
+        .and(with_state(state.clone()))
+        .and_then(list_environment_tasks);
+
+    let delete_tasks = warp::path!(String / "tasks")
+        .and(warp::delete())
+        .and(warp::query::<DeleteEnvironmentTasksQuery>())
+        .and(with_state(state))
+        .and_then(delete_environment_tasks);
+
+    list.or(create)
+        .or(detail)
+        .or(branches)
+        .or(worktrees)
+        .or(prune_worktrees)
+        .or(archives)
+        .or(archived_output)
+        .or(disk_usage)
+        .or(restore)
+        .or(tasks)
+        .or(delete_tasks)
+        .boxed()
 }
 
 #[derive(Serialize)]
@@ -118,7 +293,7 @@ struct EnvironmentResponse {
 }
 
 async fn list_environments(state: AppState) -> Result<impl Reply, Infallible> {
-    let agents = state.list_agents().await;
+    let agents = state.list_pollable_agents().await;
     let list_request_timeout_secs = state.get_list_request_timeout_secs().await;
     let mut environments = Vec::new();
 
@@ -135,35 +310,111 @@ async fn list_environments(state: AppState) -> Result<impl Reply, Infallible> {
     .await;
 
     for (host, response) in responses {
+        note_list_poll_result(&state, &host, &response).await;
         match response {
             Ok(AgentResponse::Environments { environments: envs }) => {
-                for env in envs {
-                    environments.push(EnvironmentResponse {
-                        host: host.clone(),
-                        name: env.name,
-                        directory: env.directory.to_string_lossy().to_string(),
-                    });
-                }
+                state
+                    .record_environments_for_host(&host, envs.iter().map(|env| env.name.as_str()))
+                    .await;
+                push_environment_responses(&mut environments, &host, envs);
             }
             Ok(_) => {
                 tracing::warn!("Unexpected response for list environments from {}", host);
             }
             Err(e) => {
-                tracing::warn!("Failed to list environments from host '{}': {}", host, e);
+                tracing::warn!(host = %host, error = %e, "Failed to list environments");
             }
         }
     }
 
+    sort_environment_responses(&mut environments);
+    Ok(warp::reply::json(&environments))
+}
+
+fn push_environment_responses(
+    out: &mut Vec<EnvironmentResponse>,
+    host: &str,
+    environments: Vec<Environment>,
+) {
+    out.extend(environments.into_iter().map(|env| EnvironmentResponse {
+        host: host.to_string(),
+        name: env.name,
+        directory: env.directory.to_string_lossy().to_string(),
+    }));
+}
+
+fn sort_environment_responses(environments: &mut [EnvironmentResponse]) {
     environments.sort_by(|a, b| {
         (a.host.as_str(), a.name.as_str()).cmp(&(b.host.as_str(), b.name.as_str()))
     });
-    Ok(warp::reply::json(&environments))
 }
 
+#[derive(Serialize)]
+struct ReloadHostResponse {
+    host: String,
+    added: Vec<String>,
+    removed: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Ask every connected agent to rediscover its environments right away.
+async fn reload_environments(state: AppState) -> Result<impl Reply, Infallible> {
+    let agents = state.list_agents().await;
+    let responses = join_all(agents.into_iter().map(|agent| async move {
+        let response = agent.request(AgentRequest::ReloadEnvironments).await;
+        (agent.host, response)
+    }))
+    .await;
+
+    let mut hosts: Vec<ReloadHostResponse> = responses
+        .into_iter()
+        .map(|(host, response)| match response {
+            Ok(AgentResponse::EnvironmentsReloaded { added, removed }) => {
+                tracing::info!(
+                    host = %host,
+                    added = ?added,
+                    removed = ?removed,
+                    "Reloaded environments"
+                );
+                ReloadHostResponse {
+                    host,
+                    added,
+                    removed,
+                    error: None,
+                }
+            }
+            Ok(_) => ReloadHostResponse {
+                host,
+                added: Vec::new(),
+                removed: Vec::new(),
+                error: Some("Unexpected response from agent".to_string()),
+            },
+            Err(e) => {
+                tracing::warn!(host = %host, error = %e, "Failed to reload environments");
+                ReloadHostResponse {
+                    host,
+                    added: Vec::new(),
+                    removed: Vec::new(),
+                    error: Some(e.to_string()),
+                }
+            }
+        })
+        .collect();
+    hosts.sort_by(|a, b| a.host.cmp(&b.host));
+    Ok(warp::reply::json(&hosts))
+}
+
+/// Cloning a remote can take up to the agent's clone timeout, so those requests wait it out.
+const CLONE_REQUEST_TIMEOUT: Duration = Duration::from_secs(CLONE_TIMEOUT_SECS + 60);
+
 #[derive(Deserialize)]
 struct CreateEnvironmentRequest {
     host: String,
     name: String,
+    /// Remote to clone instead of starting an empty repository.
+    #[serde(default)]
+    clone_url: Option<String>,
 }
 
 async fn create_environment(
@@ -180,12 +431,18 @@ async fn create_environment(
         Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
     };
 
-    match agent
-        .request(AgentRequest::CreateEnvironment {
-            name: req.name.clone(),
-        })
-        .await
-    {
+    let request = AgentRequest::CreateEnvironment {
+        name: req.name.clone(),
+        clone_url: req.clone_url.clone(),
+    };
+    let response = if req.clone_url.is_some() {
+        agent
+            .request_with_timeout(request, CLONE_REQUEST_TIMEOUT)
+            .await
+    } else {
+        agent.request(request).await
+    };
+    match response {
         Ok(AgentResponse::Environment { environment }) => Ok(warp::reply::with_status(
             warp::reply::json(&EnvironmentResponse {
                 host: agent.host,
@@ -207,24 +464,84 @@ struct HostQuery {
     host: Option<String>,
 }
 
+#[derive(Serialize)]
+struct EnvironmentDetailResponse {
+    host: String,
+    name: String,
+    directory: String,
+    current_branch: String,
+    branch_count: usize,
+    active_tasks: usize,
+}
+
+/// `GET /api/environments/:name`: one environment's directory, current branch, branch count
+/// and running task count on `host`.
+async fn get_environment(
+    name: String,
+    query: HostQuery,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let decoded_name = match decode_environment_name(&name) {
+        Ok(name) => name,
+        Err(reply) => return Ok(reply),
+    };
+
+    let agent = match pick_agent(state.clone(), query.host.as_deref()).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    match agent
+        .request(AgentRequest::GetEnvironmentInfo {
+            environment: decoded_name,
+        })
+        .await
+    {
+        Ok(AgentResponse::EnvironmentInfo { info }) => Ok(warp::reply::with_status(
+            warp::reply::json(&EnvironmentDetailResponse {
+                host: agent.host.clone(),
+                name: info.name,
+                directory: info.directory.to_string_lossy().to_string(),
+                current_branch: info.current_branch,
+                branch_count: info.branch_count,
+                active_tasks: info.active_tasks,
+            }),
+            StatusCode::OK,
+        )),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
 #[derive(Serialize)]
 struct BranchesResponse {
     branches: Vec<String>,
 }
 
+fn decode_environment_name(
+    name: &str,
+) -> Result<String, warp::reply::WithStatus<warp::reply::Json>> {
+    urlencoding::decode(name)
+        .map(|decoded| decoded.into_owned())
+        .map_err(|_| {
+            error_reply(
+                StatusCode::BAD_REQUEST,
+                "Environment name must be valid URL encoding",
+            )
+        })
+}
+
 async fn list_branches(
     name: String,
     query: HostQuery,
     state: AppState,
 ) -> Result<impl Reply, Infallible> {
-    let decoded_name = match urlencoding::decode(&name) {
-        Ok(decoded) => decoded.into_owned(),
-        Err(_) => {
-            return Ok(error_reply(
-                StatusCode::BAD_REQUEST,
-                "Environment name must be valid URL encoding",
-            ));
-        }
+    let decoded_name = match decode_environment_name(&name) {
+        Ok(name) => name,
+        Err(reply) => return Ok(reply),
     };
 
     let agent = match pick_agent(state.clone(), query.host.as_deref()).await {
@@ -250,269 +567,1821 @@ async fn list_branches(
     }
 }
 
-// ============================================================================
-// Task routes
-// ============================================================================
+#[derive(Serialize)]
+struct WorktreesResponse {
+    worktrees: Vec<WorktreeInfo>,
+}
 
-fn tasks_routes(
+async fn list_worktrees(
+    name: String,
+    query: HostQuery,
     state: AppState,
-) -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
-    let list = warp::path::end()
-        .and(warp::get())
-        .and(with_state(state.clone()))
-        .and_then(list_tasks);
+) -> Result<impl Reply, Infallible> {
+    let decoded_name = match decode_environment_name(&name) {
+        Ok(name) => name,
+        Err(reply) => return Ok(reply),
+    };
 
-    let create = warp::path::end()
-        .and(warp::post())
-        .and(warp::body::json())
-        .and(with_state(state.clone()))
-        .and_then(create_task);
+    let agent = match pick_agent(state.clone(), query.host.as_deref()).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
 
-    let get = warp::path!(String)
-        .and(warp::get())
-        .and(with_state(state.clone()))
-        .and_then(get_task);
+    match agent
+        .request(AgentRequest::ListWorktrees {
+            environment: decoded_name,
+        })
+        .await
+    {
+        Ok(AgentResponse::Worktrees { worktrees }) => Ok(warp::reply::with_status(
+            warp::reply::json(&WorktreesResponse { worktrees }),
+            StatusCode::OK,
+        )),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
 
-    let rename = warp::path!(String)
-        .and(warp::patch())
-        .and(warp::body::json())
-        .and(with_state(state.clone()))
-        .and_then(rename_task);
+#[derive(Deserialize)]
+struct PruneWorktreesQuery {
+    host: Option<String>,
+    /// Also remove orphaned worktrees instead of only reporting them.
+    #[serde(default)]
+    force: bool,
+}
 
-    let prompt = warp::path!(String / "prompt")
-        .and(warp::post())
-        .and(warp::body::json())
-        .and(with_state(state.clone()))
-        .and_then(send_prompt);
+#[derive(Serialize)]
+struct PruneWorktreesResponse {
+    pruned: Vec<String>,
+    orphaned: Vec<PathBuf>,
+    removed: Vec<PathBuf>,
+}
 
-    let output = warp::path!(String / "output")
-        .and(warp::get())
-        .and(warp::query::<TaskOutputQuery>())
-        .and(with_state(state.clone()))
-        .and_then(get_task_output);
+async fn prune_environment_worktrees(
+    name: String,
+    query: PruneWorktreesQuery,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let environment = match decode_environment_name(&name) {
+        Ok(name) => name,
+        Err(reply) => return Ok(reply),
+    };
 
-    let diff = warp::path!(String / "diff")
-        .and(warp::get())
-        .and(with_state(state.clone()))
-        .and_then(get_task_diff);
+    let agent = match pick_agent(state.clone(), query.host.as_deref()).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
 
-    let interrupt = warp::path!(String / "interrupt")
-        .and(warp::post())
-        .and(with_state(state.clone()))
-        .and_then(interrupt_task);
-
-    let stream = warp::path!(String / "stream")
-        .and(warp::ws())
-        .and(with_state(state.clone()))
-        .map(|id: String, ws: warp::ws::Ws, state: AppState| {
-            ws.on_upgrade(move |socket| handle_task_websocket(socket, id, state))
-        });
+    match agent
+        .request(AgentRequest::PruneWorktrees {
+            environment,
+            force: query.force,
+        })
+        .await
+    {
+        Ok(AgentResponse::WorktreesPruned {
+            pruned,
+            orphaned,
+            removed,
+        }) => Ok(warp::reply::with_status(
+            warp::reply::json(&PruneWorktreesResponse {
+                pruned,
+                orphaned,
+                removed,
+            }),
+            StatusCode::OK,
+        )),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
 
-    let terminal = warp::path!(String / "terminal")
-        .and(warp::ws())
-        .and(with_state(state.clone()))
-        .map(|id: String, ws: warp::ws::Ws, state: AppState| {
-            ws.on_upgrade(move |socket| handle_terminal_websocket(socket, id, state))
-        });
+#[derive(Serialize)]
+struct ArchivesResponse {
+    archives: Vec<ArchivedTask>,
+}
 
-    let merge = warp::path!(String / "merge")
-        .and(warp::post())
-        .and(with_state(state.clone()))
-        .and_then(merge_task);
+#[derive(Deserialize)]
+struct EnvironmentDiskUsageQuery {
+    host: Option<String>,
+    #[serde(default)]
+    exclude_git: bool,
+}
 
-    let merge_status = warp::path!(String / "merge-status")
-        .and(warp::get())
-        .and(with_state(state.clone()))
-        .and_then(get_merge_status);
+#[derive(Serialize)]
+struct EnvironmentDiskUsageResponse {
+    environment: String,
+    total_bytes: u64,
+    tasks: Vec<TaskDiskUsage>,
+}
 
-    let archive = warp::path!(String / "archive")
-        .and(warp::post())
-        .and(with_state(state.clone()))
-        .and_then(archive_task);
+async fn get_environment_disk_usage(
+    name: String,
+    query: EnvironmentDiskUsageQuery,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let environment = match decode_environment_name(&name) {
+        Ok(name) => name,
+        Err(reply) => return Ok(reply),
+    };
 
-    let delete = warp::path!(String)
-        .and(warp::delete())
-        .and(warp::query::<DeleteTaskQuery>())
-        .and(with_state(state))
-        .and_then(delete_task);
+    let agent = match pick_agent(state.clone(), query.host.as_deref()).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
 
-    list.or(create)
-        .or(rename)
-        .or(get)
-        .or(prompt)
-        .or(output)
-        .or(diff)
-        .or(interrupt)
-        .or(stream)
-        .or(terminal)
-        .or(merge)
-        .or(merge_status)
-        .or(archive)
-        .or(delete)
+    match agent
+        .request(AgentRequest::GetEnvironmentDiskUsage {
+            environment: environment.clone(),
+            exclude_git: query.exclude_git,
+        })
+        .await
+    {
+        Ok(AgentResponse::EnvironmentDiskUsage { total_bytes, tasks }) => {
+            Ok(warp::reply::with_status(
+                warp::reply::json(&EnvironmentDiskUsageResponse {
+                    environment,
+                    total_bytes,
+                    tasks,
+                }),
+                StatusCode::OK,
+            ))
+        }
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
 }
 
-#[derive(Serialize)]
-struct TaskResponse {
-    id: String,
-    host: String,
-    agent: String,
-    environment: String,
-    name: String,
-    workspace_kind: String,
-    base_branch: Option<String>,
-    merge_branch: Option<String>,
-    status: String,
-    session_id: Option<String>,
-    created_at: String,
-    worktree_date: Option<String>,
-    history: Vec<PromptRunResponse>,
+#[derive(Deserialize)]
+struct DeleteEnvironmentTasksQuery {
+    host: Option<String>,
+    #[serde(default)]
+    completed: bool,
+    #[serde(default)]
+    force: bool,
 }
 
 #[derive(Serialize)]
-struct PromptRunResponse {
-    prompt: String,
-    started_at: String,
-    finished_at: Option<String>,
-    success: Option<bool>,
+struct DeleteEnvironmentTasksResponse {
+    deleted: Vec<TaskId>,
+    skipped: Vec<SkippedTask>,
 }
 
-impl TaskResponse {
-    fn from_task(host: &str, task: &Task) -> Self {
-        Self {
-            id: task.id.to_string(),
-            host: host.to_string(),
-            agent: format!("{:?}", task.agent).to_lowercase(),
-            environment: task.environment.clone(),
-            name: task.name.clone(),
-            workspace_kind: format!("{:?}", task.workspace_kind).to_lowercase(),
-            base_branch: task.base_branch.clone(),
-            merge_branch: task.merge_branch.clone(),
-            status: format!("{:?}", task.status).to_lowercase(),
-            session_id: task.session_id.map(|id| id.to_string()),
-            created_at: task.created_at.to_rfc3339(),
-            worktree_date: None,
-            history: task
-                .history
-                .iter()
-                .map(|r| PromptRunResponse {
-                    prompt: r.prompt.clone(),
-                    started_at: r.started_at.to_rfc3339(),
-                    finished_at: r.finished_at.map(|t| t.to_rfc3339()),
-                    success: r.success,
-                })
-                .collect(),
+async fn delete_environment_tasks(
+    name: String,
+    query: DeleteEnvironmentTasksQuery,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let environment = match decode_environment_name(&name) {
+        Ok(name) => name,
+        Err(reply) => return Ok(reply),
+    };
+    // Only finished tasks can be bulk-deleted; require saying so explicitly.
+    if !query.completed {
+        return Ok(error_reply(
+            StatusCode::BAD_REQUEST,
+            "Bulk delete requires completed=true",
+        ));
+    }
+
+    let agent = match pick_agent(state.clone(), query.host.as_deref()).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    match agent
+        .request(AgentRequest::DeleteCompletedTasks {
+            environment,
+            force: query.force,
+        })
+        .await
+    {
+        Ok(AgentResponse::BulkDeleteResult { deleted, skipped }) => {
+            for task_id in &deleted {
+                close_task_terminal_session(&state, &agent, *task_id).await;
+                state.clear_task_host(*task_id).await;
+            }
+            Ok(warp::reply::with_status(
+                warp::reply::json(&DeleteEnvironmentTasksResponse { deleted, skipped }),
+                StatusCode::OK,
+            ))
         }
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
     }
 }
 
-async fn list_tasks(state: AppState) -> Result<impl Reply, Infallible> {
-    let agents = state.list_agents().await;
-    let list_request_timeout_secs = state.get_list_request_timeout_secs().await;
-    let mut tasks = Vec::new();
+async fn list_archives(
+    name: String,
+    query: HostQuery,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let environment = match decode_environment_name(&name) {
+        Ok(name) => name,
+        Err(reply) => return Ok(reply),
+    };
 
-    let responses = join_all(agents.into_iter().map(|agent| async move {
-        let host = agent.host.clone();
-        let response =
-            request_with_timeout(&agent, AgentRequest::ListTasks, list_request_timeout_secs).await;
-        (host, response)
-    }))
-    .await;
+    let agent = match pick_agent(state.clone(), query.host.as_deref()).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
 
-    for (host, response) in responses {
-        match response {
-            Ok(AgentResponse::Tasks { tasks: host_tasks }) => {
-                state.record_tasks_for_host(&host, &host_tasks).await;
-                tasks.extend(
-                    host_tasks
-                        .iter()
-                        .map(|task| TaskResponse::from_task(&host, task)),
-                );
-            }
-            Ok(_) => {
-                tracing::warn!("Unexpected list_tasks response from {}", host);
-            }
-            Err(e) => {
-                tracing::warn!("Failed to list tasks from '{}': {}", host, e);
-            }
-        }
+    match agent
+        .request(AgentRequest::ListArchives { environment })
+        .await
+    {
+        Ok(AgentResponse::Archives { archives }) => Ok(warp::reply::with_status(
+            warp::reply::json(&ArchivesResponse { archives }),
+            StatusCode::OK,
+        )),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
     }
+}
 
-    tasks.sort_by(|a, b| b.created_at.cmp(&a.created_at));
-    Ok(warp::reply::json(&tasks))
+#[derive(Serialize)]
+struct ArchivedOutputResponse {
+    task_id: TaskId,
+    events: Vec<AgentEvent>,
 }
 
-async fn get_task(id: String, state: AppState) -> Result<impl Reply, Infallible> {
+async fn get_archived_output(
+    name: String,
+    id: String,
+    query: HostQuery,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let environment = match decode_environment_name(&name) {
+        Ok(name) => name,
+        Err(reply) => return Ok(reply),
+    };
     let task_id = match parse_task_id(&id) {
         Ok(id) => id,
         Err(reply) => return Ok(reply),
     };
 
-    match find_task(&state, task_id).await {
-        Ok(Some((host, task))) => Ok(warp::reply::with_status(
-            warp::reply::json(&TaskResponse::from_task(&host, &task)),
+    let agent = match pick_agent(state.clone(), query.host.as_deref()).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    match agent
+        .request(AgentRequest::GetArchivedOutput {
+            environment,
+            task_id,
+        })
+        .await
+    {
+        Ok(AgentResponse::ArchivedOutput { task_id, events }) => Ok(warp::reply::with_status(
+            warp::reply::json(&ArchivedOutputResponse { task_id, events }),
+            StatusCode::OK,
+        )),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+async fn restore_archived_task(
+    name: String,
+    id: String,
+    query: HostQuery,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let environment = match decode_environment_name(&name) {
+        Ok(name) => name,
+        Err(reply) => return Ok(reply),
+    };
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply),
+    };
+
+    let agent = match pick_agent(state.clone(), query.host.as_deref()).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    match agent
+        .request(AgentRequest::RestoreArchivedTask {
+            environment,
+            task_id,
+        })
+        .await
+    {
+        Ok(AgentResponse::RestoredTask { task }) => {
+            state.set_task_host(task.id, agent.host.clone()).await;
+            Ok(warp::reply::with_status(
+                warp::reply::json(&TaskResponse::from_task(&agent.host, &task)),
+                StatusCode::OK,
+            ))
+        }
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+// ============================================================================
+// Task routes
+// ============================================================================
+
+fn tasks_routes(state: AppState) -> BoxedFilter<(impl Reply,)> {
+    let list = warp::path::end()
+        .and(warp::get())
+        .and(warp::query::<TaskListQuery>())
+        .and(with_state(state.clone()))
+        .and_then(list_tasks);
+
+    let create = warp::path::end()
+        .and(warp::post())
+        .and(warp::query::<CreateTaskQuery>())
+        .and(warp::body::json())
+        .and(with_state(state.clone()))
+        .and_then(create_task);
+
+    let get = warp::path!(String)
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(get_task);
+
+    let rename = warp::path!(String)
+        .and(warp::patch())
+        .and(warp::body::json())
+        .and(with_state(state.clone()))
+        .and_then(rename_task);
+
+    let tags = warp::path!(String / "tags")
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(with_state(state.clone()))
+        .and_then(set_task_tags);
+
+    let prompt = warp::path!(String / "prompt")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_state(state.clone()))
+        .and_then(send_prompt);
+
+    let output = warp::path!(String / "output")
+        .and(warp::get())
+        .and(warp::query::<TaskOutputQuery>())
+        .and(with_state(state.clone()))
+        .and_then(get_task_output);
+
+    let export = warp::path!(String / "export")
+        .and(warp::get())
+        .and(warp::query::<ExportQuery>())
+        .and(with_state(state.clone()))
+        .and_then(export_task);
+
+    let diff = warp::path!(String / "diff")
+        .and(warp::get())
+        .and(warp::query::<TaskDiffQuery>())
+        .and(with_state(state.clone()))
+        .and_then(get_task_diff);
+
+    let diff_stream = warp::path!(String / "diff" / "stream")
+        .and(warp::ws())
+        .and(with_state(state.clone()))
+        .map(|id: String, ws: warp::ws::Ws, state: AppState| {
+            ws.on_upgrade(move |socket| handle_diff_stream_websocket(socket, id, state))
+        });
+
+    let interrupt = warp::path!(String / "interrupt")
+        .and(warp::post())
+        .and(with_state(state.clone()))
+        .and_then(interrupt_task);
+
+    let cancel_all_queued = warp::path!(String / "queue")
+        .and(warp::delete())
+        .and(with_state(state.clone()))
+        .and_then(|id: String, state: AppState| cancel_queued_prompts(id, None, state));
+
+    let cancel_queued = warp::path!(String / "queue" / usize)
+        .and(warp::delete())
+        .and(with_state(state.clone()))
+        .and_then(|id: String, index: usize, state: AppState| {
+            cancel_queued_prompts(id, Some(index), state)
+        });
+
+    let approval = warp::path!(String / "approvals" / String)
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_state(state.clone()))
+        .and_then(respond_approval);
+
+    let stream = warp::path!(String / "stream")
+        .and(warp::ws())
+        .and(warp::query::<TaskStreamQuery>())
+        .and(with_state(state.clone()))
+        .map(
+            |id: String, ws: warp::ws::Ws, query: TaskStreamQuery, state: AppState| {
+                // Reject a bad id before upgrading, so the client sees why instead of a bare close.
+                match parse_task_id(&id) {
+                    Ok(task_id) => ws
+                        .on_upgrade(move |socket| {
+                            handle_task_websocket(socket, task_id, query, state)
+                        })
+                        .into_response(),
+                    Err(reply) => reply.into_response(),
+                }
+            },
+        );
+
+    let terminal = warp::path!(String / "terminal")
+        .and(warp::ws())
+        .and(warp::query::<TerminalSizeQuery>())
+        .and(with_state(state.clone()))
+        .map(
+            |id: String, ws: warp::ws::Ws, size: TerminalSizeQuery, state: AppState| {
+                match parse_task_id(&id) {
+                    Ok(task_id) => ws
+                        .on_upgrade(move |socket| {
+                            handle_terminal_websocket(socket, task_id, size, state)
+                        })
+                        .into_response(),
+                    Err(reply) => reply.into_response(),
+                }
+            },
+        );
+
+    let terminal_recording = warp::path!(String / "terminal" / "recording")
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(get_terminal_recording);
+
+    let disk_usage = warp::path!(String / "disk-usage")
+        .and(warp::get())
+        .and(warp::query::<DiskUsageQuery>())
+        .and(with_state(state.clone()))
+        .and_then(get_task_disk_usage);
+
+    let summary = warp::path!(String / "summary")
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(get_task_summary);
+
+    let bundle = warp::path!(String / "bundle")
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(get_task_bundle);
+
+    let logs = warp::path!(String / "logs")
+        .and(warp::get())
+        .and(warp::query::<TaskLogsQuery>())
+        .and(with_state(state.clone()))
+        .and_then(get_task_logs);
+
+    let merge = warp::path!(String / "merge")
+        .and(warp::post())
+        .and(warp::query::<MergeTaskQuery>())
+        .and(with_state(state.clone()))
+        .and_then(merge_task);
+
+    let merge_status = warp::path!(String / "merge-status")
+        .or(warp::path!(String / "readiness"))
+        .unify()
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(get_merge_status);
+
+    let cherry_pick = warp::path!(String / "cherry-pick")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_state(state.clone()))
+        .and_then(cherry_pick_task);
+
+    let revert = warp::path!(String / "revert")
+        .and(warp::post())
+        .and(with_state(state.clone()))
+        .and_then(revert_merge);
+
+    let archive = warp::path!(String / "archive")
+        .and(warp::post())
+        .and(with_state(state.clone()))
+        .and_then(archive_task);
+
+    let reset = warp::path!(String / "reset")
+        .and(warp::post())
+        .and(warp::query::<ResetTaskQuery>())
+        .and(with_state(state.clone()))
+        .and_then(reset_task);
+
+    let move_worktree = warp::path!(String / "move")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_state(state.clone()))
+        .and_then(move_task_worktree);
+
+    let apply_patch = warp::path!(String / "apply-patch")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_state(state.clone()))
+        .and_then(apply_patch);
+
+    let commit = warp::path!(String / "commit")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_state(state.clone()))
+        .and_then(commit_task);
+
+    let stash = warp::path!(String / "stash")
+        .and(warp::post())
+        .and(with_state(state.clone()))
+        .and_then(stash_task);
+
+    let unstash = warp::path!(String / "unstash")
+        .and(warp::post())
+        .and(with_state(state.clone()))
+        .and_then(unstash_task);
+
+    let commits = warp::path!(String / "commits")
+        .and(warp::get())
+        .and(warp::query::<TaskCommitsQuery>())
+        .and(with_state(state.clone()))
+        .and_then(get_task_commits);
+
+    let file = warp::path!(String / "file")
+        .and(warp::get())
+        .and(warp::query::<WorktreeFileQuery>())
+        .and(with_state(state.clone()))
+        .and_then(read_worktree_file);
+
+    let delete = warp::path!(String)
+        .and(warp::delete())
+        .and(warp::query::<DeleteTaskQuery>())
+        .and(with_state(state))
+        .and_then(delete_task);
+
+    list.or(create)
+        .or(rename)
+        .or(get)
+        .or(tags)
+        .or(prompt)
+        .or(output)
+        .or(export)
+        .or(diff)
+        .or(diff_stream)
+        .or(interrupt)
+        .or(cancel_all_queued)
+        .or(cancel_queued)
+        .or(approval)
+        .or(stream)
+        .or(terminal)
+        .or(terminal_recording)
+        .or(disk_usage)
+        .or(summary)
+        .or(logs)
+        .or(bundle)
+        .or(merge)
+        .or(merge_status)
+        .or(cherry_pick)
+        .or(revert)
+        .or(archive)
+        .or(reset)
+        .or(move_worktree)
+        .or(apply_patch)
+        .or(commit)
+        .or(stash)
+        .or(unstash)
+        .or(commits)
+        .or(file)
+        .or(delete)
+        .boxed()
+}
+
+#[derive(Serialize)]
+struct TaskResponse {
+    id: String,
+    host: String,
+    agent: String,
+    environment: String,
+    name: String,
+    workspace_kind: String,
+    base_branch: Option<String>,
+    merge_branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    working_dir: Option<String>,
+    tags: Vec<String>,
+    priority: i32,
+    status: String,
+    session_id: Option<String>,
+    created_at: String,
+    worktree_date: Option<String>,
+    history: Vec<PromptRunResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    depends_on: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    history_only: bool,
+}
+
+#[derive(Serialize)]
+struct PromptRunResponse {
+    prompt: String,
+    started_at: String,
+    finished_at: Option<String>,
+    success: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signal: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    outcome: Option<TaskOutcome>,
+}
+
+impl TaskResponse {
+    fn from_task(host: &str, task: &Task) -> Self {
+        Self {
+            id: task.id.to_string(),
+            host: host.to_string(),
+            agent: format!("{:?}", task.agent).to_lowercase(),
+            environment: task.environment.clone(),
+            name: task.name.clone(),
+            workspace_kind: format!("{:?}", task.workspace_kind).to_lowercase(),
+            base_branch: task.base_branch.clone(),
+            merge_branch: task.merge_branch.clone(),
+            model: task.model.clone(),
+            working_dir: task
+                .working_dir
+                .as_ref()
+                .map(|dir| dir.to_string_lossy().to_string()),
+            tags: task.tags.clone(),
+            priority: task.priority,
+            status: format!("{:?}", task.status).to_lowercase(),
+            session_id: task.session_id.map(|id| id.to_string()),
+            created_at: task.created_at.to_rfc3339(),
+            worktree_date: None,
+            history: task
+                .history
+                .iter()
+                .map(|r| PromptRunResponse {
+                    prompt: r.prompt.clone(),
+                    started_at: r.started_at.to_rfc3339(),
+                    finished_at: r.finished_at.map(|t| t.to_rfc3339()),
+                    success: r.success,
+                    exit_code: r.exit_code,
+                    signal: r.signal,
+                    outcome: r.outcome,
+                })
+                .collect(),
+            depends_on: task.depends_on.map(|id| id.to_string()),
+            history_only: task.history_only,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct TaskListQuery {
+    #[serde(default)]
+    tag: Option<String>,
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    host: Option<String>,
+    #[serde(default)]
+    environment: Option<String>,
+    #[serde(default)]
+    limit: Option<usize>,
+    #[serde(default)]
+    offset: usize,
+    #[serde(default)]
+    sort: TaskSort,
+    /// Return the legacy bare array instead of the `{ tasks, total }` envelope.
+    #[serde(default)]
+    flat: bool,
+    /// Also list every host's environments, fetched in the same batched request per host.
+    #[serde(default)]
+    with_environments: bool,
+}
+
+/// Ordering for `GET /api/tasks`.
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum TaskSort {
+    #[default]
+    Newest,
+    /// Highest priority first, newest first within a priority.
+    Priority,
+}
+
+#[derive(Serialize)]
+struct TaskListResponse {
+    tasks: Vec<TaskResponse>,
+    total: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    environments: Option<Vec<EnvironmentResponse>>,
+    /// Connected hosts whose tasks are missing from this response, because they are under
+    /// list-poll backoff or their listing failed; clients should keep what they last saw.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    unlisted_hosts: Vec<String>,
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+impl TaskListQuery {
+    fn matches(&self, task: &TaskResponse) -> bool {
+        if let Some(tag) = non_empty(&self.tag) {
+            if !task.tags.iter().any(|t| t == tag) {
+                return false;
+            }
+        }
+        if let Some(status) = non_empty(&self.status) {
+            if !task.status.eq_ignore_ascii_case(status) {
+                return false;
+            }
+        }
+        if let Some(host) = non_empty(&self.host) {
+            if task.host != host {
+                return false;
+            }
+        }
+        if let Some(environment) = non_empty(&self.environment) {
+            if task.environment != environment {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Filter, sort (newest-first by default), then apply `offset`/`limit`; `total` counts every match.
+    fn apply(&self, mut tasks: Vec<TaskResponse>) -> TaskListResponse {
+        tasks.retain(|task| self.matches(task));
+        tasks.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        if let TaskSort::Priority = self.sort {
+            tasks.sort_by_key(|task| std::cmp::Reverse(task.priority));
+        }
+        let total = tasks.len();
+        let tasks = tasks
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect();
+        TaskListResponse {
+            tasks,
+            total,
+            environments: None,
+            unlisted_hosts: Vec::new(),
+        }
+    }
+}
+
+async fn list_tasks(query: TaskListQuery, state: AppState) -> Result<impl Reply, Infallible> {
+    let mut agents = state.list_pollable_agents().await;
+    let mut unlisted_hosts: Vec<String> = state
+        .list_agents()
+        .await
+        .into_iter()
+        .map(|agent| agent.host)
+        .filter(|host| !agents.iter().any(|agent| agent.host == *host))
+        .collect();
+    if let Some(host) = non_empty(&query.host) {
+        agents.retain(|agent| agent.host == host);
+        unlisted_hosts.retain(|unlisted| unlisted == host);
+    }
+    let list_request_timeout_secs = state.get_list_request_timeout_secs().await;
+    let with_environments = query.with_environments && !query.flat;
+    let mut tasks = Vec::new();
+    let mut environments = Vec::new();
+
+    let responses = join_all(agents.into_iter().map(|agent| async move {
+        let host = agent.host.clone();
+        let request = if with_environments {
+            AgentRequest::Batch {
+                requests: vec![AgentRequest::ListTasks, AgentRequest::ListEnvironments],
+            }
+        } else {
+            AgentRequest::ListTasks
+        };
+        let response = request_with_timeout(&agent, request, list_request_timeout_secs).await;
+        (host, response)
+    }))
+    .await;
+
+    for (host, response) in responses {
+        note_list_poll_result(&state, &host, &response).await;
+        let mut results = match unbatch(response) {
+            Ok(results) => results.into_iter(),
+            Err(e) => {
+                tracing::warn!(host = %host, error = %e, "Failed to list tasks");
+                unlisted_hosts.push(host);
+                continue;
+            }
+        };
+        match results.next() {
+            Some(Ok(AgentResponse::Tasks { tasks: host_tasks })) => {
+                state.record_tasks_for_host(&host, &host_tasks).await;
+                tasks.extend(
+                    host_tasks
+                        .iter()
+                        .map(|task| TaskResponse::from_task(&host, task)),
+                );
+            }
+            Some(Err(e)) => {
+                tracing::warn!(host = %host, error = %e.error, "Failed to list tasks");
+                unlisted_hosts.push(host.clone());
+            }
+            _ => {
+                tracing::warn!("Unexpected list_tasks response from {}", host);
+                unlisted_hosts.push(host.clone());
+            }
+        }
+        match results.next() {
+            Some(Ok(AgentResponse::Environments { environments: envs })) => {
+                state
+                    .record_environments_for_host(&host, envs.iter().map(|env| env.name.as_str()))
+                    .await;
+                push_environment_responses(&mut environments, &host, envs);
+            }
+            Some(Err(e)) => {
+                tracing::warn!(host = %host, error = %e.error, "Failed to list environments");
+            }
+            Some(Ok(_)) => {
+                tracing::warn!("Unexpected response for list environments from {}", host);
+            }
+            None => {}
+        }
+    }
+
+    let mut page = query.apply(tasks);
+    unlisted_hosts.sort();
+    page.unlisted_hosts = unlisted_hosts;
+    if query.flat {
+        return Ok(warp::reply::json(&page.tasks));
+    }
+    if with_environments {
+        sort_environment_responses(&mut environments);
+        page.environments = Some(environments);
+    }
+    Ok(warp::reply::json(&page))
+}
+
+/// `GET /api/environments/:name/tasks`: the environment's tasks, newest first.
+///
+/// Only `host` is asked when given; otherwise only the hosts known to serve the environment,
+/// falling back to every pollable host until a listing has mentioned it.
+async fn list_environment_tasks(
+    name: String,
+    query: HostQuery,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let mut agents = state.list_pollable_agents().await;
+    if let Some(host) = non_empty(&query.host) {
+        agents.retain(|agent| agent.host == host);
+    } else {
+        let hosts = state.hosts_for_environment(&name).await;
+        if !hosts.is_empty() {
+            agents.retain(|agent| hosts.contains(&agent.host));
+        }
+    }
+    let list_request_timeout_secs = state.get_list_request_timeout_secs().await;
+
+    let responses = join_all(agents.into_iter().map(|agent| async move {
+        let host = agent.host.clone();
+        let response =
+            request_with_timeout(&agent, AgentRequest::ListTasks, list_request_timeout_secs).await;
+        (host, response)
+    }))
+    .await;
+
+    let mut tasks = Vec::new();
+    for (host, response) in responses {
+        note_list_poll_result(&state, &host, &response).await;
+        match response {
+            Ok(AgentResponse::Tasks { tasks: host_tasks }) => {
+                state.record_tasks_for_host(&host, &host_tasks).await;
+                tasks.extend(
+                    host_tasks
+                        .iter()
+                        .filter(|task| task.environment == name)
+                        .map(|task| TaskResponse::from_task(&host, task)),
+                );
+            }
+            Ok(_) => {
+                tracing::warn!("Unexpected list_tasks response from {}", host);
+            }
+            Err(e) => {
+                tracing::warn!(host = %host, error = %e, "Failed to list tasks");
+            }
+        }
+    }
+
+    let query = TaskListQuery {
+        environment: Some(name),
+        ..TaskListQuery::default()
+    };
+    Ok(warp::reply::json(&query.apply(tasks).tasks))
+}
+
+/// Per-request results of a response that may be an `AgentResponse::Batch`.
+fn unbatch(
+    response: Result<AgentResponse, StateError>,
+) -> Result<Vec<Result<AgentResponse, BatchError>>, StateError> {
+    match response? {
+        AgentResponse::Batch { results } => Ok(results),
+        response => Ok(vec![Ok(response)]),
+    }
+}
+
+async fn get_task(id: String, state: AppState) -> Result<impl Reply, Infallible> {
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply),
+    };
+
+    match find_task(&state, task_id).await {
+        Ok(Some((host, task))) => Ok(warp::reply::with_status(
+            warp::reply::json(&TaskResponse::from_task(&host, &task)),
+            StatusCode::OK,
+        )),
+        Ok(None) => Ok(error_reply(StatusCode::NOT_FOUND, "Task not found")),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateTaskRequest {
+    /// Exact host label; may be left empty when `host_selector` is given.
+    #[serde(default)]
+    host: String,
+    /// Labels the chosen host must carry, e.g. `{ "region": "us" }`.
+    #[serde(default)]
+    host_selector: Option<HashMap<String, String>>,
+    environment: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    use_worktree: Option<bool>,
+    #[serde(default)]
+    web_search: bool,
+    prompt: String,
+    #[serde(default)]
+    agent: Option<slopcoder_core::anyagent::AgentKind>,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    subdir: Option<String>,
+    #[serde(default)]
+    base_branch: Option<String>,
+    #[serde(default)]
+    existing_branch: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Rate-limit priority; only orders starts waiting on the host's start rate limit.
+    #[serde(default)]
+    priority: i32,
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+    /// Task that must complete first; its host runs this one when no host is given.
+    #[serde(default)]
+    depends_on: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CreateTaskQuery {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Serialize)]
+struct CreateTaskResponse {
+    id: String,
+    worktree_path: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    dry_run: bool,
+}
+
+#[derive(Deserialize)]
+struct RenameTaskRequest {
+    name: String,
+}
+
+async fn create_task(
+    query: CreateTaskQuery,
+    req: CreateTaskRequest,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let depends_on = match req.depends_on.as_deref().map(parse_task_id).transpose() {
+        Ok(depends_on) => depends_on,
+        Err(reply) => return Ok(reply),
+    };
+    let host = req.host.trim();
+    let picked = match (&req.host_selector, host.is_empty(), depends_on) {
+        (_, false, _) => pick_agent(state.clone(), Some(host)).await,
+        (Some(selector), true, _) => state.select_agent(selector, req.agent).await,
+        (None, true, Some(dependency)) => resolve_agent_for_task(&state, dependency).await,
+        (None, true, None) => {
+            return Ok(error_reply(StatusCode::BAD_REQUEST, "Host is required"));
+        }
+    };
+    let agent = match picked {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+    // Without an explicit agent the host picks one from the environment's settings.
+    if let Some(kind) = req.agent.filter(|kind| !agent.supports_agent(*kind)) {
+        return Ok(error_reply(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Agent '{}' is not installed on host '{}'",
+                kind.as_str(),
+                agent.host
+            ),
+        ));
+    }
+
+    let request = AgentCreateTaskRequest {
+        environment: req.environment,
+        name: req.name,
+        use_worktree: req.use_worktree,
+        web_search: req.web_search,
+        prompt: req.prompt,
+        agent: req.agent,
+        model: req.model,
+        subdir: req.subdir,
+        base_branch: req.base_branch,
+        existing_branch: req.existing_branch,
+        tags: req.tags,
+        priority: req.priority,
+        dry_run: query.dry_run,
+        attachments: req.attachments,
+        depends_on,
+    };
+
+    match agent.request(AgentRequest::CreateTask { request }).await {
+        Ok(AgentResponse::CreatedTask { id, worktree_path }) => {
+            // A dry run's id is only a placeholder; nothing was created on the host.
+            if query.dry_run {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&CreateTaskResponse {
+                        id: id.to_string(),
+                        worktree_path,
+                        dry_run: true,
+                    }),
+                    StatusCode::OK,
+                ));
+            }
+            state.set_task_host(id, agent.host).await;
+            Ok(warp::reply::with_status(
+                warp::reply::json(&CreateTaskResponse {
+                    id: id.to_string(),
+                    worktree_path,
+                    dry_run: false,
+                }),
+                StatusCode::CREATED,
+            ))
+        }
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+async fn rename_task(
+    id: String,
+    req: RenameTaskRequest,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply),
+    };
+
+    let agent = match resolve_agent_for_task(&state, task_id).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    match agent
+        .request(AgentRequest::RenameTask {
+            task_id,
+            name: req.name,
+        })
+        .await
+    {
+        Ok(AgentResponse::RenamedTask { task }) => Ok(warp::reply::with_status(
+            warp::reply::json(&TaskResponse::from_task(&agent.host, &task)),
+            StatusCode::OK,
+        )),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+#[derive(Deserialize)]
+struct SetTaskTagsRequest {
+    tags: Vec<String>,
+}
+
+async fn set_task_tags(
+    id: String,
+    req: SetTaskTagsRequest,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply),
+    };
+
+    let agent = match resolve_agent_for_task(&state, task_id).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    match agent
+        .request(AgentRequest::SetTaskTags {
+            task_id,
+            tags: req.tags,
+        })
+        .await
+    {
+        Ok(AgentResponse::Task { task: Some(task) }) => Ok(warp::reply::with_status(
+            warp::reply::json(&TaskResponse::from_task(&agent.host, &task)),
+            StatusCode::OK,
+        )),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+#[derive(Deserialize)]
+struct SendPromptRequest {
+    prompt: String,
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+    /// Queue behind the current run instead of failing while the task is busy.
+    #[serde(default)]
+    queue: bool,
+}
+
+async fn send_prompt(
+    id: String,
+    req: SendPromptRequest,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply),
+    };
+
+    let agent = match resolve_agent_for_task(&state, task_id).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    match agent
+        .request(AgentRequest::SendPrompt {
+            task_id,
+            prompt: req.prompt,
+            attachments: req.attachments,
+            queue: req.queue,
+        })
+        .await
+    {
+        Ok(AgentResponse::Ack) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "status": "started" })),
+            StatusCode::OK,
+        )),
+        Ok(AgentResponse::QueuedPrompts { queued }) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "status": "queued", "queued": queued })),
+            StatusCode::OK,
+        )),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+#[derive(Serialize)]
+struct TaskOutputResponse {
+    events: Vec<AgentEvent>,
+    total_events: usize,
+    has_more_before: bool,
+}
+
+#[derive(Deserialize)]
+struct TaskOutputQuery {
+    #[serde(default)]
+    before: usize,
+    #[serde(default = "default_task_output_limit")]
+    limit: usize,
+}
+
+fn default_task_output_limit() -> usize {
+    120
+}
+
+async fn get_task_output(
+    id: String,
+    query: TaskOutputQuery,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply),
+    };
+
+    let agent = match resolve_agent_for_task(&state, task_id).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    match agent
+        .request(AgentRequest::GetTaskOutput {
+            task_id,
+            pagination: TaskOutputPageRequest {
+                before: query.before,
+                limit: query.limit.max(1).min(500),
+            },
+        })
+        .await
+    {
+        Ok(AgentResponse::TaskOutput {
+            events,
+            total_events,
+            has_more_before,
+        }) => Ok(warp::reply::with_status(
+            warp::reply::json(&TaskOutputResponse {
+                events,
+                total_events,
+                has_more_before,
+            }),
+            StatusCode::OK,
+        )),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+async fn export_task(
+    id: String,
+    query: ExportQuery,
+    state: AppState,
+) -> Result<warp::reply::Response, Infallible> {
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply.into_response()),
+    };
+
+    let agent = match resolve_agent_for_task(&state, task_id).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string()).into_response()),
+    };
+
+    match agent
+        .request(AgentRequest::ExportTask {
+            task_id,
+            format: query.format,
+        })
+        .await
+    {
+        Ok(AgentResponse::TaskExport { format, content }) => {
+            Ok(
+                warp::reply::with_header(content, "content-type", format.content_type())
+                    .into_response(),
+            )
+        }
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )
+        .into_response()),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string()).into_response()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TaskDiffQuery {
+    #[serde(default)]
+    max_diff_bytes: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct TaskDiffResponse {
+    staged: String,
+    unstaged: String,
+    truncated: bool,
+}
+
+async fn get_task_diff(
+    id: String,
+    query: TaskDiffQuery,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply),
+    };
+
+    let agent = match resolve_agent_for_task(&state, task_id).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    match agent
+        .request(AgentRequest::GetTaskDiff {
+            task_id,
+            max_diff_bytes: query.max_diff_bytes,
+        })
+        .await
+    {
+        Ok(AgentResponse::TaskDiff {
+            staged,
+            unstaged,
+            truncated,
+        }) => Ok(warp::reply::with_status(
+            warp::reply::json(&TaskDiffResponse {
+                staged,
+                unstaged,
+                truncated,
+            }),
+            StatusCode::OK,
+        )),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+async fn interrupt_task(id: String, state: AppState) -> Result<impl Reply, Infallible> {
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply),
+    };
+
+    let agent = match resolve_agent_for_task(&state, task_id).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    match agent.request(AgentRequest::InterruptTask { task_id }).await {
+        Ok(AgentResponse::Ack) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "status": "interrupted" })),
+            StatusCode::OK,
+        )),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+/// Drop one queued prompt (`index`) or the whole queue, replying with how many remain.
+async fn cancel_queued_prompts(
+    id: String,
+    index: Option<usize>,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply),
+    };
+
+    let agent = match resolve_agent_for_task(&state, task_id).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    let request = match index {
+        Some(index) => AgentRequest::CancelQueuedPrompt { task_id, index },
+        None => AgentRequest::CancelAllQueued { task_id },
+    };
+    match agent.request(request).await {
+        Ok(AgentResponse::QueuedPrompts { queued }) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "queued": queued })),
+            StatusCode::OK,
+        )),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+/// Merge and readiness requests can run the environment's `merge_check_command`, so they wait
+/// out its timeout instead of the default agent request timeout.
+const MERGE_REQUEST_TIMEOUT: Duration = Duration::from_secs(MERGE_CHECK_TIMEOUT_SECS + 60);
+
+#[derive(Deserialize)]
+struct MergeTaskQuery {
+    #[serde(default)]
+    target: Option<String>,
+    #[serde(default)]
+    skip_checks: bool,
+}
+
+async fn merge_task(
+    id: String,
+    query: MergeTaskQuery,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply),
+    };
+
+    let agent = match resolve_agent_for_task(&state, task_id).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    match agent
+        .request_with_timeout(
+            AgentRequest::MergeTask {
+                task_id,
+                target: query.target,
+                skip_checks: query.skip_checks,
+            },
+            MERGE_REQUEST_TIMEOUT,
+        )
+        .await
+    {
+        Ok(AgentResponse::MergeResult { status, message }) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "status": status, "message": message })),
+            StatusCode::OK,
+        )),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+#[derive(Deserialize)]
+struct CherryPickRequest {
+    target: String,
+    commits: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct CherryPickResponse {
+    status: String,
+    applied: Vec<String>,
+    conflict: Option<String>,
+    message: String,
+}
+
+async fn cherry_pick_task(
+    id: String,
+    req: CherryPickRequest,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply),
+    };
+
+    let agent = match resolve_agent_for_task(&state, task_id).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    match agent
+        .request(AgentRequest::CherryPickTask {
+            task_id,
+            target: req.target,
+            commits: req.commits,
+        })
+        .await
+    {
+        Ok(AgentResponse::CherryPickResult {
+            status,
+            applied,
+            conflict,
+            message,
+        }) => Ok(warp::reply::with_status(
+            warp::reply::json(&CherryPickResponse {
+                status,
+                applied,
+                conflict,
+                message,
+            }),
+            StatusCode::OK,
+        )),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+async fn revert_merge(id: String, state: AppState) -> Result<impl Reply, Infallible> {
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply),
+    };
+
+    let agent = match resolve_agent_for_task(&state, task_id).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    match agent.request(AgentRequest::RevertMerge { task_id }).await {
+        Ok(AgentResponse::RevertResult { sha, message }) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "sha": sha, "message": message })),
+            StatusCode::OK,
+        )),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+#[derive(Serialize)]
+struct MergeStatusResponse {
+    can_merge: bool,
+    reason: Option<String>,
+    conflicts: Vec<String>,
+    dirty_files: Vec<String>,
+}
+
+async fn get_merge_status(id: String, state: AppState) -> Result<impl Reply, Infallible> {
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply),
+    };
+
+    let agent = match resolve_agent_for_task(&state, task_id).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    match agent
+        .request_with_timeout(
+            AgentRequest::GetMergeReadiness { task_id },
+            MERGE_REQUEST_TIMEOUT,
+        )
+        .await
+    {
+        Ok(AgentResponse::MergeReadiness {
+            can_merge,
+            reason,
+            conflicts,
+            dirty_files,
+        }) => Ok(warp::reply::with_status(
+            warp::reply::json(&MergeStatusResponse {
+                can_merge,
+                reason,
+                conflicts,
+                dirty_files,
+            }),
+            StatusCode::OK,
+        )),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+async fn archive_task(id: String, state: AppState) -> Result<impl Reply, Infallible> {
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply),
+    };
+
+    let agent = match resolve_agent_for_task(&state, task_id).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    match agent.request(AgentRequest::ArchiveTask { task_id }).await {
+        Ok(AgentResponse::ArchiveResult { status, message }) => {
+            close_task_terminal_session(&state, &agent, task_id).await;
+            state.clear_task_host(task_id).await;
+            Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "status": status, "message": message })),
+                StatusCode::OK,
+            ))
+        }
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+#[derive(Deserialize)]
+struct DeleteTaskQuery {
+    #[serde(default)]
+    force: bool,
+    #[serde(default)]
+    keep_branch: bool,
+}
+
+async fn delete_task(
+    id: String,
+    query: DeleteTaskQuery,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply),
+    };
+
+    let agent = match resolve_agent_for_task(&state, task_id).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    match agent
+        .request(AgentRequest::DeleteTask {
+            task_id,
+            force: query.force,
+            keep_branch: query.keep_branch,
+        })
+        .await
+    {
+        Ok(AgentResponse::DeleteResult { status, message }) => {
+            close_task_terminal_session(&state, &agent, task_id).await;
+            state.clear_task_host(task_id).await;
+            Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "status": status, "message": message })),
+                StatusCode::OK,
+            ))
+        }
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+#[derive(Deserialize)]
+struct ResetTaskQuery {
+    #[serde(default)]
+    keep_untracked: bool,
+}
+
+async fn reset_task(
+    id: String,
+    query: ResetTaskQuery,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply),
+    };
+
+    let agent = match resolve_agent_for_task(&state, task_id).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    match agent
+        .request(AgentRequest::ResetTaskWorktree {
+            task_id,
+            keep_untracked: query.keep_untracked,
+        })
+        .await
+    {
+        Ok(AgentResponse::ResetResult { files_reset }) => Ok(warp::reply::with_status(
+            warp::reply::json(
+                &serde_json::json!({ "status": "reset", "files_reset": files_reset }),
+            ),
+            StatusCode::OK,
+        )),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
+}
+
+#[derive(Deserialize)]
+struct MoveTaskRequest {
+    new_path: PathBuf,
+}
+
+async fn move_task_worktree(
+    id: String,
+    req: MoveTaskRequest,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply),
+    };
+
+    let agent = match resolve_agent_for_task(&state, task_id).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    // An open terminal's shell still sits in the old directory.
+    close_task_terminal_session(&state, &agent, task_id).await;
+
+    match agent
+        .request(AgentRequest::MoveTaskWorktree {
+            task_id,
+            new_path: req.new_path,
+        })
+        .await
+    {
+        Ok(AgentResponse::WorktreeMoved { worktree_path }) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "worktree_path": worktree_path })),
             StatusCode::OK,
         )),
-        Ok(None) => Ok(error_reply(StatusCode::NOT_FOUND, "Task not found")),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
         Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
     }
 }
 
 #[derive(Deserialize)]
-struct CreateTaskRequest {
-    host: String,
-    environment: String,
-    #[serde(default)]
-    name: Option<String>,
-    #[serde(default)]
-    use_worktree: bool,
-    #[serde(default)]
-    web_search: bool,
-    prompt: String,
-    #[serde(default)]
-    agent: Option<slopcoder_core::anyagent::AgentKind>,
+struct ApplyPatchRequest {
+    diff: String,
 }
 
-#[derive(Serialize)]
-struct CreateTaskResponse {
+async fn apply_patch(
     id: String,
-    worktree_path: String,
-}
-
-#[derive(Deserialize)]
-struct RenameTaskRequest {
-    name: String,
-}
+    req: ApplyPatchRequest,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply),
+    };
 
-async fn create_task(req: CreateTaskRequest, state: AppState) -> Result<impl Reply, Infallible> {
-    let host = req.host.trim();
-    if host.is_empty() {
-        return Ok(error_reply(StatusCode::BAD_REQUEST, "Host is required"));
+    if req.diff.trim().is_empty() {
+        return Ok(error_reply(StatusCode::BAD_REQUEST, "Patch is empty"));
     }
-    let agent = match pick_agent(state.clone(), Some(host)).await {
+
+    let agent = match resolve_agent_for_task(&state, task_id).await {
         Ok(agent) => agent,
         Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
     };
 
-    let request = AgentCreateTaskRequest {
-        environment: req.environment,
-        name: req.name,
-        use_worktree: req.use_worktree,
-        web_search: req.web_search,
-        prompt: req.prompt,
-        agent: req.agent,
-    };
-
-    match agent.request(AgentRequest::CreateTask { request }).await {
-        Ok(AgentResponse::CreatedTask { id, worktree_path }) => {
-            state.set_task_host(id, agent.host).await;
-            Ok(warp::reply::with_status(
-                warp::reply::json(&CreateTaskResponse {
-                    id: id.to_string(),
-                    worktree_path,
-                }),
-                StatusCode::CREATED,
-            ))
-        }
+    match agent
+        .request(AgentRequest::ApplyPatch {
+            task_id,
+            diff: req.diff,
+        })
+        .await
+    {
+        Ok(AgentResponse::Ack) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "status": "applied" })),
+            StatusCode::OK,
+        )),
         Ok(_) => Ok(error_reply(
             StatusCode::INTERNAL_SERVER_ERROR,
             "Unexpected response from agent",
@@ -521,9 +2390,15 @@ async fn create_task(req: CreateTaskRequest, state: AppState) -> Result<impl Rep
     }
 }
 
-async fn rename_task(
+#[derive(Deserialize)]
+struct ApprovalRequest {
+    approve: bool,
+}
+
+async fn respond_approval(
     id: String,
-    req: RenameTaskRequest,
+    call_id: String,
+    req: ApprovalRequest,
     state: AppState,
 ) -> Result<impl Reply, Infallible> {
     let task_id = match parse_task_id(&id) {
@@ -537,14 +2412,15 @@ async fn rename_task(
     };
 
     match agent
-        .request(AgentRequest::RenameTask {
+        .request(AgentRequest::RespondApproval {
             task_id,
-            name: req.name,
+            call_id,
+            approve: req.approve,
         })
         .await
     {
-        Ok(AgentResponse::RenamedTask { task }) => Ok(warp::reply::with_status(
-            warp::reply::json(&TaskResponse::from_task(&agent.host, &task)),
+        Ok(AgentResponse::ApprovalResult { status }) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "status": status })),
             StatusCode::OK,
         )),
         Ok(_) => Ok(error_reply(
@@ -556,13 +2432,15 @@ async fn rename_task(
 }
 
 #[derive(Deserialize)]
-struct SendPromptRequest {
-    prompt: String,
+struct CommitTaskRequest {
+    message: String,
+    #[serde(default)]
+    add_all: bool,
 }
 
-async fn send_prompt(
+async fn commit_task(
     id: String,
-    req: SendPromptRequest,
+    req: CommitTaskRequest,
     state: AppState,
 ) -> Result<impl Reply, Infallible> {
     let task_id = match parse_task_id(&id) {
@@ -570,20 +2448,28 @@ async fn send_prompt(
         Err(reply) => return Ok(reply),
     };
 
+    if req.message.trim().is_empty() {
+        return Ok(error_reply(
+            StatusCode::BAD_REQUEST,
+            "Commit message is required",
+        ));
+    }
+
     let agent = match resolve_agent_for_task(&state, task_id).await {
         Ok(agent) => agent,
         Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
     };
 
     match agent
-        .request(AgentRequest::SendPrompt {
+        .request(AgentRequest::CommitTask {
             task_id,
-            prompt: req.prompt,
+            message: req.message,
+            add_all: req.add_all,
         })
         .await
     {
-        Ok(AgentResponse::Ack) => Ok(warp::reply::with_status(
-            warp::reply::json(&serde_json::json!({ "status": "started" })),
+        Ok(AgentResponse::CommitResult { status, sha }) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "status": status, "sha": sha })),
             StatusCode::OK,
         )),
         Ok(_) => Ok(error_reply(
@@ -594,28 +2480,61 @@ async fn send_prompt(
     }
 }
 
-#[derive(Serialize)]
-struct TaskOutputResponse {
-    events: Vec<AgentEvent>,
-    total_events: usize,
-    has_more_before: bool,
+async fn stash_task(id: String, state: AppState) -> Result<impl Reply, Infallible> {
+    stash_or_unstash_task(id, state, false).await
+}
+
+async fn unstash_task(id: String, state: AppState) -> Result<impl Reply, Infallible> {
+    stash_or_unstash_task(id, state, true).await
+}
+
+async fn stash_or_unstash_task(
+    id: String,
+    state: AppState,
+    pop: bool,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, Infallible> {
+    let task_id = match parse_task_id(&id) {
+        Ok(id) => id,
+        Err(reply) => return Ok(reply),
+    };
+
+    let agent = match resolve_agent_for_task(&state, task_id).await {
+        Ok(agent) => agent,
+        Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
+    };
+
+    let request = if pop {
+        AgentRequest::UnstashTaskChanges { task_id }
+    } else {
+        AgentRequest::StashTaskChanges { task_id }
+    };
+    match agent.request(request).await {
+        Ok(AgentResponse::StashResult { status, message }) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "status": status, "message": message })),
+            StatusCode::OK,
+        )),
+        Ok(_) => Ok(error_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected response from agent",
+        )),
+        Err(e) => Ok(error_reply(state_error_status(&e), e.to_string())),
+    }
 }
 
 #[derive(Deserialize)]
-struct TaskOutputQuery {
+struct TaskCommitsQuery {
     #[serde(default)]
-    before: usize,
-    #[serde(default = "default_task_output_limit")]
-    limit: usize,
+    limit: Option<usize>,
 }
 
-fn default_task_output_limit() -> usize {
-    120
+#[derive(Serialize)]
+struct TaskCommitsResponse {
+    commits: Vec<CommitInfo>,
 }
 
-async fn get_task_output(
+async fn get_task_commits(
     id: String,
-    query: TaskOutputQuery,
+    query: TaskCommitsQuery,
     state: AppState,
 ) -> Result<impl Reply, Infallible> {
     let task_id = match parse_task_id(&id) {
@@ -629,25 +2548,14 @@ async fn get_task_output(
     };
 
     match agent
-        .request(AgentRequest::GetTaskOutput {
+        .request(AgentRequest::GetTaskCommits {
             task_id,
-            pagination: TaskOutputPageRequest {
-                before: query.before,
-                limit: query.limit.max(1).min(500),
-            },
+            limit: query.limit,
         })
         .await
     {
-        Ok(AgentResponse::TaskOutput {
-            events,
-            total_events,
-            has_more_before,
-        }) => Ok(warp::reply::with_status(
-            warp::reply::json(&TaskOutputResponse {
-                events,
-                total_events,
-                has_more_before,
-            }),
+        Ok(AgentResponse::TaskCommits { commits }) => Ok(warp::reply::with_status(
+            warp::reply::json(&TaskCommitsResponse { commits }),
             StatusCode::OK,
         )),
         Ok(_) => Ok(error_reply(
@@ -658,13 +2566,25 @@ async fn get_task_output(
     }
 }
 
+#[derive(Deserialize)]
+struct WorktreeFileQuery {
+    path: String,
+}
+
 #[derive(Serialize)]
-struct TaskDiffResponse {
-    staged: String,
-    unstaged: String,
+struct WorktreeFileResponse {
+    path: String,
+    size: u64,
+    binary: bool,
+    too_large: bool,
+    content: Option<String>,
 }
 
-async fn get_task_diff(id: String, state: AppState) -> Result<impl Reply, Infallible> {
+async fn read_worktree_file(
+    id: String,
+    query: WorktreeFileQuery,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
     let task_id = match parse_task_id(&id) {
         Ok(id) => id,
         Err(reply) => return Ok(reply),
@@ -675,9 +2595,27 @@ async fn get_task_diff(id: String, state: AppState) -> Result<impl Reply, Infall
         Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
     };
 
-    match agent.request(AgentRequest::GetTaskDiff { task_id }).await {
-        Ok(AgentResponse::TaskDiff { staged, unstaged }) => Ok(warp::reply::with_status(
-            warp::reply::json(&TaskDiffResponse { staged, unstaged }),
+    match agent
+        .request(AgentRequest::ReadWorktreeFile {
+            task_id,
+            path: query.path,
+        })
+        .await
+    {
+        Ok(AgentResponse::WorktreeFile {
+            path,
+            size,
+            binary,
+            too_large,
+            content,
+        }) => Ok(warp::reply::with_status(
+            warp::reply::json(&WorktreeFileResponse {
+                path,
+                size,
+                binary,
+                too_large,
+                content,
+            }),
             StatusCode::OK,
         )),
         Ok(_) => Ok(error_reply(
@@ -688,7 +2626,7 @@ async fn get_task_diff(id: String, state: AppState) -> Result<impl Reply, Infall
     }
 }
 
-async fn interrupt_task(id: String, state: AppState) -> Result<impl Reply, Infallible> {
+async fn get_terminal_recording(id: String, state: AppState) -> Result<impl Reply, Infallible> {
     let task_id = match parse_task_id(&id) {
         Ok(id) => id,
         Err(reply) => return Ok(reply),
@@ -699,9 +2637,12 @@ async fn interrupt_task(id: String, state: AppState) -> Result<impl Reply, Infal
         Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
     };
 
-    match agent.request(AgentRequest::InterruptTask { task_id }).await {
-        Ok(AgentResponse::Ack) => Ok(warp::reply::with_status(
-            warp::reply::json(&serde_json::json!({ "status": "interrupted" })),
+    match agent
+        .request(AgentRequest::GetTerminalRecording { task_id })
+        .await
+    {
+        Ok(AgentResponse::TerminalRecording { path, content }) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "path": path, "content": content })),
             StatusCode::OK,
         )),
         Ok(_) => Ok(error_reply(
@@ -712,7 +2653,23 @@ async fn interrupt_task(id: String, state: AppState) -> Result<impl Reply, Infal
     }
 }
 
-async fn merge_task(id: String, state: AppState) -> Result<impl Reply, Infallible> {
+#[derive(Deserialize)]
+struct DiskUsageQuery {
+    #[serde(default)]
+    exclude_git: bool,
+}
+
+#[derive(Serialize)]
+struct DiskUsageResponse {
+    task_id: String,
+    bytes: u64,
+}
+
+async fn get_task_disk_usage(
+    id: String,
+    query: DiskUsageQuery,
+    state: AppState,
+) -> Result<impl Reply, Infallible> {
     let task_id = match parse_task_id(&id) {
         Ok(id) => id,
         Err(reply) => return Ok(reply),
@@ -723,9 +2680,18 @@ async fn merge_task(id: String, state: AppState) -> Result<impl Reply, Infallibl
         Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
     };
 
-    match agent.request(AgentRequest::MergeTask { task_id }).await {
-        Ok(AgentResponse::MergeResult { status, message }) => Ok(warp::reply::with_status(
-            warp::reply::json(&serde_json::json!({ "status": status, "message": message })),
+    match agent
+        .request(AgentRequest::GetTaskDiskUsage {
+            task_id,
+            exclude_git: query.exclude_git,
+        })
+        .await
+    {
+        Ok(AgentResponse::DiskUsage { task_id, bytes }) => Ok(warp::reply::with_status(
+            warp::reply::json(&DiskUsageResponse {
+                task_id: task_id.to_string(),
+                bytes,
+            }),
             StatusCode::OK,
         )),
         Ok(_) => Ok(error_reply(
@@ -737,12 +2703,14 @@ async fn merge_task(id: String, state: AppState) -> Result<impl Reply, Infallibl
 }
 
 #[derive(Serialize)]
-struct MergeStatusResponse {
-    can_merge: bool,
-    reason: Option<String>,
+struct TaskSummaryResponse {
+    task_id: String,
+    last_message: Option<String>,
+    usage: Option<UsageStats>,
+    outcome: Option<TaskOutcome>,
 }
 
-async fn get_merge_status(id: String, state: AppState) -> Result<impl Reply, Infallible> {
+async fn get_task_summary(id: String, state: AppState) -> Result<impl Reply, Infallible> {
     let task_id = match parse_task_id(&id) {
         Ok(id) => id,
         Err(reply) => return Ok(reply),
@@ -754,11 +2722,20 @@ async fn get_merge_status(id: String, state: AppState) -> Result<impl Reply, Inf
     };
 
     match agent
-        .request(AgentRequest::GetMergeReadiness { task_id })
+        .request(AgentRequest::GetTaskSummary { task_id })
         .await
     {
-        Ok(AgentResponse::MergeReadiness { can_merge, reason }) => Ok(warp::reply::with_status(
-            warp::reply::json(&MergeStatusResponse { can_merge, reason }),
+        Ok(AgentResponse::TaskSummary {
+            last_message,
+            usage,
+            outcome,
+        }) => Ok(warp::reply::with_status(
+            warp::reply::json(&TaskSummaryResponse {
+                task_id: task_id.to_string(),
+                last_message,
+                usage,
+                outcome,
+            }),
             StatusCode::OK,
         )),
         Ok(_) => Ok(error_reply(
@@ -769,7 +2746,7 @@ async fn get_merge_status(id: String, state: AppState) -> Result<impl Reply, Inf
     }
 }
 
-async fn archive_task(id: String, state: AppState) -> Result<impl Reply, Infallible> {
+async fn get_task_bundle(id: String, state: AppState) -> Result<impl Reply, Infallible> {
     let task_id = match parse_task_id(&id) {
         Ok(id) => id,
         Err(reply) => return Ok(reply),
@@ -780,15 +2757,14 @@ async fn archive_task(id: String, state: AppState) -> Result<impl Reply, Infalli
         Err(e) => return Ok(error_reply(state_error_status(&e), e.to_string())),
     };
 
-    match agent.request(AgentRequest::ArchiveTask { task_id }).await {
-        Ok(AgentResponse::ArchiveResult { status, message }) => {
-            close_task_terminal_session(&state, &agent, task_id).await;
-            state.clear_task_host(task_id).await;
-            Ok(warp::reply::with_status(
-                warp::reply::json(&serde_json::json!({ "status": status, "message": message })),
-                StatusCode::OK,
-            ))
-        }
+    match agent
+        .request(AgentRequest::ExportTaskBundle { task_id })
+        .await
+    {
+        Ok(AgentResponse::TaskBundle { bundle }) => Ok(warp::reply::with_status(
+            warp::reply::json(&bundle),
+            StatusCode::OK,
+        )),
         Ok(_) => Ok(error_reply(
             StatusCode::INTERNAL_SERVER_ERROR,
             "Unexpected response from agent",
@@ -798,14 +2774,20 @@ async fn archive_task(id: String, state: AppState) -> Result<impl Reply, Infalli
 }
 
 #[derive(Deserialize)]
-struct DeleteTaskQuery {
+struct TaskLogsQuery {
     #[serde(default)]
-    force: bool,
+    limit: Option<usize>,
 }
 
-async fn delete_task(
+#[derive(Serialize)]
+struct TaskLogsResponse {
+    task_id: String,
+    lines: Vec<TaskLogLine>,
+}
+
+async fn get_task_logs(
     id: String,
-    query: DeleteTaskQuery,
+    query: TaskLogsQuery,
     state: AppState,
 ) -> Result<impl Reply, Infallible> {
     let task_id = match parse_task_id(&id) {
@@ -819,20 +2801,19 @@ async fn delete_task(
     };
 
     match agent
-        .request(AgentRequest::DeleteTask {
+        .request(AgentRequest::GetTaskLogs {
             task_id,
-            force: query.force,
+            limit: query.limit,
         })
         .await
     {
-        Ok(AgentResponse::DeleteResult { status, message }) => {
-            close_task_terminal_session(&state, &agent, task_id).await;
-            state.clear_task_host(task_id).await;
-            Ok(warp::reply::with_status(
-                warp::reply::json(&serde_json::json!({ "status": status, "message": message })),
-                StatusCode::OK,
-            ))
-        }
+        Ok(AgentResponse::TaskLogs { lines }) => Ok(warp::reply::with_status(
+            warp::reply::json(&TaskLogsResponse {
+                task_id: task_id.to_string(),
+                lines,
+            }),
+            StatusCode::OK,
+        )),
         Ok(_) => Ok(error_reply(
             StatusCode::INTERNAL_SERVER_ERROR,
             "Unexpected response from agent",
@@ -884,7 +2865,7 @@ async fn find_task(
             }
             Ok(_) => {}
             Err(e) => {
-                tracing::warn!("Failed to fetch mapped task {}: {}", task_id, e);
+                tracing::warn!(task_id = %task_id, error = %e, "Failed to fetch mapped task");
             }
         }
     }
@@ -905,7 +2886,9 @@ async fn find_task(
             }
             Ok(AgentResponse::Task { task: None }) => {}
             Ok(_) => {}
-            Err(e) => tracing::warn!("Failed to query task {} on {}: {}", task_id, host, e),
+            Err(e) => {
+                tracing::warn!(task_id = %task_id, host = %host, error = %e, "Failed to query task")
+            }
         }
     }
     Ok(None)
@@ -924,7 +2907,35 @@ async fn handle_agent_socket(ws: WebSocket, state: AppState) {
                 Ok(AgentEnvelope::Hello {
                     hostname,
                     display_name,
-                }) => (hostname, display_name),
+                    installed_agents,
+                    agent_versions,
+                    protocol_version,
+                    labels,
+                    running_tasks,
+                }) => {
+                    if protocol_version != PROTOCOL_VERSION {
+                        tracing::warn!(
+                            "Rejecting agent '{}': protocol version {} (coordinator speaks {})",
+                            hostname,
+                            protocol_version,
+                            PROTOCOL_VERSION
+                        );
+                        let reason = format!(
+                            "incompatible protocol version {} (coordinator requires {})",
+                            protocol_version, PROTOCOL_VERSION
+                        );
+                        let _ = sink.send(Message::close_with(1002u16, reason)).await;
+                        return;
+                    }
+                    AgentIdentity {
+                        hostname,
+                        display_name,
+                        installed_agents,
+                        agent_versions,
+                        labels,
+                        running_tasks,
+                    }
+                }
                 _ => {
                     let _ = sink.send(Message::text("expected hello")).await;
                     return;
@@ -936,22 +2947,13 @@ async fn handle_agent_socket(ws: WebSocket, state: AppState) {
     };
 
     let (outbound_tx, mut outbound_rx) = mpsc::unbounded_channel::<AgentEnvelope>();
-    let pending: Arc<Mutex<HashMap<String, oneshot::Sender<Result<AgentResponse, RemoteError>>>>> =
+    let pending: Arc<Mutex<HashMap<RequestId, oneshot::Sender<PendingResponse>>>> =
         Arc::new(Mutex::new(HashMap::new()));
 
     let agent = state
-        .register_agent(
-            hello.0.clone(),
-            hello.1.clone(),
-            outbound_tx.clone(),
-            pending.clone(),
-        )
+        .register_agent(hello, outbound_tx.clone(), pending.clone())
         .await;
-    tracing::info!(
-        "Agent connected host='{}' hostname='{}'",
-        agent.host,
-        agent.hostname
-    );
+    tracing::info!(host = %agent.host, hostname = %agent.hostname, "Agent connected");
 
     let writer = tokio::spawn(async move {
         while let Some(envelope) = outbound_rx.recv().await {
@@ -968,8 +2970,35 @@ async fn handle_agent_socket(ws: WebSocket, state: AppState) {
         }
     });
 
-    while let Some(incoming) = stream.next().await {
-        let Ok(message) = incoming else {
+    let heartbeat = state.get_heartbeat_config().await;
+    let heartbeat_enabled = !heartbeat.interval.is_zero();
+    let heartbeat_period = heartbeat.interval.max(Duration::from_millis(1));
+    let mut ping_timer = tokio::time::interval_at(
+        tokio::time::Instant::now() + heartbeat_period,
+        heartbeat_period,
+    );
+    let mut last_nonce: u64 = 0;
+    let mut missed_pongs: u32 = 0;
+
+    loop {
+        let incoming = tokio::select! {
+            incoming = stream.next() => incoming,
+            _ = ping_timer.tick(), if heartbeat_enabled => {
+                if missed_pongs >= heartbeat.max_missed {
+                    tracing::warn!(
+                        host = %agent.host,
+                        missed = missed_pongs,
+                        "Agent missed heartbeats; dropping connection"
+                    );
+                    break;
+                }
+                missed_pongs += 1;
+                last_nonce += 1;
+                let _ = outbound_tx.send(AgentEnvelope::Ping { nonce: last_nonce });
+                continue;
+            }
+        };
+        let Some(Ok(message)) = incoming else {
             break;
         };
         if message.is_close() {
@@ -987,10 +3016,20 @@ async fn handle_agent_socket(ws: WebSocket, state: AppState) {
         let envelope = match serde_json::from_str::<AgentEnvelope>(text) {
             Ok(env) => env,
             Err(e) => {
-                tracing::warn!("Failed to decode agent envelope from {}: {}", agent.host, e);
+                tracing::warn!(host = %agent.host, error = %e, "Failed to decode agent envelope");
                 continue;
             }
         };
+        // Pongs only show the socket is up; answers and task events (including run heartbeats)
+        // show the agent is working.
+        if matches!(
+            envelope,
+            AgentEnvelope::Response { .. }
+                | AgentEnvelope::Error { .. }
+                | AgentEnvelope::TaskEvent { .. }
+        ) {
+            agent.mark_seen();
+        }
 
         match envelope {
             AgentEnvelope::Response {
@@ -1029,6 +3068,20 @@ async fn handle_agent_socket(ws: WebSocket, state: AppState) {
                     .broadcast_terminal_event(terminal_id, TerminalEvent::Error(error))
                     .await;
             }
+            AgentEnvelope::DiffChunk {
+                stream_id,
+                section,
+                data,
+            } => {
+                state
+                    .forward_diff_event(stream_id, DiffStreamEvent::Chunk { section, data })
+                    .await;
+            }
+            AgentEnvelope::DiffEnd { stream_id, error } => {
+                state
+                    .forward_diff_event(stream_id, DiffStreamEvent::End { error })
+                    .await;
+            }
             AgentEnvelope::TerminalOpen { .. }
             | AgentEnvelope::TerminalInput { .. }
             | AgentEnvelope::TerminalResize { .. }
@@ -1038,7 +3091,15 @@ async fn handle_agent_socket(ws: WebSocket, state: AppState) {
                     agent.host
                 );
             }
-            AgentEnvelope::Hello { .. } | AgentEnvelope::Request { .. } => {
+            AgentEnvelope::Pong { nonce } => {
+                if nonce == last_nonce {
+                    missed_pongs = 0;
+                }
+            }
+            AgentEnvelope::Hello { .. }
+            | AgentEnvelope::Request { .. }
+            | AgentEnvelope::Ping { .. }
+            | AgentEnvelope::Shutdown => {
                 tracing::warn!("Ignoring unexpected envelope from agent '{}'", agent.host);
             }
         }
@@ -1055,23 +3116,83 @@ async fn handle_agent_socket(ws: WebSocket, state: AppState) {
 
     writer.abort();
     state.unregister_agent(agent.id).await;
+    tracing::info!(host = %agent.host, "Agent disconnected");
 }
 
 // ============================================================================
 // Task event websocket for UI
 // ============================================================================
 
-async fn handle_task_websocket(ws: WebSocket, id: String, state: AppState) {
-    let Ok(uuid) = Uuid::parse_str(&id) else {
-        tracing::warn!("Invalid task ID in websocket: {}", id);
-        return;
-    };
-    let task_id = TaskId(uuid);
-    let mut rx = state.subscribe_to_task(task_id).await;
+/// Resume point for a task stream websocket.
+#[derive(Debug, Deserialize)]
+struct TaskStreamQuery {
+    /// Last `seq` the client has rendered; buffered events after it are replayed first.
+    #[serde(default)]
+    since_seq: Option<u64>,
+    /// Epoch `since_seq` was numbered in; from another coordinator run it is ignored.
+    #[serde(default)]
+    epoch: Option<Uuid>,
+}
+
+/// A task stream websocket message: the numbered event plus the epoch its `seq` belongs to.
+#[derive(Serialize)]
+struct TaskStreamMessage<'a> {
+    epoch: Uuid,
+    #[serde(flatten)]
+    event: &'a SequencedEvent,
+}
+
+async fn handle_task_websocket(
+    ws: WebSocket,
+    task_id: TaskId,
+    query: TaskStreamQuery,
+    state: AppState,
+) {
+    let epoch = state.stream_epoch().await;
+    // A `since_seq` from before a coordinator restart says nothing about the current
+    // numbering; the client sees the new epoch and reloads the task's history instead.
+    let since_seq = query
+        .since_seq
+        .filter(|_| query.epoch.is_none_or(|client_epoch| client_epoch == epoch));
+    let (replay, mut rx) = state.subscribe_to_task(task_id, since_seq).await;
     let (mut tx, mut _rx) = ws.split();
 
-    while let Ok(event) = rx.recv().await {
-        let json = match serde_json::to_string(&event) {
+    let mut last_seq = since_seq.unwrap_or(0);
+    let mut pending: VecDeque<SequencedEvent> = replay.into();
+    // Events the replay buffer no longer holds are reported like a lagged stream.
+    if let (Some(since), Some(first)) = (since_seq, pending.front()) {
+        if first.seq > since + 1 {
+            pending.push_front(SequencedEvent {
+                seq: since,
+                event: events_dropped_marker(first.seq - since - 1),
+            });
+        }
+    }
+    loop {
+        let event = match pending.pop_front() {
+            Some(event) => event,
+            None => match rx.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!(
+                        task_id = %task_id,
+                        dropped = skipped,
+                        "Task websocket lagged"
+                    );
+                    SequencedEvent {
+                        seq: last_seq,
+                        event: events_dropped_marker(skipped),
+                    }
+                }
+                Err(RecvError::Closed) => break,
+            },
+        };
+        last_seq = event.seq;
+        let message = TaskStreamMessage {
+            epoch,
+            event: &event,
+        };
+        let json = match serde_json::to_string(&message) {
             Ok(j) => j,
             Err(e) => {
                 tracing::warn!("Failed to serialize task event: {}", e);
@@ -1084,39 +3205,126 @@ async fn handle_task_websocket(ws: WebSocket, id: String, state: AppState) {
     }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
-enum TerminalClientMessage {
-    Resize { rows: u16, cols: u16 },
-}
+/// Relay a task diff chunk by chunk, ending with an `end` message and closing.
+async fn handle_diff_stream_websocket(ws: WebSocket, id: String, state: AppState) {
+    let (mut ws_tx, _ws_rx) = ws.split();
+    let end_message = |error: String| {
+        serde_json::to_string(&DiffStreamEvent::End { error: Some(error) }).unwrap_or_default()
+    };
 
-async fn handle_terminal_websocket(ws: WebSocket, id: String, state: AppState) {
     let task_id = match parse_task_id(&id) {
         Ok(id) => id,
         Err(_) => {
-            tracing::warn!("Invalid task ID in terminal websocket: {}", id);
+            let _ = ws_tx
+                .send(Message::text(end_message("Invalid task ID".to_string())))
+                .await;
+            return;
+        }
+    };
+    let agent = match resolve_agent_for_task(&state, task_id).await {
+        Ok(agent) => agent,
+        Err(e) => {
+            let _ = ws_tx.send(Message::text(end_message(e.to_string()))).await;
             return;
         }
     };
 
+    let (stream_id, mut events) = state.open_diff_stream(&agent.host).await;
+    match agent
+        .request(AgentRequest::StreamTaskDiff { task_id, stream_id })
+        .await
+    {
+        Ok(AgentResponse::Ack) => {}
+        Ok(_) => {
+            state.close_diff_stream(stream_id).await;
+            let message = end_message("Unexpected response from agent".to_string());
+            let _ = ws_tx.send(Message::text(message)).await;
+            return;
+        }
+        Err(e) => {
+            state.close_diff_stream(stream_id).await;
+            let _ = ws_tx.send(Message::text(end_message(e.to_string()))).await;
+            return;
+        }
+    }
+
+    let mut ended = false;
+    while let Some(event) = events.recv().await {
+        ended = matches!(event, DiffStreamEvent::End { .. });
+        let json = match serde_json::to_string(&event) {
+            Ok(json) => json,
+            Err(e) => {
+                tracing::warn!("Failed to serialize diff chunk: {}", e);
+                continue;
+            }
+        };
+        if ws_tx.send(Message::text(json)).await.is_err() || ended {
+            break;
+        }
+    }
+    if !ended {
+        let _ = ws_tx
+            .send(Message::text(end_message(
+                "Diff stream cut off: the agent disconnected or the client fell too far behind"
+                    .to_string(),
+            )))
+            .await;
+    }
+    state.close_diff_stream(stream_id).await;
+    let _ = ws_tx.close().await;
+}
+
+/// Synthetic event telling the UI that its live stream skipped events.
+fn events_dropped_marker(skipped: u64) -> AgentEvent {
+    AgentEvent::BackgroundEvent {
+        event: Some("events_dropped".to_string()),
+        extra: serde_json::json!({ "dropped": skipped }),
+    }
+}
+
+/// Initial terminal dimensions passed as websocket query params.
+#[derive(Debug, Deserialize)]
+struct TerminalSizeQuery {
+    #[serde(default)]
+    rows: Option<u16>,
+    #[serde(default)]
+    cols: Option<u16>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum TerminalClientMessage {
+    Resize { rows: u16, cols: u16 },
+}
+
+async fn handle_terminal_websocket(
+    ws: WebSocket,
+    task_id: TaskId,
+    size: TerminalSizeQuery,
+    state: AppState,
+) {
     let agent = match resolve_agent_for_task(&state, task_id).await {
         Ok(agent) => agent,
         Err(_) => match find_task(&state, task_id).await {
             Ok(Some((host, _))) => match state.get_agent_for_host(&host).await {
                 Some(agent) => agent,
                 None => {
-                    tracing::warn!("Task host '{}' is not connected for terminal {}", host, id);
+                    tracing::warn!(
+                        "Task host '{}' is not connected for terminal {}",
+                        host,
+                        task_id
+                    );
                     return;
                 }
             },
             Ok(None) => {
-                tracing::warn!("Task not found for terminal websocket: {}", id);
+                tracing::warn!("Task not found for terminal websocket: {}", task_id);
                 return;
             }
             Err(e) => {
                 tracing::warn!(
                     "Failed to resolve task host for terminal websocket {}: {}",
-                    id,
+                    task_id,
                     e
                 );
                 return;
@@ -1138,11 +3346,13 @@ async fn handle_terminal_websocket(ws: WebSocket, id: String, state: AppState) {
         }
     }
 
-    let mut terminal_events = state.subscribe_to_terminal(terminal_id).await;
+    let (scrollback, mut terminal_events) = state.subscribe_to_terminal(terminal_id).await;
     if needs_open {
         if let Err(e) = agent.send_envelope(AgentEnvelope::TerminalOpen {
             terminal_id,
             task_id,
+            rows: size.rows,
+            cols: size.cols,
         }) {
             tracing::warn!(
                 "Failed to send terminal open to host '{}' for task {}: {}",
@@ -1154,11 +3364,36 @@ async fn handle_terminal_websocket(ws: WebSocket, id: String, state: AppState) {
             return;
         }
     }
+    // Reattaching to an existing PTY: bring it to this client's size.
+    if !needs_open {
+        if let (Some(rows), Some(cols)) = (size.rows, size.cols) {
+            let _ = agent.send_envelope(AgentEnvelope::TerminalResize {
+                terminal_id,
+                rows,
+                cols,
+            });
+        }
+    }
 
     let (mut ws_tx, mut ws_rx) = ws.split();
 
     let mut to_ws = tokio::spawn(async move {
-        while let Ok(event) = terminal_events.recv().await {
+        if !scrollback.is_empty() && ws_tx.send(Message::binary(scrollback)).await.is_err() {
+            return;
+        }
+        loop {
+            let event = match terminal_events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!(
+                        "Terminal {} websocket lagged; dropped {} output chunks",
+                        terminal_id,
+                        skipped
+                    );
+                    TerminalEvent::Data(b"\r\n[terminal output skipped]\r\n".to_vec())
+                }
+                Err(RecvError::Closed) => break,
+            };
             match event {
                 TerminalEvent::Data(data) => {
                     if ws_tx.send(Message::binary(data)).await.is_err() {
@@ -1290,6 +3525,7 @@ fn auth_filter_api(state: AppState) -> impl Filter<Extract = (), Error = warp::R
     warp::any()
         .and(with_state(state))
         .and(warp::method())
+        .and(warp::path::full())
         .and(warp::header::optional::<String>("x-slopcoder-password"))
         .and(raw_query)
         .and_then(check_api_auth)
@@ -1312,9 +3548,13 @@ fn auth_filter_agent(
         .untuple_one()
 }
 
+/// Accept the UI password for any request, or the read-only password for reads.
+///
+/// Reads are `GET`/`HEAD` requests other than the task terminal websocket, which takes input.
 async fn check_api_auth(
     state: AppState,
     method: Method,
+    path: FullPath,
     header_password: Option<String>,
     raw_query: String,
 ) -> Result<(), warp::Rejection> {
@@ -1325,9 +3565,18 @@ async fn check_api_auth(
     if let Some(required) = required {
         let query_password = extract_password_from_query(&raw_query);
         let provided = header_password.or(query_password);
-        if provided.as_deref() != Some(required.as_str()) {
+        if provided.as_deref() == Some(required.as_str()) {
+            return Ok(());
+        }
+        let readonly = state.get_readonly_auth_password().await;
+        if provided.is_none() || provided != readonly {
             return Err(warp::reject::custom(AuthError));
         }
+        let is_read = (method == Method::GET || method == Method::HEAD)
+            && !path.as_str().ends_with("/terminal");
+        if !is_read {
+            return Err(warp::reject::custom(ReadOnlyError));
+        }
     }
     Ok(())
 }
@@ -1371,6 +3620,9 @@ async fn handle_rejection(err: warp::Rejection) -> Result<impl Reply, Infallible
     if err.find::<AuthError>().is_some() {
         return Ok(error_reply(StatusCode::UNAUTHORIZED, "Unauthorized"));
     }
+    if err.find::<ReadOnlyError>().is_some() {
+        return Ok(error_reply(StatusCode::FORBIDDEN, "Read-only credential"));
+    }
     if err.is_not_found() {
         return Ok(error_reply(StatusCode::NOT_FOUND, "Not Found"));
     }
@@ -1403,15 +3655,33 @@ async fn pick_agent(state: AppState, host: Option<&str>) -> Result<ConnectedAgen
 fn state_error_status(err: &StateError) -> StatusCode {
     match err {
         StateError::HostRequired => StatusCode::BAD_REQUEST,
-        StateError::HostNotConnected(_) => StatusCode::NOT_FOUND,
+        StateError::HostNotConnected(_) | StateError::NoMatchingHost => StatusCode::NOT_FOUND,
         StateError::NoAgentsConnected => StatusCode::SERVICE_UNAVAILABLE,
         StateError::AgentDisconnected | StateError::AgentTimeout => StatusCode::SERVICE_UNAVAILABLE,
+        StateError::AgentBusy => StatusCode::GATEWAY_TIMEOUT,
         StateError::RemoteError { status, .. } => {
             StatusCode::from_u16(*status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
 
+/// Back off a host whose list request timed out, silent or busy; any answer ends its backoff.
+async fn note_list_poll_result(
+    state: &AppState,
+    host: &str,
+    response: &Result<AgentResponse, StateError>,
+) {
+    match response {
+        Err(StateError::AgentTimeout | StateError::AgentBusy) => {
+            state.back_off_host_list(host).await
+        }
+        // The host may have disconnected since it answered; nothing to clear then.
+        _ => {
+            let _ = state.clear_host_list_backoff(host).await;
+        }
+    }
+}
+
 async fn request_with_timeout(
     agent: &ConnectedAgent,
     request: AgentRequest,
@@ -1424,7 +3694,980 @@ async fn request_with_timeout(
 
 #[cfg(test)]
 mod tests {
-    use super::extract_password_from_query;
+    use super::{
+        events_dropped_marker, extract_password_from_query, note_list_poll_result, routes,
+        TaskListQuery, TaskResponse, TaskSort,
+    };
+    use crate::state::{AgentIdentity, AppState, HeartbeatConfig, RemoteError, StateError};
+    use slopcoder_core::{
+        agent_rpc::{
+            AgentEnvelope, AgentRequest, AgentResponse, EnvironmentInfo, PROTOCOL_VERSION,
+        },
+        anyagent::AgentKind,
+        task::{Task, TaskId, TaskWorkspaceKind},
+        AgentEvent,
+    };
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::Mutex;
+
+    fn task_response(name: &str, tags: &[&str]) -> TaskResponse {
+        let mut task = Task::new(
+            AgentKind::Codex,
+            "env".to_string(),
+            name.to_string(),
+            TaskWorkspaceKind::Environment,
+            None,
+            None,
+            false,
+            PathBuf::from("/tmp"),
+        );
+        task.set_tags(tags.iter().map(|tag| tag.to_string()).collect());
+        TaskResponse::from_task("host-a", &task)
+    }
+
+    #[test]
+    fn test_task_list_query_filters_by_tag() {
+        let tasks = [
+            task_response("one", &["backend", "urgent"]),
+            task_response("two", &["frontend"]),
+            task_response("three", &[]),
+        ];
+
+        let query = TaskListQuery {
+            tag: Some("backend".to_string()),
+            ..TaskListQuery::default()
+        };
+        let names: Vec<&str> = tasks
+            .iter()
+            .filter(|task| query.matches(task))
+            .map(|task| task.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["one"]);
+
+        let query = TaskListQuery::default();
+        assert!(tasks.iter().all(|task| query.matches(task)));
+    }
+
+    #[test]
+    fn test_task_list_query_paginates_after_filtering() {
+        let mut tasks = Vec::new();
+        for (index, name) in ["a", "b", "c", "d", "e"].iter().enumerate() {
+            let mut task = task_response(name, &[]);
+            task.created_at = format!("2024-01-0{}T00:00:00+00:00", index + 1);
+            task.status = if index % 2 == 0 {
+                "completed"
+            } else {
+                "failed"
+            }
+            .to_string();
+            tasks.push(task);
+        }
+
+        let query = TaskListQuery {
+            status: Some("Completed".to_string()),
+            limit: Some(2),
+            offset: 1,
+            ..TaskListQuery::default()
+        };
+        let page = query.apply(tasks);
+        assert_eq!(page.total, 3);
+        let names: Vec<&str> = page.tasks.iter().map(|task| task.name.as_str()).collect();
+        assert_eq!(names, vec!["c", "a"]);
+    }
+
+    #[test]
+    fn test_task_list_query_sorts_by_priority() {
+        let mut tasks = Vec::new();
+        for (index, (name, priority)) in [("low", 0), ("high", 10), ("mid", 5), ("high-old", 10)]
+            .iter()
+            .enumerate()
+        {
+            let mut task = task_response(name, &[]);
+            task.priority = *priority;
+            task.created_at = format!("2024-01-0{}T00:00:00+00:00", 5 - index);
+            tasks.push(task);
+        }
+
+        let query = TaskListQuery {
+            sort: TaskSort::Priority,
+            ..TaskListQuery::default()
+        };
+        let page = query.apply(tasks);
+        let names: Vec<&str> = page.tasks.iter().map(|task| task.name.as_str()).collect();
+        assert_eq!(names, vec!["high", "high-old", "mid", "low"]);
+    }
+
+    #[test]
+    fn test_task_list_query_filters_by_host_and_environment() {
+        let tasks = vec![task_response("one", &[])];
+        let query = TaskListQuery {
+            host: Some("host-b".to_string()),
+            ..TaskListQuery::default()
+        };
+        assert_eq!(query.apply(tasks).total, 0);
+
+        let tasks = vec![task_response("one", &[])];
+        let query = TaskListQuery {
+            host: Some("host-a".to_string()),
+            environment: Some("env".to_string()),
+            ..TaskListQuery::default()
+        };
+        assert_eq!(query.apply(tasks).total, 1);
+    }
+
+    #[test]
+    fn test_events_dropped_marker_serializes_count() {
+        let json = serde_json::to_value(events_dropped_marker(7)).unwrap();
+        assert_eq!(json["type"], "background_event");
+        assert_eq!(json["event"], "events_dropped");
+        assert_eq!(json["dropped"], 7);
+    }
+
+    #[tokio::test]
+    async fn test_task_websocket_survives_broadcast_lag() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 1);
+        let task_id = TaskId::new();
+        let mut client = warp::test::ws()
+            .path(&format!("/api/tasks/{}/stream", task_id))
+            .handshake(routes(state.clone()))
+            .await
+            .expect("websocket handshake");
+
+        let prompt = |text: &str| AgentEvent::PromptSent {
+            prompt: text.to_string(),
+        };
+        let next_json = |message: warp::ws::Message| -> serde_json::Value {
+            serde_json::from_str(message.to_str().expect("text frame")).expect("json event")
+        };
+
+        // Wait until the websocket handler has subscribed.
+        let mut subscribed = false;
+        for _ in 0..50 {
+            state.broadcast_task_event(task_id, prompt("ready")).await;
+            if let Ok(Ok(_)) = tokio::time::timeout(Duration::from_millis(20), client.recv()).await
+            {
+                subscribed = true;
+                break;
+            }
+        }
+        assert!(subscribed, "websocket never subscribed");
+
+        // Overrun the one-slot buffer without yielding to the websocket task.
+        for index in 0..20 {
+            state
+                .broadcast_task_event(task_id, prompt(&index.to_string()))
+                .await;
+        }
+        state.broadcast_task_event(task_id, prompt("after")).await;
+
+        let mut saw_marker = false;
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(2), client.recv())
+                .await
+                .expect("websocket stalled")
+                .expect("websocket closed");
+            let envelope = next_json(message);
+            let event = &envelope["event"];
+            if event["event"] == "events_dropped" {
+                saw_marker = true;
+            }
+            if event["prompt"] == "after" {
+                break;
+            }
+        }
+        assert!(saw_marker);
+    }
+
+    #[tokio::test]
+    async fn test_task_websocket_since_seq_skips_rendered_events() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let task_id = TaskId::new();
+        for index in 1..=3 {
+            state
+                .broadcast_task_event(
+                    task_id,
+                    AgentEvent::PromptSent {
+                        prompt: index.to_string(),
+                    },
+                )
+                .await;
+        }
+
+        let mut client = warp::test::ws()
+            .path(&format!("/api/tasks/{}/stream?since_seq=1", task_id))
+            .handshake(routes(state.clone()))
+            .await
+            .expect("websocket handshake");
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            let message = tokio::time::timeout(Duration::from_secs(2), client.recv())
+                .await
+                .expect("replay")
+                .expect("websocket closed");
+            let envelope: serde_json::Value =
+                serde_json::from_str(message.to_str().expect("text frame")).expect("json");
+            received.push((envelope["seq"].clone(), envelope["event"]["prompt"].clone()));
+        }
+        assert_eq!(
+            received,
+            vec![
+                (serde_json::json!(2), serde_json::json!("2")),
+                (serde_json::json!(3), serde_json::json!("3")),
+            ]
+        );
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), client.recv())
+                .await
+                .is_err(),
+            "already rendered events must not be resent"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_task_websocket_ignores_since_seq_from_another_epoch() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let task_id = TaskId::new();
+        for index in 1..=3 {
+            state
+                .broadcast_task_event(
+                    task_id,
+                    AgentEvent::PromptSent {
+                        prompt: index.to_string(),
+                    },
+                )
+                .await;
+        }
+        let epoch = state.stream_epoch().await;
+
+        let mut current = warp::test::ws()
+            .path(&format!(
+                "/api/tasks/{}/stream?since_seq=2&epoch={}",
+                task_id, epoch
+            ))
+            .handshake(routes(state.clone()))
+            .await
+            .expect("websocket handshake");
+        let message = tokio::time::timeout(Duration::from_secs(2), current.recv())
+            .await
+            .expect("replay")
+            .expect("websocket closed");
+        let envelope: serde_json::Value =
+            serde_json::from_str(message.to_str().expect("text frame")).expect("json");
+        assert_eq!(envelope["epoch"], serde_json::json!(epoch.to_string()));
+        assert_eq!(envelope["seq"], serde_json::json!(3));
+
+        // A resume point from before a restart must not hide the new run's events 1..=2.
+        let mut stale = warp::test::ws()
+            .path(&format!(
+                "/api/tasks/{}/stream?since_seq=2&epoch={}",
+                task_id,
+                uuid::Uuid::new_v4()
+            ))
+            .handshake(routes(state.clone()))
+            .await
+            .expect("websocket handshake");
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), stale.recv())
+                .await
+                .is_err(),
+            "a stale resume point replays nothing"
+        );
+        state
+            .broadcast_task_event(
+                task_id,
+                AgentEvent::PromptSent {
+                    prompt: "4".to_string(),
+                },
+            )
+            .await;
+        let message = tokio::time::timeout(Duration::from_secs(2), stale.recv())
+            .await
+            .expect("live event")
+            .expect("websocket closed");
+        let envelope: serde_json::Value =
+            serde_json::from_str(message.to_str().expect("text frame")).expect("json");
+        assert_eq!(envelope["epoch"], serde_json::json!(epoch.to_string()));
+        assert_eq!(envelope["seq"], serde_json::json!(4));
+    }
+
+    #[tokio::test]
+    async fn test_task_websocket_rejects_malformed_id_before_upgrade() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        for path in [
+            "/api/tasks/not-a-uuid/stream",
+            "/api/tasks/not-a-uuid/terminal",
+        ] {
+            let response = warp::test::request()
+                .path(path)
+                .header("connection", "upgrade")
+                .header("upgrade", "websocket")
+                .header("sec-websocket-version", "13")
+                .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+                .reply(&routes(state.clone()))
+                .await;
+            assert_eq!(response.status(), 400, "{}", path);
+            let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!(body["error"], "Invalid task ID");
+        }
+    }
+
+    async fn connect_agent(state: &AppState) -> warp::test::WsClient {
+        let mut client = warp::test::ws()
+            .path("/agent/connect")
+            .header("x-slopcoder-password", "test-password")
+            .handshake(routes(state.clone()))
+            .await
+            .expect("agent handshake");
+        let hello = AgentEnvelope::Hello {
+            hostname: "dead-host".to_string(),
+            display_name: None,
+            installed_agents: None,
+            agent_versions: Vec::new(),
+            protocol_version: PROTOCOL_VERSION,
+            labels: HashMap::new(),
+            running_tasks: Vec::new(),
+        };
+        client
+            .send_text(serde_json::to_string(&hello).unwrap())
+            .await;
+        for _ in 0..50 {
+            if !state.list_agents().await.is_empty() {
+                return client;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("agent never registered");
+    }
+
+    #[tokio::test]
+    async fn test_agent_dropped_after_missed_heartbeats() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        state
+            .set_heartbeat_config(HeartbeatConfig {
+                interval: Duration::from_millis(20),
+                max_missed: 2,
+            })
+            .await;
+        let _client = connect_agent(&state).await;
+
+        // Never answer pings; the coordinator should give up on its own.
+        for _ in 0..100 {
+            if state.list_agents().await.is_empty() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("agent was not unregistered after missed heartbeats");
+    }
+
+    #[tokio::test]
+    async fn test_agent_kept_while_answering_heartbeats() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        state
+            .set_heartbeat_config(HeartbeatConfig {
+                interval: Duration::from_millis(20),
+                max_missed: 2,
+            })
+            .await;
+        let mut client = connect_agent(&state).await;
+
+        for _ in 0..6 {
+            let message = tokio::time::timeout(Duration::from_secs(2), client.recv())
+                .await
+                .expect("ping")
+                .expect("socket open");
+            let envelope: AgentEnvelope =
+                serde_json::from_str(message.to_str().expect("text frame")).unwrap();
+            let AgentEnvelope::Ping { nonce } = envelope else {
+                panic!("expected ping, got {:?}", envelope);
+            };
+            let pong = serde_json::to_string(&AgentEnvelope::Pong { nonce }).unwrap();
+            client.send_text(pong).await;
+        }
+        assert_eq!(state.list_agents().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_pongs_alone_do_not_make_a_timed_out_agent_busy() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        state
+            .set_heartbeat_config(HeartbeatConfig {
+                interval: Duration::from_millis(20),
+                max_missed: 2,
+            })
+            .await;
+        let mut client = connect_agent(&state).await;
+        // Answer pings but never the request.
+        let responder = tokio::spawn(async move {
+            while let Ok(message) = client.recv().await {
+                let Ok(text) = message.to_str() else {
+                    continue;
+                };
+                if let Ok(AgentEnvelope::Ping { nonce }) = serde_json::from_str(text) {
+                    let pong = serde_json::to_string(&AgentEnvelope::Pong { nonce }).unwrap();
+                    client.send_text(pong).await;
+                }
+            }
+        });
+
+        let agent = state.list_agents().await.remove(0);
+        let result = agent
+            .request_with_timeout(AgentRequest::ListTasks, Duration::from_millis(200))
+            .await;
+        assert!(
+            matches!(result, Err(StateError::AgentTimeout)),
+            "{:?}",
+            result
+        );
+        responder.abort();
+    }
+
+    #[tokio::test]
+    async fn test_agent_with_mismatched_protocol_is_rejected() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let mut client = warp::test::ws()
+            .path("/agent/connect")
+            .header("x-slopcoder-password", "test-password")
+            .handshake(routes(state.clone()))
+            .await
+            .expect("agent handshake");
+        let hello = AgentEnvelope::Hello {
+            hostname: "old-host".to_string(),
+            display_name: None,
+            installed_agents: None,
+            agent_versions: Vec::new(),
+            protocol_version: PROTOCOL_VERSION + 1,
+            labels: HashMap::new(),
+            running_tasks: Vec::new(),
+        };
+        client
+            .send_text(serde_json::to_string(&hello).unwrap())
+            .await;
+
+        let reply = tokio::time::timeout(Duration::from_secs(2), client.recv())
+            .await
+            .expect("coordinator reply");
+        if let Ok(message) = reply {
+            let (code, reason) = message.close_frame().expect("close frame");
+            assert_eq!(code, 1002);
+            assert!(reason.contains("protocol version"));
+        }
+        assert!(state.list_agents().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_task_list_names_hosts_it_could_not_list() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        state
+            .register_agent(
+                AgentIdentity {
+                    hostname: "slow".to_string(),
+                    ..Default::default()
+                },
+                tx,
+                Arc::new(Mutex::new(HashMap::new())),
+            )
+            .await;
+        state.back_off_host_list("slow").await;
+        // Its receiver is gone, so the listing request fails.
+        let (tx, _) = tokio::sync::mpsc::unbounded_channel();
+        state
+            .register_agent(
+                AgentIdentity {
+                    hostname: "gone".to_string(),
+                    ..Default::default()
+                },
+                tx,
+                Arc::new(Mutex::new(HashMap::new())),
+            )
+            .await;
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let pending = Arc::new(Mutex::new(HashMap::new()));
+        state
+            .register_agent(
+                AgentIdentity {
+                    hostname: "fast".to_string(),
+                    ..Default::default()
+                },
+                tx,
+                pending.clone(),
+            )
+            .await;
+        tokio::spawn(async move {
+            while let Some(AgentEnvelope::Request { request_id, .. }) = rx.recv().await {
+                if let Some(reply) = pending.lock().await.remove(&request_id) {
+                    let _ = reply.send(Ok(AgentResponse::Tasks { tasks: Vec::new() }));
+                }
+            }
+        });
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/tasks")
+            .reply(&routes(state))
+            .await;
+        assert_eq!(response.status(), 200);
+        let page: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(page["unlisted_hosts"], serde_json::json!(["gone", "slow"]));
+    }
+
+    #[tokio::test]
+    async fn test_busy_host_is_backed_off_like_a_silent_one() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        state
+            .register_agent(
+                AgentIdentity {
+                    hostname: "busy".to_string(),
+                    ..Default::default()
+                },
+                tx,
+                Arc::new(Mutex::new(HashMap::new())),
+            )
+            .await;
+        note_list_poll_result(&state, "busy", &Err(StateError::AgentBusy)).await;
+        assert!(state.should_skip_host_list_poll("busy").await);
+    }
+
+    #[tokio::test]
+    async fn test_backed_off_host_is_listed_as_unreachable() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        for hostname in ["fast", "slow"] {
+            state
+                .register_agent(
+                    AgentIdentity {
+                        hostname: hostname.to_string(),
+                        ..Default::default()
+                    },
+                    tx.clone(),
+                    Arc::new(Mutex::new(HashMap::new())),
+                )
+                .await;
+        }
+        state.back_off_host_list("slow").await;
+        assert!(state.should_skip_host_list_poll("slow").await);
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/hosts")
+            .reply(&routes(state))
+            .await;
+        assert_eq!(response.status(), 200);
+        let hosts: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(hosts[0]["host"], "fast");
+        assert_eq!(hosts[0]["status"], "connected");
+        assert!(hosts[0].get("backoff_until").is_none());
+        assert_eq!(hosts[1]["host"], "slow");
+        assert_eq!(hosts[1]["status"], "unreachable");
+        assert!(hosts[1]["backoff_until"].is_string());
+    }
+
+    /// Register an agent that answers every request with an empty environment list after `delay`.
+    async fn register_slow_agent(state: &AppState, hostname: &str, delay: Duration) {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let pending = Arc::new(Mutex::new(HashMap::new()));
+        state
+            .register_agent(
+                AgentIdentity {
+                    hostname: hostname.to_string(),
+                    ..Default::default()
+                },
+                tx,
+                pending.clone(),
+            )
+            .await;
+        tokio::spawn(async move {
+            while let Some(envelope) = rx.recv().await {
+                let AgentEnvelope::Request { request_id, .. } = envelope else {
+                    continue;
+                };
+                let pending = pending.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    if let Some(reply) = pending.lock().await.remove(&request_id) {
+                        let _ = reply.send(Ok(AgentResponse::Environments {
+                            environments: Vec::new(),
+                        }));
+                    }
+                });
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn test_longer_list_timeout_avoids_backing_off_slow_host() {
+        let delay = Duration::from_millis(1200);
+        for (timeout_secs, backed_off) in [(1, true), (3, false)] {
+            let state = AppState::new(None, "test-password".to_string(), timeout_secs, 200);
+            register_slow_agent(&state, "slow", delay).await;
+
+            let response = warp::test::request()
+                .method("GET")
+                .path("/api/environments")
+                .reply(&routes(state.clone()))
+                .await;
+            assert_eq!(response.status(), 200);
+            assert_eq!(
+                state.should_skip_host_list_poll("slow").await,
+                backed_off,
+                "timeout {}s",
+                timeout_secs
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_clear_backoff_makes_host_pollable_again() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        state
+            .register_agent(
+                AgentIdentity {
+                    hostname: "slow".to_string(),
+                    ..Default::default()
+                },
+                tx,
+                Arc::new(Mutex::new(HashMap::new())),
+            )
+            .await;
+        state.back_off_host_list("slow").await;
+        assert!(state.list_pollable_agents().await.is_empty());
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/hosts/slow/clear-backoff")
+            .reply(&routes(state.clone()))
+            .await;
+        assert_eq!(response.status(), 200);
+        assert!(!state.should_skip_host_list_poll("slow").await);
+        assert_eq!(state.list_pollable_agents().await.len(), 1);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/hosts/gone/clear-backoff")
+            .reply(&routes(state))
+            .await;
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_interrupt_all_requires_auth_and_reports_count() {
+        let state = AppState::new(
+            Some("ui-password".to_string()),
+            "test-password".to_string(),
+            15,
+            200,
+        );
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let pending = Arc::new(Mutex::new(HashMap::new()));
+        state
+            .register_agent(
+                AgentIdentity {
+                    hostname: "boa".to_string(),
+                    ..Default::default()
+                },
+                tx,
+                pending.clone(),
+            )
+            .await;
+        tokio::spawn(async move {
+            while let Some(envelope) = rx.recv().await {
+                if let AgentEnvelope::Request {
+                    request_id,
+                    request: AgentRequest::InterruptAll,
+                } = envelope
+                {
+                    if let Some(reply) = pending.lock().await.remove(&request_id) {
+                        let _ = reply.send(Ok(AgentResponse::InterruptedAll { interrupted: 2 }));
+                    }
+                }
+            }
+        });
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/hosts/boa/interrupt-all")
+            .reply(&routes(state.clone()))
+            .await;
+        assert_eq!(response.status(), 401);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/hosts/boa/interrupt-all")
+            .header("x-slopcoder-password", "ui-password")
+            .reply(&routes(state.clone()))
+            .await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["interrupted"], 2);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/hosts/gone/interrupt-all")
+            .header("x-slopcoder-password", "ui-password")
+            .reply(&routes(state))
+            .await;
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_environment_detail_reports_agent_info() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let pending = Arc::new(Mutex::new(HashMap::new()));
+        state
+            .register_agent(
+                AgentIdentity {
+                    hostname: "boa".to_string(),
+                    ..Default::default()
+                },
+                tx,
+                pending.clone(),
+            )
+            .await;
+        tokio::spawn(async move {
+            while let Some(envelope) = rx.recv().await {
+                let AgentEnvelope::Request {
+                    request_id,
+                    request: AgentRequest::GetEnvironmentInfo { environment },
+                } = envelope
+                else {
+                    continue;
+                };
+                let reply = if environment == "web" {
+                    Ok(AgentResponse::EnvironmentInfo {
+                        info: EnvironmentInfo {
+                            name: environment,
+                            directory: PathBuf::from("/srv/web"),
+                            current_branch: "main".to_string(),
+                            branch_count: 4,
+                            active_tasks: 1,
+                        },
+                    })
+                } else {
+                    Err(RemoteError {
+                        status: 404,
+                        error: format!("Environment '{}' not found", environment),
+                    })
+                };
+                if let Some(tx) = pending.lock().await.remove(&request_id) {
+                    let _ = tx.send(reply);
+                }
+            }
+        });
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/environments/web?host=boa")
+            .reply(&routes(state.clone()))
+            .await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["host"], "boa");
+        assert_eq!(body["directory"], "/srv/web");
+        assert_eq!(body["current_branch"], "main");
+        assert_eq!(body["branch_count"], 4);
+        assert_eq!(body["active_tasks"], 1);
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/environments/missing?host=boa")
+            .reply(&routes(state.clone()))
+            .await;
+        assert_eq!(response.status(), 404);
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/environments/web?host=gone")
+            .reply(&routes(state))
+            .await;
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_readonly_password_can_stream_but_not_mutate() {
+        let state = AppState::new(
+            Some("ui-password".to_string()),
+            "test-password".to_string(),
+            15,
+            200,
+        );
+        state
+            .set_readonly_auth_password(Some("watch-only".to_string()))
+            .await;
+        let task_id = TaskId::new();
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/tasks")
+            .header("x-slopcoder-password", "watch-only")
+            .reply(&routes(state.clone()))
+            .await;
+        assert_eq!(response.status(), 200);
+
+        warp::test::ws()
+            .path(&format!(
+                "/api/tasks/{}/stream?password=watch-only",
+                task_id
+            ))
+            .handshake(routes(state.clone()))
+            .await
+            .expect("read-only stream handshake");
+        assert!(warp::test::ws()
+            .path(&format!(
+                "/api/tasks/{}/terminal?password=watch-only",
+                task_id
+            ))
+            .handshake(routes(state.clone()))
+            .await
+            .is_err());
+
+        for (method, path) in [
+            ("POST", "/api/tasks".to_string()),
+            ("POST", format!("/api/tasks/{}/interrupt", task_id)),
+            ("DELETE", format!("/api/tasks/{}", task_id)),
+        ] {
+            let response = warp::test::request()
+                .method(method)
+                .path(&path)
+                .header("x-slopcoder-password", "watch-only")
+                .reply(&routes(state.clone()))
+                .await;
+            assert_eq!(response.status(), 403, "{} {}", method, path);
+        }
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/tasks")
+            .header("x-slopcoder-password", "wrong")
+            .reply(&routes(state.clone()))
+            .await;
+        assert_eq!(response.status(), 401);
+
+        // The read-write password still reaches the handler (no agent owns the task).
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/api/tasks/{}/interrupt", task_id))
+            .header("x-slopcoder-password", "ui-password")
+            .reply(&routes(state))
+            .await;
+        assert_ne!(response.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn test_environment_tasks_only_lists_matching_tasks_from_owning_hosts() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let task_in = |environment: &str, name: &str| {
+            Task::new(
+                AgentKind::Codex,
+                environment.to_string(),
+                name.to_string(),
+                TaskWorkspaceKind::Environment,
+                None,
+                None,
+                false,
+                PathBuf::from("/tmp"),
+            )
+        };
+        let mut list_requests = HashMap::new();
+        for (host, tasks) in [
+            (
+                "boa",
+                vec![task_in("alpha", "boa-alpha"), task_in("beta", "boa-beta")],
+            ),
+            ("cobra", vec![task_in("alpha", "cobra-alpha")]),
+        ] {
+            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+            let pending = Arc::new(Mutex::new(HashMap::new()));
+            state
+                .register_agent(
+                    AgentIdentity {
+                        hostname: host.to_string(),
+                        ..Default::default()
+                    },
+                    tx,
+                    pending.clone(),
+                )
+                .await;
+            let requests = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+            list_requests.insert(host, requests.clone());
+            tokio::spawn(async move {
+                while let Some(envelope) = rx.recv().await {
+                    if let AgentEnvelope::Request {
+                        request_id,
+                        request: AgentRequest::ListTasks,
+                    } = envelope
+                    {
+                        requests.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                        if let Some(reply) = pending.lock().await.remove(&request_id) {
+                            let _ = reply.send(Ok(AgentResponse::Tasks {
+                                tasks: tasks.clone(),
+                            }));
+                        }
+                    }
+                }
+            });
+        }
+        let names = |path: &str| {
+            let state = state.clone();
+            let path = path.to_string();
+            async move {
+                let response = warp::test::request()
+                    .method("GET")
+                    .path(&path)
+                    .reply(&routes(state))
+                    .await;
+                assert_eq!(response.status(), 200);
+                let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+                let mut names: Vec<String> = body
+                    .as_array()
+                    .expect("task array")
+                    .iter()
+                    .map(|task| task["name"].as_str().unwrap().to_string())
+                    .collect();
+                names.sort();
+                names
+            }
+        };
+        let asked = |host: &str| list_requests[host].load(std::sync::atomic::Ordering::SeqCst);
+
+        // Nothing is known about "alpha" yet, so both hosts are asked.
+        assert_eq!(
+            names("/api/environments/alpha/tasks").await,
+            vec!["boa-alpha", "cobra-alpha"]
+        );
+        assert_eq!((asked("boa"), asked("cobra")), (1, 1));
+
+        // That listing showed only boa serves "beta".
+        assert_eq!(
+            names("/api/environments/beta/tasks").await,
+            vec!["boa-beta"]
+        );
+        assert_eq!((asked("boa"), asked("cobra")), (2, 1));
+
+        assert_eq!(
+            names("/api/environments/alpha/tasks?host=cobra").await,
+            vec!["cobra-alpha"]
+        );
+        assert_eq!((asked("boa"), asked("cobra")), (2, 2));
+    }
+
+    #[tokio::test]
+    async fn test_reload_without_agents_returns_empty_list() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/reload")
+            .reply(&routes(state))
+            .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body().as_ref(), b"[]");
+    }
 
     #[test]
     fn test_extract_password() {
@@ -1,14 +1,16 @@
 //! Coordinator state for connected slopagents.
 
 use chrono::{DateTime, Utc};
+use serde::Serialize;
 use slopcoder_core::{
-    agent_rpc::{AgentEnvelope, AgentRequest, AgentResponse},
+    agent_rpc::{AgentEnvelope, AgentRequest, AgentResponse, AgentVersion, DiffSection, RequestId},
+    anyagent::AgentKind,
     task::{Task, TaskId},
     AgentEvent,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 use tokio::sync::{broadcast, mpsc, oneshot, Mutex, RwLock};
 use tokio::time::timeout;
@@ -25,12 +27,19 @@ pub enum StateError {
     #[error("No agents are connected")]
     NoAgentsConnected,
 
+    #[error("No connected host matches the host selector")]
+    NoMatchingHost,
+
     #[error("Agent disconnected")]
     AgentDisconnected,
 
     #[error("Agent request timed out")]
     AgentTimeout,
 
+    /// The request timed out but the agent kept talking meanwhile, so it is alive and slow.
+    #[error("Agent request timed out while the agent was still working")]
+    AgentBusy,
+
     #[error("Remote error ({status}): {error}")]
     RemoteError { status: u16, error: String },
 }
@@ -41,11 +50,21 @@ pub struct ConnectedAgent {
     pub host: String,
     pub hostname: String,
     pub connected_at: DateTime<Utc>,
+    /// Agent CLIs advertised in the hello; `None` for agents that did not report them.
+    pub installed_agents: Option<Vec<AgentKind>>,
+    /// Agent CLI versions advertised in the hello.
+    pub agent_versions: Vec<AgentVersion>,
+    /// Host labels advertised in the hello.
+    pub labels: HashMap<String, String>,
     outbound_tx: mpsc::UnboundedSender<AgentEnvelope>,
-    pending: Arc<Mutex<HashMap<String, oneshot::Sender<PendingResponse>>>>,
+    pending: Arc<Mutex<HashMap<RequestId, oneshot::Sender<PendingResponse>>>>,
+    /// Timeout for [`ConnectedAgent::request`], from the coordinator's default.
+    request_timeout: Duration,
+    /// When the agent last answered a request or sent a task event; pongs do not count.
+    last_seen: Arc<std::sync::Mutex<Instant>>,
 }
 
-type PendingResponse = Result<AgentResponse, RemoteError>;
+pub type PendingResponse = Result<AgentResponse, RemoteError>;
 
 #[derive(Debug, Clone)]
 pub struct RemoteError {
@@ -60,6 +79,45 @@ pub enum TerminalEvent {
     Error(String),
 }
 
+/// Message on a `GET /api/tasks/:id/diff/stream` websocket.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DiffStreamEvent {
+    Chunk { section: DiffSection, data: String },
+    End { error: Option<String> },
+}
+
+/// A task event as sent on the stream websocket, numbered per task so a reconnecting
+/// client can ask for only what it missed.
+#[derive(Debug, Clone, Serialize)]
+pub struct SequencedEvent {
+    pub seq: u64,
+    pub event: AgentEvent,
+}
+
+/// Bytes of recent terminal output kept per terminal for replay on reconnect.
+const TERMINAL_SCROLLBACK_BYTES: usize = 256 * 1024;
+
+/// Default for how long an agent request may take before the coordinator gives up on it.
+pub const DEFAULT_AGENT_REQUEST_TIMEOUT_SECS: u64 = 120;
+
+/// Default for how long a host that timed out on a list request is left out of list fan-out.
+pub const DEFAULT_LIST_HOST_BACKOFF_SECS: u64 = 30;
+
+/// Host events buffered per subscriber before it starts lagging.
+const HOST_EVENT_BUFFER: usize = 64;
+
+/// Diff chunks buffered per diff stream before a slow client is cut off.
+const DIFF_STREAM_BUFFER: usize = 32;
+
+/// Message on the `GET /api/hosts/stream` websocket.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HostEvent {
+    HostConnected { host: String, hostname: String },
+    HostDisconnected { host: String, hostname: String },
+}
+
 #[derive(Debug, Clone)]
 struct TaskTerminalBinding {
     terminal_id: Uuid,
@@ -68,27 +126,37 @@ struct TaskTerminalBinding {
 
 impl ConnectedAgent {
     pub async fn request(&self, request: AgentRequest) -> Result<AgentResponse, StateError> {
-        self.request_with_timeout(request, Duration::from_secs(120))
+        self.request_with_timeout(request, self.request_timeout)
             .await
     }
 
+    /// Record that the agent is working; called for its responses and task events.
+    pub fn mark_seen(&self) {
+        *self.last_seen.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+    }
+
+    fn seen_since(&self, instant: Instant) -> bool {
+        *self.last_seen.lock().unwrap_or_else(|e| e.into_inner()) > instant
+    }
+
     pub async fn request_with_timeout(
         &self,
         request: AgentRequest,
         timeout_duration: Duration,
     ) -> Result<AgentResponse, StateError> {
-        let request_id = Uuid::new_v4().to_string();
+        let request_id = RequestId::new();
         let (tx, rx) = oneshot::channel::<PendingResponse>();
+        let sent_at = Instant::now();
 
         {
             let mut pending = self.pending.lock().await;
-            pending.insert(request_id.clone(), tx);
+            pending.insert(request_id, tx);
         }
 
         if self
             .outbound_tx
             .send(AgentEnvelope::Request {
-                request_id: request_id.clone(),
+                request_id,
                 request,
             })
             .is_err()
@@ -103,7 +171,12 @@ impl ConnectedAgent {
             Err(_) => {
                 let mut pending = self.pending.lock().await;
                 pending.remove(&request_id);
-                return Err(StateError::AgentTimeout);
+                // Heartbeats or events during the wait mean the agent is alive, just slow.
+                return Err(if self.seen_since(sent_at) {
+                    StateError::AgentBusy
+                } else {
+                    StateError::AgentTimeout
+                });
             }
         };
 
@@ -125,6 +198,14 @@ impl ConnectedAgent {
         }
     }
 
+    /// Whether this host can run `kind`. Hosts that did not advertise capabilities accept everything.
+    pub fn supports_agent(&self, kind: AgentKind) -> bool {
+        match &self.installed_agents {
+            Some(installed) => installed.contains(&kind),
+            None => true,
+        }
+    }
+
     pub fn send_envelope(&self, envelope: AgentEnvelope) -> Result<(), StateError> {
         self.outbound_tx
             .send(envelope)
@@ -137,6 +218,52 @@ pub struct HostInfo {
     pub host: String,
     pub hostname: String,
     pub connected_at: DateTime<Utc>,
+    pub installed_agents: Option<Vec<AgentKind>>,
+    pub agent_versions: Vec<AgentVersion>,
+    pub labels: HashMap<String, String>,
+    pub status: HostStatus,
+    /// End of the host's list-poll backoff, while one is active.
+    pub backoff_until: Option<DateTime<Utc>>,
+}
+
+/// Whether a connected host is currently included in list fan-out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HostStatus {
+    Connected,
+    /// Timed out on a recent list request and is skipped until its backoff ends.
+    Unreachable,
+}
+
+/// What an agent reports about itself in its hello.
+#[derive(Debug, Clone, Default)]
+pub struct AgentIdentity {
+    pub hostname: String,
+    pub display_name: Option<String>,
+    /// `None` for agents that did not probe their CLIs.
+    pub installed_agents: Option<Vec<AgentKind>>,
+    pub agent_versions: Vec<AgentVersion>,
+    pub labels: HashMap<String, String>,
+    /// Tasks the agent reports as running; mapped to its host on registration.
+    pub running_tasks: Vec<TaskId>,
+}
+
+/// Coordinator-to-agent heartbeat settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatConfig {
+    /// Time between pings; zero disables heartbeats.
+    pub interval: Duration,
+    /// Consecutive unanswered pings tolerated before the agent is dropped.
+    pub max_missed: u32,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(15),
+            max_missed: 3,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -146,15 +273,37 @@ pub struct AppState {
 
 struct AppStateInner {
     ui_auth_password: Option<String>,
+    /// Grants read-only API access (see `check_api_auth`); only meaningful with UI auth on.
+    readonly_auth_password: Option<String>,
     agent_auth_password: String,
     list_request_timeout_secs: u64,
+    /// Zero disables list-poll backoff.
+    list_backoff_secs: u64,
+    event_buffer: usize,
+    heartbeat: HeartbeatConfig,
+    agent_request_timeout: Duration,
     agents_by_id: HashMap<Uuid, ConnectedAgent>,
     host_to_id: HashMap<String, Uuid>,
     task_hosts: HashMap<TaskId, String>,
+    /// Hosts known to serve each environment, learned from environment and task listings.
+    environment_hosts: HashMap<String, HashSet<String>>,
     task_terminals: HashMap<TaskId, TaskTerminalBinding>,
     terminal_tasks: HashMap<Uuid, TaskId>,
-    event_channels: HashMap<TaskId, broadcast::Sender<AgentEvent>>,
+    event_channels: HashMap<TaskId, broadcast::Sender<SequencedEvent>>,
+    /// Identifies this coordinator run; `seq` numbers restart with each run, so they only
+    /// mean something alongside the epoch they were handed out in.
+    stream_epoch: Uuid,
+    /// Last sequence number handed out per task.
+    event_seqs: HashMap<TaskId, u64>,
+    /// Most recent events per task (up to `event_buffer`) for `since_seq` replay.
+    event_replay: HashMap<TaskId, VecDeque<SequencedEvent>>,
     terminal_channels: HashMap<Uuid, broadcast::Sender<TerminalEvent>>,
+    terminal_scrollback: HashMap<Uuid, VecDeque<u8>>,
+    /// Open diff streams by stream id, with the host producing them.
+    diff_streams: HashMap<Uuid, (String, mpsc::Sender<DiffStreamEvent>)>,
+    host_events: broadcast::Sender<HostEvent>,
+    /// Hosts skipped by list fan-out until the given time, after a list request timed out.
+    list_backoff_until: HashMap<String, DateTime<Utc>>,
 }
 
 impl AppState {
@@ -162,19 +311,33 @@ impl AppState {
         ui_auth_password: Option<String>,
         agent_auth_password: String,
         list_request_timeout_secs: u64,
+        event_buffer: usize,
     ) -> Self {
         Self {
             inner: Arc::new(RwLock::new(AppStateInner {
                 ui_auth_password,
+                readonly_auth_password: None,
                 agent_auth_password,
                 list_request_timeout_secs,
+                list_backoff_secs: DEFAULT_LIST_HOST_BACKOFF_SECS,
+                event_buffer: event_buffer.max(1),
+                heartbeat: HeartbeatConfig::default(),
+                agent_request_timeout: Duration::from_secs(DEFAULT_AGENT_REQUEST_TIMEOUT_SECS),
                 agents_by_id: HashMap::new(),
                 host_to_id: HashMap::new(),
                 task_hosts: HashMap::new(),
+                environment_hosts: HashMap::new(),
                 task_terminals: HashMap::new(),
                 terminal_tasks: HashMap::new(),
                 event_channels: HashMap::new(),
+                stream_epoch: Uuid::new_v4(),
+                event_seqs: HashMap::new(),
+                event_replay: HashMap::new(),
                 terminal_channels: HashMap::new(),
+                terminal_scrollback: HashMap::new(),
+                diff_streams: HashMap::new(),
+                host_events: broadcast::channel(HOST_EVENT_BUFFER).0,
+                list_backoff_until: HashMap::new(),
             })),
         }
     }
@@ -183,6 +346,14 @@ impl AppState {
         self.inner.read().await.ui_auth_password.clone()
     }
 
+    pub async fn set_readonly_auth_password(&self, password: Option<String>) {
+        self.inner.write().await.readonly_auth_password = password;
+    }
+
+    pub async fn get_readonly_auth_password(&self) -> Option<String> {
+        self.inner.read().await.readonly_auth_password.clone()
+    }
+
     pub async fn get_agent_auth_password(&self) -> String {
         self.inner.read().await.agent_auth_password.clone()
     }
@@ -191,34 +362,63 @@ impl AppState {
         self.inner.read().await.list_request_timeout_secs
     }
 
+    pub async fn set_list_backoff_secs(&self, secs: u64) {
+        self.inner.write().await.list_backoff_secs = secs;
+    }
+
+    pub async fn set_heartbeat_config(&self, heartbeat: HeartbeatConfig) {
+        self.inner.write().await.heartbeat = heartbeat;
+    }
+
+    pub async fn get_heartbeat_config(&self) -> HeartbeatConfig {
+        self.inner.read().await.heartbeat
+    }
+
+    /// Default timeout for agent requests; applies to agents registered afterwards.
+    pub async fn set_agent_request_timeout(&self, timeout: Duration) {
+        self.inner.write().await.agent_request_timeout = timeout;
+    }
+
     pub async fn register_agent(
         &self,
-        hostname: String,
-        display_name: Option<String>,
+        identity: AgentIdentity,
         outbound_tx: mpsc::UnboundedSender<AgentEnvelope>,
-        pending: Arc<Mutex<HashMap<String, oneshot::Sender<PendingResponse>>>>,
+        pending: Arc<Mutex<HashMap<RequestId, oneshot::Sender<PendingResponse>>>>,
     ) -> ConnectedAgent {
         let mut inner = self.inner.write().await;
 
-        let base = display_name
+        let base = identity
+            .display_name
             .as_deref()
             .map(str::trim)
             .filter(|s| !s.is_empty())
-            .unwrap_or(&hostname)
+            .unwrap_or(&identity.hostname)
             .to_string();
         let host = unique_host_label(&base, &inner.host_to_id);
 
         let agent = ConnectedAgent {
             id: Uuid::new_v4(),
             host: host.clone(),
-            hostname,
+            hostname: identity.hostname,
             connected_at: Utc::now(),
+            installed_agents: identity.installed_agents,
+            agent_versions: identity.agent_versions,
+            labels: identity.labels,
             outbound_tx,
             pending,
+            request_timeout: inner.agent_request_timeout,
+            last_seen: Arc::new(std::sync::Mutex::new(Instant::now())),
         };
 
         inner.host_to_id.insert(host.clone(), agent.id);
         inner.agents_by_id.insert(agent.id, agent.clone());
+        for task_id in identity.running_tasks {
+            inner.task_hosts.insert(task_id, host.clone());
+        }
+        let _ = inner.host_events.send(HostEvent::HostConnected {
+            host,
+            hostname: agent.hostname.clone(),
+        });
         agent
     }
 
@@ -231,6 +431,8 @@ impl AppState {
 
         inner.host_to_id.remove(&agent.host);
         inner.task_hosts.retain(|_, host| host != &agent.host);
+        forget_environment_host(&mut inner.environment_hosts, &agent.host);
+        inner.list_backoff_until.remove(&agent.host);
         let terminal_tasks: Vec<TaskId> = inner
             .task_terminals
             .iter()
@@ -245,12 +447,21 @@ impl AppState {
         for task_id in terminal_tasks {
             if let Some(binding) = inner.task_terminals.remove(&task_id) {
                 inner.terminal_tasks.remove(&binding.terminal_id);
+                inner.terminal_scrollback.remove(&binding.terminal_id);
                 if let Some(tx) = inner.terminal_channels.remove(&binding.terminal_id) {
                     channels_to_close.push(tx);
                 }
             }
         }
+        // Dropping the senders ends any diff stream the agent can no longer finish.
+        inner
+            .diff_streams
+            .retain(|_, (host, _)| host != &agent.host);
         tracing::info!("Agent '{}' disconnected", agent.host);
+        let _ = inner.host_events.send(HostEvent::HostDisconnected {
+            host: agent.host.clone(),
+            hostname: agent.hostname.clone(),
+        });
         drop(inner);
 
         for tx in channels_to_close {
@@ -258,21 +469,126 @@ impl AppState {
         }
     }
 
+    /// Subscribe to host connect/disconnect events from now on.
+    pub async fn subscribe_host_events(&self) -> broadcast::Receiver<HostEvent> {
+        self.inner.read().await.host_events.subscribe()
+    }
+
     pub async fn list_hosts(&self) -> Vec<HostInfo> {
         let inner = self.inner.read().await;
+        let now = Utc::now();
         let mut hosts: Vec<_> = inner
             .agents_by_id
             .values()
-            .map(|agent| HostInfo {
-                host: agent.host.clone(),
-                hostname: agent.hostname.clone(),
-                connected_at: agent.connected_at,
+            .map(|agent| {
+                let backoff_until = inner
+                    .list_backoff_until
+                    .get(&agent.host)
+                    .copied()
+                    .filter(|until| *until > now);
+                HostInfo {
+                    host: agent.host.clone(),
+                    hostname: agent.hostname.clone(),
+                    connected_at: agent.connected_at,
+                    installed_agents: agent.installed_agents.clone(),
+                    agent_versions: agent.agent_versions.clone(),
+                    labels: agent.labels.clone(),
+                    status: if backoff_until.is_some() {
+                        HostStatus::Unreachable
+                    } else {
+                        HostStatus::Connected
+                    },
+                    backoff_until,
+                }
             })
             .collect();
         hosts.sort_by(|a, b| a.host.cmp(&b.host));
         hosts
     }
 
+    /// Leave `host` out of list fan-out for the configured backoff.
+    pub async fn back_off_host_list(&self, host: &str) {
+        let mut inner = self.inner.write().await;
+        if inner.list_backoff_secs == 0 {
+            return;
+        }
+        let until = Utc::now() + chrono::Duration::seconds(inner.list_backoff_secs as i64);
+        inner.list_backoff_until.insert(host.to_string(), until);
+    }
+
+    /// End `host`'s list-poll backoff so the next listing polls it again.
+    pub async fn clear_host_list_backoff(&self, host: &str) -> Result<(), StateError> {
+        let mut inner = self.inner.write().await;
+        if !inner.host_to_id.contains_key(host) {
+            return Err(StateError::HostNotConnected(host.to_string()));
+        }
+        inner.list_backoff_until.remove(host);
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub async fn should_skip_host_list_poll(&self, host: &str) -> bool {
+        self.inner
+            .read()
+            .await
+            .list_backoff_until
+            .get(host)
+            .is_some_and(|until| *until > Utc::now())
+    }
+
+    /// Connected agents that are not under list-poll backoff.
+    pub async fn list_pollable_agents(&self) -> Vec<ConnectedAgent> {
+        let inner = self.inner.read().await;
+        let now = Utc::now();
+        inner
+            .agents_by_id
+            .values()
+            .filter(|agent| {
+                inner
+                    .list_backoff_until
+                    .get(&agent.host)
+                    .is_none_or(|until| *until <= now)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Pick a host whose labels include every `selector` pair and that can run `kind`.
+    /// Among several matches the one with the fewest known tasks wins, then the host label.
+    pub async fn select_agent(
+        &self,
+        selector: &HashMap<String, String>,
+        kind: Option<AgentKind>,
+    ) -> Result<ConnectedAgent, StateError> {
+        let inner = self.inner.read().await;
+        if inner.agents_by_id.is_empty() {
+            return Err(StateError::NoAgentsConnected);
+        }
+        let task_count = |host: &str| {
+            inner
+                .task_hosts
+                .values()
+                .filter(|task_host| task_host.as_str() == host)
+                .count()
+        };
+        inner
+            .agents_by_id
+            .values()
+            .filter(|agent| {
+                selector
+                    .iter()
+                    .all(|(key, value)| agent.labels.get(key) == Some(value))
+            })
+            .filter(|agent| kind.is_none_or(|kind| agent.supports_agent(kind)))
+            .min_by(|a, b| {
+                task_count(&a.host)
+                    .cmp(&task_count(&b.host))
+                    .then_with(|| a.host.cmp(&b.host))
+            })
+            .cloned()
+            .ok_or(StateError::NoMatchingHost)
+    }
+
     pub async fn list_agents(&self) -> Vec<ConnectedAgent> {
         self.inner
             .read()
@@ -283,6 +599,34 @@ impl AppState {
             .collect()
     }
 
+    /// Notify every agent that the coordinator is stopping, then wait up to `grace`
+    /// for in-flight agent requests to resolve.
+    pub async fn shutdown_agents(&self, grace: Duration) {
+        let agents = self.list_agents().await;
+        for agent in &agents {
+            let _ = agent.send_envelope(AgentEnvelope::Shutdown);
+        }
+
+        let deadline = tokio::time::Instant::now() + grace;
+        loop {
+            let mut in_flight = 0;
+            for agent in &agents {
+                in_flight += agent.pending.lock().await.len();
+            }
+            if in_flight == 0 {
+                return;
+            }
+            if tokio::time::Instant::now() >= deadline {
+                tracing::warn!(
+                    in_flight,
+                    "Shutting down with agent requests still in flight"
+                );
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
     pub async fn get_agent_for_host(&self, host: &str) -> Option<ConnectedAgent> {
         let inner = self.inner.read().await;
         let id = inner.host_to_id.get(host)?;
@@ -293,16 +637,27 @@ impl AppState {
         self.inner.read().await.task_hosts.get(&task_id).cloned()
     }
 
-    pub async fn set_task_host(&self, task_id: TaskId, host: String) {
+    /// Map `task_id` to `host`, returning whether the mapping changed. An unchanged mapping
+    /// only takes the read lock, since agents report the host on every task event.
+    pub async fn set_task_host(&self, task_id: TaskId, host: String) -> bool {
+        if self.inner.read().await.task_hosts.get(&task_id) == Some(&host) {
+            return false;
+        }
         self.inner.write().await.task_hosts.insert(task_id, host);
+        true
     }
 
+    /// Forget a task that was deleted, archived or is no longer on its host, including its
+    /// event numbering and replay buffer.
     pub async fn clear_task_host(&self, task_id: TaskId) {
         let mut inner = self.inner.write().await;
         inner.task_hosts.remove(&task_id);
+        inner.event_seqs.remove(&task_id);
+        inner.event_replay.remove(&task_id);
         if let Some(binding) = inner.task_terminals.remove(&task_id) {
             inner.terminal_tasks.remove(&binding.terminal_id);
             inner.terminal_channels.remove(&binding.terminal_id);
+            inner.terminal_scrollback.remove(&binding.terminal_id);
         }
     }
 
@@ -310,9 +665,42 @@ impl AppState {
         let mut inner = self.inner.write().await;
         for task in tasks {
             inner.task_hosts.insert(task.id, host.to_string());
+            inner
+                .environment_hosts
+                .entry(task.environment.clone())
+                .or_default()
+                .insert(host.to_string());
         }
     }
 
+    /// Replace the environments `host` is known to serve with a fresh listing.
+    pub async fn record_environments_for_host<'a>(
+        &self,
+        host: &str,
+        environments: impl IntoIterator<Item = &'a str>,
+    ) {
+        let mut inner = self.inner.write().await;
+        forget_environment_host(&mut inner.environment_hosts, host);
+        for environment in environments {
+            inner
+                .environment_hosts
+                .entry(environment.to_string())
+                .or_default()
+                .insert(host.to_string());
+        }
+    }
+
+    /// Hosts known to serve `environment`; empty until a listing has mentioned it.
+    pub async fn hosts_for_environment(&self, environment: &str) -> HashSet<String> {
+        self.inner
+            .read()
+            .await
+            .environment_hosts
+            .get(environment)
+            .cloned()
+            .unwrap_or_default()
+    }
+
     pub async fn resolve_agent_for_task(&self, task_id: TaskId) -> Option<ConnectedAgent> {
         let host = self.get_host_for_task(task_id).await?;
         self.get_agent_for_host(&host).await
@@ -329,6 +717,7 @@ impl AppState {
         if let Some(binding) = inner.task_terminals.remove(&task_id) {
             inner.terminal_tasks.remove(&binding.terminal_id);
             inner.terminal_channels.remove(&binding.terminal_id);
+            inner.terminal_scrollback.remove(&binding.terminal_id);
         }
 
         let terminal_id = Uuid::new_v4();
@@ -357,64 +746,152 @@ impl AppState {
         let binding = inner.task_terminals.remove(&task_id)?;
         inner.terminal_tasks.remove(&binding.terminal_id);
         inner.terminal_channels.remove(&binding.terminal_id);
+        inner.terminal_scrollback.remove(&binding.terminal_id);
         Some((binding.terminal_id, binding.host))
     }
 
-    pub async fn subscribe_to_task(&self, id: TaskId) -> broadcast::Receiver<AgentEvent> {
+    pub async fn stream_epoch(&self) -> Uuid {
+        self.inner.read().await.stream_epoch
+    }
+
+    /// Subscribe to a task's events. With `since_seq`, buffered events numbered after it
+    /// are returned for replay, captured under the same lock as the subscription.
+    pub async fn subscribe_to_task(
+        &self,
+        id: TaskId,
+        since_seq: Option<u64>,
+    ) -> (Vec<SequencedEvent>, broadcast::Receiver<SequencedEvent>) {
         let mut inner = self.inner.write().await;
+        let replay = match (since_seq, inner.event_replay.get(&id)) {
+            (Some(since), Some(buffer)) => buffer
+                .iter()
+                .filter(|event| event.seq > since)
+                .cloned()
+                .collect(),
+            _ => Vec::new(),
+        };
+        let capacity = inner.event_buffer;
         let tx = inner
             .event_channels
             .entry(id)
             .or_insert_with(|| {
-                let (tx, _) = broadcast::channel(200);
+                let (tx, _) = broadcast::channel(capacity);
                 tx
             })
             .clone();
-        tx.subscribe()
+        (replay, tx.subscribe())
     }
 
     pub async fn broadcast_task_event(&self, task_id: TaskId, event: AgentEvent) {
+        // Heartbeats are stale as soon as the next one is due, so they are never replayed and
+        // reuse the latest sequence number rather than opening a gap in the replay buffer.
+        let replayable = !matches!(event, AgentEvent::Heartbeat { .. });
         let mut inner = self.inner.write().await;
+        let seq = {
+            let seq = inner.event_seqs.entry(task_id).or_default();
+            if replayable {
+                *seq += 1;
+            }
+            *seq
+        };
+        let event = SequencedEvent { seq, event };
+        let capacity = inner.event_buffer;
+        if replayable {
+            let buffer = inner.event_replay.entry(task_id).or_default();
+            buffer.push_back(event.clone());
+            while buffer.len() > capacity {
+                buffer.pop_front();
+            }
+        }
         let tx = inner
             .event_channels
             .entry(task_id)
             .or_insert_with(|| {
-                let (tx, _) = broadcast::channel(200);
+                let (tx, _) = broadcast::channel(capacity);
                 tx
             })
             .clone();
         let _ = tx.send(event);
     }
 
+    /// Register a diff stream fed by `host`; events arrive until `End` or the host disconnects.
+    pub async fn open_diff_stream(&self, host: &str) -> (Uuid, mpsc::Receiver<DiffStreamEvent>) {
+        let stream_id = Uuid::new_v4();
+        let (tx, rx) = mpsc::channel(DIFF_STREAM_BUFFER);
+        self.inner
+            .write()
+            .await
+            .diff_streams
+            .insert(stream_id, (host.to_string(), tx));
+        (stream_id, rx)
+    }
+
+    /// Forward an agent's diff envelope to the stream's websocket; `End` closes the stream.
+    /// A stream whose client has fallen `DIFF_STREAM_BUFFER` chunks behind is closed early
+    /// rather than buffered without limit.
+    pub async fn forward_diff_event(&self, stream_id: Uuid, event: DiffStreamEvent) {
+        let mut inner = self.inner.write().await;
+        let mut done = matches!(event, DiffStreamEvent::End { .. });
+        if let Some((_, tx)) = inner.diff_streams.get(&stream_id) {
+            if let Err(mpsc::error::TrySendError::Full(_)) = tx.try_send(event) {
+                tracing::warn!(%stream_id, "Diff stream client fell behind; closing the stream");
+                done = true;
+            }
+        }
+        if done {
+            inner.diff_streams.remove(&stream_id);
+        }
+    }
+
+    pub async fn close_diff_stream(&self, stream_id: Uuid) {
+        self.inner.write().await.diff_streams.remove(&stream_id);
+    }
+
+    /// Subscribe to a terminal and return its buffered scrollback, captured under the same
+    /// lock so no output is duplicated or lost between the replay and the live stream.
     pub async fn subscribe_to_terminal(
         &self,
         terminal_id: Uuid,
-    ) -> broadcast::Receiver<TerminalEvent> {
+    ) -> (Vec<u8>, broadcast::Receiver<TerminalEvent>) {
         let mut inner = self.inner.write().await;
+        let scrollback = inner
+            .terminal_scrollback
+            .get(&terminal_id)
+            .map(|buffer| buffer.iter().copied().collect())
+            .unwrap_or_default();
+        let capacity = inner.event_buffer;
         let tx = inner
             .terminal_channels
             .entry(terminal_id)
             .or_insert_with(|| {
-                let (tx, _) = broadcast::channel(200);
+                let (tx, _) = broadcast::channel(capacity);
                 tx
             })
             .clone();
-        tx.subscribe()
+        (scrollback, tx.subscribe())
     }
 
     pub async fn broadcast_terminal_event(&self, terminal_id: Uuid, event: TerminalEvent) {
         let mut inner = self.inner.write().await;
+        let capacity = inner.event_buffer;
         let tx = inner
             .terminal_channels
             .entry(terminal_id)
             .or_insert_with(|| {
-                let (tx, _) = broadcast::channel(200);
+                let (tx, _) = broadcast::channel(capacity);
                 tx
             })
             .clone();
+        if let TerminalEvent::Data(data) = &event {
+            let buffer = inner.terminal_scrollback.entry(terminal_id).or_default();
+            buffer.extend(data.iter().copied());
+            let overflow = buffer.len().saturating_sub(TERMINAL_SCROLLBACK_BYTES);
+            buffer.drain(..overflow);
+        }
         let _ = tx.send(event.clone());
         if matches!(event, TerminalEvent::Closed | TerminalEvent::Error(_)) {
             inner.terminal_channels.remove(&terminal_id);
+            inner.terminal_scrollback.remove(&terminal_id);
             if let Some(task_id) = inner.terminal_tasks.remove(&terminal_id) {
                 inner.task_terminals.remove(&task_id);
             }
@@ -435,18 +912,41 @@ fn unique_host_label(base: &str, existing: &HashMap<String, Uuid>) -> String {
     format!("{}-{}", base, Uuid::new_v4().simple())
 }
 
+fn forget_environment_host(environment_hosts: &mut HashMap<String, HashSet<String>>, host: &str) {
+    environment_hosts.retain(|_, hosts| {
+        hosts.remove(host);
+        !hosts.is_empty()
+    });
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{AppState, PendingResponse, TerminalEvent};
-    use slopcoder_core::task::TaskId;
+    use super::{
+        AgentIdentity, AppState, DiffStreamEvent, HostEvent, PendingResponse, SequencedEvent,
+        StateError, TerminalEvent, DIFF_STREAM_BUFFER, TERMINAL_SCROLLBACK_BYTES,
+    };
+    use slopcoder_core::{
+        agent_rpc::{AgentEnvelope, AgentRequest, AgentVersion, DiffSection, RequestId},
+        anyagent::AgentKind,
+        task::TaskId,
+        AgentEvent,
+    };
     use std::collections::HashMap;
     use std::sync::Arc;
     use std::time::Duration;
+    use tokio::sync::broadcast::error::RecvError;
     use tokio::sync::{oneshot, Mutex};
 
+    fn identity(hostname: &str) -> AgentIdentity {
+        AgentIdentity {
+            hostname: hostname.to_string(),
+            ..Default::default()
+        }
+    }
+
     #[tokio::test]
     async fn terminals_are_reused_for_task_and_host() {
-        let state = AppState::new(None, "test-password".to_string(), 15);
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
         let task_id = TaskId::new();
 
         let (first_id, first_created) = state.ensure_task_terminal(task_id, "boa").await;
@@ -458,7 +958,7 @@ mod tests {
 
     #[tokio::test]
     async fn terminal_binding_is_cleared_when_terminal_closes() {
-        let state = AppState::new(None, "test-password".to_string(), 15);
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
         let task_id = TaskId::new();
 
         let (terminal_id, created) = state.ensure_task_terminal(task_id, "boa").await;
@@ -478,21 +978,262 @@ mod tests {
         assert_ne!(terminal_id, next_id);
     }
 
+    #[tokio::test]
+    async fn advertised_agents_limit_supported_kinds() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let agent = state
+            .register_agent(
+                AgentIdentity {
+                    hostname: "boa-host".to_string(),
+                    installed_agents: Some(vec![AgentKind::Claude]),
+                    agent_versions: vec![AgentVersion {
+                        agent: AgentKind::Claude,
+                        version: "1.0.120".to_string(),
+                    }],
+                    ..Default::default()
+                },
+                tx.clone(),
+                Arc::new(Mutex::new(HashMap::new())),
+            )
+            .await;
+        assert!(agent.supports_agent(AgentKind::Claude));
+        assert!(!agent.supports_agent(AgentKind::Codex));
+        assert_eq!(
+            state.list_hosts().await[0].installed_agents,
+            Some(vec![AgentKind::Claude])
+        );
+        assert_eq!(
+            state.list_hosts().await[0].agent_versions[0].version,
+            "1.0.120"
+        );
+
+        let legacy = state
+            .register_agent(
+                identity("old-host"),
+                tx,
+                Arc::new(Mutex::new(HashMap::new())),
+            )
+            .await;
+        assert!(legacy.supports_agent(AgentKind::Codex));
+    }
+
+    #[tokio::test]
+    async fn repeated_task_events_write_the_task_host_once() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let task_id = TaskId::new();
+        let mut writes = 0;
+        for _ in 0..1000 {
+            if state.set_task_host(task_id, "worker".to_string()).await {
+                writes += 1;
+            }
+        }
+        assert_eq!(writes, 1);
+        assert!(state.set_task_host(task_id, "other".to_string()).await);
+        assert_eq!(
+            state.get_host_for_task(task_id).await.as_deref(),
+            Some("other")
+        );
+    }
+
+    #[tokio::test]
+    async fn reconnecting_agent_restores_running_task_hosts() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let running = TaskId::new();
+        let finished = TaskId::new();
+        let agent = state
+            .register_agent(
+                identity("worker"),
+                tx.clone(),
+                Arc::new(Mutex::new(HashMap::new())),
+            )
+            .await;
+        state.set_task_host(running, agent.host.clone()).await;
+        state.set_task_host(finished, agent.host.clone()).await;
+
+        state.unregister_agent(agent.id).await;
+        assert_eq!(state.get_host_for_task(running).await, None);
+
+        let agent = state
+            .register_agent(
+                AgentIdentity {
+                    running_tasks: vec![running],
+                    ..identity("worker")
+                },
+                tx,
+                Arc::new(Mutex::new(HashMap::new())),
+            )
+            .await;
+        assert_eq!(state.get_host_for_task(running).await, Some(agent.host));
+        assert_eq!(state.get_host_for_task(finished).await, None);
+    }
+
+    #[tokio::test]
+    async fn host_events_report_connect_and_disconnect() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let mut events = state.subscribe_host_events().await;
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let agent = state
+            .register_agent(
+                AgentIdentity {
+                    display_name: Some("builder".to_string()),
+                    ..identity("build-01.internal")
+                },
+                tx,
+                Arc::new(Mutex::new(HashMap::new())),
+            )
+            .await;
+        state.unregister_agent(agent.id).await;
+
+        assert_eq!(
+            events.recv().await.unwrap(),
+            HostEvent::HostConnected {
+                host: "builder".to_string(),
+                hostname: "build-01.internal".to_string(),
+            }
+        );
+        assert_eq!(
+            events.recv().await.unwrap(),
+            HostEvent::HostDisconnected {
+                host: "builder".to_string(),
+                hostname: "build-01.internal".to_string(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn host_selector_picks_least_loaded_matching_host() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let labeled = |hostname: &str, region: &str, gpu: bool| AgentIdentity {
+            labels: HashMap::from([
+                ("region".to_string(), region.to_string()),
+                ("gpu".to_string(), gpu.to_string()),
+            ]),
+            ..identity(hostname)
+        };
+        for (hostname, region, gpu) in [
+            ("us-a", "us", true),
+            ("us-b", "us", false),
+            ("eu-a", "eu", true),
+        ] {
+            state
+                .register_agent(
+                    labeled(hostname, region, gpu),
+                    tx.clone(),
+                    Arc::new(Mutex::new(HashMap::new())),
+                )
+                .await;
+        }
+        let selector = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+
+        let us = selector(&[("region", "us")]);
+        assert_eq!(state.select_agent(&us, None).await.unwrap().host, "us-a");
+        state.set_task_host(TaskId::new(), "us-a".to_string()).await;
+        assert_eq!(state.select_agent(&us, None).await.unwrap().host, "us-b");
+
+        let us_gpu = selector(&[("region", "us"), ("gpu", "true")]);
+        assert_eq!(
+            state.select_agent(&us_gpu, None).await.unwrap().host,
+            "us-a"
+        );
+        assert!(matches!(
+            state
+                .select_agent(&selector(&[("region", "ap")]), None)
+                .await,
+            Err(StateError::NoMatchingHost)
+        ));
+        assert_eq!(
+            state.list_hosts().await[0]
+                .labels
+                .get("region")
+                .map(String::as_str),
+            Some("eu")
+        );
+    }
+
+    #[tokio::test]
+    async fn shutdown_agents_notifies_and_gives_up_after_grace() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let pending: Arc<Mutex<HashMap<RequestId, oneshot::Sender<PendingResponse>>>> =
+            Arc::new(Mutex::new(HashMap::new()));
+        let (stuck_tx, _stuck_rx) = oneshot::channel();
+        pending.lock().await.insert(RequestId::new(), stuck_tx);
+        state
+            .register_agent(identity("boa-host"), tx, pending)
+            .await;
+
+        tokio::time::timeout(
+            Duration::from_secs(2),
+            state.shutdown_agents(Duration::from_millis(100)),
+        )
+        .await
+        .expect("shutdown should finish once the grace period expires");
+        assert!(matches!(rx.try_recv(), Ok(AgentEnvelope::Shutdown)));
+    }
+
+    #[tokio::test]
+    async fn default_request_timeout_is_configurable_and_busy_agents_are_not_dead() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        state
+            .set_agent_request_timeout(Duration::from_millis(100))
+            .await;
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let pending: Arc<Mutex<HashMap<RequestId, oneshot::Sender<PendingResponse>>>> =
+            Arc::new(Mutex::new(HashMap::new()));
+        let agent = state
+            .register_agent(identity("boa-host"), tx, pending.clone())
+            .await;
+
+        let result = tokio::time::timeout(
+            Duration::from_secs(2),
+            agent.request(AgentRequest::ListEnvironments),
+        )
+        .await
+        .expect("request should give up after the configured default");
+        assert!(matches!(result, Err(StateError::AgentTimeout)));
+        assert!(pending.lock().await.is_empty());
+
+        // Traffic while the request is outstanding marks the agent as busy, not dead.
+        let busy_agent = agent.clone();
+        let heartbeat = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            busy_agent.mark_seen();
+        });
+        let result = agent.request(AgentRequest::ListEnvironments).await;
+        heartbeat.await.expect("heartbeat task");
+        assert!(matches!(result, Err(StateError::AgentBusy)));
+    }
+
     #[tokio::test]
     async fn unregister_agent_closes_bound_terminal_sessions() {
-        let state = AppState::new(None, "test-password".to_string(), 15);
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
         let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
-        let pending: Arc<Mutex<HashMap<String, oneshot::Sender<PendingResponse>>>> =
+        let pending: Arc<Mutex<HashMap<RequestId, oneshot::Sender<PendingResponse>>>> =
             Arc::new(Mutex::new(HashMap::new()));
         let agent = state
-            .register_agent("boa-host".to_string(), Some("boa".to_string()), tx, pending)
+            .register_agent(
+                AgentIdentity {
+                    display_name: Some("boa".to_string()),
+                    ..identity("boa-host")
+                },
+                tx,
+                pending,
+            )
             .await;
 
         let task_id = TaskId::new();
         state.set_task_host(task_id, agent.host.clone()).await;
         let (terminal_id, created) = state.ensure_task_terminal(task_id, &agent.host).await;
         assert!(created);
-        let mut terminal_events = state.subscribe_to_terminal(terminal_id).await;
+        let (_, mut terminal_events) = state.subscribe_to_terminal(terminal_id).await;
 
         state.unregister_agent(agent.id).await;
 
@@ -504,4 +1245,220 @@ mod tests {
         assert!(state.get_host_for_task(task_id).await.is_none());
         assert!(state.get_task_terminal(task_id).await.is_none());
     }
+
+    #[tokio::test]
+    async fn terminal_scrollback_is_replayed_on_reconnect() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let task_id = TaskId::new();
+        let (terminal_id, _) = state.ensure_task_terminal(task_id, "boa").await;
+
+        let (initial, first_rx) = state.subscribe_to_terminal(terminal_id).await;
+        assert!(initial.is_empty());
+        state
+            .broadcast_terminal_event(terminal_id, TerminalEvent::Data(b"$ ls\r\n".to_vec()))
+            .await;
+        state
+            .broadcast_terminal_event(terminal_id, TerminalEvent::Data(b"README.md\r\n".to_vec()))
+            .await;
+        drop(first_rx);
+
+        let (backlog, mut rx) = state.subscribe_to_terminal(terminal_id).await;
+        assert_eq!(backlog, b"$ ls\r\nREADME.md\r\n".to_vec());
+
+        state
+            .broadcast_terminal_event(terminal_id, TerminalEvent::Data(b"live".to_vec()))
+            .await;
+        let received = rx.recv().await.expect("live terminal data");
+        assert!(matches!(received, TerminalEvent::Data(data) if data == b"live"));
+    }
+
+    #[tokio::test]
+    async fn terminal_scrollback_is_capped_from_the_front() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let terminal_id = uuid::Uuid::new_v4();
+
+        state
+            .broadcast_terminal_event(
+                terminal_id,
+                TerminalEvent::Data(vec![b'a'; TERMINAL_SCROLLBACK_BYTES]),
+            )
+            .await;
+        state
+            .broadcast_terminal_event(terminal_id, TerminalEvent::Data(b"tail".to_vec()))
+            .await;
+
+        let (backlog, _rx) = state.subscribe_to_terminal(terminal_id).await;
+        assert_eq!(backlog.len(), TERMINAL_SCROLLBACK_BYTES);
+        assert!(backlog.ends_with(b"tail"));
+
+        state
+            .broadcast_terminal_event(terminal_id, TerminalEvent::Closed)
+            .await;
+        let (backlog, _rx) = state.subscribe_to_terminal(terminal_id).await;
+        assert!(backlog.is_empty());
+    }
+
+    #[tokio::test]
+    async fn small_event_buffer_lags_slow_subscribers() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 2);
+        let task_id = TaskId::new();
+        let (_, mut rx) = state.subscribe_to_task(task_id, None).await;
+
+        for index in 0..5 {
+            state
+                .broadcast_task_event(
+                    task_id,
+                    AgentEvent::PromptSent {
+                        prompt: index.to_string(),
+                    },
+                )
+                .await;
+        }
+
+        assert!(matches!(rx.recv().await, Err(RecvError::Lagged(3))));
+        assert!(matches!(
+            rx.recv().await,
+            Ok(SequencedEvent { seq: 4, event: AgentEvent::PromptSent { prompt } }) if prompt == "3"
+        ));
+    }
+
+    #[tokio::test]
+    async fn heartbeats_are_streamed_but_not_replayed() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let task_id = TaskId::new();
+        let (_, mut rx) = state.subscribe_to_task(task_id, None).await;
+
+        let prompt = |text: &str| AgentEvent::PromptSent {
+            prompt: text.to_string(),
+        };
+        state.broadcast_task_event(task_id, prompt("1")).await;
+        state
+            .broadcast_task_event(task_id, AgentEvent::Heartbeat { elapsed_secs: 15 })
+            .await;
+        state.broadcast_task_event(task_id, prompt("2")).await;
+
+        let mut live = Vec::new();
+        for _ in 0..3 {
+            let SequencedEvent { seq, event } = rx.recv().await.expect("event");
+            live.push((seq, matches!(event, AgentEvent::Heartbeat { .. })));
+        }
+        assert_eq!(live, vec![(1, false), (1, true), (2, false)]);
+
+        let (replay, _rx) = state.subscribe_to_task(task_id, Some(0)).await;
+        let seqs: Vec<u64> = replay.iter().map(|event| event.seq).collect();
+        assert_eq!(seqs, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn diff_streams_end_on_end_event_or_agent_disconnect() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let pending: Arc<Mutex<HashMap<RequestId, oneshot::Sender<PendingResponse>>>> =
+            Arc::new(Mutex::new(HashMap::new()));
+        let agent = state
+            .register_agent(identity("boa-host"), tx, pending)
+            .await;
+
+        let (finished_id, mut finished) = state.open_diff_stream(&agent.host).await;
+        state
+            .forward_diff_event(
+                finished_id,
+                DiffStreamEvent::Chunk {
+                    section: DiffSection::Staged,
+                    data: "diff --git".to_string(),
+                },
+            )
+            .await;
+        state
+            .forward_diff_event(finished_id, DiffStreamEvent::End { error: None })
+            .await;
+        assert!(matches!(
+            finished.recv().await,
+            Some(DiffStreamEvent::Chunk { data, .. }) if data == "diff --git"
+        ));
+        assert!(matches!(
+            finished.recv().await,
+            Some(DiffStreamEvent::End { error: None })
+        ));
+        assert!(finished.recv().await.is_none());
+
+        let (_, mut orphaned) = state.open_diff_stream(&agent.host).await;
+        state.unregister_agent(agent.id).await;
+        assert!(orphaned.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn diff_stream_is_cut_off_when_its_client_falls_behind() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 200);
+        let (stream_id, mut events) = state.open_diff_stream("boa-host").await;
+        for index in 0..DIFF_STREAM_BUFFER + 1 {
+            state
+                .forward_diff_event(
+                    stream_id,
+                    DiffStreamEvent::Chunk {
+                        section: DiffSection::Unstaged,
+                        data: index.to_string(),
+                    },
+                )
+                .await;
+        }
+
+        let mut received = 0;
+        while let Some(event) = events.recv().await {
+            assert!(matches!(event, DiffStreamEvent::Chunk { .. }));
+            received += 1;
+        }
+        assert_eq!(received, DIFF_STREAM_BUFFER);
+    }
+
+    #[tokio::test]
+    async fn since_seq_replays_only_newer_buffered_events() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 3);
+        let task_id = TaskId::new();
+        for index in 1..=5 {
+            state
+                .broadcast_task_event(
+                    task_id,
+                    AgentEvent::PromptSent {
+                        prompt: index.to_string(),
+                    },
+                )
+                .await;
+        }
+
+        let seqs = |replay: Vec<SequencedEvent>| -> Vec<u64> {
+            replay.into_iter().map(|event| event.seq).collect()
+        };
+        let (replay, _rx) = state.subscribe_to_task(task_id, Some(3)).await;
+        assert_eq!(seqs(replay), vec![4, 5]);
+        // Older than the buffer: only what is still held comes back.
+        let (replay, _rx) = state.subscribe_to_task(task_id, Some(0)).await;
+        assert_eq!(seqs(replay), vec![3, 4, 5]);
+        let (replay, _rx) = state.subscribe_to_task(task_id, Some(5)).await;
+        assert!(replay.is_empty());
+        let (replay, _rx) = state.subscribe_to_task(task_id, None).await;
+        assert!(replay.is_empty());
+    }
+
+    #[tokio::test]
+    async fn clearing_a_task_drops_its_event_numbering_and_replay() {
+        let state = AppState::new(None, "test-password".to_string(), 15, 3);
+        let task_id = TaskId::new();
+        state
+            .broadcast_task_event(
+                task_id,
+                AgentEvent::PromptSent {
+                    prompt: "hi".to_string(),
+                },
+            )
+            .await;
+
+        state.clear_task_host(task_id).await;
+        let inner = state.inner.read().await;
+        assert!(!inner.event_seqs.contains_key(&task_id));
+        assert!(!inner.event_replay.contains_key(&task_id));
+        drop(inner);
+        let (replay, _rx) = state.subscribe_to_task(task_id, Some(0)).await;
+        assert!(replay.is_empty());
+    }
 }
@@ -1,7 +1,12 @@
 import type {
   Host,
+  HostEvent,
   Environment,
+  EnvironmentDetail,
   BranchesResponse,
+  WorktreeInfo,
+  WorktreesResponse,
+  PruneWorktreesResponse,
   Task,
   CreateTaskRequest,
   CreateTaskResponse,
@@ -11,6 +16,24 @@ import type {
   AgentEvent,
   TaskOutputResponse,
   TaskDiffResponse,
+  TaskCommitsResponse,
+  CherryPickResponse,
+  WorktreeFileResponse,
+  TaskListFilters,
+  TaskListResponse,
+  ReloadHostResult,
+  ArchivedTask,
+  ArchivesResponse,
+  ArchivedOutputResponse,
+  TaskDiskUsage,
+  TaskSummary,
+  TaskLogsResponse,
+  TaskBundle,
+  EnvironmentDiskUsage,
+  DeleteEnvironmentTasksResponse,
+  TaskStreamMessage,
+  TaskStreamResume,
+  DiffStreamMessage,
 } from "../types";
 
 // Use relative URLs so the app works from any host
@@ -76,6 +99,25 @@ async function fetchJson<T>(url: string, options?: RequestInit, retry = true): P
   return response.json();
 }
 
+async function fetchText(url: string, retry = true): Promise<string> {
+  const response = await fetch(`${API_BASE}${url}`, { headers: buildHeaders() });
+
+  if (response.status === 401 && retry) {
+    clearStoredPassword();
+    const password = promptForPassword();
+    if (password) {
+      return fetchText(url, false);
+    }
+  }
+
+  if (!response.ok) {
+    const error = await response.json().catch(() => ({ error: "Unknown error" }));
+    throw new Error(error.error || `HTTP ${response.status}`);
+  }
+
+  return response.text();
+}
+
 // Environment endpoints
 export async function listEnvironments(): Promise<Environment[]> {
   return fetchJson("/api/environments");
@@ -85,6 +127,33 @@ export async function listHosts(): Promise<Host[]> {
   return fetchJson("/api/hosts");
 }
 
+export async function clearHostBackoff(host: string): Promise<void> {
+  await fetchJson(`/api/hosts/${encodeURIComponent(host)}/clear-backoff`, { method: "POST" });
+}
+
+export async function interruptAllOnHost(host: string): Promise<{ interrupted: number }> {
+  return fetchJson(`/api/hosts/${encodeURIComponent(host)}/interrupt-all`, { method: "POST" });
+}
+
+export function subscribeToHostEvents(onEvent: (event: HostEvent) => void): () => void {
+  const protocol = window.location.protocol === "https:" ? "wss:" : "ws:";
+  const wsUrl = `${protocol}//${window.location.host}`;
+  const query = cachedPassword
+    ? `?password=${encodeURIComponent(cachedPassword)}`
+    : "";
+  const ws = new WebSocket(`${wsUrl}/api/hosts/stream${query}`);
+
+  ws.onmessage = (event) => {
+    try {
+      onEvent(JSON.parse(event.data) as HostEvent);
+    } catch (e) {
+      console.error("Failed to parse host event:", e);
+    }
+  };
+
+  return () => ws.close();
+}
+
 export async function createEnvironment(req: CreateEnvironmentRequest): Promise<Environment> {
   return fetchJson("/api/environments", {
     method: "POST",
@@ -92,6 +161,15 @@ export async function createEnvironment(req: CreateEnvironmentRequest): Promise<
   });
 }
 
+export async function reloadEnvironments(): Promise<ReloadHostResult[]> {
+  return fetchJson("/api/reload", { method: "POST" });
+}
+
+export async function getEnvironment(envName: string, host?: string): Promise<EnvironmentDetail> {
+  const query = host ? `?host=${encodeURIComponent(host)}` : "";
+  return fetchJson(`/api/environments/${encodeURIComponent(envName)}${query}`);
+}
+
 export async function listBranches(envName: string, host?: string): Promise<string[]> {
   const query = host ? `?host=${encodeURIComponent(host)}` : "";
   const data = await fetchJson<BranchesResponse>(
@@ -100,17 +178,127 @@ export async function listBranches(envName: string, host?: string): Promise<stri
   return data.branches;
 }
 
+export async function listWorktrees(envName: string, host?: string): Promise<WorktreeInfo[]> {
+  const query = host ? `?host=${encodeURIComponent(host)}` : "";
+  const data = await fetchJson<WorktreesResponse>(
+    `/api/environments/${encodeURIComponent(envName)}/worktrees${query}`
+  );
+  return data.worktrees;
+}
+
+export async function pruneWorktrees(
+  envName: string,
+  options: { host?: string; force?: boolean } = {}
+): Promise<PruneWorktreesResponse> {
+  const params = new URLSearchParams();
+  if (options.host) params.set("host", options.host);
+  if (options.force) params.set("force", "true");
+  const query = params.toString();
+  return fetchJson(
+    `/api/environments/${encodeURIComponent(envName)}/prune-worktrees${query ? `?${query}` : ""}`,
+    { method: "POST" }
+  );
+}
+
+export async function listArchives(envName: string, host?: string): Promise<ArchivedTask[]> {
+  const query = host ? `?host=${encodeURIComponent(host)}` : "";
+  const data = await fetchJson<ArchivesResponse>(
+    `/api/environments/${encodeURIComponent(envName)}/archives${query}`
+  );
+  return data.archives;
+}
+
+export async function getEnvironmentDiskUsage(
+  envName: string,
+  host?: string
+): Promise<EnvironmentDiskUsage> {
+  const params = new URLSearchParams();
+  if (host) {
+    params.set("host", host);
+  }
+  const query = params.size > 0 ? `?${params.toString()}` : "";
+  return fetchJson(`/api/environments/${encodeURIComponent(envName)}/disk-usage${query}`);
+}
+
+export async function deleteCompletedTasks(
+  envName: string,
+  options: { host?: string; force?: boolean } = {}
+): Promise<DeleteEnvironmentTasksResponse> {
+  const params = new URLSearchParams({ completed: "true" });
+  if (options.host) {
+    params.set("host", options.host);
+  }
+  if (options.force) {
+    params.set("force", "true");
+  }
+  return fetchJson(`/api/environments/${encodeURIComponent(envName)}/tasks?${params}`, {
+    method: "DELETE",
+  });
+}
+
+export async function getArchivedOutput(
+  envName: string,
+  taskId: string,
+  host?: string
+): Promise<AgentEvent[]> {
+  const query = host ? `?host=${encodeURIComponent(host)}` : "";
+  const data = await fetchJson<ArchivedOutputResponse>(
+    `/api/environments/${encodeURIComponent(envName)}/archives/${taskId}${query}`
+  );
+  return data.events;
+}
+
+export async function restoreArchivedTask(
+  envName: string,
+  taskId: string,
+  host?: string
+): Promise<Task> {
+  const query = host ? `?host=${encodeURIComponent(host)}` : "";
+  return fetchJson<Task>(
+    `/api/environments/${encodeURIComponent(envName)}/archives/${taskId}/restore${query}`,
+    { method: "POST" }
+  );
+}
+
 // Task endpoints
-export async function listTasks(): Promise<Task[]> {
-  return fetchJson("/api/tasks");
+export async function listTaskPage(filters: TaskListFilters = {}): Promise<TaskListResponse> {
+  const params = new URLSearchParams();
+  for (const [key, value] of Object.entries(filters)) {
+    if (value !== undefined && value !== "") {
+      params.set(key, String(value));
+    }
+  }
+  const query = params.toString();
+  return fetchJson(`/api/tasks${query ? `?${query}` : ""}`);
+}
+
+export async function listTasks(filters: TaskListFilters = {}): Promise<Task[]> {
+  const page = await listTaskPage(filters);
+  return page.tasks;
+}
+
+export async function listEnvironmentTasks(name: string, host?: string): Promise<Task[]> {
+  const query = host ? `?host=${encodeURIComponent(host)}` : "";
+  return fetchJson(`/api/environments/${encodeURIComponent(name)}/tasks${query}`);
+}
+
+export async function setTaskTags(id: string, tags: string[]): Promise<Task> {
+  return fetchJson(`/api/tasks/${id}/tags`, {
+    method: "PUT",
+    body: JSON.stringify({ tags }),
+  });
 }
 
 export async function getTask(id: string): Promise<Task> {
   return fetchJson(`/api/tasks/${id}`);
 }
 
-export async function createTask(req: CreateTaskRequest): Promise<CreateTaskResponse> {
-  return fetchJson("/api/tasks", {
+export async function createTask(
+  req: CreateTaskRequest,
+  options?: { dryRun?: boolean }
+): Promise<CreateTaskResponse> {
+  const query = options?.dryRun ? "?dry_run=true" : "";
+  return fetchJson(`/api/tasks${query}`, {
     method: "POST",
     body: JSON.stringify(req),
   });
@@ -130,6 +318,28 @@ export async function sendPrompt(taskId: string, req: SendPromptRequest): Promis
   });
 }
 
+export async function cancelQueuedPrompt(
+  taskId: string,
+  index: number
+): Promise<{ queued: number }> {
+  return fetchJson(`/api/tasks/${taskId}/queue/${index}`, { method: "DELETE" });
+}
+
+export async function cancelAllQueued(taskId: string): Promise<{ queued: number }> {
+  return fetchJson(`/api/tasks/${taskId}/queue`, { method: "DELETE" });
+}
+
+export async function respondToApproval(
+  taskId: string,
+  callId: string,
+  approve: boolean
+): Promise<{ status: "delivered" | "unsupported" }> {
+  return fetchJson(`/api/tasks/${taskId}/approvals/${encodeURIComponent(callId)}`, {
+    method: "POST",
+    body: JSON.stringify({ approve }),
+  });
+}
+
 export async function getTaskOutput(
   taskId: string,
   options?: { before?: number; limit?: number }
@@ -145,19 +355,96 @@ export async function getTaskOutput(
   return fetchJson(`/api/tasks/${taskId}/output${query}`);
 }
 
-export async function getTaskDiff(taskId: string): Promise<TaskDiffResponse> {
-  return fetchJson(`/api/tasks/${taskId}/diff`);
+export async function getTaskDiskUsage(taskId: string): Promise<TaskDiskUsage> {
+  return fetchJson(`/api/tasks/${taskId}/disk-usage`);
+}
+
+export async function getTaskSummary(taskId: string): Promise<TaskSummary> {
+  return fetchJson(`/api/tasks/${taskId}/summary`);
+}
+
+export async function getTaskLogs(taskId: string, limit?: number): Promise<TaskLogsResponse> {
+  const query = limit === undefined ? "" : `?limit=${limit}`;
+  return fetchJson(`/api/tasks/${taskId}/logs${query}`);
+}
+
+export async function getTaskBundle(taskId: string): Promise<TaskBundle> {
+  return fetchJson(`/api/tasks/${taskId}/bundle`);
+}
+
+export async function exportTaskMarkdown(taskId: string): Promise<string> {
+  return fetchText(`/api/tasks/${taskId}/export?format=markdown`);
+}
+
+export async function getTaskDiff(
+  taskId: string,
+  maxDiffBytes?: number
+): Promise<TaskDiffResponse> {
+  const query = maxDiffBytes ? `?max_diff_bytes=${maxDiffBytes}` : "";
+  return fetchJson(`/api/tasks/${taskId}/diff${query}`);
 }
 
-export async function mergeTask(taskId: string): Promise<{ status: string; message: string }> {
-  return fetchJson(`/api/tasks/${taskId}/merge`, {
+export async function getTaskCommits(
+  taskId: string,
+  limit?: number
+): Promise<TaskCommitsResponse> {
+  const query = limit ? `?limit=${limit}` : "";
+  return fetchJson(`/api/tasks/${taskId}/commits${query}`);
+}
+
+export async function cherryPickTask(
+  taskId: string,
+  target: string,
+  commits: string[]
+): Promise<CherryPickResponse> {
+  return fetchJson(`/api/tasks/${taskId}/cherry-pick`, {
+    method: "POST",
+    body: JSON.stringify({ target, commits }),
+  });
+}
+
+export async function revertMerge(
+  taskId: string
+): Promise<{ sha: string; message: string }> {
+  return fetchJson(`/api/tasks/${taskId}/revert`, {
+    method: "POST",
+  });
+}
+
+export async function getWorktreeFile(
+  taskId: string,
+  path: string
+): Promise<WorktreeFileResponse> {
+  return fetchJson(`/api/tasks/${taskId}/file?path=${encodeURIComponent(path)}`);
+}
+
+export async function getTerminalRecording(
+  taskId: string
+): Promise<{ path: string; content: string }> {
+  return fetchJson(`/api/tasks/${taskId}/terminal/recording`);
+}
+
+export async function mergeTask(
+  taskId: string,
+  target?: string,
+  options: { skipChecks?: boolean } = {}
+): Promise<{ status: string; message: string }> {
+  const params = new URLSearchParams();
+  if (target) {
+    params.set("target", target);
+  }
+  if (options.skipChecks) {
+    params.set("skip_checks", "true");
+  }
+  const query = params.toString() ? `?${params}` : "";
+  return fetchJson(`/api/tasks/${taskId}/merge${query}`, {
     method: "POST",
   });
 }
 
 export async function getMergeStatus(
   taskId: string
-): Promise<{ can_merge: boolean; reason: string | null }> {
+): Promise<{ can_merge: boolean; reason: string | null; conflicts: string[]; dirty_files: string[] }> {
   return fetchJson(`/api/tasks/${taskId}/merge-status`);
 }
 
@@ -169,31 +456,105 @@ export async function archiveTask(taskId: string): Promise<{ status: string; mes
 
 export async function deleteTask(
   taskId: string,
-  force = false
+  force = false,
+  keepBranch = false
 ): Promise<{ status: string; message: string }> {
-  const query = force ? "?force=true" : "";
+  const params = new URLSearchParams();
+  if (force) params.set("force", "true");
+  if (keepBranch) params.set("keep_branch", "true");
+  const query = params.toString() ? `?${params}` : "";
   return fetchJson(`/api/tasks/${taskId}${query}`, {
     method: "DELETE",
   });
 }
 
+export async function resetTask(
+  taskId: string,
+  keepUntracked = false
+): Promise<{ status: string; files_reset: number }> {
+  const query = keepUntracked ? "?keep_untracked=true" : "";
+  return fetchJson(`/api/tasks/${taskId}/reset${query}`, {
+    method: "POST",
+  });
+}
+
+export async function moveTaskWorktree(
+  taskId: string,
+  newPath: string
+): Promise<{ worktree_path: string }> {
+  return fetchJson(`/api/tasks/${taskId}/move`, {
+    method: "POST",
+    body: JSON.stringify({ new_path: newPath }),
+  });
+}
+
+export async function applyPatch(taskId: string, diff: string): Promise<{ status: string }> {
+  return fetchJson(`/api/tasks/${taskId}/apply-patch`, {
+    method: "POST",
+    body: JSON.stringify({ diff }),
+  });
+}
+
+export async function commitTask(
+  taskId: string,
+  message: string,
+  addAll = true
+): Promise<{ status: "committed" | "nothing_to_commit"; sha: string | null }> {
+  return fetchJson(`/api/tasks/${taskId}/commit`, {
+    method: "POST",
+    body: JSON.stringify({ message, add_all: addAll }),
+  });
+}
+
+export async function stashTaskChanges(
+  taskId: string
+): Promise<{ status: "stashed" | "nothing_to_stash"; message: string }> {
+  return fetchJson(`/api/tasks/${taskId}/stash`, { method: "POST" });
+}
+
+export async function unstashTaskChanges(
+  taskId: string
+): Promise<{ status: "unstashed"; message: string }> {
+  return fetchJson(`/api/tasks/${taskId}/unstash`, { method: "POST" });
+}
+
 // WebSocket for streaming events
+// Pass the last position seen as `resume` when reconnecting to get only newer events. If the
+// coordinator restarted since then, nothing is replayed and `onResync` is called so the caller
+// can reload the task's history.
 export function subscribeToTask(
   taskId: string,
-  onEvent: (event: AgentEvent) => void,
-  onClose?: () => void
+  onEvent: (event: AgentEvent, position: TaskStreamResume) => void,
+  onClose?: () => void,
+  resume?: TaskStreamResume,
+  onResync?: () => void
 ): () => void {
   // Build WebSocket URL from current location
   const protocol = window.location.protocol === "https:" ? "wss:" : "ws:";
   const wsUrl = `${protocol}//${window.location.host}`;
-  const passwordQuery = cachedPassword ? `?password=${encodeURIComponent(cachedPassword)}` : "";
-  const ws = new WebSocket(`${wsUrl}/api/tasks/${taskId}/stream${passwordQuery}`);
+  const params = new URLSearchParams();
+  if (cachedPassword) {
+    params.set("password", cachedPassword);
+  }
+  if (resume) {
+    params.set("since_seq", String(resume.seq));
+    params.set("epoch", resume.epoch);
+  }
+  const query = params.toString() ? `?${params.toString()}` : "";
+  const ws = new WebSocket(`${wsUrl}/api/tasks/${taskId}/stream${query}`);
   let closedByClient = false;
+  let epochChecked = !resume;
 
   ws.onmessage = (event) => {
     try {
-      const data = JSON.parse(event.data) as AgentEvent;
-      onEvent(data);
+      const data = JSON.parse(event.data) as TaskStreamMessage;
+      if (!epochChecked) {
+        epochChecked = true;
+        if (data.epoch !== resume?.epoch) {
+          onResync?.();
+        }
+      }
+      onEvent(data.event, { epoch: data.epoch, seq: data.seq });
     } catch (e) {
       console.error("Failed to parse event:", e);
     }
@@ -217,6 +578,46 @@ export function subscribeToTask(
   };
 }
 
+export function streamTaskDiff(
+  taskId: string,
+  onChunk: (section: "staged" | "unstaged", data: string) => void,
+  onEnd: (error: string | null) => void
+): () => void {
+  const protocol = window.location.protocol === "https:" ? "wss:" : "ws:";
+  const wsUrl = `${protocol}//${window.location.host}`;
+  const query = cachedPassword
+    ? `?password=${encodeURIComponent(cachedPassword)}`
+    : "";
+  const ws = new WebSocket(`${wsUrl}/api/tasks/${taskId}/diff/stream${query}`);
+  let ended = false;
+
+  ws.onmessage = (event) => {
+    try {
+      const data = JSON.parse(event.data) as DiffStreamMessage;
+      if (data.type === "chunk") {
+        onChunk(data.section, data.data);
+      } else {
+        ended = true;
+        onEnd(data.error ?? null);
+      }
+    } catch (e) {
+      console.error("Failed to parse diff chunk:", e);
+    }
+  };
+
+  ws.onclose = () => {
+    if (!ended) {
+      ended = true;
+      onEnd("Diff stream closed before it finished");
+    }
+  };
+
+  return () => {
+    ended = true;
+    ws.close();
+  };
+}
+
 export interface TerminalSession {
   sendInput: (data: Uint8Array) => void;
   resize: (rows: number, cols: number) => void;
@@ -226,12 +627,21 @@ export interface TerminalSession {
 export function subscribeToTerminal(
   taskId: string,
   onData: (data: Uint8Array) => void,
-  onClose?: () => void
+  onClose?: () => void,
+  initialSize?: { rows: number; cols: number }
 ): TerminalSession {
   const protocol = window.location.protocol === "https:" ? "wss:" : "ws:";
   const wsUrl = `${protocol}//${window.location.host}`;
-  const passwordQuery = cachedPassword ? `?password=${encodeURIComponent(cachedPassword)}` : "";
-  const ws = new WebSocket(`${wsUrl}/api/tasks/${taskId}/terminal${passwordQuery}`);
+  const params = new URLSearchParams();
+  if (cachedPassword) {
+    params.set("password", cachedPassword);
+  }
+  if (initialSize) {
+    params.set("rows", String(initialSize.rows));
+    params.set("cols", String(initialSize.cols));
+  }
+  const query = params.toString();
+  const ws = new WebSocket(`${wsUrl}/api/tasks/${taskId}/terminal${query ? `?${query}` : ""}`);
   ws.binaryType = "arraybuffer";
   let closedByClient = false;
 
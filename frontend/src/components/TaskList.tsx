@@ -42,7 +42,7 @@ function TaskCard(props: { task: Task }) {
 }
 
 export default function TaskList() {
-  const [tasks, { refetch }] = createResource(listTasks);
+  const [tasks, { refetch }] = createResource(() => listTasks());
 
   // Refetch every 5 seconds if any task is running
   setInterval(() => {
@@ -41,7 +41,8 @@ export function TerminalPane(props: { taskId: string }) {
       () => {
         setConnected(false);
         terminal.writeln("\r\n[terminal disconnected]");
-      }
+      },
+      { rows: terminal.rows, cols: terminal.cols }
     );
 
     setConnected(true);
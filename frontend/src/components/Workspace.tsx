@@ -12,7 +12,7 @@ import {
   listHosts,
   listEnvironments,
   createEnvironment,
-  listTasks,
+  listTaskPage,
   createTask,
   getTask,
   getTaskOutput,
@@ -24,6 +24,7 @@ import {
   getMergeStatus,
   archiveTask,
   deleteTask,
+  respondToApproval,
 } from "../api/client";
 import {
   agentSupportsWebSearch,
@@ -33,6 +34,7 @@ import {
   type Environment,
   type Host,
   type Task,
+  type TaskStreamResume,
 } from "../types";
 import { DiffViewer } from "./DiffViewer";
 import { TerminalPane } from "./TerminalPane";
@@ -198,7 +200,79 @@ function EditableTaskName(props: {
   );
 }
 
-function EventRow(props: { event: AgentEvent }) {
+function ApprovalRow(props: {
+  taskId: string;
+  callId: string;
+  tool: string;
+  arguments?: string | null;
+  running: boolean;
+}) {
+  const [answer, setAnswer] = createSignal<"approved" | "denied" | null>(null);
+  const [sending, setSending] = createSignal(false);
+  const [error, setError] = createSignal("");
+
+  const respond = async (approve: boolean) => {
+    setSending(true);
+    setError("");
+    try {
+      const result = await respondToApproval(props.taskId, props.callId, approve);
+      if (result.status === "delivered") {
+        setAnswer(approve ? "approved" : "denied");
+      } else {
+        setError("This agent does not accept approval answers");
+      }
+    } catch (err) {
+      setError(err instanceof Error ? err.message : "Failed to answer approval");
+    } finally {
+      setSending(false);
+    }
+  };
+
+  return (
+    <div class="min-w-0 rounded-lg border border-amber-200 dark:border-amber-800 bg-amber-50 dark:bg-amber-950/30 px-3 py-2">
+      <div class="text-xs uppercase tracking-wide text-amber-700 dark:text-amber-300">
+        Approval requested: <span class="font-mono normal-case">{props.tool}</span>
+      </div>
+      <Show when={props.arguments}>
+        <pre class="mt-1 text-xs text-gray-900 dark:text-gray-100 whitespace-pre-wrap break-all">{props.arguments}</pre>
+      </Show>
+      <Show
+        when={answer() === null && props.running}
+        fallback={
+          <Show when={answer()}>
+            <div class="mt-1 text-xs text-gray-500 dark:text-gray-400">
+              {answer() === "approved" ? "Approved" : "Denied"}
+            </div>
+          </Show>
+        }
+      >
+        <div class="mt-2 flex gap-2">
+          <button
+            type="button"
+            class="px-3 py-1 text-xs rounded bg-green-600 text-white hover:bg-green-700 disabled:opacity-50"
+            disabled={sending()}
+            onClick={() => void respond(true)}
+          >
+            Approve
+          </button>
+          <button
+            type="button"
+            class="px-3 py-1 text-xs rounded bg-red-600 text-white hover:bg-red-700 disabled:opacity-50"
+            disabled={sending()}
+            onClick={() => void respond(false)}
+          >
+            Deny
+          </button>
+        </div>
+      </Show>
+      <Show when={error()}>
+        <div class="mt-1 text-xs text-red-600 dark:text-red-400">{error()}</div>
+      </Show>
+    </div>
+  );
+}
+
+function EventRow(props: { event: AgentEvent; taskId: string; running: boolean }) {
   const e = props.event;
   if (e.type === "prompt.sent") {
     return (
@@ -209,6 +283,27 @@ function EventRow(props: { event: AgentEvent }) {
     );
   }
 
+  if (e.type === "agent.error") {
+    return (
+      <div class="min-w-0 rounded-lg border border-red-200 dark:border-red-800 bg-red-50 dark:bg-red-950/30 px-3 py-2">
+        <div class="text-xs uppercase tracking-wide text-red-700 dark:text-red-300">Agent error</div>
+        <div class="text-sm text-gray-900 dark:text-gray-100 whitespace-pre-wrap">{e.message}</div>
+      </div>
+    );
+  }
+
+  if (e.type === "approval.requested") {
+    return (
+      <ApprovalRow
+        taskId={props.taskId}
+        callId={e.call_id}
+        tool={e.tool}
+        arguments={e.arguments}
+        running={props.running}
+      />
+    );
+  }
+
   if (e.type === "turn.started") {
     return <div class="text-xs text-blue-600 dark:text-blue-400">Turn started</div>;
   }
@@ -217,6 +312,16 @@ function EventRow(props: { event: AgentEvent }) {
     return <div class="text-xs text-gray-500 dark:text-gray-400">Session started</div>;
   }
 
+  if (e.type === "file.edited") {
+    return (
+      <div class="min-w-0 text-xs text-gray-500 dark:text-gray-400">
+        Edited <span class="font-mono break-all text-gray-700 dark:text-gray-300">{e.path}</span>{" "}
+        <span class="text-green-600 dark:text-green-400">+{e.added}</span>{" "}
+        <span class="text-red-600 dark:text-red-400">-{e.removed}</span>
+      </div>
+    );
+  }
+
   if (e.type === "turn.completed") {
     return (
       <div class="text-xs text-gray-500 dark:text-gray-400">
@@ -231,6 +336,30 @@ function EventRow(props: { event: AgentEvent }) {
     );
   }
 
+  if (e.type === "background_event" && e.event === "events_dropped") {
+    return (
+      <div class="text-xs text-amber-600 dark:text-amber-400">
+        Live view skipped {e.dropped ?? "some"} events; reload the task to see the full transcript.
+      </div>
+    );
+  }
+
+  if (e.type === "background_event" && e.event === "rate_limited") {
+    return (
+      <div class="text-xs text-gray-500 dark:text-gray-400">
+        Waiting {Math.ceil((e.wait_ms ?? 0) / 1000)}s for the host's agent start rate limit
+      </div>
+    );
+  }
+
+  if (e.type === "background_event" && e.event === "output_rotated") {
+    return (
+      <div class="text-xs text-gray-500 dark:text-gray-400">
+        Output log rotated after {Math.round((e.rotated_bytes ?? 0) / 1024)} KiB
+      </div>
+    );
+  }
+
   if (e.type === "background_event") {
     return <div class="text-xs text-gray-500 dark:text-gray-400">Background: {e.event ?? "event"}</div>;
   }
@@ -292,6 +421,17 @@ function CompletedItemRow(props: { item: CompletedItem }) {
     );
   }
 
+  if (item.type === "file_change") {
+    return (
+      <div class="min-w-0 rounded-lg border border-gray-200 dark:border-gray-700 bg-gray-50 dark:bg-gray-900 px-3 py-2">
+        <div class="text-xs uppercase tracking-wide text-gray-500 dark:text-gray-400">File changes</div>
+        <pre class="mt-1 text-xs whitespace-pre-wrap overflow-x-auto text-gray-700 dark:text-gray-200">
+          {item.output ?? (item.changes ?? []).map((change) => `${change.kind} ${change.path}`).join("\n")}
+        </pre>
+      </div>
+    );
+  }
+
   if (item.type === "command_execution") {
     const preview = formatCommandExecutionPreview(item);
     return (
@@ -380,6 +520,8 @@ function TaskPane(props: {
 
   let outputRef: HTMLDivElement | undefined;
   let promptRef: HTMLTextAreaElement | undefined;
+  // Last task stream position seen, so a resubscription only receives newer events.
+  let streamResume: TaskStreamResume | undefined;
   const scrollOutputToBottom = () => {
     if (outputRef) {
       outputRef.scrollTop = outputRef.scrollHeight;
@@ -416,6 +558,7 @@ function TaskPane(props: {
 
   createEffect(() => {
     const taskId = props.taskId;
+    streamResume = undefined;
     setPrompt(getTaskMessageDraft(taskId));
     setDraftHydratedTaskId(taskId);
     setPersistedEvents([]);
@@ -476,7 +619,8 @@ function TaskPane(props: {
     if (status === "running") {
       const unsubscribe = subscribeToTask(
         props.taskId,
-        (event) => {
+        (event, position) => {
+          streamResume = position;
           setLiveEvents((prev) => [...prev, event]);
           requestAnimationFrame(scrollOutputToBottom);
         },
@@ -487,6 +631,12 @@ function TaskPane(props: {
             setTimeout(() => refetchDiff(), 300);
           }
           setLiveEvents([]);
+        },
+        streamResume,
+        () => {
+          // The coordinator restarted, so the old position is meaningless; reload history.
+          setLiveEvents([]);
+          refetchOutput();
         }
       );
       onCleanup(unsubscribe);
@@ -668,6 +818,11 @@ function TaskPane(props: {
         <Show when={taskData()!.workspace_kind === "worktree" && mergeReady()?.reason && !mergeReady()?.can_merge}>
           <div class="mb-3 rounded-lg border border-amber-300 dark:border-amber-700 bg-amber-100 dark:bg-amber-900/40 p-3 text-sm text-amber-800 dark:text-amber-200">
             {mergeReady()!.reason}
+            <Show when={(mergeReady()!.dirty_files ?? []).length > 0}>
+              <ul class="mt-1 list-disc pl-5 font-mono text-xs">
+                <For each={mergeReady()!.dirty_files}>{(file) => <li>{file}</li>}</For>
+              </ul>
+            </Show>
           </div>
         </Show>
 
@@ -780,7 +935,15 @@ function TaskPane(props: {
                     : `Scroll up to load older messages (${persistedEvents().length}/${persistedTotalEvents()} loaded)`}
                 </div>
               </Show>
-              <For each={allEvents()}>{(event) => <EventRow event={event} />}</For>
+              <For each={allEvents()}>
+                {(event) => (
+                  <EventRow
+                    event={event}
+                    taskId={props.taskId}
+                    running={taskData()!.status === "running"}
+                  />
+                )}
+              </For>
               <Show when={taskData()!.status === "running" && allEvents().length === 0}>
                 <div class="text-gray-500 dark:text-gray-400 animate-pulse">Waiting for output...</div>
               </Show>
@@ -988,6 +1151,7 @@ function NewEnvironmentPane(props: {
 }) {
   const [host, setHost] = createSignal("");
   const [name, setName] = createSignal("");
+  const [cloneUrl, setCloneUrl] = createSignal("");
   const [agent, setAgent] = createSignal<AgentKind>("codex");
   const [prompt, setPrompt] = createSignal("");
   const [useWorktree, setUseWorktree] = createSignal(false);
@@ -1011,6 +1175,7 @@ function NewEnvironmentPane(props: {
       const env = await createEnvironment({
         host: host().trim(),
         name: name().trim(),
+        clone_url: cloneUrl().trim() || undefined,
       });
       const task = await createTask({
         host: env.host,
@@ -1022,6 +1187,7 @@ function NewEnvironmentPane(props: {
       });
       props.onCreated(task.id);
       setName("");
+      setCloneUrl("");
       setPrompt("");
     } catch (err) {
       setError(err instanceof Error ? err.message : "Failed to create environment");
@@ -1061,6 +1227,12 @@ function NewEnvironmentPane(props: {
             placeholder="Environment name"
             class="rounded-lg border border-gray-300 dark:border-gray-700 bg-white dark:bg-gray-800 px-3 py-2 text-sm text-gray-900 dark:text-gray-100"
           />
+          <input
+            value={cloneUrl()}
+            onInput={(e) => setCloneUrl(e.currentTarget.value)}
+            placeholder="Clone from URL (optional)"
+            class="md:col-span-2 rounded-lg border border-gray-300 dark:border-gray-700 bg-white dark:bg-gray-800 px-3 py-2 text-sm text-gray-900 dark:text-gray-100"
+          />
         </div>
         <div class="grid gap-3 md:grid-cols-2 mb-3">
           <select
@@ -1129,10 +1301,13 @@ export default function Workspace() {
   const params = useParams();
   const [hosts, { refetch: refetchHosts }] = createResource(listHosts);
   const [environments, { refetch: refetchEnvironments }] = createResource(listEnvironments);
-  const [tasks, { refetch: refetchTasks }] = createResource(listTasks);
+  const [tasks, { refetch: refetchTasks }] = createResource(() => listTaskPage());
   const hostsData = createMemo(() => hosts.latest ?? hosts() ?? []);
   const environmentsData = createMemo(() => environments.latest ?? environments() ?? []);
-  const tasksData = createMemo(() => tasks.latest ?? tasks() ?? []);
+  const tasksPage = createMemo(() => tasks.latest ?? tasks());
+  const tasksData = createMemo(() => tasksPage()?.tasks ?? []);
+  // Hosts the last listing skipped; their tasks are kept rather than treated as gone.
+  const unlistedHostIds = createMemo(() => new Set(tasksPage()?.unlisted_hosts ?? []));
   const [knownHosts, setKnownHosts] = createSignal<Record<string, Host>>({});
   const [knownEnvironments, setKnownEnvironments] = createSignal<Record<string, Environment>>({});
   const [knownTasks, setKnownTasks] = createSignal<Record<string, Task>>({});
@@ -1184,9 +1359,12 @@ export default function Workspace() {
 
   createEffect(() => {
     const connectedHosts = connectedHostIds();
+    const unlistedHosts = unlistedHostIds();
     setKnownTasks((prev) => {
       const next = Object.fromEntries(
-        Object.entries(prev).filter(([, task]) => !connectedHosts.has(task.host))
+        Object.entries(prev).filter(
+          ([, task]) => !connectedHosts.has(task.host) || unlistedHosts.has(task.host)
+        )
       );
       for (const task of tasksData()) {
         next[task.id] = task;
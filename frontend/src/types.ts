@@ -4,6 +4,22 @@ export interface Host {
   host: string;
   hostname: string;
   connected_at: string;
+  installed_agents?: AgentKind[];
+  agent_versions?: AgentVersion[];
+  labels?: Record<string, string>;
+  /** `unreachable` while list requests to the host are backed off after a timeout. */
+  status: "connected" | "unreachable";
+  backoff_until?: string;
+}
+
+// Messages on the `/api/hosts/stream` websocket.
+export type HostEvent =
+  | { type: "host_connected"; host: string; hostname: string }
+  | { type: "host_disconnected"; host: string; hostname: string };
+
+export interface AgentVersion {
+  agent: AgentKind;
+  version: string;
 }
 
 export interface Environment {
@@ -12,20 +28,122 @@ export interface Environment {
   directory: string;
 }
 
+export interface EnvironmentDetail extends Environment {
+  current_branch: string;
+  branch_count: number;
+  active_tasks: number;
+}
+
 export interface BranchesResponse {
   branches: string[];
 }
 
+// One `git worktree list` entry; compare `path` with tasks' `worktree_path` to spot orphans.
+export interface WorktreeInfo {
+  path: string;
+  branch?: string | null;
+  is_bare: boolean;
+  locked: boolean;
+}
+
+export interface WorktreesResponse {
+  worktrees: WorktreeInfo[];
+}
+
+export interface PruneWorktreesResponse {
+  pruned: string[];
+  orphaned: string[];
+  removed: string[];
+}
+
+export interface ArchivedTask {
+  task_id: string;
+  archived_at: string;
+}
+
+export interface ArchivesResponse {
+  archives: ArchivedTask[];
+}
+
+export interface ArchivedOutputResponse {
+  task_id: string;
+  events: AgentEvent[];
+}
+
+export interface TaskDiskUsage {
+  task_id: string;
+  bytes: number;
+}
+
+/** Support bundle; `task` is the agent host's raw task record, not the `Task` API shape. */
+export interface TaskBundle {
+  task: Record<string, unknown>;
+  events: AgentEvent[];
+  diff: { staged: string; unstaged: string; truncated: boolean } | null;
+  commits: CommitInfo[];
+  agent_config: Record<string, unknown>;
+  errors: string[];
+}
+
+export interface TaskLogLine {
+  timestamp: string;
+  level: string;
+  target: string;
+  message: string;
+}
+
+export interface TaskLogsResponse {
+  task_id: string;
+  lines: TaskLogLine[];
+}
+
+export interface TaskSummary {
+  task_id: string;
+  last_message: string | null;
+  usage: UsageStats | null;
+  outcome: TaskOutcome | null;
+}
+
+export interface EnvironmentDiskUsage {
+  environment: string;
+  total_bytes: number;
+  tasks: TaskDiskUsage[];
+}
+
+export interface DeleteEnvironmentTasksResponse {
+  deleted: string[];
+  skipped: { task_id: string; reason: string }[];
+}
+
 export interface CreateEnvironmentRequest {
   host: string;
   name: string;
+  clone_url?: string;
+}
+
+export interface ReloadHostResult {
+  host: string;
+  added: string[];
+  removed: string[];
+  error?: string;
 }
 
+export type TaskOutcome =
+  | "completed"
+  | "failed"
+  | "interrupted"
+  | "timed_out"
+  | "tool_error"
+  | "crashed";
+
 export interface PromptRun {
   prompt: string;
   started_at: string;
   finished_at: string | null;
   success: boolean | null;
+  exit_code?: number;
+  signal?: number;
+  outcome?: TaskOutcome;
 }
 
 export interface Task {
@@ -37,34 +155,85 @@ export interface Task {
   workspace_kind: "environment" | "worktree";
   base_branch?: string | null;
   merge_branch?: string | null;
+  model?: string;
+  working_dir?: string;
+  tags: string[];
+  priority: number;
   status: "pending" | "running" | "completed" | "failed" | "interrupted";
   session_id: string | null;
   created_at: string;
   worktree_date?: string | null;
   history: PromptRun[];
+  queued_prompts?: string[];
+  // Task that must complete before this one starts; it stays "pending" until then.
+  depends_on?: string;
+  history_only?: boolean;
+}
+
+export interface TaskListResponse {
+  tasks: Task[];
+  total: number;
+  // Present when requested with `with_environments`.
+  environments?: Environment[];
+  // Connected hosts whose tasks are missing from this page (backed off or failed to list).
+  unlisted_hosts?: string[];
+}
+
+export interface TaskListFilters {
+  tag?: string;
+  status?: Task["status"];
+  host?: string;
+  environment?: string;
+  limit?: number;
+  offset?: number;
+  sort?: "newest" | "priority";
+  with_environments?: boolean;
 }
 
 export interface CreateTaskRequest {
+  // Exact host; may be empty when `host_selector` picks one by labels.
   host: string;
+  host_selector?: Record<string, string>;
   environment: string;
   name?: string;
   use_worktree?: boolean;
   web_search?: boolean;
   prompt: string;
-  agent: AgentKind;
+  agent?: AgentKind;
+  model?: string;
+  subdir?: string;
+  base_branch?: string;
+  existing_branch?: string;
+  tags?: string[];
+  // Rate-limit priority: orders starts waiting on the host's start rate limit, nothing else.
+  priority?: number;
+  attachments?: PromptAttachment[];
+  // Start only after this task completes; `host` may be empty to use the dependency's host.
+  depends_on?: string;
 }
 
 export interface CreateTaskResponse {
   id: string;
   worktree_path: string;
+  dry_run?: boolean;
 }
 
 export interface RenameTaskRequest {
   name: string;
 }
 
+// File sent with a prompt; `base64` is the standard padded encoding of its contents.
+export interface PromptAttachment {
+  filename: string;
+  mime: string;
+  base64: string;
+}
+
 export interface SendPromptRequest {
   prompt: string;
+  attachments?: PromptAttachment[];
+  /** Queue behind the current run instead of failing while the task is busy. */
+  queue?: boolean;
 }
 
 export interface TaskOutputResponse {
@@ -76,6 +245,34 @@ export interface TaskOutputResponse {
 export interface TaskDiffResponse {
   staged: string;
   unstaged: string;
+  // Set when a file's diff was cut at the size cap and replaced by a marker.
+  truncated: boolean;
+}
+
+export interface CommitInfo {
+  sha: string;
+  author: string;
+  timestamp: string;
+  subject: string;
+}
+
+export interface TaskCommitsResponse {
+  commits: CommitInfo[];
+}
+
+export interface CherryPickResponse {
+  status: "picked" | "conflict";
+  applied: string[];
+  conflict: string | null;
+  message: string;
+}
+
+export interface WorktreeFileResponse {
+  path: string;
+  size: number;
+  binary: boolean;
+  too_large: boolean;
+  content: string | null;
 }
 
 // Codex Event Types (from WebSocket)
@@ -88,6 +285,8 @@ export interface CompletedItem {
   arguments?: string;
   call_id?: string;
   output?: string;
+  // Milliseconds since the matching tool_call; set on tool_output items.
+  duration_ms?: number;
   command?: string;
   aggregated_output?: string;
   exit_code?: number;
@@ -117,6 +316,29 @@ export type AgentEvent =
   | { type: "turn.started" }
   | { type: "item.completed"; item: CompletedItem }
   | { type: "turn.completed"; usage?: UsageStats }
-  | { type: "background_event"; event?: string }
+  | { type: "background_event"; event?: string; dropped?: number; wait_ms?: number; rotated_bytes?: number }
   | { type: "prompt.sent"; prompt: string }
+  | { type: "agent.error"; message: string }
+  | { type: "approval.requested"; call_id: string; tool: string; arguments?: string | null }
+  | { type: "agent.heartbeat"; elapsed_secs: number }
+  | { type: "file.edited"; path: string; added: number; removed: number }
   | { type: "unknown" };
+
+// Envelope for each message on the task stream websocket; `seq` increases per task and
+// restarts with each coordinator run, which is identified by `epoch`.
+export interface TaskStreamMessage {
+  epoch: string;
+  seq: number;
+  event: AgentEvent;
+}
+
+// Where a task stream left off, for resuming it without duplicates.
+export interface TaskStreamResume {
+  epoch: string;
+  seq: number;
+}
+
+// Messages on the streamed diff websocket; `end` is always the last one.
+export type DiffStreamMessage =
+  | { type: "chunk"; section: "staged" | "unstaged"; data: string }
+  | { type: "end"; error?: string | null };